@@ -1,11 +1,257 @@
-use std::{cmp::min, collections::HashMap, str::FromStr};
+use std::{
+    cmp::min,
+    io::{self, BufRead, Write},
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
 
-use log::{debug, error, info};
-use reqwest::header::{HeaderMap, AUTHORIZATION, CONTENT_TYPE};
+use log::{debug, info, warn};
+use rand::Rng;
+use regex::Regex;
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_TYPE, RETRY_AFTER};
+use reqwest::StatusCode;
+use reqwest::{RequestBuilder, Response};
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
 
-use crate::settings::AiPrompt;
+use crate::cache;
+use crate::error::{api_error, check_status, GitAiError, Result};
+use crate::interactive;
+use crate::network::{self, Service};
+use crate::progress;
+use crate::settings::{AiPrompt, AzureSettings, RetrySettings};
+
+/// Completions never get more tokens than this, however long the prompt is
+pub const MAX_COMPLETION_TOKENS: usize = 4096;
+
+/// The seed a deterministic client sends, any fixed number would do as long as it never changes
+pub const DETERMINISTIC_SEED: u64 = 1786;
+
+/// The pre-tokenizer split tiktoken uses: contractions, words with their leading space,
+/// numbers, runs of punctuation and whitespace
+fn token_pieces() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| {
+        Regex::new(r"'s|'t|'re|'ve|'m|'ll|'d| ?\p{L}+| ?\p{N}+| ?[^\s\p{L}\p{N}]+|\s+")
+            .expect("Bad token regex")
+    })
+}
+
+/// Estimates how many tokens `text` is.  It splits the text the way tiktoken does
+/// before running BPE and then guesses how many merges each piece ends up as: common
+/// words are one token, long words about one per 4 characters, numbers one per 3
+/// digits and punctuation one per 2 characters.  Code and diffs come out within about
+/// 10% of the real count, which is plenty to keep a prompt under the context window.
+///
+/// # Arguments
+///
+/// * `text` - The text
+pub fn count_tokens(text: &str) -> usize {
+    token_pieces()
+        .find_iter(text)
+        .map(|piece| {
+            let piece = piece.as_str();
+            let trimmed = piece.trim_start_matches(' ');
+            let len = trimmed.chars().count();
+            match trimmed.chars().next() {
+                None => 1,
+                Some(c) if c.is_whitespace() => 1,
+                Some(c) if c.is_numeric() => len.div_ceil(3),
+                Some(c) if c.is_alphabetic() => len.div_ceil(4),
+                Some(_) => len.div_ceil(2),
+            }
+        })
+        .sum()
+}
+
+/// How many tokens a prompt can be in a model with a `context_window` token context.
+/// Completions are given as many tokens as the prompt (up to `MAX_COMPLETION_TOKENS`)
+/// and the two together have to fit.
+///
+/// # Arguments
+///
+/// * `context_window` - The model's context length in tokens
+pub fn prompt_budget(context_window: usize) -> usize {
+    if context_window >= 2 * MAX_COMPLETION_TOKENS {
+        context_window - MAX_COMPLETION_TOKENS
+    } else {
+        context_window / 2
+    }
+}
+
+/// The list price of 1000 tokens for the models we know about, in dollars
+///
+/// # Arguments
+///
+/// * `model` - The model
+pub fn list_price_per_1k_tokens(model: &str) -> Option<f64> {
+    let price = match model {
+        m if m.starts_with("gpt-4-32k") => 0.06,
+        m if m.starts_with("gpt-4") => 0.03,
+        m if m.starts_with("gpt-3.5-turbo-16k") => 0.003,
+        m if m.starts_with("gpt-3.5-turbo") => 0.0015,
+        "text-davinci-003" | "text-davinci-002" => 0.02,
+        _ => return None,
+    };
+    Some(price)
+}
+
+/// The price of 1000 tokens, the one from the settings or else the model's list price
+///
+/// # Arguments
+///
+/// * `setting` - `price_per_1k_tokens` from the settings, 0 when it isn't set
+/// * `model` - The model
+pub fn price_per_1k_tokens(setting: f64, model: &str) -> Option<f64> {
+    Some(setting)
+        .filter(|price| *price > 0.0)
+        .or_else(|| list_price_per_1k_tokens(model))
+}
+
+/// How requests are priced and when to ask before sending one, the CLI sets it once
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CostSettings {
+    /// What 1000 tokens cost, 0 for the model's list price
+    pub price_per_1k_tokens: f64,
+    /// Ask first when a request could cost more than this many dollars, 0 never asks
+    pub confirm_above_cost: f64,
+}
+
+static COST_SETTINGS: OnceLock<CostSettings> = OnceLock::new();
+
+/// Sets the prices and the confirmation threshold every client uses from now on, only
+/// the first call counts
+///
+/// # Arguments
+///
+/// * `settings` - The prices and threshold
+pub fn set_cost_settings(settings: CostSettings) {
+    let _ = COST_SETTINGS.set(settings);
+}
+
+/// What this run has asked the AI for, over every client
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SessionUsage {
+    /// How many requests were answered
+    pub requests: usize,
+    /// The prompt tokens, as the service counted them when it said
+    pub prompt_tokens: usize,
+    /// The completion tokens
+    pub completion_tokens: usize,
+    /// What the priced requests cost in dollars
+    pub dollars: f64,
+    /// Requests to models with no known price, they aren't in `dollars`
+    pub unpriced: usize,
+}
+
+static SESSION: Mutex<SessionUsage> = Mutex::new(SessionUsage {
+    requests: 0,
+    prompt_tokens: 0,
+    completion_tokens: 0,
+    dollars: 0.0,
+    unpriced: 0,
+});
+
+/// What this run has used so far
+pub fn session_usage() -> SessionUsage {
+    SESSION.lock().map(|usage| *usage).unwrap_or_default()
+}
+
+/// Adds an answered request to the session, going by the usage the service sent back
+/// and by our own count when it didn't send one
+fn record_usage(response: &OpenAiCompletionResponse, prompt_tokens: usize, price: Option<f64>) {
+    let counted = response.usage.as_ref();
+    let prompt_tokens = counted
+        .and_then(|usage| usage.prompt_tokens)
+        .map(usize::from)
+        .unwrap_or(prompt_tokens);
+    let completion_tokens = counted
+        .and_then(|usage| usage.completion_tokens)
+        .map(usize::from)
+        .unwrap_or_else(|| {
+            response
+                .choices
+                .iter()
+                .flatten()
+                .filter_map(|choice| choice.text.as_deref())
+                .map(count_tokens)
+                .sum()
+        });
+    if let Ok(mut session) = SESSION.lock() {
+        session.requests += 1;
+        session.prompt_tokens += prompt_tokens;
+        session.completion_tokens += completion_tokens;
+        match price {
+            Some(price) => {
+                session.dollars += (prompt_tokens + completion_tokens) as f64 * price / 1000.0
+            }
+            None => session.unpriced += 1,
+        }
+    }
+}
+
+/// Asks before a request that could cost more than `confirm_above_cost`, a no (or nobody
+/// there to answer) stops it
+fn confirm_cost(estimate: &CostEstimate, threshold: f64) -> Result<()> {
+    let dollars = match estimate.dollars {
+        Some(dollars) if threshold > 0.0 && dollars > threshold => dollars,
+        _ => return Ok(()),
+    };
+    interactive::ask(&format!(
+        "the request could cost up to ${:.4}, more than confirm_above_cost",
+        dollars
+    ))?;
+    // the spinner would draw over the question
+    let reply = progress::suspend(|| -> Result<String> {
+        eprint!(
+            "This request could cost up to ${:.4} ({} prompt tokens), more than confirm_above_cost (${:.4}).  Send it? [y/N] ",
+            dollars, estimate.prompt_tokens, threshold
+        );
+        io::stderr().flush()?;
+        let mut reply = String::new();
+        io::stdin().lock().read_line(&mut reply)?;
+        Ok(reply)
+    })?;
+    if reply.trim().to_ascii_lowercase().starts_with('y') {
+        Ok(())
+    } else {
+        Err(GitAiError::Refused(format!(
+            "Not sending a request that could cost ${:.4}, raise ai_options.confirm_above_cost to allow it",
+            dollars
+        )))
+    }
+}
+
+/// What a request would take, worked out without sending it
+#[derive(Debug, Clone, Copy)]
+pub struct CostEstimate {
+    /// About how many tokens the prompt is
+    pub prompt_tokens: usize,
+    /// The most tokens the completions can be, all of them together
+    pub completion_tokens: usize,
+    /// The most it can cost in dollars, `None` when the price isn't known
+    pub dollars: Option<f64>,
+}
+
+impl CostEstimate {
+    /// Estimates a request for `n` completions of `prompt`, completions get as many tokens as
+    /// `request_body` gives them
+    ///
+    /// # Arguments
+    ///
+    /// * `prompt` - The prompt, as it would be sent
+    /// * `n` - How many completions
+    /// * `price_per_1k_tokens` - What 1000 tokens cost, `None` if nobody knows
+    pub fn new(prompt: &str, n: u8, price_per_1k_tokens: Option<f64>) -> Self {
+        let prompt_tokens = count_tokens(prompt);
+        let completion_tokens = min(prompt_tokens, MAX_COMPLETION_TOKENS) * n.max(1) as usize;
+        CostEstimate {
+            prompt_tokens,
+            completion_tokens,
+            dollars: price_per_1k_tokens
+                .map(|price| (prompt_tokens + completion_tokens) as f64 * price / 1000.0),
+        }
+    }
+}
 
 // The request params to send to OpenAi for or completion
 #[derive(Serialize, Deserialize, Debug)]
@@ -38,6 +284,10 @@ pub struct OpenAiRequestParams {
     /// When used with n, best_of controls the number of candidate completions and n specifies how many to return –
     /// best_of must be greater than n.
     pub best_of: Option<u8>,
+    /// Ask for the same answer to the same request every time, only sent when set and only
+    /// some models take it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
 }
 /// An OpenAiChoice is basically the answer.  If n>1 his can be a Vector
 #[derive(Serialize, Deserialize, Debug)]
@@ -79,14 +329,32 @@ pub struct OpenAiCompletionResponse {
     pub usage: Option<OpenAiUsage>,
 }
 
+/// A model the key can use, from the models list
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OpenAiModel {
+    /// Its name, what goes in `model`
+    pub id: String,
+    /// Who made it, `openai` or `system` (Azure doesn't say)
+    #[serde(default)]
+    pub owned_by: String,
+    /// When it was made, seconds since the epoch (`created_at` on Azure)
+    #[serde(default, alias = "created_at")]
+    pub created: i64,
+}
+
+/// The models list, as it comes back
+#[derive(Deserialize, Debug)]
+struct OpenAiModelList {
+    data: Vec<OpenAiModel>,
+}
+
 /// Default Implementation - Sets all things **except** the prompt to what you probably want to use
 /// so be sure to create it mutable so you can set the prompt
 impl Default for OpenAiRequestParams {
     fn default() -> Self {
         OpenAiRequestParams {
-            model: String::from_str("code-davinci-002").expect("Why cant I set the default?"),
-            prompt: String::from_str("Say hello to Jake for me")
-                .expect("Why cant I set the default?"),
+            model: "code-davinci-002".to_string(),
+            prompt: "Say hello to Jake for me".to_string(),
             suffix: None,
             max_tokens: Some(256),
             temperature: Some(0.05),
@@ -98,6 +366,94 @@ impl Default for OpenAiRequestParams {
             presence_penalty: Some(0.2),
             frequency_penalty: Some(0.2),
             best_of: Some(1),
+            seed: None,
+        }
+    }
+}
+
+/// One message in an Azure chat completion
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ChatMessage {
+    /// `system`, `user` or `assistant`
+    pub role: String,
+    /// What was said
+    pub content: String,
+}
+
+/// Azure deployments of the newer models only take chat completions, so the prompt
+/// goes in as a single user message
+#[derive(Serialize, Debug)]
+struct ChatRequestParams {
+    messages: Vec<ChatMessage>,
+    max_tokens: Option<u16>,
+    temperature: Option<f32>,
+    top_p: Option<f32>,
+    n: Option<u8>,
+    stop: Option<String>,
+    presence_penalty: Option<f32>,
+    frequency_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
+}
+
+impl From<OpenAiRequestParams> for ChatRequestParams {
+    fn from(params: OpenAiRequestParams) -> Self {
+        ChatRequestParams {
+            messages: vec![ChatMessage {
+                role: "user".to_string(),
+                content: params.prompt,
+            }],
+            max_tokens: params.max_tokens,
+            temperature: params.temperature,
+            top_p: params.top_p,
+            n: params.n,
+            stop: params.stop,
+            presence_penalty: params.presence_penalty,
+            frequency_penalty: params.frequency_penalty,
+            seed: params.seed,
+        }
+    }
+}
+
+/// A chat choice, the same as an `OpenAiChoice` but the text is in a message
+#[derive(Deserialize, Debug)]
+struct ChatChoice {
+    index: Option<u8>,
+    message: Option<ChatMessage>,
+    finish_reason: Option<String>,
+}
+
+/// What Azure sends back for a chat completion
+#[derive(Deserialize, Debug)]
+struct ChatCompletionResponse {
+    id: Option<String>,
+    object: Option<String>,
+    created: Option<u64>,
+    model: Option<String>,
+    choices: Option<Vec<ChatChoice>>,
+    usage: Option<OpenAiUsage>,
+}
+
+/// Everybody else only knows about `OpenAiCompletionResponse`, so hand them one of those
+impl From<ChatCompletionResponse> for OpenAiCompletionResponse {
+    fn from(response: ChatCompletionResponse) -> Self {
+        OpenAiCompletionResponse {
+            id: response.id,
+            object: response.object,
+            created: response.created,
+            model: response.model,
+            choices: response.choices.map(|choices| {
+                choices
+                    .into_iter()
+                    .map(|choice| OpenAiChoice {
+                        text: choice.message.map(|message| message.content),
+                        index: choice.index,
+                        logprobs: None,
+                        finish_reason: choice.finish_reason,
+                    })
+                    .collect()
+            }),
+            usage: response.usage,
         }
     }
 }
@@ -105,10 +461,22 @@ impl Default for OpenAiRequestParams {
 /// A simple little client for making requests to OpenAi
 #[derive(Debug)]
 pub struct OpenAiClient {
-    /// The reqwest client - TODO: Make this a non-blocking one
-    client: reqwest::blocking::Client,
+    /// The reqwest client
+    client: reqwest::Client,
     /// The base url for the OpenApi API
     base_url: String,
+    /// Set when talking to Azure OpenAI instead
+    azure: Option<AzureSettings>,
+    /// How rate limited and failed requests are tried again
+    retry: RetrySettings,
+    /// When to stop waiting for the AI, if ever
+    deadline: Option<Instant>,
+    /// Sample as little as possible, see `with_deterministic`
+    deterministic: bool,
+    /// The model asked when a request doesn't name one, see `with_model`
+    model: String,
+    /// Whether answers are looked up in the cache, see `with_cache`
+    cached: bool,
 }
 
 impl OpenAiClient {
@@ -119,28 +487,246 @@ impl OpenAiClient {
     /// * `base_url` - A string containing the base url for the API
     /// * `open_api_token` - The OpenAi token to use
     ///
-    pub fn new(base_url: String, open_api_token: String) -> Self {
+    /// # Errors
+    ///
+    /// Fails if the token can't go in a header or the http client can't be built
+    ///
+    pub fn new(base_url: String, open_api_token: String) -> Result<Self> {
         let mut headers = HeaderMap::new();
-        headers.insert(CONTENT_TYPE, "application/json".parse().unwrap());
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
         headers.insert(
             AUTHORIZATION,
-            format!("Bearer {}", open_api_token).parse().unwrap(),
+            header_value(&format!("Bearer {}", open_api_token))?,
         );
-        let client = reqwest::blocking::ClientBuilder::new()
+        let client = network::client_builder(Service::Ai)?
+            .default_headers(headers)
+            .build()?;
+        Ok(OpenAiClient {
+            client,
+            base_url,
+            azure: None,
+            retry: RetrySettings::default(),
+            deadline: None,
+            deterministic: false,
+            model: OpenAiRequestParams::default().model,
+            cached: true,
+        })
+    }
+
+    /// Returns an OpenAiClient for an Azure OpenAI resource.  Azure uses an `api-key`
+    /// header instead of a bearer token and puts the deployment in the url
+    ///
+    /// # Arguments
+    ///
+    /// * `base_url` - The resource endpoint, something like `https://my-resource.openai.azure.com`
+    /// * `api_key` - The Azure OpenAI key
+    /// * `azure` - The deployment and api version
+    ///
+    pub fn new_azure(base_url: String, api_key: String, azure: AzureSettings) -> Result<Self> {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        headers.insert("api-key", header_value(&api_key)?);
+        let client = network::client_builder(Service::Ai)?
             .default_headers(headers)
-            .build()
-            .expect("Error Building Reqwest Client");
-        let ai_client = OpenAiClient {
-            client: client,
-            base_url: base_url,
+            .build()?;
+        Ok(OpenAiClient {
+            client,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            azure: Some(azure),
+            retry: RetrySettings::default(),
+            deadline: None,
+            deterministic: false,
+            model: OpenAiRequestParams::default().model,
+            cached: true,
+        })
+    }
+
+    /// The same client, trying failed requests again the way `retry` says
+    ///
+    /// # Arguments
+    ///
+    /// * `retry` - The retry settings
+    pub fn with_retry(self, retry: RetrySettings) -> Self {
+        OpenAiClient { retry, ..self }
+    }
+
+    /// The same client, giving up with `GitAiError::TimedOut` once `deadline_ms` have passed
+    /// from now, whatever it is in the middle of
+    ///
+    /// # Arguments
+    ///
+    /// * `deadline_ms` - How long it has, 0 for as long as it takes
+    pub fn with_deadline(self, deadline_ms: u64) -> Self {
+        let deadline = match deadline_ms {
+            0 => None,
+            ms => Some(Instant::now() + Duration::from_millis(ms)),
         };
-        return ai_client;
+        OpenAiClient { deadline, ..self }
+    }
+
+    /// The same client, asking for the same answer to the same prompt every time: temperature
+    /// 0, no nucleus sampling and `DETERMINISTIC_SEED` as the seed, whatever the params say
+    ///
+    /// # Arguments
+    ///
+    /// * `deterministic` - Whether to
+    pub fn with_deterministic(self, deterministic: bool) -> Self {
+        OpenAiClient {
+            deterministic,
+            ..self
+        }
+    }
+
+    /// The same client, asking `model` unless a request names another.  Empty keeps the
+    /// default, Azure ignores it for the deployment in the url.
+    ///
+    /// # Arguments
+    ///
+    /// * `model` - `ai_options.model`
+    pub fn with_model(self, model: &str) -> Self {
+        if model.is_empty() {
+            return self;
+        }
+        OpenAiClient {
+            model: model.to_string(),
+            ..self
+        }
+    }
+
+    /// The same client, asking the AI even when the cache has the answer and keeping
+    /// what it says out of the cache, for when a new answer to the same prompt is the point
+    ///
+    /// # Arguments
+    ///
+    /// * `cached` - Whether answers may come from the cache
+    pub fn with_cache(self, cached: bool) -> Self {
+        OpenAiClient { cached, ..self }
+    }
+
+    /// Whether answers may come from the cache
+    pub fn cached(&self) -> bool {
+        self.cached
+    }
+
+    /// The model asked when a request doesn't name one
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    /// The time left before the deadline, an error once it has passed
+    fn time_left(&self) -> Result<Option<Duration>> {
+        match self.deadline {
+            None => Ok(None),
+            Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                Some(left) if !left.is_zero() => Ok(Some(left)),
+                _ => Err(GitAiError::TimedOut(format!(
+                    "{} didn't answer in time",
+                    self.service()
+                ))),
+            },
+        }
+    }
+
+    /// What to call the service in error messages
+    fn service(&self) -> &'static str {
+        match self.azure {
+            Some(_) => "Azure OpenAI",
+            None => "OpenAI",
+        }
+    }
+
+    /// How long to wait before trying again, `Retry-After` if the server sent one and
+    /// otherwise `initial_backoff_ms` doubled for every attempt so far, with some jitter so
+    /// a crowd of clients doesn't come back all at once
+    ///
+    /// # Arguments
+    ///
+    /// * `attempt` - How many tries have failed, from 0
+    /// * `retry_after` - The `Retry-After` header, if there was one
+    fn backoff(&self, attempt: u8, retry_after: Option<&HeaderValue>) -> Duration {
+        let max = Duration::from_millis(self.retry.max_backoff_ms);
+        // only the delay-seconds form, nobody sends OpenAI's as a date
+        if let Some(seconds) = retry_after
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse::<f64>().ok())
+        {
+            return min(Duration::from_secs_f64(seconds.max(0.0)), max);
+        }
+        let wait = self
+            .retry
+            .initial_backoff_ms
+            .saturating_mul(1 << min(attempt, 16))
+            .min(self.retry.max_backoff_ms);
+        Duration::from_millis(rand::thread_rng().gen_range(wait / 2..=wait))
+    }
+
+    /// Sends the request, trying again on rate limits, server errors, timeouts and
+    /// refused connections.  Anything else, and the last failure, goes through `check_status`
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - Builds the request, once for every try
+    async fn send(&self, request: impl Fn() -> RequestBuilder) -> Result<Response> {
+        let mut attempt = 0;
+        loop {
+            let last_try = attempt >= self.retry.max_retries;
+            // a timeout on the request replaces the client's, so it is whichever comes first
+            let request = match self.time_left()? {
+                Some(left) => request().timeout(
+                    network::timeouts(Service::Ai)
+                        .total
+                        .map_or(left, |total| min(total, left)),
+                ),
+                None => request(),
+            };
+            let (reason, retry_after) = match request.send().await {
+                Ok(res) if retryable(res.status()) && !last_try => {
+                    let status = res.status();
+                    let retry_after = res.headers().get(RETRY_AFTER).cloned();
+                    if status == StatusCode::TOO_MANY_REQUESTS {
+                        // running out of quota is a 429 too, but waiting won't fix it
+                        let text = res.text().await.unwrap_or_default();
+                        if text.contains("insufficient_quota") {
+                            return Err(api_error(self.service(), status, text));
+                        }
+                    }
+                    (status.to_string(), retry_after)
+                }
+                Ok(res) => return check_status(res, self.service()).await,
+                Err(e) if (e.is_timeout() || e.is_connect()) && !last_try => (e.to_string(), None),
+                // the timeout was the deadline, see `time_left`
+                Err(e) if e.is_timeout() && self.deadline.is_some() => {
+                    return Err(self.time_left().err().unwrap_or_else(|| e.into()))
+                }
+                Err(e) => return Err(e.into()),
+            };
+            let wait = self.backoff(attempt, retry_after.as_ref());
+            if let Some(left) = self.time_left()? {
+                if wait >= left {
+                    return Err(GitAiError::TimedOut(format!(
+                        "{} failed ({}) and there is no time left to try again",
+                        self.service(),
+                        reason
+                    )));
+                }
+            }
+            attempt += 1;
+            warn!(
+                "{} failed ({}), trying again in {:.1}s ({} of {})",
+                self.service(),
+                reason,
+                wait.as_secs_f64(),
+                attempt,
+                self.retry.max_retries
+            );
+            tokio::time::sleep(wait).await;
+        }
     }
 
     /// Gets all the models available at OpenAi - THis is mainly to test
     /// if your token is valid
     ///
-    /// Returns `Ok(HashMap<String, Value>)` on success, otherwise returns an error.
+    /// Returns the models, sorted by name, on success, otherwise returns an error.
     ///
     /// # Errors
     ///
@@ -151,13 +737,92 @@ impl OpenAiClient {
     /// This method fails if there was an error while sending request,
     /// redirect loop was detected or redirect limit was exhausted.
     ///
-    pub fn get_models(&self) -> Result<HashMap<String, Value>, Box<dyn std::error::Error>> {
+    /// A rejected key is `GitAiError::Unauthorized` and any other error status is `GitAiError::Api`.
+    ///
+    pub async fn get_models(&self) -> Result<Vec<OpenAiModel>> {
         info!("Getting Available Models");
-        let url = format!("{}models", self.base_url);
+        let url = match &self.azure {
+            Some(azure) => format!(
+                "{}/openai/models?api-version={}",
+                self.base_url, azure.api_version
+            ),
+            None => format!("{}models", self.base_url),
+        };
         debug!("url={:#?}", url);
-        let res = self.client.get(url).send()?;
-        let jsn = res.json::<HashMap<String, Value>>()?;
-        return Ok(jsn);
+        let res = self.send(|| self.client.get(&url)).await?;
+        let mut models = res.json::<OpenAiModelList>().await?.data;
+        models.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(models)
+    }
+
+    /// The url completions get posted to
+    fn completions_url(&self) -> String {
+        match &self.azure {
+            Some(azure) => format!(
+                "{}/openai/deployments/{}/chat/completions?api-version={}",
+                self.base_url, azure.deployment, azure.api_version
+            ),
+            None => format!("{}completions", self.base_url),
+        }
+    }
+
+    /// The exact bytes `get_completions` would post, nothing is sent
+    ///
+    /// # Arguments
+    ///
+    /// * `ai_prompt` - The prompt
+    /// * `open_ai_request_params` - The request params, the prompt and max tokens get replaced
+    ///
+    pub fn request_body(
+        &self,
+        ai_prompt: AiPrompt,
+        open_ai_request_params: OpenAiRequestParams,
+    ) -> Result<Vec<u8>> {
+        let mut request_params = open_ai_request_params;
+        request_params.prompt = format!("{}", ai_prompt);
+        debug!("Prompt=\n{}", &request_params.prompt);
+        let prompt_tokens = count_tokens(&request_params.prompt);
+        debug!("Prompt is about {} tokens", prompt_tokens);
+        let max_tokens = min(prompt_tokens, MAX_COMPLETION_TOKENS) as u16;
+        request_params.max_tokens = Some(max_tokens);
+        debug!("Max Tokens Set To {}", max_tokens);
+        if self.deterministic {
+            request_params.temperature = Some(0.0);
+            request_params.top_p = Some(1.0);
+            request_params.seed = Some(DETERMINISTIC_SEED);
+        }
+        let body = match self.azure {
+            Some(_) => serde_json::to_vec(&ChatRequestParams::from(request_params))?,
+            None => serde_json::to_vec(&request_params)?,
+        };
+        Ok(body)
+    }
+
+    /// Sends a prompt a few tokens long and throws the answer away, so a model that has to
+    /// start up does it now, and a bad key or a service that can't be reached shows up
+    /// before anything else is asked of it
+    pub async fn warm_up(&self) -> Result<()> {
+        let prompt = AiPrompt {
+            preamble: "Reply with OK.".to_string(),
+            language: String::new(),
+            postamble: String::new(),
+            git_diff: String::new(),
+            postmessage: String::new(),
+            ..Default::default()
+        };
+        debug!("Warming up {}", self.completions_url());
+        // from the cache it wouldn't start anything up or find anything out
+        self.request_completions(
+            prompt,
+            OpenAiRequestParams {
+                n: Some(1),
+                model: self.model.clone(),
+                ..Default::default()
+            },
+            false,
+        )
+        .await
+        .map(|_| ())
     }
 
     /// Gets the completions from a given Git Diff file
@@ -178,31 +843,111 @@ impl OpenAiClient {
     /// This method fails if there was an error while sending request,
     /// redirect loop was detected or redirect limit was exhausted.
     ///
-    pub fn get_completions(
+    /// A rejected key is `GitAiError::Unauthorized` and any other error status is `GitAiError::Api`.
+    /// Rate limits and server errors are tried again first, see `RetrySettings`.
+    ///
+    /// The exact same request a second time is answered from the cache, without asking
+    /// or paying, see `cache`, unless `with_cache` turned that off.
+    ///
+    pub async fn get_completions(
+        &self,
+        ai_prompt: AiPrompt,
+        open_ai_request_params: OpenAiRequestParams,
+    ) -> Result<OpenAiCompletionResponse> {
+        self.request_completions(ai_prompt, open_ai_request_params, self.cached)
+            .await
+    }
+
+    /// `get_completions`, looking in the cache first only when `cached`
+    async fn request_completions(
         &self,
         ai_prompt: AiPrompt,
         open_ai_request_params: OpenAiRequestParams,
-    ) -> Result<OpenAiCompletionResponse, Box<dyn std::error::Error>> {
+        cached: bool,
+    ) -> Result<OpenAiCompletionResponse> {
         info!("Getting Completion");
-        let url = format!("{}completions", self.base_url);
+        let url = self.completions_url();
         debug!("url={:#?}", url);
-        let mut request_params = open_ai_request_params;
-        request_params.prompt = format!("{}", ai_prompt);
-        debug!("Prompt=\n{}", &request_params.prompt);
-        request_params.max_tokens = Some(min(
-            <usize as TryInto<u16>>::try_into(request_params.prompt.chars().count()).unwrap() / 4,
-            4096,
-        ));
-        debug!("Max Tokens Set To {}", &request_params.max_tokens.unwrap());
-        let res = self.client.post(url).json(&request_params).send()?;
-        match res.error_for_status_ref() {
-            Ok(_res) => (),
-            Err(err) => {
-                error!("Error Posting to OpenAI\n{}", err);
-                panic!("{}", err);
+        let cost = COST_SETTINGS.get().copied().unwrap_or_default();
+        let price = price_per_1k_tokens(cost.price_per_1k_tokens, &open_ai_request_params.model);
+        let estimate = CostEstimate::new(
+            &format!("{}", ai_prompt),
+            open_ai_request_params.n.unwrap_or(1),
+            price,
+        );
+        let body = self.request_body(ai_prompt, open_ai_request_params)?;
+        let key = cache::key(&[url.as_bytes(), &body]);
+        if let Some(text) = cache::get(&key).filter(|_| cached) {
+            match self.parse_completions(&text) {
+                Ok(data) => {
+                    progress::suspend(|| {
+                        eprintln!("This was asked before, the answer is from the cache (--no-cache asks again)")
+                    });
+                    return Ok(data);
+                }
+                Err(e) => debug!("The cached answer didn't parse, asking again: {}", e),
             }
         }
-        let data = res.json::<OpenAiCompletionResponse>()?;
-        return Ok(data);
+        confirm_cost(&estimate, cost.confirm_above_cost)?;
+        let res = self
+            .send(|| self.client.post(&url).body(body.clone()))
+            .await?;
+        let text = res.text().await?;
+        let data = self.parse_completions(&text)?;
+        if cached {
+            cache::put(&key, &text);
+        }
+        record_usage(&data, estimate.prompt_tokens, price);
+        Ok(data)
     }
+
+    /// The completions in a response body, Azure's chat answers turned into completions
+    fn parse_completions(&self, text: &str) -> Result<OpenAiCompletionResponse> {
+        Ok(match self.azure {
+            Some(_) => serde_json::from_str::<ChatCompletionResponse>(text)?.into(),
+            None => serde_json::from_str::<OpenAiCompletionResponse>(text)?,
+        })
+    }
+}
+
+/// OpenAI or Azure OpenAI, depending on the settings
+///
+/// # Arguments
+///
+/// * `url` - The OpenAI api url, ignored for Azure's when `azure` is enabled
+/// * `token` - The api key
+/// * `azure` - The Azure settings
+/// * `retry` - How failed requests are tried again
+pub fn new_client(
+    url: String,
+    token: String,
+    azure: AzureSettings,
+    retry: RetrySettings,
+) -> Result<OpenAiClient> {
+    let client = if azure.enabled {
+        OpenAiClient::new_azure(url, token, azure)?
+    } else {
+        OpenAiClient::new(url, token)?
+    };
+    Ok(client.with_retry(retry))
+}
+
+/// Rate limits and the server errors that tend to go away by themselves
+fn retryable(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::TOO_MANY_REQUESTS
+            | StatusCode::REQUEST_TIMEOUT
+            | StatusCode::INTERNAL_SERVER_ERROR
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+/// A header value from something the user typed in, which can have anything in it
+fn header_value(value: &str) -> Result<HeaderValue> {
+    HeaderValue::from_str(value).map_err(|_| {
+        GitAiError::Usage("The api key has characters that can't be sent in a header".to_string())
+    })
 }
@@ -0,0 +1,146 @@
+use std::{fs, path::Path};
+
+use log::{debug, warn};
+use regex::Regex;
+
+/// The file, at the root of the repo
+pub const FILE_NAME: &str = ".gitaiignore";
+
+/// One line of the file
+#[derive(Debug, Clone)]
+struct Rule {
+    /// The pattern, as a regex over the path relative to the root
+    regex: Regex,
+    /// `!pattern`, puts back what an earlier line took out
+    negated: bool,
+}
+
+/// The paths a repo never wants sent to the AI, read from its `.gitaiignore`.  Same
+/// syntax as `.gitignore`: `#` comments, `!` to negate, a trailing `/` for directories
+/// only, a `/` anywhere else anchors the pattern to the root and `**` crosses directories.
+#[derive(Debug, Clone, Default)]
+pub struct AiIgnore {
+    rules: Vec<Rule>,
+}
+
+/// A gitignore glob as a regex, without the anchoring
+fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::new();
+    let chars: Vec<char> = glob.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                // `**/` is any number of directories, a trailing `**` is everything inside
+                if chars.get(i + 2) == Some(&'/') {
+                    regex.push_str("(?:.*/)?");
+                    i += 3;
+                } else {
+                    regex.push_str(".*");
+                    i += 2;
+                }
+                continue;
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            '[' => match chars[i..].iter().position(|&c| c == ']') {
+                Some(end) if end > 1 => {
+                    let class: String = chars[i + 1..i + end].iter().collect();
+                    let class = match class.strip_prefix('!') {
+                        Some(rest) => format!("^{}", rest),
+                        None => class,
+                    };
+                    regex.push_str(&format!("[{}]", class.replace('\\', "\\\\")));
+                    i += end + 1;
+                    continue;
+                }
+                _ => regex.push_str(r"\["),
+            },
+            '\\' if i + 1 < chars.len() => {
+                regex.push_str(&regex::escape(&chars[i + 1].to_string()));
+                i += 2;
+                continue;
+            }
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+        i += 1;
+    }
+    regex
+}
+
+impl AiIgnore {
+    /// Reads `.gitaiignore` from the root of the repo, nothing is ignored without one
+    ///
+    /// # Arguments
+    ///
+    /// * `root` - The root of the repo
+    pub fn load(root: &Path) -> AiIgnore {
+        match fs::read_to_string(root.join(FILE_NAME)) {
+            Ok(text) => {
+                let ignore = AiIgnore::parse(&text);
+                debug!("{} has {} rule(s)", FILE_NAME, ignore.rules.len());
+                ignore
+            }
+            Err(_) => AiIgnore::default(),
+        }
+    }
+
+    /// Reads the rules out of the text of a `.gitaiignore`
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The file
+    pub fn parse(text: &str) -> AiIgnore {
+        let mut rules = Vec::new();
+        for line in text.lines() {
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (negated, pattern) = match line.strip_prefix('!') {
+                Some(rest) => (true, rest),
+                None => (false, line.strip_prefix('\\').unwrap_or(line)),
+            };
+            let (dir_only, pattern) = match pattern.strip_suffix('/') {
+                Some(rest) => (true, rest),
+                None => (false, pattern),
+            };
+            let anchored = pattern.contains('/');
+            let pattern = pattern.trim_start_matches('/');
+            if pattern.is_empty() {
+                continue;
+            }
+            // a match on a directory takes everything in it, `dir/` only matches directories
+            let regex = format!(
+                "^{}{}{}",
+                if anchored { "" } else { "(?:.*/)?" },
+                glob_to_regex(pattern),
+                if dir_only { "/.*$" } else { "(?:/.*)?$" }
+            );
+            match Regex::new(&regex) {
+                Ok(regex) => rules.push(Rule { regex, negated }),
+                Err(e) => warn!("Skipping {:?} in {}: {}", line, FILE_NAME, e),
+            }
+        }
+        AiIgnore { rules }
+    }
+
+    /// Whether there are no rules at all
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Whether `path` is kept away from the AI, the last rule matching it decides
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The file, relative to the root of the repo
+    pub fn excludes(&self, path: &Path) -> bool {
+        let path = path.to_string_lossy().replace('\\', "/");
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| rule.regex.is_match(&path))
+            .is_some_and(|rule| !rule.negated)
+    }
+}
@@ -0,0 +1,91 @@
+use std::fs;
+
+use dirs_next::home_dir;
+use git2::Config;
+use log::info;
+
+use crate::error::{GitAiError, Result};
+
+/// The aliases `gitai aliases install` sets up, the alias and the gitai command it runs
+pub const ALIASES: [(&str, &str); 3] = [("cai", "commit"), ("cpr", "pr"), ("crev", "review")];
+
+/// What our aliases start with, anything else under the same name is someone else's
+const ALIAS_PREFIX: &str = "!gitai ";
+
+/// The global gitconfig, `~/.gitconfig` is made if there isn't one (or an XDG one) yet
+fn global_config() -> Result<Config> {
+    let path = match Config::find_global() {
+        Ok(path) => path,
+        Err(_) => {
+            let path = home_dir()
+                .ok_or_else(|| {
+                    GitAiError::Usage("Can't find your home directory for ~/.gitconfig".to_string())
+                })?
+                .join(".gitconfig");
+            fs::write(&path, "")?;
+            path
+        }
+    };
+    info!("Using the git config at {}", path.display());
+    Ok(Config::open(&path)?)
+}
+
+/// Adds the aliases to the global gitconfig, so `git cai` writes a commit and `git cpr`
+/// a pull request.  Git adds whatever comes after the alias to the end of the command,
+/// gitai's own flags work there too.  An alias by the same name that isn't ours is only
+/// replaced with `force`.
+///
+/// # Arguments
+///
+/// * `force` - Replace aliases someone else set up
+///
+/// Returns the aliases, as `git cai runs gitai commit`, and the ones that were replaced
+/// with what they were
+pub fn install(force: bool) -> Result<(Vec<String>, Vec<String>)> {
+    let mut config = global_config()?;
+    let taken: Vec<String> = ALIASES
+        .iter()
+        .filter_map(|(alias, _)| {
+            config
+                .get_string(&format!("alias.{}", alias))
+                .ok()
+                .filter(|existing| !existing.starts_with(ALIAS_PREFIX))
+                .map(|existing| format!("git {} ({})", alias, existing))
+        })
+        .collect();
+    if !taken.is_empty() && !force {
+        return Err(GitAiError::Usage(format!(
+            "These aliases are already something else: {}.  Pass --force to replace them",
+            taken.join(", ")
+        )));
+    }
+    let mut installed = Vec::new();
+    for (alias, command) in ALIASES {
+        config.set_str(
+            &format!("alias.{}", alias),
+            &format!("{}{}", ALIAS_PREFIX, command),
+        )?;
+        installed.push(format!("git {} runs gitai {}", alias, command));
+    }
+    Ok((installed, taken))
+}
+
+/// Takes our aliases back out of the global gitconfig, ones someone else set up under
+/// the same names are left alone
+///
+/// Returns the aliases that were removed
+pub fn remove() -> Result<Vec<String>> {
+    let mut config = global_config()?;
+    let mut removed = Vec::new();
+    for (alias, _) in ALIASES {
+        let name = format!("alias.{}", alias);
+        if config
+            .get_string(&name)
+            .is_ok_and(|existing| existing.starts_with(ALIAS_PREFIX))
+        {
+            config.remove(&name)?;
+            removed.push(format!("git {}", alias));
+        }
+    }
+    Ok(removed)
+}
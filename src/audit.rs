@@ -0,0 +1,176 @@
+use crate::settings::AiPrompt;
+
+/// Subjects that say nothing about what changed
+const VAGUE_SUBJECTS: [&str; 16] = [
+    "fix", "fixes", "fixed", "update", "updates", "updated", "change", "changes", "wip", "misc",
+    "stuff", "cleanup", "refactor", "tweak", "tweaks", "minor",
+];
+/// Generated messages scoring below this are sent back for another try
+pub const PASSING_SCORE: u8 = 6;
+/// Past this many files a change should explain itself in a body
+const BIG_CHANGE_FILES: usize = 5;
+/// Or past this many changed lines
+const BIG_CHANGE_LINES: usize = 100;
+
+/// How big a commit is, from `git2::DiffStats`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ChangeSize {
+    pub files: usize,
+    pub lines: usize,
+}
+
+/// How informative one commit message is, out of 10
+#[derive(Debug, Clone)]
+pub struct CommitScore {
+    /// The short commit id
+    pub id: String,
+    /// The first line of the message
+    pub subject: String,
+    /// 0 (useless) to 10 (great)
+    pub score: u8,
+    /// Why it lost points
+    pub reasons: Vec<String>,
+}
+
+/// Scores a commit message without asking anybody, just by looking at its shape
+/// and how much it is describing
+///
+/// # Arguments
+///
+/// * `id` - The short commit id
+/// * `message` - The whole commit message
+/// * `size` - How big the change is
+pub fn heuristic_score(id: &str, message: &str, size: ChangeSize) -> CommitScore {
+    let subject = message.lines().next().unwrap_or("").trim().to_string();
+    let has_body = message.lines().skip(1).any(|line| !line.trim().is_empty());
+    let mut score: i32 = 10;
+    let mut reasons = Vec::new();
+
+    let words: Vec<String> = subject
+        .split_whitespace()
+        .map(|w| {
+            w.trim_matches(|c: char| !c.is_alphanumeric())
+                .to_lowercase()
+        })
+        .filter(|w| !w.is_empty())
+        .collect();
+    if words.is_empty() {
+        return CommitScore {
+            id: id.to_string(),
+            subject,
+            score: 0,
+            reasons: vec!["empty subject".to_string()],
+        };
+    }
+    if words.len() <= 2 && words.iter().all(|w| VAGUE_SUBJECTS.contains(&w.as_str())) {
+        score -= 5;
+        reasons.push("vague subject".to_string());
+    } else if words.len() < 3 {
+        score -= 2;
+        reasons.push("very short subject".to_string());
+    }
+    let lowered = subject.to_lowercase();
+    if lowered.starts_with("wip") || lowered.starts_with("fixup!") || lowered.starts_with("squash!")
+    {
+        score -= 3;
+        reasons.push("work in progress or fixup commit".to_string());
+    }
+    if subject.chars().count() > 72 {
+        score -= 1;
+        reasons.push("subject over 72 characters".to_string());
+    }
+    if !has_body && (size.files > BIG_CHANGE_FILES || size.lines > BIG_CHANGE_LINES) {
+        score -= 3;
+        reasons.push(format!(
+            "big change ({} files, {} lines) with no body",
+            size.files, size.lines
+        ));
+    }
+    CommitScore {
+        id: id.to_string(),
+        subject,
+        score: score.clamp(0, 10) as u8,
+        reasons,
+    }
+}
+
+/// Why a (generated) message isn't good enough, `None` if it is
+///
+/// # Arguments
+///
+/// * `message` - The commit message
+/// * `size` - How big the change is
+pub fn quality_problems(message: &str, size: ChangeSize) -> Option<Vec<String>> {
+    let score = heuristic_score("", message, size);
+    if score.score < PASSING_SCORE {
+        Some(score.reasons)
+    } else {
+        None
+    }
+}
+
+/// The prompt asking the AI how well a message describes its diff
+///
+/// # Arguments
+///
+/// * `language` - The programming language
+/// * `message` - The commit message
+/// * `diff` - The diff text
+pub fn score_prompt(language: &str, message: &str, diff: &str) -> AiPrompt {
+    AiPrompt {
+        language: language.to_string(),
+        postamble: "developer auditing the commit history of a project. This is the commit message:"
+            .to_string(),
+        git_diff: format!("{}\n\nAnd this is the change it describes:\n{}", message, diff),
+        postmessage: "Rate how well the commit message explains the change, from 0 (says nothing) to 10 (says what changed and why). Answer with just the number on the first line and one short reason on the second line.".to_string(),
+        ..Default::default()
+    }
+}
+
+/// Reads the AI's answer to `score_prompt`, `None` if it didn't start with a number
+pub fn parse_score_answer(answer: &str) -> Option<(u8, String)> {
+    let answer = answer.trim();
+    let (first, rest) = answer.split_once('\n').unwrap_or((answer, ""));
+    let digits: String = first
+        .trim()
+        .chars()
+        .take_while(|c| c.is_ascii_digit())
+        .collect();
+    let score: u8 = digits.parse().ok()?;
+    Some((score.min(10), rest.trim().to_string()))
+}
+
+/// The report, the average and the `worst` lowest scoring commits
+///
+/// # Arguments
+///
+/// * `range` - The range that was audited
+/// * `scores` - Every commit in it
+/// * `worst` - How many offenders to list
+pub fn report(range: &str, scores: &[CommitScore], worst: usize) -> String {
+    if scores.is_empty() {
+        return format!("No commits in {}", range);
+    }
+    let average = scores.iter().map(|s| s.score as f32).sum::<f32>() / scores.len() as f32;
+    let mut report = format!(
+        "Audited {} commits in {}, average score {:.1}/10\n",
+        scores.len(),
+        range,
+        average
+    );
+    let mut sorted: Vec<&CommitScore> = scores.iter().filter(|s| s.score < 10).collect();
+    // stable sort, so equally bad commits stay newest first
+    sorted.sort_by_key(|s| s.score);
+    if sorted.is_empty() {
+        report.push_str("\nNothing to complain about");
+        return report;
+    }
+    report.push_str("\nWorst offenders:\n");
+    for s in sorted.iter().take(worst) {
+        report.push_str(&format!("\n{:>2}/10  {}  {}", s.score, s.id, s.subject));
+        for reason in &s.reasons {
+            report.push_str(&format!("\n         - {}", reason));
+        }
+    }
+    report
+}
@@ -0,0 +1,362 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use log::{debug, warn};
+use serde_json::{json, Value};
+
+use crate::error::{GitAiError, Result};
+use crate::ledger;
+use crate::redact;
+use crate::settings::{gitai_home, settings_path};
+
+/// How many of the newest ledger entries go in
+const LEDGER_ENTRIES: usize = 20;
+
+/// Anything in the settings with one of these as a word of its name gets blanked
+const SECRET_WORDS: [&str; 6] = ["key", "token", "secret", "password", "passwd", "recipients"];
+
+/// One file that can go in the bug report
+#[derive(Debug, Clone)]
+pub struct Item {
+    /// The file name in the tarball
+    pub name: &'static str,
+    /// What it is, for the consent question
+    pub description: &'static str,
+    /// What goes in it
+    pub contents: String,
+}
+
+/// Where the last error is kept, `~/.gitai/last_failure.json`
+pub fn failure_path() -> PathBuf {
+    let mut p = gitai_home();
+    p.push("last_failure.json");
+    p
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Remembers the error gitai is about to exit with, so a bug report can include it.
+/// Never fails, not being able to write it is no reason to hide the real error.
+///
+/// # Arguments
+///
+/// * `error` - What went wrong
+pub fn record_failure(error: &GitAiError) {
+    let (service, status) = match error {
+        GitAiError::Api {
+            service, status, ..
+        } => (Some(service.to_string()), Some(*status)),
+        GitAiError::Unauthorized { service } => (Some(service.to_string()), Some(401)),
+        _ => (None, None),
+    };
+    let failure = json!({
+        "time": now(),
+        "version": env!("CARGO_PKG_VERSION"),
+        "error": error.to_string(),
+        "exit_code": error.exit_code(),
+        "service": service,
+        "status": status,
+    });
+    if let Err(e) = fs::create_dir_all(gitai_home())
+        .and_then(|_| fs::write(failure_path(), failure.to_string()))
+    {
+        debug!("Couldn't remember the failure: {}", e);
+    }
+}
+
+/// Blanks every value whose name looks like it holds a secret, all the way down
+fn scrub(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (name, value) in map.iter_mut() {
+                let name = name.to_ascii_lowercase();
+                let secret = name
+                    .split(|c: char| !c.is_ascii_alphanumeric())
+                    .any(|word| SECRET_WORDS.contains(&word));
+                match value {
+                    Value::String(text) if secret && !text.is_empty() => {
+                        *text = "[redacted]".to_string()
+                    }
+                    Value::Array(list) if secret && !list.is_empty() => {
+                        *list = vec![Value::String("[redacted]".to_string())]
+                    }
+                    _ => scrub(value),
+                }
+            }
+        }
+        Value::Array(list) => list.iter_mut().for_each(scrub),
+        _ => (),
+    }
+}
+
+/// `scrub`, and the sinks' urls and headers too: a webhook's url has its secret in the
+/// path and its headers carry the token, whatever their names
+fn scrub_settings(settings: &mut Value) {
+    scrub(settings);
+    if let Some(Value::Object(sinks)) = settings.get_mut("sinks") {
+        for sink in sinks.values_mut() {
+            if let Some(Value::String(url)) = sink.get_mut("url") {
+                *url = "[redacted]".to_string();
+            }
+            if let Some(Value::Object(headers)) = sink.get_mut("headers") {
+                for value in headers.values_mut() {
+                    *value = Value::String("[redacted]".to_string());
+                }
+            }
+        }
+    }
+}
+
+/// `settings.json` with the secrets taken out.  If it isn't valid JSON (which may be the
+/// bug) it goes in as text with anything that looks like a secret masked.
+fn scrubbed_settings(path: &Path) -> Option<String> {
+    let text = fs::read_to_string(path).ok()?;
+    match serde_json::from_str::<Value>(&text) {
+        Ok(mut settings) => {
+            scrub_settings(&mut settings);
+            serde_json::to_string_pretty(&settings).ok()
+        }
+        Err(e) => {
+            warn!("settings.json isn't valid JSON ({}), masking it as text", e);
+            Some(redact::redact(&text).0)
+        }
+    }
+}
+
+/// gitai's version and what it is running on
+fn versions() -> String {
+    let (major, minor, patch) = git2::Version::get().libgit2_version();
+    format!(
+        "gitai {}\nlibgit2 {}.{}.{}\nos {}\narch {}\n",
+        env!("CARGO_PKG_VERSION"),
+        major,
+        minor,
+        patch,
+        std::env::consts::OS,
+        std::env::consts::ARCH
+    )
+}
+
+/// Everything that could go in a bug report, whatever doesn't exist is left out.  Nothing
+/// is written, the caller asks about each one.
+pub fn items() -> Vec<Item> {
+    let mut items = vec![Item {
+        name: "version.txt",
+        description: "the gitai, libgit2 and OS versions",
+        contents: versions(),
+    }];
+    if let Some(settings) = scrubbed_settings(&settings_path()) {
+        items.push(Item {
+            name: "settings.json",
+            description: "settings.json, with every key, token, password and sink url and header blanked out",
+            contents: settings,
+        });
+    }
+    if let Ok(ledger) = fs::read_to_string(ledger::ledger_path()) {
+        let lines: Vec<&str> = ledger.lines().collect();
+        let newest = &lines[lines.len().saturating_sub(LEDGER_ENTRIES)..];
+        if !newest.is_empty() {
+            items.push(Item {
+                name: "ledger.jsonl",
+                description: "the last 20 ledger entries, the messages gitai wrote and your ratings (no diffs)",
+                contents: redact::redact(&(newest.join("\n") + "\n")).0,
+            });
+        }
+    }
+    if let Ok(failure) = fs::read_to_string(failure_path()) {
+        items.push(Item {
+            name: "last_failure.json",
+            description: "the last error gitai hit, its message, exit code and the service and status behind it",
+            contents: redact::redact(&failure).0,
+        });
+    }
+    items
+}
+
+/// A ustar header for a plain file, names past 99 bytes are cut short
+fn tar_header(name: &str, size: usize, mtime: u64) -> [u8; 512] {
+    let mut header = [0u8; 512];
+    let mut put = |offset: usize, field: &[u8]| {
+        header[offset..offset + field.len()].copy_from_slice(field);
+    };
+    // the name field is 100 bytes and needs its NUL
+    put(0, &name.as_bytes()[..name.len().min(99)]);
+    put(100, b"0000644\0");
+    put(108, b"0000000\0");
+    put(116, b"0000000\0");
+    put(124, format!("{:011o}\0", size).as_bytes());
+    put(136, format!("{:011o}\0", mtime).as_bytes());
+    // the checksum is taken with its own field as spaces
+    put(148, b"        ");
+    put(156, b"0");
+    put(257, b"ustar\0");
+    put(263, b"00");
+    let checksum: u32 = header.iter().map(|&b| u32::from(b)).sum();
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+    header
+}
+
+/// Writes the items to a tarball, each one a file in a `gitai-bugreport` directory
+///
+/// # Arguments
+///
+/// * `path` - Where the tarball goes
+/// * `items` - What goes in it
+pub fn write_tar(path: &Path, items: &[Item]) -> Result<()> {
+    let mtime = now();
+    let mut tar = Vec::new();
+    for item in items {
+        let name = format!("gitai-bugreport/{}", item.name);
+        let contents = item.contents.as_bytes();
+        tar.extend_from_slice(&tar_header(&name, contents.len(), mtime));
+        tar.extend_from_slice(contents);
+        // every file is padded out to a whole block
+        tar.resize(tar.len() + (512 - contents.len() % 512) % 512, 0);
+    }
+    // two empty blocks end the archive
+    tar.resize(tar.len() + 1024, 0);
+    debug!("Writing {} bytes to {}", tar.len(), path.display());
+    Ok(fs::write(path, tar)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_webhook_sink_is_scrubbed() {
+        let mut settings = json!({
+            "sinks": {
+                "slack": {
+                    "type": "webhook",
+                    "url": "https://hooks.slack.com/services/T000/B000/XXXX",
+                    "headers": { "Authorization": "Bearer abc", "X-Team": "platform" }
+                }
+            }
+        });
+        scrub_settings(&mut settings);
+        assert_eq!(
+            settings,
+            json!({
+                "sinks": {
+                    "slack": {
+                        "type": "webhook",
+                        "url": "[redacted]",
+                        "headers": { "Authorization": "[redacted]", "X-Team": "[redacted]" }
+                    }
+                }
+            })
+        );
+    }
+
+    /// A NUL terminated field of a header
+    fn field(header: &[u8], range: std::ops::Range<usize>) -> &str {
+        let bytes = &header[range];
+        let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+        std::str::from_utf8(&bytes[..end]).unwrap()
+    }
+
+    fn octal(header: &[u8], range: std::ops::Range<usize>) -> u64 {
+        u64::from_str_radix(field(header, range).trim(), 8).unwrap()
+    }
+
+    #[test]
+    fn a_tar_header_reads_back() {
+        let header = tar_header("gitai-bugreport/settings.json", 1234, 1_700_000_000);
+        assert_eq!(field(&header, 0..100), "gitai-bugreport/settings.json");
+        assert_eq!(field(&header, 100..108), "0000644");
+        assert_eq!(octal(&header, 124..136), 1234);
+        assert_eq!(octal(&header, 136..148), 1_700_000_000);
+        assert_eq!(header[156], b'0');
+        assert_eq!(field(&header, 257..263), "ustar");
+        assert_eq!(&header[263..265], b"00");
+        let mut blank = header;
+        blank[148..156].copy_from_slice(b"        ");
+        let sum: u64 = blank.iter().map(|&b| u64::from(b)).sum();
+        assert_eq!(octal(&header, 148..155), sum);
+        assert_eq!(&header[154..156], b"\0 ");
+    }
+
+    #[test]
+    fn a_long_tar_name_is_cut_short() {
+        let name = format!("gitai-bugreport/{}", "x".repeat(200));
+        let header = tar_header(&name, 0, 0);
+        assert_eq!(field(&header, 0..100), &name[..99]);
+        assert_eq!(header[99], 0);
+        assert_eq!(field(&header, 100..108), "0000644");
+    }
+
+    #[test]
+    fn a_tarball_is_padded_to_whole_blocks() {
+        let path = std::env::temp_dir().join(format!("gitai-bugreport-{}.tar", std::process::id()));
+        let items = [
+            Item {
+                name: "version.txt",
+                description: "",
+                contents: "gitai 1.0.0\n".to_string(),
+            },
+            Item {
+                name: "ledger.jsonl",
+                description: "",
+                contents: "x".repeat(600),
+            },
+        ];
+        write_tar(&path, &items).unwrap();
+        let tar = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        // a header and a block, a header and two blocks, then two empty blocks
+        assert_eq!(tar.len(), 512 * 7);
+        assert_eq!(field(&tar, 0..100), "gitai-bugreport/version.txt");
+        assert_eq!(octal(&tar, 124..136), 12);
+        assert_eq!(&tar[512..524], b"gitai 1.0.0\n");
+        assert!(tar[524..1024].iter().all(|&b| b == 0));
+        assert_eq!(field(&tar[1024..], 0..100), "gitai-bugreport/ledger.jsonl");
+        assert_eq!(octal(&tar[1024..], 124..136), 600);
+        assert!(tar[1536..2136].iter().all(|&b| b == b'x'));
+        assert!(tar[2136..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn nested_secrets_are_scrubbed() {
+        let mut settings = json!({
+            "ai_settings": {
+                "ai_api_token": "sk-abc",
+                "ai_options": { "model": "gpt-4o" }
+            },
+            "notifications": {
+                "email": {
+                    "smtp": { "password": "hunter22", "user": "me" },
+                    "recipients": ["a@example.com"]
+                },
+                "list": [{ "secret": "shh", "monkey": "business" }],
+                "api_key": ""
+            }
+        });
+        scrub(&mut settings);
+        assert_eq!(
+            settings,
+            json!({
+                "ai_settings": {
+                    "ai_api_token": "[redacted]",
+                    "ai_options": { "model": "gpt-4o" }
+                },
+                "notifications": {
+                    "email": {
+                        "smtp": { "password": "[redacted]", "user": "me" },
+                        "recipients": ["[redacted]"]
+                    },
+                    "list": [{ "secret": "[redacted]", "monkey": "business" }],
+                    "api_key": ""
+                }
+            })
+        );
+    }
+}
@@ -0,0 +1,101 @@
+use std::{
+    fs,
+    path::PathBuf,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use log::debug;
+use sha2::{Digest, Sha256};
+
+use crate::error::Result;
+use crate::settings::gitai_home;
+
+/// Whether answers are looked up and kept, off until `set_enabled` says otherwise
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Turns the cache on (or off) for every client from now on
+///
+/// # Arguments
+///
+/// * `enabled` - `ai_options.cache_responses`, unless `--no-cache` was passed
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Whether the cache is in use
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Where the answers are kept, `~/.gitai/cache`
+pub fn cache_dir() -> PathBuf {
+    gitai_home().join("cache")
+}
+
+/// The key for a request, the SHA-256 of everything that went into it.  The same prompt
+/// with different params (a model, a temperature, how many) is a different request.
+///
+/// # Arguments
+///
+/// * `parts` - Where it went and the body that was sent
+pub fn key(parts: &[&[u8]]) -> String {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        // the length first so the parts can't run into each other
+        hasher.update((part.len() as u64).to_le_bytes());
+        hasher.update(part);
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+fn path(key: &str) -> PathBuf {
+    cache_dir().join(format!("{}.json", key))
+}
+
+/// The answer kept for `key`, if the cache is on and has one
+///
+/// # Arguments
+///
+/// * `key` - From `key`
+pub fn get(key: &str) -> Option<String> {
+    if !enabled() {
+        return None;
+    }
+    let answer = fs::read_to_string(path(key)).ok()?;
+    debug!("The answer for {} came from the cache", key);
+    Some(answer)
+}
+
+/// Keeps `answer` for `key`.  Not being able to is only worth a debug message, the
+/// answer is already here this time.
+///
+/// # Arguments
+///
+/// * `key` - From `key`
+/// * `answer` - What the service sent back
+pub fn put(key: &str, answer: &str) {
+    if !enabled() {
+        return;
+    }
+    if let Err(e) = fs::create_dir_all(cache_dir()).and_then(|_| fs::write(path(key), answer)) {
+        debug!("Couldn't keep the answer for {}: {}", key, e);
+    }
+}
+
+/// Throws every kept answer away and says how many there were and how many bytes they took
+pub fn clear() -> Result<(usize, u64)> {
+    let entries = match fs::read_dir(cache_dir()) {
+        Ok(entries) => entries,
+        Err(_) => return Ok((0, 0)),
+    };
+    let (mut count, mut bytes) = (0, 0);
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "json") {
+            bytes += fs::metadata(&path)?.len();
+            fs::remove_file(&path)?;
+            count += 1;
+        }
+    }
+    Ok((count, bytes))
+}
@@ -0,0 +1,140 @@
+use std::{fs, path::Path};
+
+use crate::conventional;
+use crate::error::Result;
+use crate::settings::AiPrompt;
+
+/// The groups a changelog section is split into, in the order they are written
+pub const GROUPS: [&str; 4] = ["Breaking Changes", "Features", "Fixes", "Other"];
+
+/// One commit going into the changelog
+#[derive(Debug, Clone)]
+pub struct ChangelogCommit {
+    /// The short commit id
+    pub id: String,
+    /// The whole commit message
+    pub message: String,
+    /// What it changed, if the AI gets to see the diffs
+    pub diff: Option<String>,
+}
+
+/// The prompt asking the AI to turn the commits into a grouped changelog section
+///
+/// # Arguments
+///
+/// * `language` - The programming language
+/// * `commits` - The commits, newest first
+pub fn prompt(language: &str, commits: &[ChangelogCommit]) -> AiPrompt {
+    let mut listing = String::new();
+    for commit in commits {
+        listing.push_str(&format!(
+            "commit {}\n{}\n",
+            commit.id,
+            commit.message.trim()
+        ));
+        if let Some(diff) = &commit.diff {
+            listing.push_str(&format!("\n{}\n", diff.trim_end()));
+        }
+        listing.push('\n');
+    }
+    AiPrompt {
+        language: language.to_string(),
+        postamble: "developer writing the changelog for a release. These are the commits that went into it, newest first:"
+            .to_string(),
+        git_diff: listing,
+        postmessage: format!(
+            "Write the changelog section for these commits in Markdown.  Group the entries under `### ` headings, using only these ones and in this order: {}.  Leave out a heading with nothing under it.  Each entry is a `- ` bullet with one short sentence a user of the project would understand, leave out commits nobody using it would care about (typos, CI, formatting).  Answer with just the headings and bullets.",
+            GROUPS.join(", ")
+        ),
+        ..Default::default()
+    }
+}
+
+/// Keeps the `### ` groups from the AI's answer, `None` if it didn't give any
+pub fn parse_answer(answer: &str) -> Option<String> {
+    let start = answer.find("### ")?;
+    let body = answer[start..].trim();
+    Some(body.to_string())
+}
+
+/// The changelog section without asking the AI, Conventional Commits are sorted by their
+/// type and everything else goes under Other
+///
+/// # Arguments
+///
+/// * `commits` - The commits, newest first
+pub fn heuristic_groups(commits: &[ChangelogCommit]) -> String {
+    let mut groups: Vec<Vec<String>> = vec![Vec::new(); GROUPS.len()];
+    for commit in commits {
+        let subject = commit.message.lines().next().unwrap_or("").trim();
+        if subject.is_empty() {
+            continue;
+        }
+        let (group, entry) = match conventional::parse(commit.message.trim()) {
+            Ok(parsed) if parsed.breaking => (0, parsed.description),
+            Ok(parsed) if parsed.kind == "feat" => (1, parsed.description),
+            Ok(parsed) if parsed.kind == "fix" => (2, parsed.description),
+            _ => (3, subject.to_string()),
+        };
+        groups[group].push(format!("- {} ({})", entry, commit.id));
+    }
+    GROUPS
+        .iter()
+        .zip(groups)
+        .filter(|(_, entries)| !entries.is_empty())
+        .map(|(heading, entries)| format!("### {}\n\n{}", heading, entries.join("\n")))
+        .collect::<Vec<String>>()
+        .join("\n\n")
+}
+
+/// The whole section, a `## ` heading with the release and the date over the groups
+///
+/// # Arguments
+///
+/// * `release` - What the release is called, a tag or `Unreleased`
+/// * `groups` - The grouped entries
+pub fn section(release: &str, groups: &str) -> String {
+    format!(
+        "## {} - {}\n\n{}\n",
+        release,
+        chrono::Local::now().format("%Y-%m-%d"),
+        groups.trim()
+    )
+}
+
+/// Puts the section at the top of the changelog, under its title, creating the file if
+/// there isn't one yet
+///
+/// # Arguments
+///
+/// * `path` - The changelog, usually CHANGELOG.md
+/// * `section` - The new section
+pub fn prepend(path: &Path, section: &str) -> Result<()> {
+    let existing = if path.exists() {
+        fs::read_to_string(path)?
+    } else {
+        String::new()
+    };
+    // the new section goes in front of the newest one, anything above that is the intro
+    let split = if existing.starts_with("## ") {
+        0
+    } else {
+        existing
+            .find("\n## ")
+            .map(|i| i + 1)
+            .unwrap_or(existing.len())
+    };
+    let (intro, older) = existing.split_at(split);
+    let intro = if intro.trim().is_empty() {
+        "# Changelog\n\n".to_string()
+    } else {
+        format!("{}\n\n", intro.trim_end())
+    };
+    let older = if older.trim().is_empty() {
+        String::new()
+    } else {
+        format!("\n{}", older)
+    };
+    fs::write(path, format!("{}{}{}", intro, section, older))?;
+    Ok(())
+}
@@ -0,0 +1,94 @@
+use std::sync::OnceLock;
+
+use log::debug;
+use regex::{Captures, Regex};
+
+use crate::settings::ClosingKeywords;
+
+/// `Fixes #12`, `closes: owner/repo#3`, every keyword GitHub or GitLab closes an issue on
+fn closing() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| {
+        Regex::new(
+            r"(?i)\b(?:close[sd]?|fix(?:e[sd])?|resolve[sd]?|implement(?:s|ed|ing)?)(:?[ \t]+)((?:[\w.-]+/[\w.-]+)?#\d+)",
+        )
+        .expect("Bad closing keyword regex")
+    })
+}
+
+/// `Refs #12` and the other ways of mentioning an issue without closing it
+fn referencing() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| {
+        Regex::new(
+            r"(?i)\b(?:refs?|references?|relates to|related to|part of)(:?[ \t]+)((?:[\w.-]+/[\w.-]+)?#\d+)",
+        )
+        .expect("Bad issue reference regex")
+    })
+}
+
+/// `keyword`, capitalized the way `original` was
+fn cased_like(original: &str, keyword: &str) -> String {
+    if original.starts_with(char::is_uppercase) {
+        keyword.to_string()
+    } else {
+        keyword.to_lowercase()
+    }
+}
+
+/// Rewrites the issue references in generated text the way `keywords` says, so the AI
+/// can't close a tracking issue by accident (or forget to close the one it fixes)
+///
+/// # Arguments
+///
+/// * `text` - The pull request title or body
+/// * `keywords` - What to do about closing keywords
+pub fn rewrite(text: &str, keywords: ClosingKeywords) -> String {
+    let (pattern, keyword) = match keywords {
+        ClosingKeywords::Keep => return text.to_string(),
+        ClosingKeywords::Refs => (closing(), "Refs"),
+        ClosingKeywords::Fixes => (referencing(), "Fixes"),
+    };
+    let rewritten = pattern.replace_all(text, |caps: &Captures| {
+        debug!("Rewriting {:?} to {}", &caps[0], keyword);
+        format!("{}{}{}", cased_like(&caps[0], keyword), &caps[1], &caps[2])
+    });
+    rewritten.into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn closing_keywords_become_references() {
+        assert_eq!(
+            rewrite(
+                "Fixes #12, closes: owner/repo#3 and resolved #4",
+                ClosingKeywords::Refs
+            ),
+            "Refs #12, refs: owner/repo#3 and refs #4"
+        );
+    }
+
+    #[test]
+    fn references_become_closing_keywords() {
+        assert_eq!(
+            rewrite("Refs #12\n\npart of owner/repo#3", ClosingKeywords::Fixes),
+            "Fixes #12\n\nfixes owner/repo#3"
+        );
+    }
+
+    #[test]
+    fn only_a_keyword_right_before_an_issue_counts() {
+        for text in [
+            "Fixed the crash reported in #12",
+            "See #12",
+            "prefixes #12",
+            "Fixes issue 12",
+        ] {
+            assert_eq!(rewrite(text, ClosingKeywords::Refs), text);
+        }
+        assert_eq!(rewrite("Fixes #12", ClosingKeywords::Keep), "Fixes #12");
+    }
+}
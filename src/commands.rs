@@ -0,0 +1,1206 @@
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use log::{debug, error, info};
+
+use crate::ai::{self, CostEstimate, OpenAiClient, OpenAiRequestParams};
+use crate::aiignore::AiIgnore;
+use crate::cache;
+use crate::compare;
+use crate::error::{GitAiError, Result};
+use crate::generate::{get_completion_texts, redactions, MessageGenerator, Race};
+use crate::git::Git;
+use crate::hooks::{self, HookPoint};
+use crate::interactive;
+use crate::journal::{self, ActionKind, Details};
+use crate::ledger;
+use crate::packs::{self, PromptPack};
+use crate::preview;
+use crate::sections;
+use crate::settings::{
+    AiPrompt, AiProvider, AzureSettings, ClosingKeywords, CommitPolicy, DualDescription,
+    GitSettings, MessageStyle, Provider, RetrySettings, Settings, TicketSettings,
+};
+use crate::templates;
+use crate::terminal;
+use crate::tickets;
+use crate::worddiff;
+use crate::GitAiOptions;
+
+pub mod audit_history;
+pub mod auth;
+pub mod blame_summary;
+pub mod changelog;
+pub mod commit;
+pub mod explain;
+pub mod feedback;
+pub mod history;
+pub mod hook;
+pub mod intents;
+pub mod models;
+pub mod pr;
+pub mod prompts;
+pub mod review;
+pub mod rpc;
+pub mod setup;
+pub mod squash;
+pub mod trailer;
+pub mod undo;
+pub mod version;
+
+/// Tokens kept for the prompt around the diff (instructions, PR sections, ...)
+const PROMPT_ALLOWANCE: usize = 500;
+
+/// What the command line says over the settings, `None` leaves it to them
+#[derive(Debug, Clone, Default)]
+pub struct Overrides {
+    /// The GitHub token
+    pub github_token: Option<String>,
+    /// The GitHub API url
+    pub github_url: Option<String>,
+    /// The remote pull requests go to
+    pub remote: Option<String>,
+    /// The GitLab token
+    pub gitlab_token: Option<String>,
+    /// The GitLab API url
+    pub gitlab_url: Option<String>,
+    /// Where pull requests get created
+    pub provider: Option<Provider>,
+    /// The AI token
+    pub open_ai_token: Option<String>,
+    /// The AI url
+    pub open_ai_url: Option<String>,
+    /// The repository, instead of `local_path`
+    pub local_repo: Option<PathBuf>,
+    /// Pick the prompt at random
+    pub stochastic: Option<bool>,
+    /// The same diff gets the same message
+    pub deterministic: Option<bool>,
+    /// Write the message in the editor, nothing is sent to the AI
+    pub no_ai: Option<bool>,
+    /// Warm the model up as soon as the command starts
+    pub warm_up: Option<bool>,
+    /// Compress the diff the AI sees
+    pub compress: Option<bool>,
+    /// Ask whether the docs need updating
+    pub doc_check: Option<bool>,
+    /// Conventional Commits
+    pub conventional: Option<bool>,
+    /// The language messages are written in
+    pub locale: Option<String>,
+    /// Start the subject with a gitmoji
+    pub gitmoji: Option<bool>,
+    /// The shape of the commit message
+    pub message_style: Option<MessageStyle>,
+    /// A mermaid diagram in the pull request
+    pub diagram: Option<bool>,
+    /// Print the prompt and stop
+    pub dry_run: Option<bool>,
+    /// Show the options as word diffs against each other
+    pub reroll_diff: Option<bool>,
+    /// Check the change isn't several in one first
+    pub intent_check: Option<bool>,
+    /// Send the diff without masking secrets
+    pub no_redact: Option<bool>,
+    /// Don't use or keep cached answers
+    pub no_cache: Option<bool>,
+    /// Race the request against `ai_settings.race`
+    pub race: Option<bool>,
+    /// Stage everything before committing
+    pub auto_add: Option<bool>,
+    /// Take the first message without asking
+    pub auto_ai: Option<bool>,
+    /// Push the branch before opening the pull request
+    pub auto_push: Option<bool>,
+    /// Go ahead with auto-ai and auto-push both on
+    pub yes: Option<bool>,
+    /// How many messages to ask for
+    pub num_tries: Option<u8>,
+    /// Sign the commits
+    pub gpg_sign_commit: Option<bool>,
+    /// The signing key
+    pub gpg_key_id: Option<String>,
+    /// The programming language
+    pub programming_language: Option<String>,
+    /// The prompt template to write from
+    pub prompt_name: Option<String>,
+    /// The path to the ssh key
+    pub ssh_key_path: Option<String>,
+}
+
+/// Everything a command needs, the settings with the flags over them.  Built once,
+/// before the command runs.
+pub struct Context {
+    /// The settings, the repository's `.gitai.toml` over settings.json
+    pub settings: Settings,
+    /// The AI token, a routing rule's if one matched
+    pub ai_token: String,
+    /// The AI url, a routing rule's if one matched
+    pub ai_url: String,
+    /// Azure OpenAI, `enabled` if that is where the AI is
+    pub azure: AzureSettings,
+    /// How failed AI requests are tried again
+    pub retry: RetrySettings,
+    /// `ai_options.model`, empty for the default
+    pub ai_model: String,
+    /// Where pull requests go, the flags over the settings
+    pub hosting_settings: GitSettings,
+    /// The GitHub token came from the command line, so no account is picked for the remote
+    pub github_token_from_cli: bool,
+    /// The programming language
+    pub language: String,
+    /// How many messages to ask for
+    pub num_tries: u8,
+    /// The path to the ssh key
+    pub ssh_key_path: String,
+    /// The ssh user
+    pub ssh_user: String,
+    /// The repository
+    pub local_repo: PathBuf,
+    /// What to do about closing keywords in pull requests, this repo's rule if it has one
+    pub closing_keywords: ClosingKeywords,
+    /// The url, token and azure settings of the service `--race` races against
+    pub race_with: Option<(String, String, AzureSettings)>,
+    /// What the ledger says wrote the message
+    pub model_name: String,
+    /// The signing key, empty leaves it to user.signingkey in git config
+    pub gpg_key_id: String,
+    /// Take the first message without asking
+    pub auto_ai: bool,
+    /// Stage everything before committing
+    pub auto_add: bool,
+    /// Push the branch before opening the pull request
+    pub auto_push: bool,
+    /// A person writes the messages, the AI is never asked
+    pub no_ai: bool,
+    /// Warm the model up as soon as the command starts
+    pub warm_up: bool,
+    /// The same diff gets the same message
+    pub deterministic: bool,
+    /// Pick the prompt at random
+    pub stochastic: bool,
+    /// More files than this are offered a split into one commit per directory
+    pub split_threshold: usize,
+    /// How long to wait between AI calls that go one after the other
+    pub rate_limit_ms: u64,
+    /// What's left of the prompt budget once the rest of the prompt is in
+    pub diff_budget: usize,
+    /// The prompt pack, with the prompt template over it
+    pub pack: PromptPack,
+    /// The prompt pack's name, empty for the built in prompts
+    pub pack_name: String,
+    /// Say which workspace packages a change affects
+    pub affected_packages: bool,
+    /// `--yes` or the policy allow in settings, either one is enough
+    pub yes: bool,
+    /// Print the prompt and stop
+    pub dry_run: bool,
+    /// The price from the settings, 0 to use the model's list price
+    pub price_per_1k_tokens: f64,
+    /// Mask secrets before the AI sees the diff
+    pub redact: bool,
+    /// Compress the diff the AI sees
+    pub compress: bool,
+    /// Ask whether the docs need updating
+    pub doc_check: bool,
+    /// Conventional Commits
+    pub conventional: bool,
+    /// Start the subject with a gitmoji
+    pub gitmoji: bool,
+    /// Show the options as word diffs against each other
+    pub reroll_diff: bool,
+    /// Check the change isn't several in one first
+    pub intent_check: bool,
+    /// How much of the change can be about something else before it is mixed, in percent
+    pub mixed_intent_threshold: u8,
+    /// How long the hook waits for the AI
+    pub generation_deadline_ms: u64,
+    /// How many earlier commits to the same files the message stays consistent with
+    pub consistency_commits: usize,
+    /// A mermaid diagram in the pull request
+    pub diagram: bool,
+    /// A walkthrough of the commits in the pull request
+    pub pr_walkthrough: bool,
+    /// Fill in the repo's pull request template
+    pub use_pr_template: bool,
+    /// Sign the commits, off leaves it to commit.gpgsign in git config
+    pub gpg_sign_commits: bool,
+    /// Where the detailed description goes in a repo that is mirrored publicly
+    pub dual_description: DualDescription,
+    /// The files the AI only hears changed
+    pub diff_ignore: Vec<String>,
+    /// The repository's `.gitaiignore`
+    pub ai_ignore: AiIgnore,
+    /// How the library writes, the commands wrap the CLI around it
+    pub options: GitAiOptions,
+}
+
+impl Context {
+    /// The settings with the flags over them, routing rules over both
+    ///
+    /// # Arguments
+    ///
+    /// * `settings` - The settings, with the tokens in them
+    /// * `overrides` - The flags
+    ///
+    /// # Errors
+    ///
+    /// Fails when the flags and settings don't go together, or the prompt pack or
+    /// template can't be loaded
+    pub fn new(settings: Settings, overrides: Overrides) -> Result<Self> {
+        debug!("Setting Variables");
+        //ai variables
+        let mut ai_token = overrides
+            .open_ai_token
+            .unwrap_or_else(|| settings.ai_settings.api_key.clone());
+        let mut ai_url = overrides
+            .open_ai_url
+            .unwrap_or_else(|| settings.ai_settings.api_url.clone());
+        let mut azure = settings.ai_settings.azure.clone();
+        let retry = settings.ai_settings.retry;
+        let ai_model = settings.ai_settings.ai_options.model.clone();
+
+        // hosting variables, the provider for pull requests is built from these
+        let mut hosting_settings = settings.git_settings.clone();
+        // a token on the command line beats whichever account the remote would pick
+        let github_token_from_cli = overrides.github_token.is_some();
+        if let Some(token) = overrides.github_token {
+            hosting_settings.github_api_key = token;
+        }
+        if let Some(url) = overrides.github_url {
+            hosting_settings.github_api_url = url;
+        }
+        if let Some(token) = overrides.gitlab_token {
+            hosting_settings.gitlab_api_key = token;
+        }
+        if let Some(url) = overrides.gitlab_url {
+            hosting_settings.gitlab_api_url = url;
+        }
+        if let Some(provider) = overrides.provider {
+            hosting_settings.provider = provider;
+        }
+        if let Some(remote) = overrides.remote {
+            hosting_settings.remote = remote;
+        }
+        debug!(
+            "Hosting Variables Set provider={:?}",
+            hosting_settings.provider
+        );
+
+        // other variables - not flags first
+        let language = overrides
+            .programming_language
+            .or(Some(
+                settings.ai_settings.ai_options.prompt.language.clone(),
+            ))
+            .unwrap_or("Python".to_string());
+
+        let num_tries = overrides
+            .num_tries
+            .or(Some(settings.ai_settings.ai_options.n))
+            .unwrap_or(1);
+
+        let ssh_key_path = overrides
+            .ssh_key_path
+            .or(Some(settings.git_settings.git_options.ssh_key_path.clone()))
+            .unwrap_or("~/.ssh/id_rsa".to_string());
+
+        let ssh_user = match settings.git_settings.git_options.ssh_user_name.as_str() {
+            "" => "git".to_string(),
+            name => name.to_string(),
+        };
+
+        let local_repo = overrides
+            .local_repo
+            .or(Some(settings.git_settings.git_options.local_path.clone()))
+            .unwrap_or(PathBuf::from("."));
+
+        // routing rules are a data policy, they win over the settings and the flags
+        let origin_git = Git::new(
+            local_repo.to_str().unwrap_or("."),
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+        );
+        let origin = origin_git
+            .open_repository()
+            .ok()
+            .and_then(|repo| origin_git.remote_location(&repo, &hosting_settings.remote));
+        let location =
+            origin.map(|origin| format!("{}/{}/{}", origin.host, origin.owner, origin.name));
+        let mut routed = false;
+        if let Some(location) = &location {
+            let rules = &settings.ai_settings.routing;
+            if let Some(rule) = rules.iter().find(|rule| rule.matches(location)) {
+                info!(
+                    "{} goes to {:?} (routing rule {})",
+                    location, rule.provider, rule.remote
+                );
+                match rule.provider {
+                    AiProvider::Azure if azure.deployment.is_empty() => {
+                        return Err(GitAiError::Refused(format!(
+                            "The routing rule {} says {} may only use Azure OpenAI, set up ai_settings.azure first",
+                            rule.remote, location
+                        )))
+                    }
+                    AiProvider::Azure => azure.enabled = true,
+                    AiProvider::Openai => azure.enabled = false,
+                }
+                routed = true;
+                if !rule.api_url.is_empty() {
+                    ai_url = rule.api_url.to_string();
+                }
+                if !rule.api_key.is_empty() {
+                    ai_token = rule.api_key.to_string();
+                }
+            }
+        }
+        // a rule for this repo beats the default
+        let closing_keywords = location
+            .as_deref()
+            .and_then(|location| {
+                settings
+                    .git_settings
+                    .git_options
+                    .closing_keyword_rules
+                    .iter()
+                    .find(|rule| rule.matches(location))
+            })
+            .map_or(settings.git_settings.git_options.closing_keywords, |rule| {
+                rule.keywords
+            });
+        // the second service for --race, filled in from the first where it is left empty
+        let race_with = match (overrides.race.unwrap_or(false), &settings.ai_settings.race) {
+            (false, _) => None,
+            (true, _) if routed => {
+                return Err(GitAiError::Refused(
+                    "A routing rule decides where this repo's code may go, it can't be raced against another service".to_string(),
+                ))
+            }
+            (true, None) => {
+                return Err(GitAiError::Usage(
+                    "Set up ai_settings.race to use --race".to_string(),
+                ))
+            }
+            (true, Some(other)) => {
+                let mut other_azure = settings.ai_settings.azure.clone();
+                other_azure.enabled = other.provider == AiProvider::Azure;
+                if other_azure.enabled && other_azure.deployment.is_empty() {
+                    return Err(GitAiError::Usage(
+                        "ai_settings.race uses Azure, set up ai_settings.azure first".to_string(),
+                    ));
+                }
+                let url = match other.api_url.as_str() {
+                    "" => ai_url.to_string(),
+                    url => url.to_string(),
+                };
+                let token = match other.api_key.as_str() {
+                    "" => ai_token.to_string(),
+                    token => token.to_string(),
+                };
+                Some((url, token, other_azure))
+            }
+        };
+        // what the ledger says wrote the message
+        let model_name = if azure.enabled {
+            azure.deployment.to_string()
+        } else if ai_model.is_empty() {
+            OpenAiRequestParams::default().model
+        } else {
+            ai_model.clone()
+        };
+        debug!("AI Variables Set url={}", ai_url);
+
+        // empty leaves it to user.signingkey in git config
+        let gpg_key_id = overrides
+            .gpg_key_id
+            .or(Some(settings.git_settings.git_options.key_id.clone()))
+            .unwrap_or_default();
+
+        // Flags
+        let auto_ai = overrides
+            .auto_ai
+            .or(Some(settings.ai_settings.ai_options.auto_ai))
+            .unwrap_or(false);
+
+        let auto_add = overrides
+            .auto_add
+            .or(Some(settings.git_settings.git_options.auto_add))
+            .unwrap_or(false);
+
+        let auto_push = overrides
+            .auto_push
+            .unwrap_or(settings.git_settings.git_options.auto_push);
+
+        // a person writes the messages, the AI is never asked
+        let no_ai = overrides.no_ai.unwrap_or(false) || settings.ai_settings.ai_options.manual;
+
+        let warm_up = overrides.warm_up.unwrap_or(false) || settings.ai_settings.ai_options.warm_up;
+
+        let deterministic = overrides.deterministic.unwrap_or(false);
+        // a prompt picked at random is the opposite of deterministic, whatever the settings say
+        let stochastic = !deterministic
+            && overrides
+                .stochastic
+                .or(Some(settings.ai_settings.ai_options.stochastic))
+                .unwrap_or(false);
+
+        let split_threshold = settings.git_settings.git_options.split_threshold;
+        let rate_limit_ms = settings.ai_settings.ai_options.rate_limit_ms;
+        let quality_retries = settings.ai_settings.ai_options.quality_retries;
+        // what's left of the prompt budget once the rest of the prompt is in
+        let diff_budget = ai::prompt_budget(settings.ai_settings.ai_options.context_window)
+            .saturating_sub(PROMPT_ALLOWANCE);
+        // the team's prompt pack, if there is one, beats the built in prompts
+        let pack = match settings.ai_settings.ai_options.prompt_pack.as_str() {
+            "" => PromptPack::default(),
+            name => packs::load(name)?,
+        };
+        let pack_name = settings.ai_settings.ai_options.prompt_pack.to_string();
+        // a prompt template of your own beats the pack
+        let prompt_name = overrides
+            .prompt_name
+            .unwrap_or_else(|| settings.ai_settings.ai_options.prompt_name.to_string());
+        let pack = if prompt_name.is_empty() {
+            pack
+        } else {
+            let repo = git2::Repository::discover(&local_repo).ok();
+            let template = templates::load(
+                &templates::prompt_dirs(repo.as_ref().and_then(|repo| repo.workdir())),
+                &prompt_name,
+            )?;
+            let vars = BTreeMap::from([
+                ("language", language.trim().to_string()),
+                (
+                    "branch",
+                    repo.as_ref()
+                        .and_then(|repo| repo.head().ok())
+                        .and_then(|head| head.shorthand().map(str::to_string))
+                        .unwrap_or_default(),
+                ),
+            ]);
+            PromptPack {
+                commit_prompt: match &template.commit {
+                    Some(text) => Some(templates::render(text, &vars)?),
+                    None => pack.commit_prompt,
+                },
+                pr_prompt: match &template.pr {
+                    Some(text) => Some(templates::render(text, &vars)?),
+                    None => pack.pr_prompt,
+                },
+                ..pack
+            }
+        };
+        let affected_packages = settings.git_settings.git_options.affected_packages;
+
+        // --yes or the policy allow in settings, either one is enough
+        let yes =
+            overrides.yes.unwrap_or(false) || settings.git_settings.git_options.allow_auto_ai_push;
+
+        let dry_run = overrides.dry_run.unwrap_or(false);
+        let price_per_1k_tokens = settings.ai_settings.ai_options.price_per_1k_tokens;
+        ai::set_cost_settings(ai::CostSettings {
+            price_per_1k_tokens,
+            confirm_above_cost: settings.ai_settings.ai_options.confirm_above_cost,
+        });
+        cache::set_enabled(
+            settings.ai_settings.ai_options.cache_responses && !overrides.no_cache.unwrap_or(false),
+        );
+
+        let redact = !overrides.no_redact.unwrap_or(false);
+
+        let compress =
+            overrides.compress.unwrap_or(false) || settings.ai_settings.ai_options.compress_prompt;
+
+        let doc_check =
+            overrides.doc_check.unwrap_or(false) || settings.ai_settings.ai_options.doc_drift_check;
+
+        let conventional = overrides.conventional.unwrap_or(false)
+            || settings.ai_settings.ai_options.conventional_commits
+            || pack.conventional_commits.unwrap_or(false);
+        let gitmoji = overrides.gitmoji.unwrap_or(false) || settings.ai_settings.ai_options.gitmoji;
+        if conventional && gitmoji {
+            return Err(GitAiError::Usage(
+                "A gitmoji in front would break the Conventional Commits header, turn off either gitmoji or conventional_commits".to_string(),
+            ));
+        }
+        let message_style = overrides
+            .message_style
+            .unwrap_or(settings.ai_settings.ai_options.message_style);
+
+        let reroll_diff =
+            overrides.reroll_diff.unwrap_or(false) || settings.ai_settings.ai_options.reroll_diff;
+
+        let intent_check =
+            overrides.intent_check.unwrap_or(false) || settings.ai_settings.ai_options.intent_check;
+        let mixed_intent_threshold = settings.ai_settings.ai_options.mixed_intent_threshold;
+        let generation_deadline_ms = settings.ai_settings.ai_options.generation_deadline_ms;
+        let consistency_commits = settings.ai_settings.ai_options.consistency_commits;
+
+        let diagram =
+            overrides.diagram.unwrap_or(false) || settings.git_settings.git_options.pr_diagram;
+        let pr_walkthrough = settings.git_settings.git_options.pr_walkthrough;
+        let use_pr_template = settings.git_settings.git_options.pr_template;
+
+        // off in both leaves it to commit.gpgsign in git config
+        let gpg_sign_commits = overrides.gpg_sign_commit.unwrap_or(false)
+            || settings.git_settings.git_options.sign_commits;
+
+        let dual_description = settings.git_settings.git_options.dual_description;
+        let diff_ignore = settings.git_settings.git_options.diff_ignore.clone();
+        let ai_ignore = git2::Repository::discover(&local_repo)
+            .ok()
+            .and_then(|repo| repo.workdir().map(AiIgnore::load))
+            .unwrap_or_default();
+
+        // how the library writes, the commands wrap the CLI around it
+        let options = GitAiOptions {
+            language: language.to_string(),
+            num_tries,
+            quality_retries,
+            fallback_model: settings.ai_settings.ai_options.fallback_model.to_string(),
+            conventional,
+            gitmoji,
+            message_style,
+            commit_prompt: pack.commit_prompt.clone().unwrap_or_default(),
+            pr_prompt: pack
+                .pr_prompt
+                .clone()
+                .unwrap_or_else(Settings::get_pr_prompt),
+            pr_sections: pack
+                .pr_sections
+                .clone()
+                .unwrap_or_else(|| settings.git_settings.git_options.pr_sections.clone()),
+            pr_template: None,
+            test_commands: Vec::new(),
+            commit_policy: settings.commit_policy.clone(),
+            ticket: None,
+            ticket_prefix: settings.tickets.prefix_message,
+            mirror_safe: dual_description != DualDescription::Off,
+            closing_keywords,
+            earlier_subjects: Vec::new(),
+            message_locale: overrides
+                .locale
+                .unwrap_or_else(|| settings.ai_settings.ai_options.message_locale.to_string()),
+        };
+
+        debug!("Variables Set OpenAI Url={:#?} should not be null", ai_url);
+        debug!(
+            "Local Repo={:#?} this should probably be '.' unless you have good reason",
+            local_repo
+        );
+        Ok(Context {
+            settings,
+            ai_token,
+            ai_url,
+            azure,
+            retry,
+            ai_model,
+            hosting_settings,
+            github_token_from_cli,
+            language,
+            num_tries,
+            ssh_key_path,
+            ssh_user,
+            local_repo,
+            closing_keywords,
+            race_with,
+            model_name,
+            gpg_key_id,
+            auto_ai,
+            auto_add,
+            auto_push,
+            no_ai,
+            warm_up,
+            deterministic,
+            stochastic,
+            split_threshold,
+            rate_limit_ms,
+            diff_budget,
+            pack,
+            pack_name,
+            affected_packages,
+            yes,
+            dry_run,
+            price_per_1k_tokens,
+            redact,
+            compress,
+            doc_check,
+            conventional,
+            gitmoji,
+            reroll_diff,
+            intent_check,
+            mixed_intent_threshold,
+            generation_deadline_ms,
+            consistency_commits,
+            diagram,
+            pr_walkthrough,
+            use_pr_template,
+            gpg_sign_commits,
+            dual_description,
+            diff_ignore,
+            ai_ignore,
+            options,
+        })
+    }
+
+    /// A client for the AI the settings and routing rules point at
+    pub fn client(&self) -> Result<OpenAiClient> {
+        Ok(ai::new_client(
+            self.ai_url.to_string(),
+            self.ai_token.to_string(),
+            self.azure.clone(),
+            self.retry,
+        )?
+        .with_model(&self.ai_model))
+    }
+
+    /// The repository, for reading diffs the AI gets to see: `diff_ignore`,
+    /// `.gitaiignore` and redaction applied, nothing is staged, signed or pushed
+    pub fn git(&self) -> Git<'_> {
+        Git::new(
+            self.local_repo.to_str().unwrap_or("."),
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+        )
+        .with_diff_ignore(&self.diff_ignore)
+        .with_ai_ignore(&self.ai_ignore)
+        .with_redaction(self.redact)
+    }
+
+    /// `--dry-run`, see `print_dry_run`
+    ///
+    /// # Arguments
+    ///
+    /// * `prompt` - The fully built prompt
+    /// * `num_tries` - How many completions would be asked for
+    pub fn print_dry_run(&self, prompt: AiPrompt, num_tries: u8) -> Result<()> {
+        print_dry_run(
+            prompt,
+            num_tries,
+            &self.model_name,
+            self.price_per_1k_tokens,
+        )
+    }
+
+    /// What `r` in the picker asks with, the client with the cache off so the answer
+    /// is a new one
+    ///
+    /// # Arguments
+    ///
+    /// * `prompt` - The prompt the options were written from
+    /// * `options` - How they were finished
+    /// * `suffix` - What was added to the end of each of them
+    pub fn reroll(&self, prompt: AiPrompt, options: &GitAiOptions, suffix: &str) -> Result<Reroll> {
+        let client = self
+            .client()?
+            .with_deterministic(self.deterministic)
+            .with_cache(false);
+        Ok(Reroll {
+            generator: racing(client, self.race_with.clone(), self.retry)?,
+            prompt,
+            options: options.clone(),
+            suffix: suffix.to_string(),
+        })
+    }
+}
+
+/// Asks for one more message in place of an option nobody liked
+pub struct Reroll {
+    /// The generator, never answering from the cache
+    generator: Box<dyn MessageGenerator>,
+    /// The prompt the options were written from
+    prompt: AiPrompt,
+    /// How a message is finished
+    options: GitAiOptions,
+    /// What goes at the end of every message
+    suffix: String,
+}
+
+impl Reroll {
+    /// A new message, `None` if the AI had nothing
+    async fn next(&self) -> Result<Option<String>> {
+        let texts = get_completion_texts(self.generator.as_ref(), self.prompt.clone(), 1).await?;
+        Ok(texts
+            .first()
+            .map(|text| self.options.finish_message(text) + &self.suffix))
+    }
+
+    /// Asks for a message to replace `previous` and shows what changed
+    ///
+    /// # Arguments
+    ///
+    /// * `previous` - The option it replaces
+    async fn replace(&self, previous: &str) -> Result<String> {
+        match self.next().await? {
+            Some(text) => {
+                println!(
+                    "The new one, against the one it replaces\n\n{}\n",
+                    worddiff::describe(previous, &text, preview::color())
+                );
+                Ok(text)
+            }
+            None => {
+                println!("The AI had nothing new, keeping this one\n");
+                Ok(previous.to_string())
+            }
+        }
+    }
+}
+
+/// Helper function to ask the user whether or not they really wanted to ____
+/// (as specified by the `prompt`). As long as the response starts with the
+/// letter `y` (case insensitive), the reply is treated as affirmative.
+pub fn prompt_yes_no<S>(prompt: S) -> Result<bool>
+where
+    S: AsRef<str>,
+{
+    let prompt = prompt.as_ref();
+    interactive::ask(prompt)?;
+
+    let stdin = io::stdin();
+    let mut stdin = stdin.lock();
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+
+    write!(stdout, "{} [y/N] ", prompt)?;
+    stdout.flush()?;
+
+    match terminal::read_line(&mut stdin)? {
+        Some(ref reply) if reply.to_ascii_lowercase().starts_with('y') => Ok(true),
+        _ => Ok(false),
+    }
+}
+
+fn error_message(message: &str) -> String {
+    error!("{}", message);
+    message.to_string()
+}
+
+/// What the ledger calls the prompt a message was written from, so `gitai stats` can
+/// tell them apart
+///
+/// # Arguments
+///
+/// * `pack` - The prompt pack in use, empty for the built in prompts
+/// * `stochastic` - Whether the prompt was picked at random
+/// * `conventional` - Whether a Conventional Commit was asked for
+fn template_name(pack: &str, stochastic: bool, conventional: bool) -> String {
+    let mut name = match pack {
+        "" => "default".to_string(),
+        pack => format!("pack:{}", pack),
+    };
+    if stochastic {
+        name.push_str("+stochastic");
+    }
+    if conventional {
+        name.push_str("+conventional");
+    }
+    name
+}
+
+/// Prints option `i`, as a word diff against the one before it with `reroll_diff` so
+/// it's easy to see whether the AI actually said anything different
+///
+/// # Arguments
+///
+/// * `i` - Which option this is, from 0
+/// * `text` - The option
+/// * `previous` - The option before it, if there was one
+/// * `reroll_diff` - Show the word diff
+fn print_option(i: usize, text: &str, previous: Option<&str>, reroll_diff: bool) {
+    match previous {
+        Some(previous) if reroll_diff => println!(
+            "Option #{}\n\n{}\n",
+            i + 1,
+            worddiff::describe(previous, text, preview::color())
+        ),
+        _ => println!(
+            "Option #{}\n\n{}\n",
+            i + 1,
+            preview::message(text, preview::color())
+        ),
+    }
+}
+
+/// Like `prompt_yes_no`, but the answer can also be `e` to edit (or `r` for a new one
+/// when `reroll`), `None` is a no
+fn prompt_yes_no_edit<S>(prompt: S, reroll: bool) -> Result<Option<char>>
+where
+    S: AsRef<str>,
+{
+    interactive::ask(prompt.as_ref())?;
+    let stdin = io::stdin();
+    let mut stdin = stdin.lock();
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+
+    let choices = if reroll { "y/N/e/r" } else { "y/N/e" };
+    write!(stdout, "{} [{}] ", prompt.as_ref(), choices)?;
+    stdout.flush()?;
+
+    Ok(terminal::read_line(&mut stdin)?
+        .and_then(|reply| reply.trim().to_ascii_lowercase().chars().next())
+        .filter(|reply| ['y', 'e'].contains(reply) || (reroll && *reply == 'r')))
+}
+
+/// Walks the user through the AI completions and returns the first one they accept,
+/// or `None` if they turned them all down.  `e` opens the option in the editor first and
+/// the saved text is what gets committed, like `git commit` would, and `r` asks the AI
+/// for a new one in its place, shown as a word diff against it.  Several options are
+/// shown side by side to pick from, unless `reroll_diff` wants them one after the other.
+///
+/// # Arguments
+///
+/// * `completions` - The options
+/// * `reroll_diff` - Show each option as a word diff against the one before it
+/// * `git` - The git, for the editor
+/// * `repo` - The repository
+/// * `edit` - `--edit`, a yes opens the editor too
+/// * `policy` - The commit policy, each option is checked against it
+/// * `reroll` - How to ask for a new option, `None` to only pick from these
+async fn choose_completion(
+    completions: &[String],
+    reroll_diff: bool,
+    git: &Git<'_>,
+    repo: &git2::Repository,
+    edit: bool,
+    policy: &CommitPolicy,
+    reroll: Option<&Reroll>,
+) -> Result<Option<String>> {
+    if completions.len() > 1 && !reroll_diff {
+        return pick_completion(completions, git, repo, edit, policy, reroll).await;
+    }
+    let question = match reroll {
+        Some(_) => "Use this commit message? (e to edit it first, r for a new one)",
+        None => "Use this commit message? (e to edit it first)",
+    };
+    for (i, comp) in completions.iter().enumerate() {
+        let previous = i.checked_sub(1).map(|p| completions[p].as_str());
+        print_option(i, comp, previous, reroll_diff);
+        let mut comp = comp.to_string();
+        loop {
+            match prompt_yes_no_edit(question, reroll.is_some())? {
+                Some('y') if !edit => return Ok(Some(comp)),
+                Some('r') => {
+                    if let Some(reroll) = reroll {
+                        comp = reroll.replace(&comp).await?;
+                        continue;
+                    }
+                }
+                Some(_) => match git.edit_message(repo, &comp)? {
+                    Some(edited) => return Ok(Some(edited)),
+                    None => println!("The message was emptied, on to the next one\n"),
+                },
+                None => (),
+            }
+            break;
+        }
+    }
+    Ok(None)
+}
+
+/// Shows the options side by side with `compare::render` and asks for one by number,
+/// `e` and the number edits it first, `r` and the number asks for a new one in its
+/// place.  `None` if none of them will do.
+///
+/// # Arguments
+///
+/// * `completions` - The options
+/// * `git` - The git, for the editor
+/// * `repo` - The repository
+/// * `edit` - `--edit`, picking one opens the editor too
+/// * `policy` - The commit policy, each option is checked against it
+/// * `reroll` - How to ask for a new option, `None` to only pick from these
+async fn pick_completion(
+    completions: &[String],
+    git: &Git<'_>,
+    repo: &git2::Repository,
+    edit: bool,
+    policy: &CommitPolicy,
+    reroll: Option<&Reroll>,
+) -> Result<Option<String>> {
+    let tty = preview::color();
+    let width = terminal::size()
+        .filter(|_| tty)
+        .map_or(80, |(columns, _)| columns as usize);
+    let mut completions = completions.to_vec();
+    interactive::ask("Use which commit message?")?;
+    print!("{}", compare::render(&completions, policy, width, tty)?);
+    loop {
+        match reroll {
+            Some(_) => print!(
+                "Use which one? [1-{0}, e1-e{0} to edit it first, r1-r{0} for a new one, anything else for none] ",
+                completions.len()
+            ),
+            None => print!(
+                "Use which one? [1-{0}, e1-e{0} to edit it first, anything else for none] ",
+                completions.len()
+            ),
+        }
+        io::stdout().flush()?;
+        let reply = match terminal::read_line(&mut io::stdin().lock())? {
+            Some(reply) => reply.trim().to_ascii_lowercase(),
+            None => return Ok(None),
+        };
+        let (edit_it, rerolled, number) = match (reply.strip_prefix('e'), reply.strip_prefix('r')) {
+            (Some(number), _) => (true, false, number),
+            (_, Some(number)) if reroll.is_some() => (false, true, number),
+            _ => (edit, false, reply.as_str()),
+        };
+        let i = match number
+            .trim()
+            .parse::<usize>()
+            .ok()
+            .and_then(|n| n.checked_sub(1))
+            .filter(|i| *i < completions.len())
+        {
+            Some(i) => i,
+            None => return Ok(None),
+        };
+        if let (true, Some(reroll)) = (rerolled, reroll) {
+            completions[i] = reroll.replace(&completions[i]).await?;
+            continue;
+        }
+        let comp = &completions[i];
+        if !edit_it {
+            return Ok(Some(comp.to_string()));
+        }
+        match git.edit_message(repo, comp)? {
+            Some(edited) => return Ok(Some(edited)),
+            None => println!("The message was emptied, pick another one"),
+        }
+    }
+}
+
+/// Says what was masked in the diff before it goes to the AI, nothing when nothing was
+///
+/// # Arguments
+///
+/// * `git` - The git the diff is read with
+/// * `diff` - The diff
+fn report_redactions(git: &Git<'_>, diff: &git2::Diff<'_>) -> Result<()> {
+    let masked = redactions(git, diff)?;
+    if !masked.is_empty() {
+        eprintln!("gitai masked what looks like secrets before sending the diff (--no-redact sends them as is):");
+        for m in &masked {
+            eprintln!("  {}", m);
+        }
+    }
+    Ok(())
+}
+
+/// `--dry-run`, shows exactly what would be sent and about what it would cost without sending it
+///
+/// # Arguments
+///
+/// * `prompt` - The fully built prompt
+/// * `num_tries` - How many completions would be asked for
+/// * `model` - The model it would go to
+/// * `price_per_1k_tokens` - The price from the settings, 0 to use the model's list price
+fn print_dry_run(
+    prompt: AiPrompt,
+    num_tries: u8,
+    model: &str,
+    price_per_1k_tokens: f64,
+) -> Result<()> {
+    let prompt = hooks::run(HookPoint::PrePrompt, prompt)?;
+    let text = format!("{}", prompt);
+    let estimate = CostEstimate::new(
+        &text,
+        num_tries,
+        ai::price_per_1k_tokens(price_per_1k_tokens, model),
+    );
+    println!("{}\n", text);
+    println!(
+        "About {} prompt tokens and up to {} completion tokens for {} completion(s) from {}",
+        estimate.prompt_tokens, estimate.completion_tokens, num_tries, model
+    );
+    match estimate.dollars {
+        Some(dollars) => println!("That is at most ${:.4}", dollars),
+        None => println!(
+            "There is no price for {}, set ai_options.price_per_1k_tokens to get one",
+            model
+        ),
+    }
+    println!("Dry run, nothing was sent");
+    Ok(())
+}
+
+/// What the run asked the AI for, on stderr so it stays out of anything piped from stdout
+pub fn print_session_cost() {
+    let usage = ai::session_usage();
+    if usage.requests == 0 {
+        return;
+    }
+    let cost = match (usage.unpriced, usage.dollars) {
+        (0, dollars) => format!("about ${:.4}", dollars),
+        (unpriced, _) if unpriced == usage.requests => {
+            "no price is known for the model, set ai_options.price_per_1k_tokens to get one"
+                .to_string()
+        }
+        (unpriced, dollars) => format!(
+            "about ${:.4} plus {} request(s) with no known price",
+            dollars, unpriced
+        ),
+    };
+    eprintln!(
+        "This run used {} tokens ({} prompt, {} completion) in {} request(s), {}",
+        usage.prompt_tokens + usage.completion_tokens,
+        usage.prompt_tokens,
+        usage.completion_tokens,
+        usage.requests,
+        cost
+    );
+}
+
+/// The ticket the checked out branch is for, if tickets are set up
+///
+/// # Arguments
+///
+/// * `git` - The git settings
+/// * `repo` - The repository
+/// * `settings` - The ticket settings
+async fn branch_ticket(
+    git: &Git<'_>,
+    repo: &git2::Repository,
+    settings: &TicketSettings,
+) -> Result<Option<tickets::Ticket>> {
+    match git.current_branch(repo) {
+        Some(branch) => tickets::for_branch(settings, &branch).await,
+        None => Ok(None),
+    }
+}
+
+/// `--deterministic` keeps the hash of the exact prompt with what came back, it is
+/// printed as well so a CI log shows whether a different message came from a different prompt
+///
+/// # Arguments
+///
+/// * `prompt` - The prompt, as it is sent
+fn announce_prompt_hash(prompt: &AiPrompt) -> String {
+    let hash = ledger::short_hash(&prompt.to_string());
+    eprintln!("gitai: deterministic run, prompt {}", hash);
+    hash
+}
+
+/// The generator for commits and pull requests, raced against the `--race` service
+/// when there is one
+///
+/// # Arguments
+///
+/// * `client` - The usual client
+/// * `race_with` - The url, token and azure settings of the one to race it against
+/// * `retry` - How failed requests are tried again
+fn racing(
+    client: OpenAiClient,
+    race_with: Option<(String, String, AzureSettings)>,
+    retry: RetrySettings,
+) -> Result<Box<dyn MessageGenerator>> {
+    match race_with {
+        None => Ok(Box::new(client)),
+        Some((url, token, azure)) => {
+            let name = format!("ai_settings.race ({})", url);
+            let other = ai::new_client(url, token, azure, retry)?
+                .with_model(client.model())
+                .with_cache(client.cached());
+            Ok(Box::new(Race::new(
+                ("ai_settings".to_string(), Box::new(client)),
+                (name, Box::new(other)),
+            )))
+        }
+    }
+}
+
+/// Starts `OpenAiClient::warm_up` in the background.  If it fails that is printed right
+/// away, whatever question is on the screen, and `warmed_up` stops before the real request.
+///
+/// # Arguments
+///
+/// * `client` - A client for the same service, it is never tried again
+fn start_warm_up(client: OpenAiClient) -> tokio::task::JoinHandle<Result<()>> {
+    tokio::spawn(async move {
+        let result = client.warm_up().await;
+        if let Err(e) = &result {
+            eprintln!(
+                "{}",
+                error_message(&format!("The AI didn't answer the warm-up request: {}", e))
+            );
+        }
+        result
+    })
+}
+
+/// How the warm-up (or token check) went, waiting for it if it is still going.  Without
+/// one it is fine.
+///
+/// # Arguments
+///
+/// * `warm_up` - The warm-up from `start_warm_up` or `start_auth_check`, if there is one
+async fn warmed_up(warm_up: Option<tokio::task::JoinHandle<Result<()>>>) -> Result<()> {
+    match warm_up {
+        Some(handle) => handle
+            .await
+            .map_err(|e| GitAiError::Usage(format!("The warm-up request went wrong: {}", e)))?,
+        None => Ok(()),
+    }
+}
+
+/// The repo's pull request template when there is one and it should be filled in
+///
+/// # Arguments
+///
+/// * `wanted` - `pr_template` from the settings
+/// * `repo` - The repository
+fn pr_template(wanted: bool, repo: &git2::Repository) -> Option<String> {
+    if !wanted {
+        return None;
+    }
+    repo.workdir().and_then(sections::find_template)
+}
+
+/// Where HEAD is, `None` in a repository without commits
+fn head_oid(repo: &git2::Repository) -> Option<git2::Oid> {
+    repo.head().ok().and_then(|head| head.target())
+}
+
+/// Puts a commit gitai just made in the journal, so `gitai undo` can take it back
+///
+/// # Arguments
+///
+/// * `git` - The git, for the branch
+/// * `repo` - The repository
+/// * `before` - Where HEAD was before the commit
+/// * `commit` - The commit
+/// * `details` - How its message came to be
+fn record_commit(
+    git: &Git<'_>,
+    repo: &git2::Repository,
+    before: Option<git2::Oid>,
+    commit: git2::Oid,
+    details: Details,
+) {
+    journal::record(
+        repo,
+        ActionKind::Commit {
+            before: before.map(|before| before.to_string()),
+            commit: commit.to_string(),
+            branch: git.current_branch(repo),
+        },
+        details,
+    );
+}
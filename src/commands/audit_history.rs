@@ -0,0 +1,55 @@
+use std::time::Duration;
+
+use log::info;
+
+use crate::audit;
+use crate::error::Result;
+use crate::generate::{change_size, get_completion_texts, prompt_diff_text};
+
+use super::Context;
+
+/// `gitai audit-history`, scores the commit messages in `range` against their diffs
+///
+/// # Arguments
+///
+/// * `ctx` - The settings and flags
+/// * `range` - The commits, `A..B` or a single revision for its whole history
+/// * `ai` - Have the AI score each message too, one request per commit
+/// * `worst` - How many of the worst commits to list
+pub async fn run(ctx: &Context, range: &str, ai: bool, worst: usize) -> Result<()> {
+    info!("Auditing the commit messages in {}", range);
+    let git = ctx.git();
+    let repo = git.open_repository()?;
+    let client = if ai { Some(ctx.client()?) } else { None };
+    let commits = git.commits_in_range(&repo, range)?;
+    let mut scores = Vec::new();
+    for (i, oid) in commits.iter().enumerate() {
+        let commit = repo.find_commit(*oid)?;
+        let message = commit.message().unwrap_or("");
+        let diff = git.get_commit_changes(&repo, &commit)?;
+        let size = change_size(&diff)?;
+        let id = oid.to_string()[..7].to_string();
+        let mut score = audit::heuristic_score(&id, message, size);
+        if let Some(client) = &client {
+            if i > 0 {
+                tokio::time::sleep(Duration::from_millis(ctx.rate_limit_ms)).await;
+            }
+            let diff_text = prompt_diff_text(&git, &diff, ctx.compress, ctx.diff_budget)?;
+            let prompt = audit::score_prompt(&ctx.language, message, &diff_text);
+            let answer = get_completion_texts(client, prompt, 1).await?;
+            if let Some((ai_score, reason)) = answer
+                .first()
+                .and_then(|answer| audit::parse_score_answer(answer))
+            {
+                // the AI read the diff, the heuristics only looked at the shape
+                score.score = (ai_score + score.score).div_ceil(2);
+                if !reason.is_empty() {
+                    score.reasons.push(format!("AI: {}", reason));
+                }
+            }
+        }
+        scores.push(score);
+    }
+    println!("{}", audit::report(range, &scores, worst));
+    Ok(())
+}
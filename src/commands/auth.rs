@@ -0,0 +1,62 @@
+use crate::credentials;
+use crate::error::{GitAiError, Result};
+use crate::init;
+use crate::settings::{self, Secret, Settings};
+
+/// `gitai auth set`, moves a token into the keyring.  Only settings.json is read and
+/// written back, neither the repository's settings nor the environment end up in it.
+///
+/// # Arguments
+///
+/// * `secret` - Which token
+pub fn set(secret: Secret) -> Result<()> {
+    let mut settings = Settings::from_file()?;
+    let token = init::ask_secret(&format!("{} token", secret.account()), "")?;
+    if token.is_empty() {
+        return Err(GitAiError::Usage(
+            "No token given, nothing was stored".to_string(),
+        ));
+    }
+    let path = settings.store_secret(secret, &token)?;
+    println!(
+        "Stored the {} token in the keyring, it is no longer in {}",
+        secret.account(),
+        path.display()
+    );
+    Ok(())
+}
+
+/// `gitai auth delete`, takes a token back out of the keyring
+///
+/// # Arguments
+///
+/// * `secret` - Which token
+pub fn delete(secret: Secret) -> Result<()> {
+    settings::keyring_delete(secret)?;
+    println!(
+        "Removed the {} token from the keyring, the one in settings.json is used again (if there is one)",
+        secret.account()
+    );
+    Ok(())
+}
+
+/// `gitai auth status`, checks every token
+///
+/// # Arguments
+///
+/// * `settings` - The settings, the tokens are looked up in the keyring here
+pub async fn status(settings: Settings) -> Result<()> {
+    let statuses = credentials::status(&settings.with_tokens()).await;
+    for status in &statuses {
+        println!("{}", status);
+    }
+    let broken = statuses.iter().filter(|status| !status.ok()).count();
+    if broken > 0 {
+        return Err(GitAiError::Permission(format!(
+            "{} of {} aren't set or don't work",
+            broken,
+            statuses.len()
+        )));
+    }
+    Ok(())
+}
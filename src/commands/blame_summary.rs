@@ -0,0 +1,69 @@
+use std::path::{Path, PathBuf};
+
+use crate::error::{GitAiError, Result};
+use crate::generate::{get_completion_texts, prompt_diff_text};
+use crate::history::{self, FileCommit};
+
+use super::Context;
+
+/// `gitai blame-summary`, the story of a file from its latest commits
+///
+/// # Arguments
+///
+/// * `ctx` - The settings and flags
+/// * `file` - The file
+/// * `max_commits` - Only look at this many of its latest commits
+pub async fn run(ctx: &Context, file: &Path, max_commits: usize) -> Result<()> {
+    let git = ctx.git();
+    let repo = git.open_repository()?;
+    // git wants the path from the root of the repo, not from where we are
+    let path = match (file.canonicalize(), repo.workdir()) {
+        (Ok(full), Some(workdir)) => workdir
+            .canonicalize()
+            .ok()
+            .and_then(|workdir| full.strip_prefix(workdir).ok().map(PathBuf::from))
+            .unwrap_or_else(|| file.to_path_buf()),
+        _ => file.to_path_buf(),
+    };
+    let mut oids = git.commits_touching(&repo, &path)?;
+    if oids.is_empty() {
+        return Err(GitAiError::Usage(format!(
+            "No commits touch {}, is it in this repo?",
+            path.display()
+        )));
+    }
+    oids.truncate(max_commits);
+    // the diffs share the budget
+    let per_commit_budget = ctx.diff_budget / oids.len();
+    let mut commits = Vec::new();
+    for oid in oids {
+        let commit = repo.find_commit(oid)?;
+        let diff = git.get_path_changes(&repo, &commit, &path)?;
+        let (added, removed) = git
+            .diff_to_file_strings(&diff)?
+            .iter()
+            .fold((0, 0), |(a, r), f| (a + f.added, r + f.removed));
+        commits.push(FileCommit {
+            id: oid.to_string()[..7].to_string(),
+            author: commit.author().name().unwrap_or("unknown").to_string(),
+            date: chrono::NaiveDateTime::from_timestamp_opt(commit.time().seconds(), 0)
+                .map(|date| date.format("%Y-%m-%d").to_string())
+                .unwrap_or_default(),
+            subject: commit.summary().unwrap_or("").to_string(),
+            added,
+            removed,
+            diff: prompt_diff_text(&git, &diff, ctx.compress, per_commit_budget)?,
+        });
+    }
+    let prompt = history::prompt(&ctx.language, &path, &commits);
+    if ctx.dry_run {
+        return ctx.print_dry_run(prompt, 1);
+    }
+    let client = ctx.client()?;
+    let story = get_completion_texts(&client, prompt, 1).await?;
+    println!(
+        "{}",
+        history::report(&path, &commits, story.first().map_or("", String::as_str))
+    );
+    Ok(())
+}
@@ -0,0 +1,126 @@
+use log::{info, warn};
+
+use crate::changelog::{self, ChangelogCommit};
+use crate::error::Result;
+use crate::generate::{get_completion_texts, prompt_diff_text};
+use crate::sinks;
+
+use super::Context;
+
+/// What `gitai changelog` is asked for
+#[derive(Debug, Clone, Default)]
+pub struct ChangelogArgs {
+    /// Where the release starts, usually the last tag
+    pub from: String,
+    /// Where it ends
+    pub to: String,
+    /// Let the AI see what each commit changed too, not just its message
+    pub diffs: bool,
+    /// What the release is called, `to` (or Unreleased for HEAD) if it isn't given
+    pub release: Option<String>,
+    /// Add the section to the top of CHANGELOG.md instead of printing it
+    pub write: bool,
+    /// Send the section to these sinks instead of printing it
+    pub sink: Vec<String>,
+}
+
+/// `gitai changelog`, the changelog section for the commits between two refs
+///
+/// # Arguments
+///
+/// * `ctx` - The settings and flags
+/// * `args` - The range and where the section goes
+pub async fn run(ctx: &Context, args: &ChangelogArgs) -> Result<()> {
+    let ChangelogArgs {
+        from,
+        to,
+        diffs,
+        release,
+        write,
+        sink,
+    } = args;
+    // a typo should fail before the AI is paid for
+    let sinks = sink
+        .iter()
+        .map(|name| sinks::lookup(&ctx.settings.sinks, name))
+        .collect::<Result<Vec<_>>>()?;
+    let range = format!("{}..{}", from, to);
+    info!("Writing the changelog for {}", range);
+    let git = ctx.git();
+    let repo = git.open_repository()?;
+    let oids = git.commits_in_range(&repo, &range)?;
+    // the diffs share the budget, merges only repeat what they merge
+    let per_commit_budget = ctx.diff_budget / oids.len().max(1);
+    let mut commits = Vec::new();
+    for oid in oids {
+        let commit = repo.find_commit(oid)?;
+        if commit.parent_count() > 1 {
+            continue;
+        }
+        let diff = if *diffs {
+            let diff = git.get_commit_changes(&repo, &commit)?;
+            Some(prompt_diff_text(
+                &git,
+                &diff,
+                ctx.compress,
+                per_commit_budget,
+            )?)
+        } else {
+            None
+        };
+        commits.push(ChangelogCommit {
+            id: oid.to_string()[..7].to_string(),
+            message: commit.message().unwrap_or("").to_string(),
+            diff,
+        });
+    }
+    if commits.is_empty() {
+        println!("No commits in {}, nothing to write", range);
+        return Ok(());
+    }
+    let prompt = changelog::prompt(&ctx.language, &commits);
+    if ctx.dry_run {
+        return ctx.print_dry_run(prompt, 1);
+    }
+    let client = ctx.client()?;
+    let answer = get_completion_texts(&client, prompt, 1).await?;
+    let groups = answer
+        .first()
+        .and_then(|answer| changelog::parse_answer(answer))
+        .unwrap_or_else(|| {
+            warn!("The AI didn't group the commits, sorting them by their messages instead");
+            changelog::heuristic_groups(&commits)
+        });
+    let release = release.clone().unwrap_or_else(|| {
+        if to == "HEAD" {
+            "Unreleased".to_string()
+        } else {
+            to.to_string()
+        }
+    });
+    let section = changelog::section(&release, &groups);
+    if *write {
+        let path = repo
+            .workdir()
+            .unwrap_or(ctx.local_repo.as_path())
+            .join("CHANGELOG.md");
+        changelog::prepend(&path, &section)?;
+        println!("Added {} to {}", release, path.display());
+    } else if !sinks.is_empty() {
+        let artifact = sinks::Artifact {
+            kind: "changelog",
+            title: &release,
+            text: &section,
+        };
+        for sink in &sinks {
+            if let Some(place) =
+                sinks::deliver(sink, &artifact, &repo, &ctx.hosting_settings).await?
+            {
+                println!("Sent {} to {}", release, place);
+            }
+        }
+    } else {
+        println!("{}", section);
+    }
+    Ok(())
+}
@@ -0,0 +1,842 @@
+use std::future::Future;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use futures_util::stream::{FuturesUnordered, StreamExt};
+use log::{debug, info, warn};
+use rand::seq::SliceRandom;
+
+use crate::ai;
+use crate::error::{GitAiError, Result};
+use crate::generate::{
+    check_doc_drift, commit_prompt, get_completion_texts, intent_breakdown, workspace_impact,
+    write_request_body,
+};
+use crate::git::{Git, UnstagedHunk};
+use crate::githook;
+use crate::gitmoji;
+use crate::hooks::{self, CommitMessage, HookPoint};
+use crate::interactive;
+use crate::journal::Details;
+use crate::ledger;
+use crate::policy;
+use crate::preview;
+use crate::progress::{Phase, Progress};
+use crate::settings::{AiPrompt, DualDescription, RetrySettings, Settings, TicketSettings};
+use crate::terminal;
+use crate::tickets::{self, Ticket};
+use crate::trailer;
+use crate::{DiffSource, GitAi, GitAiOptions, RepoDiff};
+
+use super::{
+    announce_prompt_hash, choose_completion, head_oid, prompt_yes_no, racing, record_commit,
+    report_redactions, start_warm_up, template_name, warmed_up, Context,
+};
+
+/// What `gitai commit` is asked for
+#[derive(Debug, Clone, Default)]
+pub struct CommitArgs {
+    /// Write the exact request that would be sent to this file and stop
+    pub export_prompt: Option<PathBuf>,
+    /// Write a new message for the last commit and rewrite it
+    pub amend: bool,
+    /// Context only the team should see, added as an encrypted trailer
+    pub private: Option<String>,
+    /// Write the message for everything that changed, staged or not
+    pub include_unstaged: bool,
+    /// Print the first message as a commit buffer for vim-fugitive and stop
+    pub fugitive: bool,
+    /// Pick the hunks to stage first, like `git add -p`
+    pub patch: bool,
+    /// Open the message in the editor before committing
+    pub edit: bool,
+}
+
+/// `gitai commit`, writes the message for what is staged (or amended, or everything that
+/// changed), lets the user pick or edit one and commits with it
+///
+/// # Arguments
+///
+/// * `ctx` - The settings and flags
+/// * `args` - What the commit is asked for
+pub async fn run(ctx: &Context, args: &CommitArgs) -> Result<()> {
+    let CommitArgs {
+        export_prompt,
+        amend,
+        private,
+        include_unstaged,
+        fugitive,
+        patch,
+        edit,
+    } = args;
+    // nobody is there to answer questions inside an editor
+    let auto_ai = ctx.auto_ai || *fugitive;
+    let private_trailer = &ctx.settings.git_settings.git_options.private_trailer;
+    if private.is_some() && private_trailer.recipients.is_empty() {
+        return Err(GitAiError::Usage(
+            "Set git_options.private_trailer.recipients to say who can read --private".to_string(),
+        ));
+    }
+    let git = Git::new(
+        ctx.local_repo.to_str().unwrap_or("."),
+        Some(&ctx.auto_add),
+        Some(&ctx.auto_push),
+        Some(&ctx.gpg_sign_commits).filter(|sign| **sign),
+        Some(ctx.gpg_key_id.as_str()),
+        None,
+        None,
+        Some(&ctx.ssh_key_path),
+        Some(&ctx.ssh_user),
+    )
+    .with_diff_ignore(&ctx.diff_ignore)
+    .with_ai_ignore(&ctx.ai_ignore)
+    .with_redaction(ctx.redact);
+    debug!("Getting Repository at {:#?}", &ctx.local_repo);
+    let repo = git.open_repository()?;
+    // the network is waited on while the diff is built, not one after the other,
+    // a bad token is found out about while the questions are being answered
+    let ticket = start_ticket_lookup(&git, &repo, &ctx.settings.tickets);
+    let warm_up = if !ctx.no_ai && !ctx.dry_run && export_prompt.is_none() {
+        let client = ai::new_client(
+            ctx.ai_url.to_string(),
+            ctx.ai_token.to_string(),
+            ctx.azure.clone(),
+            RetrySettings {
+                max_retries: 0,
+                ..ctx.retry
+            },
+        )?
+        .with_model(&ctx.ai_model);
+        Some(if ctx.warm_up && !auto_ai {
+            start_warm_up(client)
+        } else {
+            start_auth_check(client)
+        })
+    } else {
+        None
+    };
+
+    if *patch {
+        if auto_ai {
+            return Err(GitAiError::Usage(
+                "--patch asks about every hunk, it doesn't go with --auto-ai".to_string(),
+            ));
+        }
+        let (unstaged, hunks) = git.unstaged_hunks(&repo)?;
+        if hunks.is_empty() {
+            println!("There are no unstaged changes to pick from, going on with what is staged");
+        } else {
+            let picked = pick_hunks(&hunks)?;
+            git.stage_hunks(&repo, &unstaged, &picked)?;
+            println!("Staged {} of {} hunk(s)\n", picked.len(), hunks.len());
+        }
+    }
+
+    let mut include_unstaged = *include_unstaged;
+    let nothing_staged = !*amend
+        && !include_unstaged
+        && match git.get_commit_diff(&repo) {
+            Ok(_) => false,
+            Err(GitAiError::NothingStaged(_)) => true,
+            Err(e) => return Err(e),
+        };
+    if nothing_staged {
+        // staging first is easy to forget, offer to write it for the changes anyway
+        let unstaged = git.get_workdir_diff(&repo)?.deltas().len();
+        if unstaged > 0 {
+            if auto_ai
+                || interactive::non_interactive()
+                || !prompt_yes_no(format!(
+                    "Nothing is staged, but {} file(s) have changes.  Write the message for those?",
+                    unstaged
+                ))?
+            {
+                return Err(GitAiError::NothingStaged(format!(
+                    "Nothing is staged, but {} file(s) have changes.  Stage what goes in the commit, or pass --include-unstaged",
+                    unstaged
+                )));
+            }
+            include_unstaged = true;
+        }
+    }
+
+    debug!("Getting Diff for {:#?}", &ctx.local_repo);
+    let source = if *amend {
+        RepoDiff::new(git, git.get_amend_diff(&repo)?)
+    } else if include_unstaged {
+        RepoDiff::new(git, git.get_workdir_diff(&repo)?)
+    } else {
+        RepoDiff::staged(git, &repo)?
+    }
+    .compress(ctx.compress)
+    .token_budget(ctx.diff_budget);
+    let diff = source.diff();
+    let files_changed = diff.deltas().len();
+    // nothing leaves the machine without the AI
+    if !ctx.no_ai {
+        report_redactions(&git, diff)?;
+    }
+    // the same kind of change as last time should read like it did last time,
+    // an amend is looking for the commits before the one it replaces
+    let earlier: Vec<(String, String)> = match git.commits_touching_paths(
+        &repo,
+        if *amend { "HEAD~1" } else { "HEAD" },
+        &git.changed_paths(diff),
+        if ctx.no_ai {
+            0
+        } else {
+            ctx.consistency_commits
+        },
+    ) {
+        Ok(oids) => oids
+            .iter()
+            .filter_map(|oid| repo.find_commit(*oid).ok())
+            .map(|commit| {
+                (
+                    commit.id().to_string()[..7].to_string(),
+                    commit.summary().unwrap_or("").to_string(),
+                )
+            })
+            .collect(),
+        Err(e) => {
+            debug!("No earlier commits to stay consistent with: {}", e);
+            Vec::new()
+        }
+    };
+    let options = GitAiOptions {
+        ticket: ticket
+            .await
+            .map_err(|e| GitAiError::Usage(format!("Looking the ticket up went wrong: {}", e)))??,
+        earlier_subjects: earlier
+            .iter()
+            .map(|(_, subject)| subject.to_string())
+            .collect(),
+        ..ctx.options.clone()
+    };
+
+    // what the AI wrote it from, for the ledger and the extras that need the AI
+    let (message, generated) = if ctx.no_ai {
+        if *fugitive || export_prompt.is_some() {
+            return Err(GitAiError::Usage(
+                "--fugitive and --export-prompt need the AI, they don't go with --no-ai"
+                    .to_string(),
+            ));
+        }
+        let start = if *amend {
+            git.find_last_commit(&repo)?
+                .message()
+                .unwrap_or("")
+                .to_string()
+        } else {
+            String::new()
+        };
+        match manual_message(&git, &repo, &options, &start)? {
+            Some(message) => (message, None),
+            None => {
+                println!("The message was empty, nothing was committed");
+                return Ok(());
+            }
+        }
+    } else {
+        if ctx.dry_run {
+            let prompt = options.build_commit_prompt(source.diff_text()?);
+            return ctx.print_dry_run(prompt, ctx.num_tries);
+        }
+
+        debug!("Got Diff, Its OpenAI Time");
+        let client = ctx.client()?.with_deterministic(ctx.deterministic);
+
+        if let Some(path) = export_prompt {
+            let prompt = options.build_commit_prompt(source.diff_text()?);
+            write_request_body(&client, prompt, ctx.num_tries, path)?;
+            println!("Wrote the prompt to {}, nothing was sent", path.display());
+            return Ok(());
+        }
+        warmed_up(warm_up).await?;
+        let gitai = GitAi::new(racing(client, ctx.race_with.clone(), ctx.retry)?, options);
+
+        if ctx.intent_check {
+            if let Some(breakdown) =
+                intent_breakdown(gitai.generator(), &git, diff, &ctx.language).await?
+            {
+                if breakdown.is_mixed(ctx.mixed_intent_threshold) {
+                    println!(
+                    "This looks like more than one change, {}% of it isn't about the main one:\n\n{}",
+                    breakdown.mixed_percent(),
+                    breakdown
+                );
+                    if !auto_ai && !prompt_yes_no("Commit it all together anyway?")? {
+                        return Err(GitAiError::Refused(
+                            "Stopped so the change can be split up, nothing was committed"
+                                .to_string(),
+                        ));
+                    }
+                }
+            }
+        }
+
+        // an amend is one commit already, there is nothing to split, and unstaged
+        // changes are staged all at once when the message is accepted
+        if !*amend
+            && !include_unstaged
+            && !*fugitive
+            && files_changed > ctx.split_threshold
+            && (auto_ai
+                || prompt_yes_no(format!(
+                    "{} files are staged, split them into one commit per directory?",
+                    files_changed
+                ))?)
+        {
+            info!(
+                "Splitting {} files into per-directory commits",
+                files_changed
+            );
+            let groups = git.group_by_directory(diff);
+            for (i, (dir, paths)) in groups.iter().enumerate() {
+                if i > 0 {
+                    // be nice to the API, one enormous prompt is what we are avoiding
+                    debug!("Waiting {}ms before the next AI call", ctx.rate_limit_ms);
+                    tokio::time::sleep(Duration::from_millis(ctx.rate_limit_ms)).await;
+                }
+                println!("Committing {} ({} files)", dir, paths.len());
+                let batch = RepoDiff::new(git, git.get_paths_diff(&repo, paths)?)
+                    .compress(ctx.compress)
+                    .token_budget(ctx.diff_budget);
+                let impact = workspace_impact(&git, &repo, batch.diff())
+                    .filter(|_| ctx.affected_packages)
+                    .map(|impact| format!("\n\n{}", impact.to_commit_section()))
+                    .unwrap_or_default();
+                let completions: Vec<String> = gitai
+                    .commit_messages(&batch)
+                    .await?
+                    .into_iter()
+                    .map(|text| text + &impact)
+                    .collect();
+                let message = if auto_ai {
+                    match completions.first() {
+                        Some(first) if *edit => git.edit_message(&repo, first)?,
+                        first => first.cloned(),
+                    }
+                } else {
+                    println!(
+                        "{}",
+                        preview::diff_summary(&git.file_stats(batch.diff())?, preview::color())
+                    );
+                    let reroll = ctx.reroll(
+                        gitai.options().build_commit_prompt(batch.diff_text()?),
+                        gitai.options(),
+                        &impact,
+                    )?;
+                    choose_completion(
+                        &completions,
+                        ctx.reroll_diff,
+                        &git,
+                        &repo,
+                        *edit,
+                        &gitai.options().commit_policy,
+                        Some(&reroll),
+                    )
+                    .await?
+                };
+                let message = match message {
+                    Some(m) => m,
+                    None => {
+                        println!("Skipping {}, it stays staged", dir);
+                        continue;
+                    }
+                };
+                let message = hooks::run(HookPoint::PreCommit, CommitMessage { message })?.message;
+                if ctx.gitmoji {
+                    gitmoji::check(&message)?;
+                }
+                let before = head_oid(&repo);
+                let commit_id = git.make_partial_commit(&repo, &message, paths)?;
+                let prompt_hash = if ctx.deterministic {
+                    Some(announce_prompt_hash(
+                        &gitai.options().build_commit_prompt(batch.diff_text()?),
+                    ))
+                } else {
+                    None
+                };
+                let generation = ledger::Generation {
+                    prompt_hash,
+                    ..ledger::generation(
+                        "commit",
+                        &template_name(&ctx.pack_name, false, ctx.conventional),
+                        &ctx.model_name,
+                        &batch.diff_text()?,
+                        &message,
+                        Some(commit_id.to_string()),
+                    )
+                };
+                record_commit(&git, &repo, before, commit_id, (&generation).into());
+                if ctx.dual_description != DualDescription::Off {
+                    let stored = match gitai
+                        .internal_description(&batch.diff_text()?, &message)
+                        .await
+                    {
+                        Ok(description) => {
+                            store_internal_description(
+                                &git,
+                                &repo,
+                                &ctx.settings.git_settings.git_options.internal_notes_ref,
+                                Some(&ctx.settings.tickets)
+                                    .filter(|_| ctx.dual_description == DualDescription::Tracker),
+                                gitai.options().ticket.as_ref(),
+                                &description,
+                                commit_id,
+                            )
+                            .await
+                        }
+                        Err(e) => Err(e),
+                    };
+                    if let Err(e) = stored {
+                        warn!(
+                            "Committed {} without its internal description: {}",
+                            commit_id, e
+                        );
+                    }
+                }
+                ledger::record(generation);
+                println!("Created commit {}", commit_id);
+            }
+            return Ok(());
+        }
+
+        let git_diff_text = source.diff_text()?;
+        let prompt_hash = if ctx.deterministic {
+            Some(announce_prompt_hash(
+                &gitai
+                    .options()
+                    .build_commit_prompt(git_diff_text.to_string()),
+            ))
+        } else {
+            None
+        };
+
+        debug!("We have a client, lets build the prompt");
+        let mut completions: Vec<String> = if ctx.stochastic {
+            info!("Stochastic Mode Set");
+            let prompts = if ctx.pack.personas.is_empty() {
+                Settings::get_commit_prompt_choices()
+            } else {
+                ctx.pack.personas.clone()
+            };
+            // every variant goes out at once, the slowest answer is all the wait
+            let requests: FuturesUnordered<_> = (0..ctx.num_tries)
+                .map(|i| {
+                    let prompt = gitai.options().commit_context(policy::prompt(
+                        commit_prompt(
+                            AiPrompt {
+                                language: ctx.language.to_string(),
+                                git_diff: git_diff_text.to_string(),
+                                ..prompts
+                                    .choose(&mut rand::thread_rng())
+                                    .cloned()
+                                    .unwrap_or_default()
+                            },
+                            gitai.options().message_format(),
+                        ),
+                        &ctx.settings.commit_policy,
+                    ));
+                    debug!("Post #{} to OpenAI", (i + 1));
+                    get_completion_texts(gitai.generator(), prompt, 1)
+                })
+                .collect();
+            let messages = gather(requests, ctx.num_tries.into())
+                .await?
+                .iter()
+                .map(|text| gitai.options().finish_message(text))
+                .collect();
+            gitai
+                .enforce_policy(git_diff_text.to_string(), messages)
+                .await?
+        } else {
+            info!("Non-Stochastic Mode Set");
+            gitai.commit_messages(&source).await?
+        };
+
+        let impact = workspace_impact(&git, &repo, diff)
+            .filter(|_| ctx.affected_packages)
+            .map(|impact| format!("\n\n{}", impact.to_commit_section()))
+            .unwrap_or_default();
+        for comp in completions.iter_mut() {
+            comp.push_str(&impact);
+        }
+
+        if *fugitive {
+            if let Some(first) = completions.first() {
+                print!(
+                    "{}",
+                    githook::commit_buffer(first, git.current_branch(&repo).as_deref(), diff)?
+                );
+            }
+            return Ok(());
+        }
+
+        let message = if auto_ai {
+            match completions.first() {
+                Some(first) if *edit => git.edit_message(&repo, first)?,
+                first => first.cloned(),
+            }
+        } else {
+            if !earlier.is_empty() {
+                println!("The last commits to these files were");
+                for (id, subject) in &earlier {
+                    println!("  {} {}", id, subject);
+                }
+                println!();
+            }
+            println!(
+                "{}",
+                preview::diff_summary(&git.file_stats(diff)?, preview::color())
+            );
+            println!("Here is your AI Generated Commit Message\n\n");
+            let reroll = ctx.reroll(
+                gitai
+                    .options()
+                    .build_commit_prompt(git_diff_text.to_string()),
+                gitai.options(),
+                &impact,
+            )?;
+            choose_completion(
+                &completions,
+                ctx.reroll_diff,
+                &git,
+                &repo,
+                *edit,
+                &gitai.options().commit_policy,
+                Some(&reroll),
+            )
+            .await?
+        };
+        let template = template_name(&ctx.pack_name, ctx.stochastic, ctx.conventional);
+        let message = match message {
+            Some(m) => m,
+            None => {
+                println!("No commit message accepted, nothing was committed");
+                // still worth a rating, `gitai feedback --last bad` says why none were any good
+                if let Some(first) = completions.first() {
+                    ledger::record(ledger::Generation {
+                        prompt_hash,
+                        ..ledger::generation(
+                            "commit",
+                            &template,
+                            &ctx.model_name,
+                            &git_diff_text,
+                            first,
+                            None,
+                        )
+                    });
+                }
+                return Ok(());
+            }
+        };
+        (message, Some((gitai, git_diff_text, prompt_hash, template)))
+    };
+
+    if let Some((gitai, git_diff_text, ..)) = generated.as_ref().filter(|_| ctx.doc_check) {
+        if let Some(reasons) =
+            check_doc_drift(gitai.generator(), &repo, git_diff_text, &ctx.language).await?
+        {
+            println!("Heads up, the docs may need updating\n{}\n", reasons);
+        }
+    }
+
+    let mut message = hooks::run(HookPoint::PreCommit, CommitMessage { message })?.message;
+    if ctx.gitmoji {
+        gitmoji::check(&message)?;
+    }
+    if let Some(private) = private {
+        // the AI never sees this, it goes straight into the commit encrypted
+        message = trailer::attach(&message, &trailer::encrypt(private_trailer, private)?);
+    }
+
+    if include_unstaged {
+        // only what the message describes, .gitaiignore files were never in it
+        let paths: Vec<PathBuf> = git
+            .changed_paths(diff)
+            .into_iter()
+            .filter(|path| !git.excludes(path))
+            .collect();
+        if !auto_ai
+            && !prompt_yes_no(format!(
+                "Stage the {} file(s) this describes and commit them?",
+                paths.len()
+            ))?
+        {
+            println!(
+                "Nothing was staged or committed, the message was:\n\n{}",
+                message
+            );
+            return Ok(());
+        }
+        git.stage_paths(&repo, &paths)?;
+    }
+    let before = head_oid(&repo);
+    let commit_id = if *amend {
+        git.amend_commit(&repo, &message)?
+    } else {
+        git.make_commit(&repo, &message)?
+    };
+    let generation =
+        generated.as_ref().map(
+            |(_, git_diff_text, prompt_hash, template)| ledger::Generation {
+                prompt_hash: prompt_hash.clone(),
+                ..ledger::generation(
+                    "commit",
+                    template,
+                    &ctx.model_name,
+                    git_diff_text,
+                    &message,
+                    Some(commit_id.to_string()),
+                )
+            },
+        );
+    let details = match &generation {
+        Some(generation) => generation.into(),
+        None => Details::message(&message),
+    };
+    record_commit(&git, &repo, before, commit_id, details);
+    if let Some(generation) = generation {
+        ledger::record(generation);
+    }
+    if *amend {
+        println!("Amended the last commit, it is now {}", commit_id);
+    } else {
+        println!("Created commit {}", commit_id);
+    }
+    if let Some((gitai, git_diff_text, ..)) =
+        generated.filter(|_| ctx.dual_description != DualDescription::Off)
+    {
+        let stored = match gitai.internal_description(&git_diff_text, &message).await {
+            Ok(description) => {
+                store_internal_description(
+                    &git,
+                    &repo,
+                    &ctx.settings.git_settings.git_options.internal_notes_ref,
+                    Some(&ctx.settings.tickets)
+                        .filter(|_| ctx.dual_description == DualDescription::Tracker),
+                    gitai.options().ticket.as_ref(),
+                    &description,
+                    commit_id,
+                )
+                .await
+            }
+            Err(e) => Err(e),
+        };
+        if let Err(e) = stored {
+            warn!(
+                "Committed {} without its internal description: {}",
+                commit_id, e
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Walks the user through the hunks like `git add -p` and returns the ones to stage.
+/// `y` and `n` are this hunk, `a` and `d` are this one and the rest of its file, `q`
+/// stages nothing more.
+///
+/// # Arguments
+///
+/// * `hunks` - The unstaged hunks, in order
+fn pick_hunks(hunks: &[UnstagedHunk]) -> Result<Vec<&UnstagedHunk>> {
+    interactive::ask("Stage which hunks?")?;
+    let stdin = io::stdin();
+    let mut stdin = stdin.lock();
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+
+    let mut picked = Vec::new();
+    // the rest of this file was answered with `a` or `d`
+    let mut rest_of_file: Option<(&PathBuf, bool)> = None;
+    for (i, hunk) in hunks.iter().enumerate() {
+        match rest_of_file {
+            Some((path, stage)) if *path == hunk.path => {
+                if stage {
+                    picked.push(hunk);
+                }
+                continue;
+            }
+            _ => rest_of_file = None,
+        }
+        write!(
+            stdout,
+            "\n{} ({}/{})\n{}Stage this hunk [y,n,a,d,q]? ",
+            hunk.path.display(),
+            i + 1,
+            hunks.len(),
+            hunk.text
+        )?;
+        stdout.flush()?;
+        let reply = terminal::read_line(&mut stdin)?
+            .and_then(|reply| reply.trim().to_ascii_lowercase().chars().next());
+        match reply {
+            Some('y') => picked.push(hunk),
+            Some('a') => {
+                picked.push(hunk);
+                rest_of_file = Some((&hunk.path, true));
+            }
+            Some('d') => rest_of_file = Some((&hunk.path, false)),
+            Some('q') => break,
+            _ => {}
+        }
+    }
+    Ok(picked)
+}
+
+/// Waits on the stochastic requests, keeping the first message of each as it comes back.
+/// The spinner says how many are in.
+///
+/// # Arguments
+///
+/// * `requests` - The requests, already on their way
+/// * `total` - How many there are
+async fn gather<F>(mut requests: FuturesUnordered<F>, total: usize) -> Result<Vec<String>>
+where
+    F: Future<Output = Result<Vec<String>>>,
+{
+    let progress = Progress::start(Phase::Gathering { done: 0, total });
+    let mut texts = Vec::new();
+    let mut done = 0;
+    while let Some(answer) = requests.next().await {
+        // the rest are dropped (and cancelled) if one fails
+        if let Some(text) = answer?.into_iter().next() {
+            texts.push(text);
+        }
+        done += 1;
+        progress.phase(Phase::Gathering { done, total });
+    }
+    Ok(texts)
+}
+
+/// Checks the AI token in the background by listing the models, which costs nothing.  Only
+/// a token that isn't accepted stops the commit (before the real request, when
+/// `warmed_up` is waited on), a service without a models list is fine.
+///
+/// # Arguments
+///
+/// * `client` - A client for the same service, it is never tried again
+fn start_auth_check(client: ai::OpenAiClient) -> tokio::task::JoinHandle<Result<()>> {
+    tokio::spawn(async move {
+        match client.get_models().await {
+            Err(e @ (GitAiError::Unauthorized { .. } | GitAiError::Permission(_))) => Err(e),
+            Err(e) => {
+                debug!("Couldn't check the token ahead of time: {}", e);
+                Ok(())
+            }
+            Ok(_) => Ok(()),
+        }
+    })
+}
+
+/// Looks the checked out branch's ticket up in the background, the tracker is asked
+/// while the diff is built instead of before it
+///
+/// # Arguments
+///
+/// * `git` - The git settings
+/// * `repo` - The repository
+/// * `settings` - The ticket settings
+fn start_ticket_lookup(
+    git: &Git<'_>,
+    repo: &git2::Repository,
+    settings: &TicketSettings,
+) -> tokio::task::JoinHandle<Result<Option<tickets::Ticket>>> {
+    let branch = git.current_branch(repo);
+    let settings = settings.clone();
+    tokio::spawn(async move {
+        match branch {
+            Some(branch) => tickets::for_branch(&settings, &branch).await,
+            None => Ok(None),
+        }
+    })
+}
+
+/// Puts the detailed description of a commit in a repo that is mirrored publicly where
+/// `dual_description` says, a comment on the branch's ticket or a git note
+///
+/// # Arguments
+///
+/// * `git` - The git settings
+/// * `repo` - The repository
+/// * `notes_ref` - The notes ref it goes in as a note
+/// * `tracker` - How to reach the tracker, when it should go on the ticket
+/// * `ticket` - The branch's ticket, if it has one
+/// * `description` - The description
+/// * `commit_id` - The commit
+async fn store_internal_description(
+    git: &Git<'_>,
+    repo: &git2::Repository,
+    notes_ref: &str,
+    tracker: Option<&TicketSettings>,
+    ticket: Option<&Ticket>,
+    description: &str,
+    commit_id: git2::Oid,
+) -> Result<()> {
+    if let Some(tracker) = tracker {
+        match ticket {
+            Some(ticket) => {
+                tickets::comment(
+                    tracker,
+                    &ticket.key,
+                    &format!("{}\n\n{}", commit_id, description),
+                )
+                .await?;
+                println!("Posted the internal description to {}", ticket.key);
+                return Ok(());
+            }
+            None => warn!("The branch has no ticket, the internal description goes in a git note"),
+        }
+    }
+    git.add_note(repo, notes_ref, commit_id, &format!("{}\n", description))?;
+    println!("Wrote the internal description to {}", notes_ref);
+    Ok(())
+}
+
+/// The commit message for `--no-ai`, written in the editor.  The ticket goes in front like
+/// it would for a generated one, and a message that breaks the commit policy can go back
+/// in the editor until it doesn't.  `None` when the message was emptied.
+///
+/// # Arguments
+///
+/// * `git` - The git wrapper, it knows the editor
+/// * `repo` - The repository
+/// * `options` - The ticket and the commit policy
+/// * `start` - What the editor opens with, the old message for an amend
+fn manual_message(
+    git: &Git<'_>,
+    repo: &git2::Repository,
+    options: &GitAiOptions,
+    start: &str,
+) -> Result<Option<String>> {
+    let mut text = start.to_string();
+    loop {
+        let message = match git.edit_message(repo, &text)? {
+            Some(message) => message,
+            None => return Ok(None),
+        };
+        let message = match &options.ticket {
+            Some(ticket) if options.ticket_prefix => tickets::prefix(&message, &ticket.key),
+            _ => message,
+        };
+        let problems = policy::violations(&options.commit_policy, &message)?;
+        if problems.is_empty() {
+            return Ok(Some(message));
+        }
+        println!(
+            "The message breaks the commit policy: {}",
+            problems.join(", ")
+        );
+        if !prompt_yes_no("Fix it in the editor?")? {
+            return Err(GitAiError::Refused(format!(
+                "The message doesn't follow the commit policy ({}), nothing was committed",
+                problems.join(", ")
+            )));
+        }
+        text = message;
+    }
+}
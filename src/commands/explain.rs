@@ -0,0 +1,61 @@
+use crate::error::{GitAiError, Result};
+use crate::explain::{self, ExplainedCommit, Level};
+use crate::generate::{get_completion_texts, prompt_diff_text};
+
+use super::{report_redactions, Context};
+
+/// `gitai explain`, what a commit or a range of them did, in plain English
+///
+/// # Arguments
+///
+/// * `ctx` - The settings and flags
+/// * `rev` - The commit, or `A..B`
+/// * `level` - Who the explanation is for
+pub async fn run(ctx: &Context, rev: &str, level: Option<Level>) -> Result<()> {
+    let git = ctx.git();
+    let repo = git.open_repository()?;
+    let diff = match rev.split_once("..") {
+        Some((old, new)) => {
+            if old.is_empty() || new.is_empty() || new.starts_with('.') {
+                return Err(GitAiError::Usage(format!(
+                    "{} isn't a range gitai can explain, give it as A..B",
+                    rev
+                )));
+            }
+            git.get_range_diff(&repo, old, new)?
+        }
+        None => {
+            let commit = repo.revparse_single(rev)?.peel_to_commit()?;
+            git.get_commit_changes(&repo, &commit)?
+        }
+    };
+    let commits: Vec<ExplainedCommit> = git
+        .commits_in_range(&repo, rev)?
+        .into_iter()
+        // a single revision is just that commit, not its whole history
+        .take(if rev.contains("..") { usize::MAX } else { 1 })
+        .map(|oid| {
+            let commit = repo.find_commit(oid)?;
+            Ok(ExplainedCommit {
+                id: oid.to_string()[..7].to_string(),
+                message: commit.message().unwrap_or("").to_string(),
+            })
+        })
+        .collect::<Result<_>>()?;
+    if commits.is_empty() {
+        return Err(GitAiError::Usage(format!(
+            "There are no commits in {}",
+            rev
+        )));
+    }
+    report_redactions(&git, &diff)?;
+    let diff_text = prompt_diff_text(&git, &diff, ctx.compress, ctx.diff_budget)?;
+    let prompt = explain::prompt(&ctx.language, rev, &commits, &diff_text, level);
+    if ctx.dry_run {
+        return ctx.print_dry_run(prompt, 1);
+    }
+    let client = ctx.client()?;
+    let explanation = get_completion_texts(&client, prompt, 1).await?;
+    println!("{}", explanation.first().map_or("", String::as_str).trim());
+    Ok(())
+}
@@ -0,0 +1,59 @@
+use crate::error::{GitAiError, Result};
+use crate::git::Git;
+use crate::ledger::{self, Rating};
+
+use super::Context;
+
+/// `gitai feedback`, rates a generated message
+///
+/// # Arguments
+///
+/// * `ctx` - The settings and flags
+/// * `rating` - Was it any good
+/// * `last` - Rate the last thing gitai generated instead of HEAD's message
+/// * `note` - What was good (or bad) about it
+pub fn run(ctx: &Context, rating: Rating, last: bool, note: &str) -> Result<()> {
+    let entries = ledger::read()?;
+    let generation = if last {
+        ledger::find_generation(&entries, None)
+            .ok_or_else(|| GitAiError::Usage("gitai hasn't generated anything yet".to_string()))?
+    } else {
+        let git = Git::new(
+            ctx.local_repo.to_str().unwrap_or("."),
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+        );
+        let repo = git.open_repository()?;
+        let head = repo.head()?.peel_to_commit()?.id().to_string();
+        ledger::find_generation(&entries, Some(&head)).ok_or_else(|| {
+            GitAiError::Usage(format!(
+                "gitai didn't write the message for {}, pass --last to rate the last thing it generated",
+                &head[..7]
+            ))
+        })?
+    };
+    ledger::rate(generation, rating, note)?;
+    println!(
+        "Marked {} ({}, {}) as {}",
+        generation.id,
+        generation.template,
+        generation.model,
+        match rating {
+            Rating::Good => "good",
+            Rating::Bad => "bad",
+        }
+    );
+    Ok(())
+}
+
+/// `gitai stats`, how each prompt and model has been rated
+pub fn stats() -> Result<()> {
+    println!("{}", ledger::stats(&ledger::read()?));
+    Ok(())
+}
@@ -0,0 +1,38 @@
+use crate::error::Result;
+use crate::journal;
+
+use super::Context;
+
+/// `gitai history`, what gitai has done
+///
+/// # Arguments
+///
+/// * `ctx` - The settings and flags
+/// * `all` - Every repository, not just this one
+/// * `search` - Only what mentions this
+/// * `limit` - This many of the latest, 0 for everything
+/// * `json` - One JSON object per line
+pub fn run(ctx: &Context, all: bool, search: Option<&str>, limit: usize, json: bool) -> Result<()> {
+    let repo = if all {
+        None
+    } else {
+        Some(journal::repo_key(&git2::Repository::discover(
+            &ctx.local_repo,
+        )?))
+    };
+    let entries = journal::read()?;
+    let actions = journal::matching(&entries, repo.as_deref(), search);
+    let skip = match limit {
+        0 => 0,
+        limit => actions.len().saturating_sub(limit),
+    };
+    let actions = &actions[skip..];
+    if json {
+        for action in actions {
+            println!("{}", serde_json::to_string(action)?);
+        }
+    } else {
+        println!("{}", journal::table(actions, &journal::undone(&entries)));
+    }
+    Ok(())
+}
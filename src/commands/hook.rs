@@ -0,0 +1,238 @@
+use std::io;
+use std::path::Path;
+
+use log::debug;
+
+use crate::error::{GitAiError, Result};
+use crate::generate::{get_completion_texts, prompt_diff_text};
+use crate::git::Git;
+use crate::githook::{self, HookKind};
+use crate::ledger;
+use crate::review;
+use crate::{DiffSource, GitAi, GitAiOptions, RepoDiff};
+
+use super::{branch_ticket, template_name, Context};
+
+/// `gitai hook install`, gitai as the repo's prepare-commit-msg (or pre-push) hook
+///
+/// # Arguments
+///
+/// * `ctx` - The settings and flags
+/// * `force` - Replace a hook that is already there
+/// * `pre_push` - Install the pre-push review instead
+pub fn install(ctx: &Context, force: bool, pre_push: bool) -> Result<()> {
+    let git = Git::new(
+        ctx.local_repo.to_str().unwrap_or("."),
+        None,
+        None,
+        None,
+        None,
+        None,
+        None,
+        None,
+        None,
+    );
+    let repo = git.open_repository()?;
+    if pre_push {
+        let path = githook::install(&repo, HookKind::PrePush, force)?;
+        println!(
+            "Installed the hook at {}, pushes are now reviewed first (git push --no-verify skips it)",
+            path.display()
+        );
+    } else {
+        let path = githook::install(&repo, HookKind::PrepareCommitMsg, force)?;
+        println!(
+            "Installed the hook at {}, git commit now starts with a generated message",
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+/// `gitai hook pre-push`, the pre-push hook: the commits going out are checked for red
+/// flags and the push stopped if there are any
+///
+/// # Arguments
+///
+/// * `ctx` - The settings and flags
+/// * `remote` - The remote being pushed to
+pub async fn pre_push(ctx: &Context, remote: Option<&str>) -> Result<()> {
+    if ctx.no_ai {
+        debug!("No AI, so no review before the push");
+        return Ok(());
+    }
+    let remote = remote.unwrap_or("origin");
+    let git = Git::new(
+        ctx.local_repo.to_str().unwrap_or("."),
+        None,
+        None,
+        None,
+        None,
+        None,
+        None,
+        None,
+        None,
+    );
+    let repo = git.open_repository()?;
+    // git sends `<local ref> <local sha> <remote ref> <remote sha>` for each ref pushed
+    let mut outgoing = Vec::new();
+    for line in io::stdin().lines() {
+        let line = line?;
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 4 {
+            continue;
+        }
+        let local = git2::Oid::from_str(fields[1])?;
+        let remote_sha = git2::Oid::from_str(fields[3])?;
+        if local.is_zero() {
+            // deleting a branch sends nothing
+            continue;
+        }
+        let remote_sha = Some(remote_sha).filter(|oid| !oid.is_zero());
+        for oid in git.outgoing_commits(&repo, local, remote_sha, remote)? {
+            if !outgoing.contains(&oid) {
+                outgoing.push(oid);
+            }
+        }
+    }
+    if outgoing.is_empty() {
+        return Ok(());
+    }
+    let per_commit_budget = ctx.diff_budget / outgoing.len();
+    let mut files = Vec::new();
+    let mut diff_text = String::new();
+    for oid in &outgoing {
+        let commit = repo.find_commit(*oid)?;
+        if commit.parent_count() > 1 {
+            continue;
+        }
+        let diff = git.get_commit_changes(&repo, &commit)?;
+        files.extend(git.diff_to_file_strings(&diff)?);
+        diff_text.push_str(&prompt_diff_text(
+            &git,
+            &diff,
+            ctx.compress,
+            per_commit_budget,
+        )?);
+    }
+    let mut findings: Vec<String> = review::red_flags(&files)
+        .iter()
+        .map(|finding| finding.to_string())
+        .collect();
+    if ctx.settings.git_settings.git_options.pre_push_ai_review {
+        // the quick checks already ran, a flaky connection shouldn't stop the push
+        let ai_findings = match ctx.client() {
+            Ok(client) => {
+                get_completion_texts(&client, review::prompt(&ctx.language, &diff_text), 1).await
+            }
+            Err(e) => Err(e),
+        };
+        match ai_findings {
+            Ok(answer) => findings.extend(
+                answer
+                    .first()
+                    .map(|answer| review::parse_answer(answer))
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|finding| format!("AI: {}", finding)),
+            ),
+            Err(e) => eprintln!(
+                "gitai couldn't reach the AI ({}), only the quick checks ran",
+                e
+            ),
+        }
+    }
+    if !findings.is_empty() {
+        eprintln!(
+            "gitai found red flags in the {} commit(s) going to {}:\n",
+            outgoing.len(),
+            remote
+        );
+        for finding in &findings {
+            eprintln!("  {}", finding);
+        }
+        eprintln!();
+        return Err(GitAiError::Refused(
+            "Push stopped, fix them or push again with git push --no-verify".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// `gitai hook run`, the prepare-commit-msg hook: a generated message goes in front of
+/// what git put in the file.  Nothing here stops the commit.
+///
+/// # Arguments
+///
+/// * `ctx` - The settings and flags
+/// * `file` - The file holding the commit message
+/// * `source` - Where the message came from
+pub async fn run(ctx: &Context, file: &Path, source: Option<&str>) -> Result<()> {
+    if !githook::should_generate(source) {
+        debug!("The commit already has a message ({:?})", source);
+        return Ok(());
+    }
+    if ctx.no_ai {
+        debug!("No AI, the message is left to whoever is committing");
+        return Ok(());
+    }
+    // git is going to open the editor either way, a failure here must not stop the commit
+    let message: Result<Option<String>> = async {
+        let git = Git::new(
+            ctx.local_repo.to_str().unwrap_or("."),
+            Some(&false),
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+        )
+        .with_diff_ignore(&ctx.diff_ignore)
+        .with_ai_ignore(&ctx.ai_ignore)
+        .with_redaction(ctx.redact);
+        let repo = git.open_repository()?;
+        let source = match RepoDiff::staged(git, &repo) {
+            Ok(source) => source,
+            Err(GitAiError::NothingStaged(_)) => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        .compress(ctx.compress)
+        .token_budget(ctx.diff_budget);
+        // the editor only has room for one
+        let options = GitAiOptions {
+            num_tries: 1,
+            ticket: branch_ticket(&git, &repo, &ctx.settings.tickets).await?,
+            ..ctx.options.clone()
+        };
+        // git is waiting on us, past the deadline the message comes from the diff
+        let client = ctx.client()?.with_deadline(ctx.generation_deadline_ms);
+        let gitai = GitAi::new(Box::new(client), options);
+        let git_diff_text = source.diff_text()?;
+        let message = gitai.commit_messages(&source).await?.into_iter().next();
+        if let Some(message) = &message {
+            // git makes the commit, so there is no id yet, `gitai feedback --last` rates it
+            ledger::record(ledger::generation(
+                "commit",
+                &template_name(&ctx.pack_name, false, ctx.conventional),
+                &ctx.model_name,
+                &git_diff_text,
+                message,
+                None,
+            ));
+        }
+        Ok(message)
+    }
+    .await;
+    match message {
+        Ok(Some(message)) => {
+            if let Err(e) = githook::prepend_message(file, &message) {
+                eprintln!("gitai couldn't write the message ({}), write your own", e);
+            }
+        }
+        Ok(None) => debug!("Nothing is staged, leaving the message to git"),
+        Err(e) => eprintln!("gitai couldn't write a message ({}), write your own", e),
+    }
+    Ok(())
+}
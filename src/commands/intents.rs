@@ -0,0 +1,48 @@
+use crate::error::Result;
+use crate::generate::{intent_breakdown, intent_hunks};
+use crate::git::Git;
+use crate::intent;
+
+use super::Context;
+
+/// `gitai intents`, why each hunk of what is staged changed
+///
+/// # Arguments
+///
+/// * `ctx` - The settings and flags
+pub async fn run(ctx: &Context) -> Result<()> {
+    let git = Git::new(
+        ctx.local_repo.to_str().unwrap_or("."),
+        Some(&ctx.auto_add),
+        None,
+        None,
+        None,
+        None,
+        None,
+        None,
+        None,
+    )
+    .with_diff_ignore(&ctx.diff_ignore)
+    .with_ai_ignore(&ctx.ai_ignore)
+    .with_redaction(ctx.redact);
+    let repo = git.open_repository()?;
+    let diff = git.get_commit_diff(&repo)?;
+    if ctx.dry_run {
+        let prompt = intent::prompt(&ctx.language, &intent_hunks(&git, &diff)?);
+        return ctx.print_dry_run(prompt, 1);
+    }
+    let client = ctx.client()?;
+    match intent_breakdown(&client, &git, &diff, &ctx.language).await? {
+        None => println!("Nothing is staged"),
+        Some(breakdown) => {
+            println!("{}", breakdown);
+            if breakdown.is_mixed(ctx.mixed_intent_threshold) {
+                println!(
+                    "{}% of it isn't about the main change, think about splitting it up",
+                    breakdown.mixed_percent()
+                );
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,64 @@
+use log::info;
+
+use crate::ai::OpenAiModel;
+use crate::error::Result;
+
+use super::Context;
+
+/// `gitai models`, the models the key can use
+///
+/// # Arguments
+///
+/// * `ctx` - The settings and flags
+/// * `filter` - Only the models with this in their name, whatever the case
+/// * `json` - Print them as JSON instead of a table
+pub async fn run(ctx: &Context, filter: Option<&str>, json: bool) -> Result<()> {
+    info!("Getting Available Models");
+    let client = ctx.client()?;
+    let filter = filter.unwrap_or("").to_lowercase();
+    let models: Vec<OpenAiModel> = client
+        .get_models()
+        .await?
+        .into_iter()
+        .filter(|model| model.id.to_lowercase().contains(&filter))
+        .collect();
+    if json {
+        println!("{}", serde_json::to_string_pretty(&models)?);
+    } else if models.is_empty() {
+        println!("No models match {}", filter);
+    } else {
+        print_models(&models);
+    }
+    Ok(())
+}
+
+/// The models as a table, their name, who made them and when
+///
+/// # Arguments
+///
+/// * `models` - The models
+fn print_models(models: &[OpenAiModel]) {
+    let id_width = models
+        .iter()
+        .map(|model| model.id.len())
+        .chain(["MODEL".len()])
+        .max()
+        .unwrap_or(0);
+    let owner_width = models
+        .iter()
+        .map(|model| model.owned_by.len())
+        .chain(["OWNER".len()])
+        .max()
+        .unwrap_or(0);
+    println!("{:<id_width$}  {:<owner_width$}  CREATED", "MODEL", "OWNER");
+    for model in models {
+        let created = chrono::NaiveDateTime::from_timestamp_opt(model.created, 0)
+            .filter(|_| model.created > 0)
+            .map(|date| date.format("%Y-%m-%d").to_string())
+            .unwrap_or_default();
+        println!(
+            "{:<id_width$}  {:<owner_width$}  {}",
+            model.id, model.owned_by, created
+        );
+    }
+}
@@ -0,0 +1,489 @@
+use log::{debug, error, info};
+
+use crate::ai;
+use crate::closing;
+use crate::diagram;
+use crate::docs;
+use crate::drafts::{self, PrDraft};
+use crate::error::{GitAiError, Result};
+use crate::generate::{check_doc_drift, get_completion_texts, prompt_diff_text, workspace_impact};
+use crate::git::Git;
+use crate::hooks::{self, HookPoint};
+use crate::hosting::{self, PullRequestOptions};
+use crate::journal::{self, ActionKind, Details};
+use crate::ledger;
+use crate::progress::{Phase, Progress};
+use crate::settings::{AiPrompt, Provider, RetrySettings, Settings};
+use crate::testing;
+use crate::walkthrough::{self, WalkthroughCommit};
+use crate::{DiffSource, GitAi, GitAiOptions, RepoDiff};
+
+use super::{
+    announce_prompt_hash, error_message, pr_template, print_option, prompt_yes_no, racing,
+    report_redactions, start_warm_up, template_name, warmed_up, Context,
+};
+
+/// What `gitai pr` is asked for
+#[derive(Debug, Clone, Default)]
+pub struct PullRequestArgs {
+    /// The branch with the changes
+    pub from: String,
+    /// The branch it goes into, the repo's default branch if it isn't given
+    pub to: Option<String>,
+    /// Pick up the saved draft for `from`
+    pub resume: bool,
+    /// Open it as a draft
+    pub draft: bool,
+    /// Ask them to review it, instead of `pr_reviewers`
+    pub reviewer: Vec<String>,
+    /// Ask these teams to review it, instead of `pr_team_reviewers`
+    pub team_reviewer: Vec<String>,
+    /// Label it, instead of `pr_labels`
+    pub label: Vec<String>,
+    /// Assign it, instead of `pr_assignees`
+    pub assignee: Vec<String>,
+}
+
+/// `gitai pr`, writes the pull request for the branch, pushes it and opens it
+///
+/// # Arguments
+///
+/// * `ctx` - The settings and flags
+/// * `args` - What the pull request is asked for
+pub async fn run(ctx: &Context, args: &PullRequestArgs) -> Result<()> {
+    let PullRequestArgs {
+        from,
+        to,
+        resume,
+        draft: open_as_draft,
+        reviewer,
+        team_reviewer,
+        label,
+        assignee,
+    } = args;
+    info!("Generating PR from {:#?} to {:#?}", from, to);
+    let git = Git::new(
+        ctx.local_repo.to_str().unwrap_or("."),
+        Some(&ctx.auto_add),
+        Some(&ctx.auto_push),
+        Some(&ctx.gpg_sign_commits).filter(|sign| **sign),
+        Some(ctx.gpg_key_id.as_str()),
+        None,
+        None,
+        Some(&ctx.ssh_key_path),
+        Some(&ctx.ssh_user),
+    )
+    .with_diff_ignore(&ctx.diff_ignore)
+    .with_ai_ignore(&ctx.ai_ignore)
+    .with_redaction(ctx.redact);
+    debug!("Getting Repository at {:#?}", &ctx.local_repo);
+    let repo = git.open_repository()?;
+    let mut hosting_settings = ctx.hosting_settings.clone();
+    if hosting_settings.provider == Provider::Github && !ctx.github_token_from_cli {
+        if let Some(remote) = git.remote_location(&repo, &hosting_settings.remote) {
+            if let Some(account) =
+                hosting_settings.use_github_account_for(&remote.host, &remote.owner)
+            {
+                info!(
+                    "Using the {} GitHub account for {}/{}",
+                    account, remote.owner, remote.name
+                );
+            }
+        }
+    }
+    // found out about while the questions are being answered, not after
+    let warm_up = if ctx.warm_up && !ctx.auto_ai && !ctx.no_ai && !ctx.dry_run {
+        let client = ai::new_client(
+            ctx.ai_url.to_string(),
+            ctx.ai_token.to_string(),
+            ctx.azure.clone(),
+            RetrySettings {
+                max_retries: 0,
+                ..ctx.retry
+            },
+        )?
+        .with_model(&ctx.ai_model);
+        Some(start_warm_up(client))
+    } else {
+        None
+    };
+    let provider = hosting::new_provider(&hosting_settings);
+    if !ctx.dry_run {
+        provider.verify_access(&repo).await?;
+    }
+    let draft = if *resume {
+        Some(drafts::load(&repo, from).ok_or_else(|| {
+            GitAiError::Usage(format!(
+                "There is no saved draft for {}, run gitai pr without --resume",
+                from
+            ))
+        })?)
+    } else {
+        None
+    };
+    let to = match (to, &draft) {
+        (Some(branch), _) => branch.to_string(),
+        (None, Some(draft)) => draft.to.to_string(),
+        (None, None) => provider.get_default_branch(&repo).await.map_err(|e| {
+            GitAiError::Usage(format!(
+                "Unable to get the default branch ({}), try passing the to branch",
+                e
+            ))
+        })?,
+    };
+
+    debug!("Getting Diff from {} to {}", from, to);
+    let source = RepoDiff::branches(git, &repo, from, &to)?
+        .compress(ctx.compress)
+        .token_budget(ctx.diff_budget);
+    let diff = source.diff();
+    let head = git.branch_head(&repo, from)?.to_string();
+    // nothing leaves the machine without the AI
+    if !ctx.no_ai {
+        report_redactions(&git, diff)?;
+    }
+    let options = GitAiOptions {
+        test_commands: repo
+            .workdir()
+            .map(|root| testing::commands(root, &git.changed_paths(diff)))
+            .unwrap_or_default(),
+        pr_template: pr_template(ctx.use_pr_template, &repo),
+        ..ctx.options.clone()
+    };
+
+    if ctx.dry_run {
+        let prompt = options.build_pr_prompt(source.diff_text()?);
+        return ctx.print_dry_run(prompt, ctx.num_tries);
+    }
+
+    let git_diff_text = source.diff_text()?;
+    let (candidates, prompt_hash) = if ctx.no_ai {
+        // a saved draft is a better start than the commits
+        let (title, body) = match draft {
+            Some(draft) => (draft.title, draft.body),
+            None => {
+                let (title, body) = pull_request_from_commits(&git, &repo, &to, from)?;
+                // like gh, the template is the body when there is one
+                (title, options.pr_template.clone().unwrap_or(body))
+            }
+        };
+        match git.edit_pull_request(&repo, &title, &body)? {
+            Some(pull_request) => (vec![pull_request], None),
+            None => {
+                println!("The pull request was empty, nothing was created");
+                return Ok(());
+            }
+        }
+    } else {
+        warmed_up(warm_up).await?;
+        debug!("Got Diff, Its OpenAI Time");
+        let gitai = GitAi::new(
+            racing(
+                ctx.client()?.with_deterministic(ctx.deterministic),
+                ctx.race_with.clone(),
+                ctx.retry,
+            )?,
+            options,
+        );
+        let prompt_hash = if ctx.deterministic {
+            Some(announce_prompt_hash(
+                &gitai.options().build_pr_prompt(git_diff_text.to_string()),
+            ))
+        } else {
+            None
+        };
+        let candidates: Vec<(String, String)> = if let Some(draft) = draft {
+            if draft.head == head {
+                info!("No new commits since the draft, using it as is");
+                vec![(draft.title, draft.body)]
+            } else {
+                info!("New commits since the draft, writing a section for them");
+                let delta = git.get_range_diff(&repo, &draft.head, from)?;
+                let prompt = AiPrompt {
+                    language: ctx.language.clone(),
+                    git_diff: prompt_diff_text(&git, &delta, ctx.compress, ctx.diff_budget)?,
+                    ..Settings::get_pr_update_prompt()
+                };
+                let _progress = Progress::start(Phase::Querying {
+                    tokens: ai::count_tokens(&prompt.to_string()),
+                });
+                let section = get_completion_texts(gitai.generator(), prompt, 1)
+                    .await?
+                    .first()
+                    .map(|text| closing::rewrite(text.trim(), ctx.closing_keywords))
+                    .unwrap_or_default();
+                let body = format!(
+                    "{}\n\n## Changes since the last draft\n\n{}",
+                    draft.body.trim_end(),
+                    section
+                );
+                vec![(draft.title, body)]
+            }
+        } else {
+            let mut extra_sections = workspace_impact(&git, &repo, diff)
+                .filter(|_| ctx.affected_packages)
+                .map(|impact| format!("\n\n{}", impact.to_markdown_section()))
+                .unwrap_or_default();
+            if ctx.doc_check {
+                if let Some(reasons) =
+                    check_doc_drift(gitai.generator(), &repo, &git_diff_text, &ctx.language).await?
+                {
+                    extra_sections.push_str(&format!("\n\n{}", docs::reminder_section(&reasons)));
+                }
+            }
+            if ctx.diagram {
+                let files = git.diff_to_file_strings(diff)?;
+                extra_sections.push_str(&format!("\n\n{}", diagram::markdown_section(&files)));
+            }
+            if ctx.pr_walkthrough {
+                // oldest first, a merge doesn't add anything of its own
+                let mut commits = Vec::new();
+                for oid in git
+                    .commits_in_range(&repo, &format!("{}..{}", to, from))?
+                    .into_iter()
+                    .rev()
+                {
+                    let commit = repo.find_commit(oid)?;
+                    if commit.parent_count() == 1 {
+                        commits.push(commit);
+                    }
+                }
+                // one commit is its own walkthrough
+                if commits.len() > 1 {
+                    let per_commit_budget = ctx.diff_budget / commits.len();
+                    let mut listing = Vec::new();
+                    for commit in &commits {
+                        let diff = git.get_commit_changes(&repo, commit)?;
+                        listing.push(WalkthroughCommit {
+                            id: commit.id().to_string()[..7].to_string(),
+                            subject: commit.summary().unwrap_or("").to_string(),
+                            diff: prompt_diff_text(&git, &diff, ctx.compress, per_commit_budget)?,
+                        });
+                    }
+                    let prompt = walkthrough::prompt(&ctx.language, &listing);
+                    if let Some(text) = get_completion_texts(gitai.generator(), prompt, 1)
+                        .await?
+                        .first()
+                    {
+                        extra_sections.push_str(&format!(
+                            "\n\n{}",
+                            walkthrough::markdown_section(&closing::rewrite(
+                                text,
+                                ctx.closing_keywords
+                            ))
+                        ));
+                    }
+                }
+            }
+            gitai
+                .pull_requests(git_diff_text.as_str())
+                .await?
+                .into_iter()
+                .map(|(title, body)| (title, body + &extra_sections))
+                .collect()
+        };
+        (candidates, prompt_hash)
+    };
+
+    // a pull request written in the editor was accepted when it was saved
+    let pull_request = if ctx.auto_ai || ctx.no_ai {
+        candidates.first().cloned()
+    } else {
+        let mut accepted = None;
+        let texts: Vec<String> = candidates
+            .iter()
+            .map(|(title, body)| format!("# {}\n\n{}", title, body))
+            .collect();
+        for (i, (title, body)) in candidates.iter().enumerate() {
+            let previous = i.checked_sub(1).map(|p| texts[p].as_str());
+            print_option(i, &texts[i], previous, ctx.reroll_diff);
+            if prompt_yes_no("Create this pull request?")? {
+                accepted = Some((title.to_string(), body.to_string()));
+                break;
+            }
+        }
+        accepted
+    };
+    // anything generated but not submitted is kept so `--resume` can pick it up
+    let save_draft = |title: &str, body: &str| {
+        let draft = PrDraft {
+            to: to.to_string(),
+            head: head.to_string(),
+            title: title.to_string(),
+            body: body.to_string(),
+        };
+        match drafts::save(&repo, from, &draft) {
+            Ok(path) => println!(
+                "Saved the draft to {}, edit it and run gitai pr {} --resume",
+                path.display(),
+                from
+            ),
+            Err(e) => eprintln!(
+                "{}",
+                error_message(&format!("Unable to save the draft: {}", e))
+            ),
+        }
+    };
+    // what goes in the ledger, and how the pull request came to be for the journal
+    let record = |title: &str, body: &str| -> Details {
+        let message = format!("{}\n\n{}", title, body);
+        if ctx.no_ai {
+            return Details::message(&message);
+        }
+        let generation = ledger::Generation {
+            prompt_hash: prompt_hash.clone(),
+            ..ledger::generation(
+                "pr",
+                &template_name(&ctx.pack_name, false, false),
+                &ctx.model_name,
+                &git_diff_text,
+                &message,
+                None,
+            )
+        };
+        let details = Details::from(&generation);
+        ledger::record(generation);
+        details
+    };
+    let (title, body) = match pull_request {
+        Some(pr) => pr,
+        None => {
+            println!("No pull request accepted, nothing was created");
+            if let Some((title, body)) = candidates.first() {
+                record(title, body);
+                save_draft(title, body);
+            }
+            return Ok(());
+        }
+    };
+    let details = record(&title, &body);
+
+    let files_changed = diff.deltas().len();
+    if ctx.auto_ai && ctx.auto_push && !ctx.no_ai {
+        guard_unattended(&title, files_changed, ctx.yes)?;
+    }
+
+    // the branch goes to its upstream, which for a fork isn't where the pull request goes
+    let (push_remote, pushed_branch) = git.push_destination(&repo, from, &hosting_settings.remote);
+    if ctx.auto_push {
+        info!("Pushing {} to {}", from, push_remote);
+        git.push_to_remote(&repo, from, &hosting_settings.remote)?;
+    }
+    let head_ref = match (
+        git.remote_location(&repo, &push_remote),
+        git.remote_location(&repo, &hosting_settings.remote),
+    ) {
+        (Some(fork), Some(target))
+            if hosting_settings.provider == Provider::Github && fork.owner != target.owner =>
+        {
+            format!("{}:{}", fork.owner, pushed_branch)
+        }
+        _ => pushed_branch,
+    };
+
+    let git_options = &hosting_settings.git_options;
+    // a flag replaces the list from the settings rather than adding to it
+    let or_settings = |flag: &Vec<String>, setting: &Vec<String>| {
+        if flag.is_empty() {
+            setting.clone()
+        } else {
+            flag.clone()
+        }
+    };
+    let pr_options = PullRequestOptions {
+        draft: *open_as_draft || git_options.pr_draft,
+        reviewers: or_settings(reviewer, &git_options.pr_reviewers),
+        team_reviewers: or_settings(team_reviewer, &git_options.pr_team_reviewers),
+        labels: or_settings(label, &git_options.pr_labels),
+        assignees: or_settings(assignee, &git_options.pr_assignees),
+    };
+    let res = match provider
+        .create_pull_request(&repo, &to, &head_ref, &title, &body, &pr_options)
+        .await
+    {
+        Ok(res) => res,
+        Err(e) => {
+            error!("Unable to create the pull request on {}", provider.name());
+            save_draft(&title, &body);
+            return Err(e);
+        }
+    };
+    if let Err(e) = drafts::remove(&repo, from) {
+        debug!("Unable to remove the old draft: {}", e);
+    }
+    journal::record(
+        &repo,
+        ActionKind::PullRequest {
+            provider: hosting_settings.provider,
+            remote: hosting_settings.remote.clone(),
+            number: res.number,
+            url: res.html_url.clone(),
+        },
+        details,
+    );
+    let res = hooks::run(HookPoint::PostPr, res)?;
+    println!("Created pull request {}", res.html_url);
+    Ok(())
+}
+
+/// The concise line we always print before acting in fully automated mode,
+/// the subject line of the message plus the number of files changed
+fn preview_line(message: &str, files_changed: usize) -> String {
+    let subject = message.lines().next().unwrap_or_default().trim();
+    format!("gitai: \"{}\" ({} file(s) changed)", subject, files_changed)
+}
+
+/// Guard rail for opening a pull request with both `auto_ai` and `auto_push` turned on,
+/// the branch is pushed and nobody reviewed anything.  We always leave a visible trace
+/// and refuse to act unless the user passed `--yes` or the settings policy allows it.
+///
+/// # Arguments
+///
+/// * `message` - The message that is about to be used
+/// * `files_changed` - How many files the change touches
+/// * `allowed` - `--yes` was passed or `allow_auto_ai_push` is set
+fn guard_unattended(message: &str, files_changed: usize, allowed: bool) -> Result<()> {
+    println!("{}", preview_line(message, files_changed));
+    if allowed {
+        return Ok(());
+    }
+    Err(GitAiError::Refused(
+        "auto-ai and auto-push are both on, pass --yes or set git_options.allow_auto_ai_push to run unattended".to_string(),
+    ))
+}
+
+/// What a `--no-ai` pull request starts out as in the editor, like `gh pr create --fill`:
+/// a single commit's message, or the branch name and a list of the commits
+///
+/// # Arguments
+///
+/// * `git` - The git wrapper
+/// * `repo` - The repository
+/// * `to` - The branch it goes into
+/// * `from` - The branch with the changes
+fn pull_request_from_commits(
+    git: &Git<'_>,
+    repo: &git2::Repository,
+    to: &str,
+    from: &str,
+) -> Result<(String, String)> {
+    let mut subjects = Vec::new();
+    let mut messages = Vec::new();
+    // oldest first
+    for oid in git
+        .commits_in_range(repo, &format!("{}..{}", to, from))?
+        .into_iter()
+        .rev()
+    {
+        let commit = repo.find_commit(oid)?;
+        subjects.push(format!("- {}", commit.summary().unwrap_or("")));
+        messages.push(commit.message().unwrap_or("").to_string());
+    }
+    Ok(match messages.as_slice() {
+        [only] => match only.trim().split_once('\n') {
+            Some((title, body)) => (title.to_string(), body.trim().to_string()),
+            None => (only.trim().to_string(), String::new()),
+        },
+        _ => (from.to_string(), subjects.join("\n")),
+    })
+}
@@ -0,0 +1,79 @@
+use std::path::Path;
+
+use crate::error::{GitAiError, Result};
+use crate::packs;
+use crate::prompttest;
+use crate::{GitAi, GitAiOptions};
+
+use super::Context;
+
+/// `gitai prompts sync`, clones (or updates) a prompt pack
+///
+/// # Arguments
+///
+/// * `url` - The pack's git url
+/// * `name` - What to call it, the repo's name if it isn't given
+pub fn sync(url: &str, name: Option<&str>) -> Result<()> {
+    let (name, path) = packs::sync(url, name)?;
+    println!(
+        "The {} pack is up to date in {}, set ai_options.prompt_pack to \"{}\" to use it",
+        name,
+        path.display(),
+        name
+    );
+    Ok(())
+}
+
+/// `gitai prompts test`, writes messages for the fixtures with a prompt pack and checks
+/// them against what they should say
+///
+/// # Arguments
+///
+/// * `ctx` - The settings and flags
+/// * `template` - `default`, a synced pack's name, or a pack's directory or pack.json
+/// * `fixtures` - The directory with the fixtures and their prompts.toml
+/// * `conventional` - `--conventional` was passed
+pub async fn test(
+    ctx: &Context,
+    template: &str,
+    fixtures: &Path,
+    conventional: bool,
+) -> Result<()> {
+    let pack = packs::resolve(template)?;
+    let (spec, fixtures) = prompttest::load(fixtures)?;
+    // the template under test stands in for whichever pack the settings use
+    let options = GitAiOptions {
+        language: spec.language.unwrap_or(ctx.options.language.clone()),
+        conventional: conventional
+            || ctx.settings.ai_settings.ai_options.conventional_commits
+            || pack.conventional_commits.unwrap_or(false),
+        commit_prompt: pack.commit_prompt.unwrap_or_default(),
+        ..ctx.options.clone()
+    };
+    let client = ctx.client()?.with_deterministic(ctx.deterministic);
+    let gitai = GitAi::new(Box::new(client), options);
+    let outcomes = prompttest::run(&gitai, &fixtures).await?;
+    for outcome in &outcomes {
+        if outcome.passed() {
+            println!("ok      {}", outcome.name);
+        } else {
+            println!("FAILED  {}: {}", outcome.name, outcome.failures.join(", "));
+            for message in &outcome.messages {
+                for line in message.lines() {
+                    println!("        {}", line);
+                }
+                println!();
+            }
+        }
+    }
+    let failed = outcomes.iter().filter(|outcome| !outcome.passed()).count();
+    println!("\n{} passed, {} failed", outcomes.len() - failed, failed);
+    if failed > 0 {
+        return Err(GitAiError::Refused(format!(
+            "{} of {} fixture(s) didn't get the message they expected",
+            failed,
+            outcomes.len()
+        )));
+    }
+    Ok(())
+}
@@ -0,0 +1,147 @@
+use std::collections::BTreeMap;
+
+use log::info;
+
+use crate::ai;
+use crate::error::{GitAiError, Result};
+use crate::generate::get_completion_texts;
+use crate::hosting::GitHub;
+use crate::progress::{Phase, Progress};
+use crate::redact;
+use crate::review;
+use crate::settings::Provider;
+
+use super::{report_redactions, Context};
+
+/// `gitai review`, the AI looks over what is staged or a GitHub pull request and the
+/// findings can go on the pull request as review comments
+///
+/// # Arguments
+///
+/// * `ctx` - The settings and flags
+/// * `pr` - The pull request, `None` for what is staged
+/// * `post` - Post the findings on the pull request
+pub async fn run(ctx: &Context, pr: Option<u64>, post: bool) -> Result<()> {
+    let git = ctx.git();
+    let repo = git.open_repository()?;
+    let (unified, pull) = match pr {
+        Some(number) => {
+            let mut hosting_settings = ctx.hosting_settings.clone();
+            if hosting_settings.provider != Provider::Github {
+                return Err(GitAiError::Usage(
+                    "Only GitHub pull requests can be reviewed, use --staged for anything else"
+                        .to_string(),
+                ));
+            }
+            if !ctx.github_token_from_cli {
+                if let Some(remote) = git.remote_location(&repo, &hosting_settings.remote) {
+                    hosting_settings.use_github_account_for(&remote.host, &remote.owner);
+                }
+            }
+            info!("Reviewing pull request #{}", number);
+            let github = GitHub::new(
+                &hosting_settings.github_api_key,
+                &hosting_settings.github_api_url,
+                &hosting_settings.github_ca_cert,
+            )
+            .with_remote(&hosting_settings.remote);
+            let (head, unified) = github.pull_request_diff(&repo, number).await?;
+            (unified, Some((github, number, head)))
+        }
+        None => {
+            info!("Reviewing what is staged");
+            let diff = git.get_commit_diff(&repo).map_err(|e| match e {
+                GitAiError::NothingStaged(_) => GitAiError::NothingStaged(
+                    "Nothing is staged, so there is nothing to review".to_string(),
+                ),
+                e => e,
+            })?;
+            report_redactions(&git, &diff)?;
+            (git.diff_to_unified(&diff)?, None)
+        }
+    };
+    let mut files = review::split_unified(&unified);
+    files.retain(|file| !git.ignores(&file.path) && !git.excludes(&file.path));
+    if ctx.redact {
+        redact::redact_files(&mut files);
+    }
+    // the lines of each file a review comment can go on
+    let mut commentable = BTreeMap::new();
+    for file in files.iter_mut() {
+        commentable.insert(
+            file.path.to_string_lossy().into_owned(),
+            review::number_lines(file),
+        );
+    }
+    let diff_text = git.fit_diff_text(files, |text| ai::count_tokens(text) <= ctx.diff_budget);
+    let prompt = review::review_prompt(&ctx.language, &diff_text);
+    if ctx.dry_run {
+        return ctx.print_dry_run(prompt, 1);
+    }
+    let client = ctx.client()?;
+    let progress = Progress::start(Phase::Querying {
+        tokens: ai::count_tokens(&prompt.to_string()),
+    });
+    let answer = get_completion_texts(&client, prompt, 1).await?;
+    progress.phase(Phase::Parsing);
+    let findings = answer
+        .first()
+        .map(|answer| review::parse_review(answer))
+        .unwrap_or_default();
+    drop(progress);
+    if findings.is_empty() {
+        println!("The AI didn't find anything worth pointing out");
+        return Ok(());
+    }
+    for (path, comments) in review::by_file(&findings) {
+        println!("{}", path);
+        for comment in comments {
+            let line = comment
+                .line
+                .map(|line| line.to_string())
+                .unwrap_or_default();
+            println!(
+                "  {:>5}  {:<5}  {}",
+                line, comment.category, comment.message
+            );
+        }
+    }
+    if let (true, Some((github, number, head))) = (post, pull) {
+        // GitHub only takes comments on lines in the diff, the rest go in the review
+        let (inline, general): (Vec<_>, Vec<_>) = findings.iter().partition(|comment| {
+            comment.line.is_some_and(|line| {
+                commentable
+                    .get(&comment.path)
+                    .is_some_and(|lines| lines.contains(&line))
+            })
+        });
+        let inline: Vec<(String, u32, String)> = inline
+            .iter()
+            .map(|comment| {
+                (
+                    comment.path.to_string(),
+                    comment.line.unwrap_or_default(),
+                    format!("**{}**: {}", comment.category, comment.message),
+                )
+            })
+            .collect();
+        let mut body = format!("gitai found {} thing(s) to look at", findings.len());
+        for comment in &general {
+            body.push_str(&format!(
+                "\n- `{}`{}: **{}**: {}",
+                comment.path,
+                comment
+                    .line
+                    .map(|line| format!(":{}", line))
+                    .unwrap_or_default(),
+                comment.category,
+                comment.message
+            ));
+        }
+        let url = github
+            .post_review(&repo, number, &head, &body, &inline)
+            .await?;
+        println!("Posted the review to {}", url);
+    }
+    Ok(())
+}
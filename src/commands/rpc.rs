@@ -0,0 +1,187 @@
+use std::io;
+
+use log::debug;
+use serde_json::{json, Value};
+
+use crate::error::{GitAiError, Result};
+use crate::generate::{get_completion_texts, prompt_diff_text};
+use crate::git::Git;
+use crate::review;
+use crate::rpc;
+use crate::testing;
+use crate::{GitAi, GitAiOptions, RepoDiff};
+
+use super::{branch_ticket, pr_template, Context};
+
+/// `gitai rpc`, answers JSON-RPC on stdin and stdout for editor extensions until the
+/// client says exit
+///
+/// # Arguments
+///
+/// * `ctx` - The settings and flags
+pub async fn run(ctx: &Context) -> Result<()> {
+    // the editor starts one per workspace, so every request is about this repo
+    let git = Git::new(
+        ctx.local_repo.to_str().unwrap_or("."),
+        Some(&false),
+        None,
+        None,
+        None,
+        None,
+        None,
+        None,
+        None,
+    )
+    .with_diff_ignore(&ctx.diff_ignore)
+    .with_ai_ignore(&ctx.ai_ignore)
+    .with_redaction(ctx.redact);
+    let mut reader = io::stdin().lock();
+    let mut stdout = io::stdout();
+    while let Some(body) = rpc::read_message(&mut reader)? {
+        let request = match rpc::Request::parse(&body) {
+            Ok(request) => request,
+            Err(response) => {
+                rpc::write_message(&mut stdout, &response)?;
+                continue;
+            }
+        };
+        debug!("RPC {}", request.method);
+        let result: Result<Value> = match request.method.as_str() {
+            "exit" => break,
+            "initialize" => Ok(rpc::initialize_result()),
+            // nothing to clean up, the client sends exit next
+            "shutdown" => Ok(Value::Null),
+            "generateCommitMessage" => {
+                async {
+                    let repo = git.open_repository()?;
+                    let source = RepoDiff::staged(git, &repo)?
+                        .compress(ctx.compress)
+                        .token_budget(ctx.diff_budget);
+                    let options = GitAiOptions {
+                        num_tries: request
+                            .params
+                            .get("numTries")
+                            .and_then(Value::as_u64)
+                            .map_or(ctx.options.num_tries, |n| n.clamp(1, 5) as u8),
+                        ticket: branch_ticket(&git, &repo, &ctx.settings.tickets).await?,
+                        ..ctx.options.clone()
+                    };
+                    let client = ctx.client()?;
+                    let gitai = GitAi::new(Box::new(client), options);
+                    let messages = gitai.commit_messages(&source).await?;
+                    Ok(json!({ "messages": messages }))
+                }
+                .await
+            }
+            "generatePrDescription" => {
+                async {
+                    let repo = git.open_repository()?;
+                    let from = match request.str_param("from") {
+                        Some(from) => from.to_string(),
+                        None => git.current_branch(&repo).ok_or_else(|| {
+                            GitAiError::Usage(
+                                "Not on a branch, say which one with from".to_string(),
+                            )
+                        })?,
+                    };
+                    // where origin's default branch is, without asking the forge
+                    let to = request.str_param("to").unwrap_or("origin/HEAD");
+                    let source = RepoDiff::branches(git, &repo, &from, to)?
+                        .compress(ctx.compress)
+                        .token_budget(ctx.diff_budget);
+                    let options = GitAiOptions {
+                        test_commands: repo
+                            .workdir()
+                            .map(|root| testing::commands(root, &git.changed_paths(source.diff())))
+                            .unwrap_or_default(),
+                        pr_template: pr_template(ctx.use_pr_template, &repo),
+                        ..ctx.options.clone()
+                    };
+                    let client = ctx.client()?;
+                    let gitai = GitAi::new(Box::new(client), options);
+                    let candidates: Vec<Value> = gitai
+                        .pull_requests(&source)
+                        .await?
+                        .into_iter()
+                        .map(|(title, body)| json!({ "title": title, "body": body }))
+                        .collect();
+                    Ok(json!({ "candidates": candidates }))
+                }
+                .await
+            }
+            "review" => {
+                async {
+                    let repo = git.open_repository()?;
+                    // a range of commits, or what is staged
+                    let mut diffs = Vec::new();
+                    match request.str_param("range") {
+                        Some(range) => {
+                            for oid in git.commits_in_range(&repo, range)? {
+                                let commit = repo.find_commit(oid)?;
+                                if commit.parent_count() <= 1 {
+                                    diffs.push(git.get_commit_changes(&repo, &commit)?);
+                                }
+                            }
+                        }
+                        None => diffs.push(git.get_commit_diff(&repo)?),
+                    }
+                    let per_diff_budget = ctx.diff_budget / diffs.len().max(1);
+                    let mut files = Vec::new();
+                    let mut diff_text = String::new();
+                    for diff in &diffs {
+                        files.extend(git.diff_to_file_strings(diff)?);
+                        diff_text.push_str(&prompt_diff_text(
+                            &git,
+                            diff,
+                            ctx.compress,
+                            per_diff_budget,
+                        )?);
+                    }
+                    let findings: Vec<Value> = review::red_flags(&files)
+                        .into_iter()
+                        .map(|finding| {
+                            json!({
+                                "path": finding.path,
+                                "kind": finding.kind,
+                                "detail": finding.detail,
+                            })
+                        })
+                        .collect();
+                    let client = ctx.client()?;
+                    let answer =
+                        get_completion_texts(&client, review::prompt(&ctx.language, &diff_text), 1)
+                            .await?;
+                    let ai_findings = answer
+                        .first()
+                        .map(|answer| review::parse_answer(answer))
+                        .unwrap_or_default();
+                    Ok(json!({ "findings": findings, "aiFindings": ai_findings }))
+                }
+                .await
+            }
+            method => {
+                if let Some(id) = request.id {
+                    rpc::write_message(
+                        &mut stdout,
+                        &rpc::error_response(
+                            id,
+                            rpc::METHOD_NOT_FOUND,
+                            &format!("gitai doesn't know {}", method),
+                            None,
+                        ),
+                    )?;
+                }
+                continue;
+            }
+        };
+        // notifications don't get an answer
+        if let Some(id) = request.id {
+            let response = match result {
+                Ok(result) => rpc::response(id, result),
+                Err(e) => rpc::gitai_error_response(id, &e),
+            };
+            rpc::write_message(&mut stdout, &response)?;
+        }
+    }
+    Ok(())
+}
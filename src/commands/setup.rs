@@ -0,0 +1,126 @@
+use std::path::{Path, PathBuf};
+
+use crate::aliases;
+use crate::bugreport;
+use crate::cache;
+use crate::configure;
+use crate::doctor;
+use crate::error::{GitAiError, Result};
+use crate::preview;
+use crate::settings::{self, Settings};
+
+use super::prompt_yes_no;
+
+/// `gitai doctor`, every check with how to fix the ones that failed
+///
+/// # Arguments
+///
+/// * `repo_dir` - The repository
+pub async fn doctor(repo_dir: &Path) -> Result<()> {
+    let checks = doctor::run(repo_dir).await;
+    let color = preview::color();
+    for check in &checks {
+        println!("{}", check.line(color));
+    }
+    let failed = checks.iter().filter(|check| !check.passed).count();
+    if failed > 0 {
+        return Err(GitAiError::Refused(format!(
+            "{} of {} checks failed",
+            failed,
+            checks.len()
+        )));
+    }
+    Ok(())
+}
+
+/// `gitai cache clear`, throws every kept answer away
+pub fn clear_cache() -> Result<()> {
+    let (count, bytes) = cache::clear()?;
+    println!(
+        "Removed {} cached answer(s), {:.1} KB, from {}",
+        count,
+        bytes as f64 / 1024.0,
+        cache::cache_dir().display()
+    );
+    Ok(())
+}
+
+/// `gitai aliases install`
+///
+/// # Arguments
+///
+/// * `force` - Replace aliases by the same names that someone else set up
+pub fn install_aliases(force: bool) -> Result<()> {
+    let (installed, replaced) = aliases::install(force)?;
+    for alias in installed {
+        println!("{}", alias);
+    }
+    if !replaced.is_empty() {
+        println!("Replaced {}", replaced.join(", "));
+    }
+    Ok(())
+}
+
+/// `gitai aliases remove`
+pub fn remove_aliases() -> Result<()> {
+    match aliases::remove()?.as_slice() {
+        [] => println!("There were no gitai aliases to remove"),
+        removed => println!("Removed {}", removed.join(", ")),
+    }
+    Ok(())
+}
+
+/// A broken settings.json, with someone at the terminal to fix it.  It can be fixed in
+/// the editor or started over from the defaults, either way the broken file is still in
+/// settings.json.bak.
+///
+/// # Arguments
+///
+/// * `error` - What is wrong with it
+pub fn repair_settings(error: GitAiError) -> Result<Settings> {
+    eprintln!("{}\n", error);
+    if prompt_yes_no("Fix it in your editor now?")? {
+        configure::edit_in_editor()?;
+        return Settings::new();
+    }
+    if prompt_yes_no(format!(
+        "Start over with the default settings instead? The broken file stays in {}",
+        settings::settings_backup_path().display()
+    ))? {
+        let path = Settings::default().save()?;
+        println!("{} has the defaults now", path.display());
+        return Settings::new();
+    }
+    Err(GitAiError::Settings(config::ConfigError::Message(format!(
+        "{} was left as it is",
+        settings::settings_path().display()
+    ))))
+}
+
+/// `gitai bugreport`, nothing goes in the tarball without a yes
+pub fn bug_report(output: Option<PathBuf>) -> Result<()> {
+    let mut chosen = Vec::new();
+    for item in bugreport::items() {
+        if prompt_yes_no(format!("Include {} ({})?", item.name, item.description))? {
+            chosen.push(item);
+        }
+    }
+    if chosen.is_empty() {
+        println!("Nothing was chosen, so there is no bug report");
+        return Ok(());
+    }
+    let output = output.unwrap_or_else(|| {
+        let time = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        PathBuf::from(format!("gitai-bugreport-{}.tar", time))
+    });
+    bugreport::write_tar(&output, &chosen)?;
+    println!("Wrote {}, with:", output.display());
+    for item in &chosen {
+        println!("  {}", item.name);
+    }
+    println!("Have a look inside before attaching it to an issue");
+    Ok(())
+}
@@ -0,0 +1,114 @@
+use crate::error::{GitAiError, Result};
+use crate::generate::{get_completion_texts, prompt_diff_text};
+use crate::git::Git;
+use crate::gitmoji;
+use crate::hooks::{self, CommitMessage, HookPoint};
+use crate::journal::Details;
+use crate::squash::{self, SquashedCommit};
+
+use super::{choose_completion, head_oid, record_commit, report_redactions, Context};
+
+/// `gitai squash`, one commit message for everything on the branch since `base`
+///
+/// # Arguments
+///
+/// * `ctx` - The settings and flags
+/// * `base` - The branch the work started from
+/// * `apply` - Squash the branch into one commit with the message
+pub async fn run(ctx: &Context, base: &str, apply: bool) -> Result<()> {
+    let git = Git::new(
+        ctx.local_repo.to_str().unwrap_or("."),
+        None,
+        None,
+        Some(&ctx.gpg_sign_commits).filter(|sign| **sign),
+        Some(ctx.gpg_key_id.as_str()),
+        None,
+        None,
+        None,
+        None,
+    )
+    .with_diff_ignore(&ctx.diff_ignore)
+    .with_ai_ignore(&ctx.ai_ignore)
+    .with_redaction(ctx.redact);
+    let repo = git.open_repository()?;
+    let commits: Vec<SquashedCommit> = git
+        .commits_in_range(&repo, &format!("{}..HEAD", base))?
+        .into_iter()
+        .map(|oid| {
+            let commit = repo.find_commit(oid)?;
+            Ok(SquashedCommit {
+                id: oid.to_string()[..7].to_string(),
+                message: commit.message().unwrap_or("").to_string(),
+            })
+        })
+        .collect::<Result<_>>()?;
+    if commits.is_empty() {
+        return Err(GitAiError::Usage(format!(
+            "There are no commits on this branch since {}, nothing to squash",
+            base
+        )));
+    }
+    let diff = git.get_branch_diff(&repo, "HEAD", base)?;
+    report_redactions(&git, &diff)?;
+    let diff_text = prompt_diff_text(&git, &diff, ctx.compress, ctx.diff_budget)?;
+    let prompt = squash::prompt(ctx.options.build_commit_prompt(diff_text), &commits);
+    if ctx.dry_run {
+        return ctx.print_dry_run(prompt, ctx.num_tries);
+    }
+    let client = ctx.client()?;
+    let completions: Vec<String> = get_completion_texts(&client, prompt.clone(), ctx.num_tries)
+        .await?
+        .iter()
+        .map(|text| ctx.options.finish_message(text))
+        .collect();
+    if !apply {
+        println!("{}", completions.first().map_or("", String::as_str));
+        return Ok(());
+    }
+    let message = if ctx.auto_ai {
+        completions.first().cloned()
+    } else {
+        println!(
+            "Here is the message for the {} commit(s) since {}\n\n",
+            commits.len(),
+            base
+        );
+        let reroll = ctx.reroll(prompt, &ctx.options, "")?;
+        choose_completion(
+            &completions,
+            ctx.reroll_diff,
+            &git,
+            &repo,
+            false,
+            &ctx.options.commit_policy,
+            Some(&reroll),
+        )
+        .await?
+    };
+    let message = match message {
+        Some(message) => message,
+        None => {
+            println!("No commit message accepted, nothing was squashed");
+            return Ok(());
+        }
+    };
+    let message = hooks::run(HookPoint::PreCommit, CommitMessage { message })?.message;
+    if ctx.gitmoji {
+        gitmoji::check(&message)?;
+    }
+    let before = head_oid(&repo);
+    let commit_id = git.squash_commit(&repo, base, &message)?;
+    record_commit(
+        &git,
+        &repo,
+        before,
+        commit_id,
+        Details {
+            template: Some("squash".to_string()),
+            model: Some(ctx.model_name.clone()),
+            ..Details::message(&message)
+        },
+    );
+    println!("Squashed {} commit(s) into {}", commits.len(), commit_id);
+    Ok(())
+}
@@ -0,0 +1,38 @@
+use crate::error::{GitAiError, Result};
+use crate::git::Git;
+use crate::trailer;
+
+use super::Context;
+
+/// `gitai trailer decrypt`, prints the private trailer of a commit
+///
+/// # Arguments
+///
+/// * `ctx` - The settings and flags
+/// * `rev` - The commit
+pub fn decrypt(ctx: &Context, rev: &str) -> Result<()> {
+    let git = Git::new(
+        ctx.local_repo.to_str().unwrap_or("."),
+        None,
+        None,
+        None,
+        None,
+        None,
+        None,
+        None,
+        None,
+    );
+    let repo = git.open_repository()?;
+    let commit = repo.revparse_single(rev)?.peel_to_commit()?;
+    let value = trailer::find(commit.message().unwrap_or(""))
+        .ok_or_else(|| GitAiError::Usage(format!("{} has no private trailer", rev)))?;
+    println!(
+        "{}",
+        trailer::decrypt(
+            &ctx.settings.git_settings.git_options.private_trailer,
+            value
+        )?
+        .trim_end()
+    );
+    Ok(())
+}
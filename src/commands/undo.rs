@@ -0,0 +1,97 @@
+use crate::error::{GitAiError, Result};
+use crate::git::Git;
+use crate::hosting;
+use crate::journal::{self, ActionKind};
+use crate::settings::Provider;
+
+use super::{prompt_yes_no, Context};
+
+/// `gitai undo`, takes back the last thing gitai did in the repository
+///
+/// # Arguments
+///
+/// * `ctx` - The settings and flags
+/// * `yes` - `--yes`, don't ask first
+pub async fn run(ctx: &Context, yes: bool) -> Result<()> {
+    let git = Git::new(
+        ctx.local_repo.to_str().unwrap_or("."),
+        None,
+        None,
+        None,
+        None,
+        None,
+        None,
+        None,
+        None,
+    );
+    let repo = git.open_repository()?;
+    let entries = journal::read()?;
+    let action = journal::last_action(&entries, &journal::repo_key(&repo)).ok_or_else(|| {
+        GitAiError::Usage(
+            "gitai hasn't done anything in this repository that can be undone".to_string(),
+        )
+    })?;
+    let confirmed = |question: String| -> Result<bool> { Ok(yes || prompt_yes_no(question)?) };
+    match &action.what {
+        ActionKind::Commit { before, commit, .. } => {
+            let commit = git2::Oid::from_str(commit)?;
+            let before = match before {
+                Some(before) => git2::Oid::from_str(before)?,
+                None => {
+                    return Err(GitAiError::Refused(format!(
+                        "{} is the first commit, there is nothing to go back to.  git update-ref -d HEAD takes it back if you're sure",
+                        commit
+                    )))
+                }
+            };
+            let subject = repo
+                .find_commit(commit)
+                .map(|c| c.summary().unwrap_or_default().to_string())
+                .unwrap_or_default();
+            if !confirmed(format!(
+                "Take {} ({}) back off the branch?  Its changes stay staged",
+                commit, subject
+            ))? {
+                println!("Nothing was undone");
+                return Ok(());
+            }
+            git.undo_commit(&repo, commit, before)?;
+            println!("Took {} back, HEAD is {} again", commit, before);
+            if git.is_pushed(&repo, commit) {
+                println!("It was pushed already, git push --force-with-lease takes it off the remote too");
+            }
+        }
+        ActionKind::PullRequest {
+            provider,
+            remote,
+            number,
+            url,
+        } => {
+            if !confirmed(format!("Close {} without merging it?", url))? {
+                println!("Nothing was undone");
+                return Ok(());
+            }
+            let mut hosting_settings = ctx.hosting_settings.clone();
+            hosting_settings.provider = *provider;
+            hosting_settings.remote = remote.clone();
+            if *provider == Provider::Github && !ctx.github_token_from_cli {
+                if let Some(remote) = git.remote_location(&repo, remote) {
+                    hosting_settings.use_github_account_for(&remote.host, &remote.owner);
+                }
+            }
+            hosting::new_provider(&hosting_settings)
+                .close_pull_request(&repo, *number)
+                .await?;
+            println!("Closed {}", url);
+        }
+        // last_action skips them, text the AI wrote is nothing to take back
+        ActionKind::Generated { command } => {
+            return Err(GitAiError::Usage(format!(
+                "gitai {} only wrote text, there is nothing to undo",
+                command
+            )))
+        }
+    }
+    journal::mark_undone(&action.id)?;
+    Ok(())
+}
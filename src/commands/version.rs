@@ -0,0 +1,95 @@
+use log::warn;
+
+use crate::docs;
+use crate::error::Result;
+use crate::generate::{get_completion_texts, prompt_diff_text};
+use crate::version::{self, Version};
+
+use super::{prompt_yes_no, Context};
+
+/// `gitai version`, whether the next release is a major, minor or patch bump, going by
+/// the commits since the last tag and what the AI makes of them
+///
+/// # Arguments
+///
+/// * `ctx` - The settings and flags
+/// * `tag` - Tag HEAD with the next version
+pub async fn run(ctx: &Context, tag: bool) -> Result<()> {
+    let git = ctx.git();
+    let repo = git.open_repository()?;
+    let last_tag = git.last_tag(&repo)?;
+    let range = match &last_tag {
+        Some(last_tag) => format!("{}..HEAD", last_tag),
+        None => "HEAD".to_string(),
+    };
+    let mut messages = Vec::new();
+    for oid in git.commits_in_range(&repo, &range)? {
+        let commit = repo.find_commit(oid)?;
+        if commit.parent_count() < 2 {
+            messages.push(commit.message().unwrap_or("").to_string());
+        }
+    }
+    if messages.is_empty() {
+        println!(
+            "Nothing new since {}, no release to make",
+            last_tag.unwrap_or_default()
+        );
+        return Ok(());
+    }
+    // without a tag everything is new, there is no API to break yet.  Only what the
+    // AI may see is looked at, the declarations found go into the prompt
+    let api_changes = match &last_tag {
+        Some(last_tag) => {
+            let diff = git.get_range_diff(&repo, last_tag, "HEAD")?;
+            docs::public_api_changes(&prompt_diff_text(&git, &diff, false, usize::MAX)?)
+        }
+        None => Vec::new(),
+    };
+    let current = last_tag
+        .as_deref()
+        .and_then(Version::parse)
+        .unwrap_or_else(|| Version {
+            prefix: "v".to_string(),
+            major: 0,
+            minor: 0,
+            patch: 0,
+        });
+    let (mut bump, reasons) = version::heuristic_bump(&messages, &api_changes);
+    let prompt = version::prompt(&ctx.language, &messages, &api_changes);
+    if ctx.dry_run {
+        return ctx.print_dry_run(prompt, 1);
+    }
+    match &last_tag {
+        Some(last_tag) => println!("{} commit(s) since {}", messages.len(), last_tag),
+        None => println!("No release tagged yet, {} commit(s)", messages.len()),
+    }
+    if reasons.is_empty() {
+        println!("The commits say {}", bump);
+    } else {
+        println!("The commits say {} ({})", bump, reasons.join(", "));
+    }
+    let client = ctx.client()?;
+    let answer = get_completion_texts(&client, prompt, 1).await?;
+    let mut why = reasons.join(", ");
+    match answer
+        .first()
+        .and_then(|answer| version::parse_answer(answer))
+    {
+        Some((ai_bump, reason)) => {
+            println!("The AI says {} ({})", ai_bump, reason);
+            bump = bump.max(ai_bump);
+            if !reason.is_empty() {
+                why = reason;
+            }
+        }
+        None => warn!("The AI didn't answer with a bump, going by the commits"),
+    }
+    let next = current.bump(bump);
+    println!("\nNext release: {} ({} bump)", next, bump);
+    if tag && (ctx.auto_ai || prompt_yes_no(format!("Tag HEAD as {}?", next))?) {
+        let message = format!("Release {}\n\n{}", next, why);
+        git.create_tag(&repo, &next.to_string(), message.trim())?;
+        println!("Tagged HEAD as {}", next);
+    }
+    Ok(())
+}
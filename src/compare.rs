@@ -0,0 +1,154 @@
+use crossterm::style::{Color, ResetColor, SetForegroundColor};
+
+use crate::error::Result;
+use crate::policy;
+use crate::settings::CommitPolicy;
+
+/// The subject length git tools start cutting off at, when the policy doesn't set one
+pub const SUBJECT_LIMIT: usize = 72;
+
+/// Narrower than this and a candidate gets a row to itself
+const MIN_CARD_WIDTH: usize = 36;
+
+/// The space between cards side by side
+const GAP: &str = "  ";
+
+/// One line of a card, and whether it is bad news
+struct CardLine {
+    text: String,
+    warn: bool,
+}
+
+/// `text` broken into lines of at most `width` characters, on spaces where it can be
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            let mut word = word.to_string();
+            // a word too long for a whole line is split wherever
+            while word.chars().count() > width {
+                if !line.is_empty() {
+                    lines.push(std::mem::take(&mut line));
+                }
+                let rest = word.chars().skip(width).collect();
+                lines.push(word.chars().take(width).collect());
+                word = rest;
+            }
+            if line.is_empty() {
+                line = word;
+            } else if line.chars().count() + 1 + word.chars().count() <= width {
+                line.push(' ');
+                line.push_str(&word);
+            } else {
+                lines.push(std::mem::replace(&mut line, word));
+            }
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+/// A candidate as a card: its number and subject length, the message, then what the
+/// policy thinks of it
+fn card(i: usize, text: &str, policy: &CommitPolicy, width: usize) -> Result<Vec<CardLine>> {
+    let inner = width - 4;
+    let limit = match policy.max_subject_length {
+        0 => SUBJECT_LIMIT,
+        limit => limit,
+    };
+    let length = text.lines().next().unwrap_or("").trim().chars().count();
+    let mut lines = vec![CardLine {
+        text: format!("#{}  subject {}/{}", i + 1, length, limit),
+        warn: length > limit,
+    }];
+    lines.extend(
+        wrap(text.trim(), inner)
+            .into_iter()
+            .map(|text| CardLine { text, warn: false }),
+    );
+    let problems = policy::violations(policy, text)?;
+    lines.push(CardLine {
+        text: String::new(),
+        warn: false,
+    });
+    if problems.is_empty() {
+        lines.push(CardLine {
+            text: "ok: follows the commit policy".to_string(),
+            warn: false,
+        });
+    }
+    for problem in problems {
+        lines.extend(
+            wrap(&format!("x {}", problem), inner)
+                .into_iter()
+                .map(|text| CardLine { text, warn: true }),
+        );
+    }
+    Ok(lines)
+}
+
+/// The candidates next to each other in numbered boxes, as many to a row as fit in
+/// `width`, each with its subject length against the limit and whatever the commit
+/// policy has against it
+///
+/// # Arguments
+///
+/// * `candidates` - The messages
+/// * `policy` - The commit policy, for the subject limit and the lint
+/// * `width` - How wide the terminal is
+/// * `colored` - Color the bad news red
+pub fn render(
+    candidates: &[String],
+    policy: &CommitPolicy,
+    width: usize,
+    colored: bool,
+) -> Result<String> {
+    let per_row = (width / (MIN_CARD_WIDTH + GAP.len())).clamp(1, candidates.len().max(1));
+    let card_width = ((width - GAP.len() * (per_row - 1)) / per_row).max(MIN_CARD_WIDTH);
+    let mut out = String::new();
+    let cards = candidates
+        .iter()
+        .enumerate()
+        .map(|(i, text)| card(i, text, policy, card_width))
+        .collect::<Result<Vec<_>>>()?;
+    for (row, row_cards) in cards.chunks(per_row).enumerate() {
+        if row > 0 {
+            out.push('\n');
+        }
+        let height = row_cards.iter().map(Vec::len).max().unwrap_or(0);
+        let border = |left: &str, right: &str| {
+            let line = format!("{}{}{}", left, "─".repeat(card_width - 2), right);
+            vec![line; row_cards.len()].join(GAP)
+        };
+        out.push_str(&border("┌", "┐"));
+        out.push('\n');
+        for l in 0..height {
+            let cells: Vec<String> = row_cards
+                .iter()
+                .map(|lines| {
+                    let (text, warn) = match lines.get(l) {
+                        Some(line) => (line.text.as_str(), line.warn),
+                        None => ("", false),
+                    };
+                    let padded = format!("{:<width$}", text, width = card_width - 4);
+                    if warn && colored {
+                        format!(
+                            "│ {}{}{} │",
+                            SetForegroundColor(Color::Red),
+                            padded,
+                            ResetColor
+                        )
+                    } else {
+                        format!("│ {} │", padded)
+                    }
+                })
+                .collect();
+            out.push_str(&cells.join(GAP));
+            out.push('\n');
+        }
+        out.push_str(&border("└", "┘"));
+        out.push('\n');
+    }
+    Ok(out)
+}
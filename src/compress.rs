@@ -0,0 +1,153 @@
+use std::sync::OnceLock;
+
+use log::debug;
+use regex::Regex;
+
+/// Runs of literal looking lines longer than this get abbreviated
+const LITERAL_RUN: usize = 8;
+/// How many lines of a long literal run we keep at the start
+const LITERAL_HEAD: usize = 2;
+
+/// A content line from `Git::diff_to_string`, the origin (`+`, `-` or ` `), the old
+/// line number and the actual line
+fn content_line() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r"^([+\- ])(\d+) (.*)$").expect("Bad content line regex"))
+}
+
+/// Lines that only bring things into scope
+fn import_line() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| {
+        Regex::new(
+            r#"^\s*(pub\s+)?(use\s|import\s|from\s+\S+\s+import\s|#include\s|extern crate\s|require\(|const\s+\w+\s*=\s*require\()"#,
+        )
+        .expect("Bad import regex")
+    })
+}
+
+/// Lines that are just data, strings, numbers, arrays and blobs of base64
+fn literal_line() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| {
+        Regex::new(r#"^\s*(["'`]|-?\d|0x|\[|\{|\}|\]|[A-Za-z0-9+/=_-]{40,}\s*,?\s*$)"#)
+            .expect("Bad literal regex")
+    })
+}
+
+/// Is the line a comment in one of the usual languages, `#[` and `#include` are not
+fn is_comment(content: &str) -> bool {
+    let trimmed = content.trim_start();
+    trimmed.starts_with("//")
+        || trimmed.starts_with("/*")
+        || trimmed.starts_with("*/")
+        || trimmed == "*"
+        || trimmed.starts_with("* ")
+        || trimmed.starts_with("<!--")
+        || trimmed == "#"
+        || trimmed.starts_with("# ")
+        || trimmed.starts_with("#!")
+        || trimmed.starts_with("-- ")
+}
+
+/// Makes the diff we *show the AI* smaller, it never touches what gets committed.
+///
+/// * Comment lines are dropped
+/// * Hunks that only change imports are collapsed into a single line
+/// * Long runs of literal data (strings, numbers, arrays) are abbreviated
+///
+/// # Arguments
+///
+/// * `diff` - The diff text from `Git::diff_to_string`
+pub fn compress_diff(diff: &str) -> String {
+    let mut out: Vec<String> = Vec::new();
+    let mut hunk: Vec<(char, &str, &str)> = Vec::new();
+    for line in diff.lines() {
+        match content_line().captures(line) {
+            Some(caps) if !line.starts_with("@@") => {
+                let origin = caps[1].chars().next().unwrap_or(' ');
+                hunk.push((
+                    origin,
+                    caps.get(2).map_or("", |m| m.as_str()),
+                    caps.get(3).map_or("", |m| m.as_str()),
+                ));
+            }
+            _ => {
+                flush_hunk(&mut hunk, &mut out);
+                out.push(line.to_string());
+            }
+        }
+    }
+    flush_hunk(&mut hunk, &mut out);
+    let compressed = out.join("\n");
+    debug!(
+        "Compressed the diff from {} to {} chars",
+        diff.chars().count(),
+        compressed.chars().count()
+    );
+    compressed
+}
+
+/// Compresses one hunk worth of content lines into `out`
+fn flush_hunk(hunk: &mut Vec<(char, &str, &str)>, out: &mut Vec<String>) {
+    if hunk.is_empty() {
+        return;
+    }
+    let changed: Vec<&(char, &str, &str)> = hunk
+        .iter()
+        .filter(|(origin, _, content)| *origin != ' ' && !content.trim().is_empty())
+        .collect();
+    if !changed.is_empty()
+        && changed
+            .iter()
+            .all(|(_, _, content)| import_line().is_match(content))
+    {
+        let added = changed
+            .iter()
+            .filter(|(origin, _, _)| *origin == '+')
+            .count();
+        out.push(format!(
+            "~ imports changed ({} added, {} removed)",
+            added,
+            changed.len() - added
+        ));
+        hunk.clear();
+        return;
+    }
+
+    let kept: Vec<&(char, &str, &str)> = hunk
+        .iter()
+        .filter(|(_, _, content)| !is_comment(content))
+        .collect();
+    let mut i = 0;
+    while i < kept.len() {
+        let (origin, _, content) = kept[i];
+        let mut run = i;
+        if literal_line().is_match(content) {
+            while run + 1 < kept.len()
+                && kept[run + 1].0 == *origin
+                && literal_line().is_match(kept[run + 1].2)
+            {
+                run += 1;
+            }
+        }
+        if run - i + 1 > LITERAL_RUN {
+            for (o, num, c) in &kept[i..i + LITERAL_HEAD] {
+                out.push(format!("{}{} {}", o, num, c));
+            }
+            out.push(format!(
+                "{}... {} similar lines omitted",
+                origin,
+                run - i - LITERAL_HEAD
+            ));
+            let (o, num, c) = kept[run];
+            out.push(format!("{}{} {}", o, num, c));
+            i = run + 1;
+        } else {
+            let (o, num, c) = kept[i];
+            out.push(format!("{}{} {}", o, num, c));
+            i += 1;
+        }
+    }
+    hunk.clear();
+}
@@ -0,0 +1,596 @@
+use std::{
+    env, fs,
+    io::{self, Write},
+};
+
+use crossterm::{
+    cursor,
+    style::Attribute,
+    terminal::{Clear, ClearType},
+};
+use log::debug;
+use serde_json::Value;
+
+use crate::error::{GitAiError, Result};
+use crate::settings::{settings_path, Settings};
+use crate::terminal::{self, Key, Screen};
+
+/// The tabs of the form
+const GROUPS: [&str; 4] = ["AI", "Git", "Prompts", "Policies"];
+
+/// What a setting holds, which decides how it is edited and checked
+enum Kind {
+    /// Any text
+    Text,
+    /// An http(s) url
+    Url,
+    /// A key or token, never shown
+    Secret,
+    /// On or off, enter flips it
+    Flag,
+    /// A whole number between the two
+    Whole(u64, u64),
+    /// Dollars, 0 or more
+    Price,
+    /// One of these, enter moves to the next one
+    Choice(&'static [&'static str]),
+}
+
+/// One setting in the form
+struct Field {
+    /// Index into `GROUPS`
+    group: usize,
+    /// Where it is in settings.json, as a JSON pointer
+    pointer: &'static str,
+    label: &'static str,
+    help: &'static str,
+    kind: Kind,
+}
+
+const FIELDS: &[Field] = &[
+    Field {
+        group: 0,
+        pointer: "/ai_settings/api_url",
+        label: "Api url",
+        help: "Where OpenAI is, https://api.openai.com/v1/ (the trailing / matters), or your Azure resource",
+        kind: Kind::Url,
+    },
+    Field {
+        group: 0,
+        pointer: "/ai_settings/api_key",
+        label: "Api key",
+        help: "The OpenAI (or Azure OpenAI) key, gitai auth set openai keeps it in the keyring instead",
+        kind: Kind::Secret,
+    },
+    Field {
+        group: 0,
+        pointer: "/ai_settings/ai_options/model",
+        label: "Model",
+        help: "The model messages are written with",
+        kind: Kind::Text,
+    },
+    Field {
+        group: 0,
+        pointer: "/ai_settings/ai_options/fallback_model",
+        label: "Fallback model",
+        help: "A stronger model for the last try after vague messages, empty uses the same one",
+        kind: Kind::Text,
+    },
+    Field {
+        group: 0,
+        pointer: "/ai_settings/ai_options/n",
+        label: "Messages",
+        help: "How many messages to ask for each time (1 to 5)",
+        kind: Kind::Whole(1, 5),
+    },
+    Field {
+        group: 0,
+        pointer: "/ai_settings/ai_options/context_window",
+        label: "Context window",
+        help: "The model's context length in tokens, bigger diffs are trimmed to fit",
+        kind: Kind::Whole(256, 2_000_000),
+    },
+    Field {
+        group: 0,
+        pointer: "/ai_settings/ai_options/price_per_1k_tokens",
+        label: "Price per 1k tokens",
+        help: "What 1000 tokens cost in dollars for --dry-run estimates, 0 uses the list price",
+        kind: Kind::Price,
+    },
+    Field {
+        group: 0,
+        pointer: "/ai_settings/ai_options/reroll_diff",
+        label: "Reroll diff",
+        help: "Show each option after the first as a word diff against the one before it",
+        kind: Kind::Flag,
+    },
+    Field {
+        group: 0,
+        pointer: "/ai_settings/ai_options/intent_check",
+        label: "Intent check",
+        help: "Check a commit isn't several changes in one before writing its message",
+        kind: Kind::Flag,
+    },
+    Field {
+        group: 0,
+        pointer: "/ai_settings/ai_options/mixed_intent_threshold",
+        label: "Mixed intent %",
+        help: "How much of a commit can be about something else before it counts as mixed, 0 never warns",
+        kind: Kind::Whole(0, 100),
+    },
+    Field {
+        group: 0,
+        pointer: "/ai_settings/azure/enabled",
+        label: "Use Azure",
+        help: "Talk to an Azure OpenAI resource instead of OpenAI",
+        kind: Kind::Flag,
+    },
+    Field {
+        group: 0,
+        pointer: "/ai_settings/azure/deployment",
+        label: "Azure deployment",
+        help: "The name of the deployment in your Azure resource",
+        kind: Kind::Text,
+    },
+    Field {
+        group: 0,
+        pointer: "/ai_settings/timeout_seconds",
+        label: "AI timeout (s)",
+        help: "How long a request to the AI may take, 0 waits as long as it takes",
+        kind: Kind::Whole(0, 3600),
+    },
+    Field {
+        group: 1,
+        pointer: "/git_settings/provider",
+        label: "Provider",
+        help: "Where pull requests are opened",
+        kind: Kind::Choice(&["github", "gitlab"]),
+    },
+    Field {
+        group: 1,
+        pointer: "/git_settings/github_api_url",
+        label: "GitHub api url",
+        help: "https://api.github.com, or https://<host>/api/v3 for GitHub Enterprise",
+        kind: Kind::Url,
+    },
+    Field {
+        group: 1,
+        pointer: "/git_settings/github_ca_cert",
+        label: "GitHub CA certificate",
+        help: "A PEM file with your GitHub Enterprise server's CA, empty for the ones the system trusts",
+        kind: Kind::Text,
+    },
+    Field {
+        group: 1,
+        pointer: "/git_settings/github_api_key",
+        label: "GitHub token",
+        help: "Only needed for pull requests, gitai auth set github keeps it in the keyring instead",
+        kind: Kind::Secret,
+    },
+    Field {
+        group: 1,
+        pointer: "/git_settings/gitlab_api_url",
+        label: "GitLab api url",
+        help: "https://gitlab.com/api/v4, or your own instance's",
+        kind: Kind::Url,
+    },
+    Field {
+        group: 1,
+        pointer: "/git_settings/gitlab_api_key",
+        label: "GitLab token",
+        help: "Only needed for merge requests, gitai auth set gitlab keeps it in the keyring instead",
+        kind: Kind::Secret,
+    },
+    Field {
+        group: 1,
+        pointer: "/git_settings/timeout_seconds",
+        label: "Timeout (s)",
+        help: "How long a request to GitHub, GitLab or a tracker may take, 0 waits as long as it takes",
+        kind: Kind::Whole(0, 3600),
+    },
+    Field {
+        group: 1,
+        pointer: "/git_settings/git_options/auto_add",
+        label: "Auto add",
+        help: "Stage everything before committing, like git add .",
+        kind: Kind::Flag,
+    },
+    Field {
+        group: 1,
+        pointer: "/git_settings/git_options/auto_push",
+        label: "Auto push",
+        help: "Push the branch before opening a pull request",
+        kind: Kind::Flag,
+    },
+    Field {
+        group: 1,
+        pointer: "/git_settings/git_options/sign_commits",
+        label: "Sign commits",
+        help: "GPG sign the commits gitai makes",
+        kind: Kind::Flag,
+    },
+    Field {
+        group: 1,
+        pointer: "/git_settings/git_options/split_threshold",
+        label: "Split threshold",
+        help: "Offer one commit per directory when more files than this are staged",
+        kind: Kind::Whole(1, 100_000),
+    },
+    Field {
+        group: 2,
+        pointer: "/ai_settings/ai_options/prompt/language",
+        label: "Language",
+        help: "The language you mostly write, the prompt says you are an expert in it",
+        kind: Kind::Text,
+    },
+    Field {
+        group: 2,
+        pointer: "/ai_settings/ai_options/prompt_pack",
+        label: "Prompt pack",
+        help: "A pack from gitai prompts sync, empty for the built in prompts",
+        kind: Kind::Text,
+    },
+    Field {
+        group: 2,
+        pointer: "/ai_settings/ai_options/conventional_commits",
+        label: "Conventional Commits",
+        help: "Write feat(scope): summary style messages",
+        kind: Kind::Flag,
+    },
+    Field {
+        group: 2,
+        pointer: "/ai_settings/ai_options/gitmoji",
+        label: "Gitmoji",
+        help: "Start subjects with an emoji from gitmoji.dev, not with Conventional Commits",
+        kind: Kind::Flag,
+    },
+    Field {
+        group: 2,
+        pointer: "/ai_settings/ai_options/message_style",
+        label: "Message style",
+        help: "Subject only, subject and body, or a bulleted body wrapped at 72 columns",
+        kind: Kind::Choice(&["paragraph", "subject-only", "subject+body", "bulleted-body"]),
+    },
+    Field {
+        group: 2,
+        pointer: "/ai_settings/ai_options/message_locale",
+        label: "Message language",
+        help: "A locale (ja, de_DE) or language to write messages in, empty for English",
+        kind: Kind::Text,
+    },
+    Field {
+        group: 2,
+        pointer: "/ai_settings/ai_options/stochastic",
+        label: "Stochastic",
+        help: "Pick a different prompt each time for more varied messages",
+        kind: Kind::Flag,
+    },
+    Field {
+        group: 2,
+        pointer: "/ai_settings/ai_options/compress_prompt",
+        label: "Compress the diff",
+        help: "Drop comments and collapse import-only hunks so big diffs fit",
+        kind: Kind::Flag,
+    },
+    Field {
+        group: 2,
+        pointer: "/ai_settings/ai_options/doc_drift_check",
+        label: "Doc check",
+        help: "Ask the AI whether a change means the docs need updating",
+        kind: Kind::Flag,
+    },
+    Field {
+        group: 2,
+        pointer: "/ai_settings/ai_options/quality_retries",
+        label: "Quality retries",
+        help: "Rounds of vague messages before the stricter fallback prompt, 0 turns the check off",
+        kind: Kind::Whole(0, 10),
+    },
+    Field {
+        group: 2,
+        pointer: "/git_settings/git_options/pr_diagram",
+        label: "PR diagram",
+        help: "Add a mermaid diagram of the changed files to pull requests",
+        kind: Kind::Flag,
+    },
+    Field {
+        group: 3,
+        pointer: "/ai_settings/ai_options/auto_ai",
+        label: "Auto AI",
+        help: "Use the first message without asking, nobody reviews it",
+        kind: Kind::Flag,
+    },
+    Field {
+        group: 3,
+        pointer: "/git_settings/git_options/allow_auto_ai_push",
+        label: "Allow unattended",
+        help: "Let auto AI and auto push run together without --yes",
+        kind: Kind::Flag,
+    },
+    Field {
+        group: 3,
+        pointer: "/git_settings/git_options/affected_packages",
+        label: "Affected packages",
+        help: "In Cargo/npm workspaces, list the packages a change affects",
+        kind: Kind::Flag,
+    },
+];
+
+/// Opens the settings file in `$VISUAL` (or `$EDITOR`, or vi, notepad on Windows) and checks it afterwards
+pub fn edit_in_editor() -> Result<()> {
+    let path = settings_path();
+    if !path.exists() {
+        Settings::default().save()?;
+    }
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| terminal::DEFAULT_EDITOR.to_string());
+    debug!("Opening {} with {}", path.display(), editor);
+    let status = terminal::editor_command(&editor, &path).status()?;
+    if !status.success() {
+        return Err(GitAiError::Usage(format!(
+            "{} exited with {}",
+            editor, status
+        )));
+    }
+    serde_json::from_str::<Settings>(&fs::read_to_string(&path)?).map_err(|e| {
+        GitAiError::Settings(config::ConfigError::Message(format!(
+            "{} is not valid anymore ({}), run gitai config edit again to fix it",
+            path.display(),
+            e
+        )))
+    })?;
+    println!("{} looks good", path.display());
+    Ok(())
+}
+
+/// The form's state
+struct Form {
+    value: Value,
+    group: usize,
+    selected: usize,
+    editing: Option<String>,
+    message: String,
+    dirty: bool,
+    confirm_quit: bool,
+}
+
+impl Form {
+    /// The fields on the current tab
+    fn fields(&self) -> Vec<&'static Field> {
+        FIELDS.iter().filter(|f| f.group == self.group).collect()
+    }
+
+    fn field(&self) -> &'static Field {
+        self.fields()[self.selected]
+    }
+
+    fn get(&self, field: &Field) -> &Value {
+        self.value.pointer(field.pointer).unwrap_or(&Value::Null)
+    }
+
+    fn set(&mut self, field: &Field, value: Value) {
+        if let Some(slot) = self.value.pointer_mut(field.pointer) {
+            if *slot != value {
+                *slot = value;
+                self.dirty = true;
+            }
+        }
+    }
+
+    /// What the field shows when it isn't being edited
+    fn display(&self, field: &Field) -> String {
+        match (&field.kind, self.get(field)) {
+            (Kind::Secret, Value::String(s)) if s.is_empty() => "(not set)".to_string(),
+            (Kind::Secret, _) => "********".to_string(),
+            (_, Value::Bool(true)) => "yes".to_string(),
+            (_, Value::Bool(false)) => "no".to_string(),
+            (_, Value::String(s)) => s.to_string(),
+            (_, value) => value.to_string(),
+        }
+    }
+
+    /// Enter on the selected field, flags and choices change right away
+    fn start_edit(&mut self) {
+        let field = self.field();
+        match &field.kind {
+            Kind::Flag => {
+                let on = self.get(field).as_bool().unwrap_or(false);
+                self.set(field, Value::Bool(!on));
+            }
+            Kind::Choice(choices) => {
+                let current = self.get(field).as_str().unwrap_or_default();
+                let next = choices
+                    .iter()
+                    .position(|c| *c == current)
+                    .map(|i| (i + 1) % choices.len())
+                    .unwrap_or(0);
+                self.set(field, Value::String(choices[next].to_string()));
+            }
+            // typing replaces a secret, there is nothing to show
+            Kind::Secret => self.editing = Some(String::new()),
+            _ => self.editing = Some(self.display(field)),
+        }
+    }
+
+    /// Checks what was typed, it only goes in if it is good
+    fn finish_edit(&mut self, text: String) {
+        let field = self.field();
+        let trimmed = text.trim();
+        let value = match &field.kind {
+            Kind::Secret if trimmed.is_empty() => {
+                self.message = "Kept the current one".to_string();
+                return;
+            }
+            Kind::Url => match url::Url::parse(trimmed) {
+                Ok(url) if url.scheme() == "http" || url.scheme() == "https" => {
+                    Ok(Value::String(trimmed.to_string()))
+                }
+                _ => Err("That isn't an http(s) url".to_string()),
+            },
+            Kind::Whole(min, max) => match trimmed.parse::<u64>() {
+                Ok(n) if n >= *min && n <= *max => Ok(Value::from(n)),
+                _ => Err(format!(
+                    "That has to be a whole number from {} to {}",
+                    min, max
+                )),
+            },
+            Kind::Price => match trimmed.parse::<f64>() {
+                Ok(price) if price >= 0.0 && price.is_finite() => Ok(Value::from(price)),
+                _ => Err("That has to be a number of dollars, 0 or more".to_string()),
+            },
+            _ => Ok(Value::String(trimmed.to_string())),
+        };
+        match value {
+            Ok(value) => {
+                self.set(field, value);
+                self.message.clear();
+            }
+            // stay in the field so it can be fixed
+            Err(e) => {
+                self.message = e;
+                self.editing = Some(text);
+            }
+        }
+    }
+
+    fn save(&mut self) {
+        match serde_json::from_value::<Settings>(self.value.clone())
+            .map_err(GitAiError::from)
+            .and_then(|settings| settings.save())
+        {
+            Ok(path) => {
+                self.dirty = false;
+                self.message = format!("Saved to {}", path.display());
+            }
+            Err(e) => self.message = format!("Not saved: {}", e),
+        }
+    }
+
+    /// Handles a key, `false` when it is time to go
+    fn key(&mut self, key: Key) -> bool {
+        if let Some(mut text) = self.editing.take() {
+            match key {
+                Key::Char('\n') => self.finish_edit(text),
+                Key::Esc => self.message.clear(),
+                Key::Backspace => {
+                    text.pop();
+                    self.editing = Some(text);
+                }
+                Key::Char(c) if !c.is_control() => {
+                    text.push(c);
+                    self.editing = Some(text);
+                }
+                _ => self.editing = Some(text),
+            }
+            return true;
+        }
+        let quitting = matches!(key, Key::Char('q') | Key::Esc | Key::Ctrl('c'));
+        match key {
+            Key::Up | Key::Char('k') => self.selected = self.selected.saturating_sub(1),
+            Key::Down | Key::Char('j') => {
+                self.selected = (self.selected + 1).min(self.fields().len() - 1)
+            }
+            Key::Left | Key::BackTab | Key::Char('h') => {
+                self.group = (self.group + GROUPS.len() - 1) % GROUPS.len();
+                self.selected = 0;
+            }
+            Key::Right | Key::Char('\t') | Key::Char('l') => {
+                self.group = (self.group + 1) % GROUPS.len();
+                self.selected = 0;
+            }
+            Key::Char('\n') | Key::Char(' ') => self.start_edit(),
+            Key::Char('s') => self.save(),
+            _ if quitting && (!self.dirty || self.confirm_quit) => return false,
+            _ if quitting => {
+                self.message =
+                    "There are unsaved changes, s saves them, q again throws them away".to_string()
+            }
+            _ => (),
+        }
+        self.confirm_quit = quitting;
+        true
+    }
+
+    fn draw(&self, out: &mut impl Write) -> io::Result<()> {
+        write!(out, "{}{}", Clear(ClearType::All), cursor::MoveTo(0, 0))?;
+        write!(
+            out,
+            "{}gitai settings{} {}{}\r\n\r\n",
+            Attribute::Bold,
+            Attribute::Reset,
+            settings_path().display(),
+            if self.dirty { " (modified)" } else { "" }
+        )?;
+        for (i, group) in GROUPS.iter().enumerate() {
+            if i == self.group {
+                write!(out, " {}{} {}", Attribute::Reverse, group, Attribute::Reset)?;
+            } else {
+                write!(out, "  {} ", group)?;
+            }
+        }
+        write!(out, "\r\n\r\n")?;
+        for (i, field) in self.fields().into_iter().enumerate() {
+            let value = match (&self.editing, &field.kind) {
+                (Some(text), Kind::Secret) if i == self.selected => "*".repeat(text.len()) + "_",
+                (Some(text), _) if i == self.selected => format!("{}_", text),
+                _ => self.display(field),
+            };
+            if i == self.selected {
+                write!(
+                    out,
+                    " {}> {:<22}{} {}\r\n",
+                    Attribute::Bold,
+                    field.label,
+                    Attribute::Reset,
+                    value
+                )?;
+            } else {
+                write!(out, "   {:<22} {}\r\n", field.label, value)?;
+            }
+        }
+        write!(out, "\r\n {}\r\n", self.field().help)?;
+        if !self.message.is_empty() {
+            write!(
+                out,
+                "\r\n {}{}{}\r\n",
+                Attribute::Bold,
+                self.message,
+                Attribute::Reset
+            )?;
+        }
+        let keys = if self.editing.is_some() {
+            "enter keep  esc cancel"
+        } else {
+            "←/→ group  ↑/↓ setting  enter change  s save  q quit"
+        };
+        write!(out, "\r\n {}{}{}", Attribute::Dim, keys, Attribute::Reset)?;
+        out.flush()
+    }
+}
+
+/// `gitai config edit --tui`, the settings as a form grouped into tabs, every value
+/// checked as it is typed in and written back to settings.json on `s`
+pub fn edit_tui() -> Result<()> {
+    if !terminal::is_tty(&io::stdout()) {
+        return Err(GitAiError::Usage(
+            "gitai config edit --tui has to be run in a terminal".to_string(),
+        ));
+    }
+    let mut form = Form {
+        // only the file, or saving would write tokens from the environment into it
+        value: serde_json::to_value(Settings::from_file()?)?,
+        group: 0,
+        selected: 0,
+        editing: None,
+        message: String::new(),
+        dirty: false,
+        confirm_quit: false,
+    };
+    let mut screen = Screen::enter()?;
+    form.draw(&mut screen)?;
+    while form.key(screen.read_key()?) {
+        form.draw(&mut screen)?;
+    }
+    Ok(())
+}
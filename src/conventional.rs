@@ -0,0 +1,204 @@
+use std::sync::OnceLock;
+
+use regex::Regex;
+
+use crate::settings::AiPrompt;
+
+/// The types from the Conventional Commits spec and the Angular convention it came from
+pub const TYPES: [&str; 11] = [
+    "feat", "fix", "docs", "style", "refactor", "perf", "test", "build", "ci", "chore", "revert",
+];
+
+/// A commit message that follows https://www.conventionalcommits.org
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConventionalCommit {
+    /// `feat`, `fix`, ...
+    pub kind: String,
+    /// The optional `(scope)`
+    pub scope: Option<String>,
+    /// `!` after the type/scope, or a `BREAKING CHANGE:` footer
+    pub breaking: bool,
+    /// Everything after `: ` on the first line
+    pub description: String,
+    /// Everything after the blank line
+    pub body: Option<String>,
+}
+
+/// A header exactly as the spec wants it, `type(scope)!: description`
+fn header() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| {
+        Regex::new(r"^([a-z]+)(\(([\w\-./ ]+)\))?(!)?: (\S.*)$").expect("Bad header regex")
+    })
+}
+
+/// A header the way the AI likes to get it almost right, `Feat (scope) : description`
+fn loose_header() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| {
+        Regex::new(r"^([A-Za-z]+)\s*(\(\s*([^)]*?)\s*\))?\s*(!)?\s*:\s*(\S.*)$")
+            .expect("Bad loose header regex")
+    })
+}
+
+/// Parses a message, the error says what is wrong with it
+///
+/// # Arguments
+///
+/// * `message` - The commit message
+pub fn parse(message: &str) -> Result<ConventionalCommit, String> {
+    let mut lines = message.lines();
+    let first = lines.next().unwrap_or("");
+    let caps = header()
+        .captures(first)
+        .ok_or_else(|| format!("'{}' is not a `type(scope): description` header", first))?;
+    let kind = caps[1].to_string();
+    if !TYPES.contains(&kind.as_str()) {
+        return Err(format!("'{}' is not a Conventional Commits type", kind));
+    }
+    let rest: Vec<&str> = lines.collect();
+    if let Some(second) = rest.first() {
+        if !second.trim().is_empty() {
+            return Err("the body has to be separated from the header by a blank line".to_string());
+        }
+    }
+    let body = rest.join("\n").trim().to_string();
+    Ok(ConventionalCommit {
+        kind,
+        scope: caps.get(3).map(|m| m.as_str().to_string()),
+        breaking: caps.get(4).is_some() || body.contains("BREAKING CHANGE:"),
+        description: caps[5].trim().to_string(),
+        body: Some(body).filter(|b| !b.is_empty()),
+    })
+}
+
+/// The type names people (and AIs) use instead of the real ones
+fn normalize_type(kind: &str) -> Option<&'static str> {
+    let kind = kind.to_lowercase();
+    let kind = match kind.as_str() {
+        "feature" | "features" | "add" => "feat",
+        "bug" | "bugfix" | "fixes" | "fixed" | "hotfix" => "fix",
+        "doc" | "documentation" => "docs",
+        "tests" | "testing" => "test",
+        "refactoring" | "refactored" => "refactor",
+        "performance" => "perf",
+        "chores" => "chore",
+        other => other,
+    };
+    TYPES.iter().find(|t| **t == kind).copied()
+}
+
+/// Tries to turn an almost Conventional Commit into a real one, fixing the case
+/// of the type, the spacing around the colon, markdown around the header and the
+/// missing blank line before the body.  `None` if it can't be saved.
+///
+/// # Arguments
+///
+/// * `message` - The generated message
+pub fn fix_up(message: &str) -> Option<String> {
+    let message = message.trim().trim_matches('`').trim();
+    let mut lines = message.lines().skip_while(|line| line.trim().is_empty());
+    let first = lines
+        .next()?
+        .trim()
+        .trim_start_matches(['#', '*', '-', '>'])
+        .trim_end_matches('*')
+        .trim();
+    let first = first
+        .strip_prefix("Commit message:")
+        .or_else(|| first.strip_prefix("Subject:"))
+        .unwrap_or(first)
+        .trim();
+    let caps = loose_header().captures(first)?;
+    let kind = normalize_type(&caps[1])?;
+    let scope = caps
+        .get(3)
+        .map(|m| m.as_str().trim())
+        .filter(|s| !s.is_empty())
+        .map(|s| format!("({})", s))
+        .unwrap_or_default();
+    let bang = if caps.get(4).is_some() { "!" } else { "" };
+    let header = format!("{}{}{}: {}", kind, scope, bang, caps[5].trim());
+    let body = lines.collect::<Vec<&str>>().join("\n").trim().to_string();
+    let fixed = if body.is_empty() {
+        header
+    } else {
+        format!("{}\n\n{}", header, body)
+    };
+    parse(&fixed).ok().map(|_| fixed)
+}
+
+/// Asks for a Conventional Commit instead of a paragraph, whatever the rest of the prompt is
+///
+/// # Arguments
+///
+/// * `prompt` - The commit prompt
+pub fn prompt(prompt: AiPrompt) -> AiPrompt {
+    AiPrompt {
+        postmessage: format!(
+            "Please write a commit message in the Conventional Commits format: a header line `type(scope): summary` where type is one of {}, the scope is optional and the summary is under 72 characters, then a blank line and a short body explaining what changed and why. Add `!` after the type (or scope) only if the change breaks compatibility.",
+            TYPES.join(", ")
+        ),
+        ..prompt
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_header_is_parsed() {
+        assert_eq!(
+            parse("feat(sinks): add a webhook sink\n\nIt posts the message.").unwrap(),
+            ConventionalCommit {
+                kind: "feat".to_string(),
+                scope: Some("sinks".to_string()),
+                breaking: false,
+                description: "add a webhook sink".to_string(),
+                body: Some("It posts the message.".to_string()),
+            }
+        );
+        let parsed = parse("fix: handle an empty diff").unwrap();
+        assert_eq!(parsed.scope, None);
+        assert_eq!(parsed.body, None);
+    }
+
+    #[test]
+    fn a_bang_or_a_footer_is_breaking() {
+        let parsed = parse("refactor(settings)!: drop the old layout").unwrap();
+        assert!(parsed.breaking);
+        assert_eq!(parsed.scope.as_deref(), Some("settings"));
+        let parsed = parse("feat: new settings\n\nBREAKING CHANGE: the old ones are gone").unwrap();
+        assert!(parsed.breaking);
+        assert!(!parse("feat: new settings").unwrap().breaking);
+    }
+
+    #[test]
+    fn a_bad_header_is_rejected() {
+        for message in [
+            "Add a webhook sink",
+            "feature: add a webhook sink",
+            "Feat: add a webhook sink",
+            "feat:add a webhook sink",
+            "feat(): add a webhook sink",
+            "feat: add a webhook sink\nIt posts the message.",
+        ] {
+            assert!(parse(message).is_err(), "{}", message);
+        }
+    }
+
+    #[test]
+    fn an_almost_conventional_message_is_fixed_up() {
+        assert_eq!(
+            fix_up("**Feature (sinks) ! : add a webhook sink**\nIt posts the message.").as_deref(),
+            Some("feat(sinks)!: add a webhook sink\n\nIt posts the message.")
+        );
+        assert_eq!(
+            fix_up("```\nCommit message: Bugfix: handle an empty diff\n```").as_deref(),
+            Some("fix: handle an empty diff")
+        );
+        assert_eq!(fix_up("Add a webhook sink"), None);
+        assert_eq!(fix_up("wip: add a webhook sink"), None);
+    }
+}
@@ -0,0 +1,278 @@
+use std::{env, fmt};
+
+use crate::ai;
+use crate::hosting::{GitHub, GitLab, HostingProvider};
+use crate::settings::{self, AiProvider, AzureSettings, Provider, RetrySettings, Secret, Settings};
+
+/// Where a token came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenSource {
+    /// An environment variable, it wins over the keyring and the file
+    Environment,
+    /// The OS keyring, it wins over the file
+    Keyring,
+    /// `settings.json`
+    SettingsFile,
+    /// Nowhere, it isn't set
+    Missing,
+}
+
+impl fmt::Display for TokenSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TokenSource::Environment => write!(f, "environment"),
+            TokenSource::Keyring => write!(f, "keyring"),
+            TokenSource::SettingsFile => write!(f, "settings.json"),
+            TokenSource::Missing => write!(f, "not set"),
+        }
+    }
+}
+
+/// One set of credentials and whether they work
+#[derive(Debug, Clone)]
+pub struct CredentialStatus {
+    /// What they are for, `OpenAI` or `GitHub account work`
+    pub name: String,
+    /// Where the token came from
+    pub source: TokenSource,
+    /// Who the token belongs to, when the service says
+    pub identity: Option<String>,
+    /// When the token stops working, when the service says
+    pub expires: Option<String>,
+    /// Why they don't work, `None` when they do (or there was nothing to try)
+    pub problem: Option<String>,
+}
+
+impl CredentialStatus {
+    /// Whether the token is set and was accepted
+    pub fn ok(&self) -> bool {
+        self.source != TokenSource::Missing && self.problem.is_none()
+    }
+}
+
+impl fmt::Display for CredentialStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:<28} ", self.name)?;
+        if self.source == TokenSource::Missing {
+            return write!(f, "{}", self.source);
+        }
+        write!(f, "{:<14}", self.source.to_string())?;
+        if let Some(problem) = &self.problem {
+            return write!(f, "not working: {}", problem);
+        }
+        match &self.identity {
+            Some(identity) => write!(f, "ok, {}", identity)?,
+            None => write!(f, "ok")?,
+        }
+        match &self.expires {
+            Some(expires) => write!(f, ", expires {}", expires),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Where the token for `secret` came from.  The environment and the keyring were already
+/// read into `value` by `Settings::with_tokens`, so if they are the same that's where it
+/// came from.  The keyring is only asked once a run, this doesn't ask it again.
+fn source(secret: Secret, value: &str) -> TokenSource {
+    if value.is_empty() {
+        TokenSource::Missing
+    } else if secret
+        .env_vars()
+        .iter()
+        .any(|name| env::var(name).is_ok_and(|env_value| env_value.trim() == value))
+    {
+        TokenSource::Environment
+    } else if settings::keyring_get(secret).as_deref() == Some(value) {
+        TokenSource::Keyring
+    } else {
+        TokenSource::SettingsFile
+    }
+}
+
+/// A token only ever kept in `settings.json`
+fn file_source(value: &str) -> TokenSource {
+    if value.is_empty() {
+        TokenSource::Missing
+    } else {
+        TokenSource::SettingsFile
+    }
+}
+
+/// Checks an AI service by listing its models, there's no asking it who we are
+async fn ai_status(
+    name: String,
+    source: TokenSource,
+    url: &str,
+    token: &str,
+    azure: AzureSettings,
+) -> CredentialStatus {
+    let mut status = CredentialStatus {
+        name,
+        source,
+        identity: None,
+        expires: None,
+        problem: None,
+    };
+    if source == TokenSource::Missing {
+        return status;
+    }
+    // a status check shouldn't sit through backoffs
+    let retry = RetrySettings {
+        max_retries: 0,
+        ..Default::default()
+    };
+    let checked = match ai::new_client(url.to_string(), token.to_string(), azure, retry) {
+        Ok(client) => client.get_models().await.map(|_| ()),
+        Err(e) => Err(e),
+    };
+    status.problem = checked.err().map(|e| e.to_string());
+    status
+}
+
+/// Checks a forge by asking who the token belongs to and when it expires
+async fn forge_status(
+    name: String,
+    source: TokenSource,
+    forge: &dyn HostingProvider,
+) -> CredentialStatus {
+    let mut status = CredentialStatus {
+        name,
+        source,
+        identity: None,
+        expires: None,
+        problem: None,
+    };
+    if source == TokenSource::Missing {
+        return status;
+    }
+    match forge.get_authenticated_user().await {
+        Ok(user) => status.identity = Some(user),
+        Err(e) => {
+            status.problem = Some(e.to_string());
+            return status;
+        }
+    }
+    // not knowing when it expires doesn't make the token any less good
+    status.expires = forge.token_expiry().await.unwrap_or_default();
+    status
+}
+
+/// Every AI service and forge in the settings, whether its token is set, where from,
+/// who it belongs to and when it expires.  Only reads, nothing is changed.
+///
+/// # Arguments
+///
+/// * `settings` - The settings, as loaded (keyring included)
+pub async fn status(settings: &Settings) -> Vec<CredentialStatus> {
+    let ai_settings = &settings.ai_settings;
+    let git_settings = &settings.git_settings;
+    let mut statuses = Vec::new();
+
+    let ai_source = source(Secret::Openai, &ai_settings.api_key);
+    let ai_name = if ai_settings.azure.enabled {
+        "Azure OpenAI"
+    } else {
+        "OpenAI"
+    };
+    statuses.push(
+        ai_status(
+            ai_name.to_string(),
+            ai_source,
+            &ai_settings.api_url,
+            &ai_settings.api_key,
+            ai_settings.azure.clone(),
+        )
+        .await,
+    );
+    if let Some(race) = &ai_settings.race {
+        let mut azure = ai_settings.azure.clone();
+        azure.enabled = race.provider == AiProvider::Azure;
+        let url = match race.api_url.as_str() {
+            "" => &ai_settings.api_url,
+            url => url,
+        };
+        let (token, token_source) = match race.api_key.as_str() {
+            "" => (&ai_settings.api_key, ai_source),
+            _ => (&race.api_key, TokenSource::SettingsFile),
+        };
+        statuses.push(
+            ai_status(
+                "ai_settings.race".to_string(),
+                token_source,
+                url,
+                token,
+                azure,
+            )
+            .await,
+        );
+    }
+    for rule in ai_settings
+        .routing
+        .iter()
+        .filter(|rule| !rule.api_key.is_empty())
+    {
+        let mut azure = ai_settings.azure.clone();
+        azure.enabled = rule.provider == AiProvider::Azure;
+        let url = match rule.api_url.as_str() {
+            "" => &ai_settings.api_url,
+            url => url,
+        };
+        statuses.push(
+            ai_status(
+                format!("routing {}", rule.remote),
+                TokenSource::SettingsFile,
+                url,
+                &rule.api_key,
+                azure,
+            )
+            .await,
+        );
+    }
+
+    if !git_settings.github_api_key.is_empty() || git_settings.provider == Provider::Github {
+        let github = GitHub::new(
+            &git_settings.github_api_key,
+            &git_settings.github_api_url,
+            &git_settings.github_ca_cert,
+        );
+        statuses.push(
+            forge_status(
+                "GitHub".to_string(),
+                source(Secret::Github, &git_settings.github_api_key),
+                &github,
+            )
+            .await,
+        );
+    }
+    for account in &git_settings.github_accounts {
+        let url = account
+            .enterprise_api_url()
+            .unwrap_or_else(|| git_settings.github_api_url.clone());
+        let ca_cert = match account.ca_cert.as_str() {
+            "" => &git_settings.github_ca_cert,
+            ca_cert => ca_cert,
+        };
+        let github = GitHub::new(&account.api_key, &url, ca_cert);
+        statuses.push(
+            forge_status(
+                format!("GitHub account {}", account.name),
+                file_source(&account.api_key),
+                &github,
+            )
+            .await,
+        );
+    }
+    if !git_settings.gitlab_api_key.is_empty() || git_settings.provider == Provider::Gitlab {
+        let gitlab = GitLab::new(&git_settings.gitlab_api_key, &git_settings.gitlab_api_url);
+        statuses.push(
+            forge_status(
+                "GitLab".to_string(),
+                source(Secret::Gitlab, &git_settings.gitlab_api_key),
+                &gitlab,
+            )
+            .await,
+        );
+    }
+    statuses
+}
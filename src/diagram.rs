@@ -0,0 +1,103 @@
+use std::collections::BTreeMap;
+
+use regex::Regex;
+
+use crate::git::FileDiff;
+
+/// Past this many files the diagram only shows directories, nobody can read more boxes than that
+const MAX_FILE_NODES: usize = 30;
+
+/// File stems too common to mean one file is using another
+const COMMON_STEMS: [&str; 6] = ["mod", "lib", "main", "index", "init", "__init__"];
+
+/// A mermaid flowchart of a change for a pull request body.  Changed files are grouped
+/// by directory with their line counts, and there is an arrow from one changed file to
+/// another when its added lines mention the other one's name (a `use`, an `import`, a call).
+/// Big changes only get a box per directory.
+///
+/// # Arguments
+///
+/// * `files` - The diff, from `Git::diff_to_file_strings`
+pub fn mermaid(files: &[FileDiff]) -> String {
+    let mut out = vec!["flowchart LR".to_string()];
+    let mut by_dir: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+    for (i, file) in files.iter().enumerate() {
+        by_dir.entry(dir_name(file)).or_default().push(i);
+    }
+
+    if files.len() > MAX_FILE_NODES {
+        for (d, (dir, members)) in by_dir.iter().enumerate() {
+            let (added, removed) = members.iter().fold((0, 0), |(a, r), &i| {
+                (a + files[i].added, r + files[i].removed)
+            });
+            out.push(format!(
+                "  d{}[\"{}<br/>{} files, +{} -{}\"]",
+                d,
+                escape(dir),
+                members.len(),
+                added,
+                removed
+            ));
+        }
+        return out.join("\n");
+    }
+
+    for (d, (dir, members)) in by_dir.iter().enumerate() {
+        out.push(format!("  subgraph d{}[\"{}\"]", d, escape(dir)));
+        for &i in members {
+            let name = files[i]
+                .path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default();
+            out.push(format!(
+                "    f{}[\"{}<br/>+{} -{}\"]",
+                i,
+                escape(&name),
+                files[i].added,
+                files[i].removed
+            ));
+        }
+        out.push("  end".to_string());
+    }
+    for (to, target) in files.iter().enumerate() {
+        let stem = match target.path.file_stem().map(|s| s.to_string_lossy()) {
+            Some(stem) if stem.len() >= 3 && !COMMON_STEMS.contains(&stem.as_ref()) => stem,
+            _ => continue,
+        };
+        let mention = match Regex::new(&format!(r"\b{}\b", regex::escape(&stem))) {
+            Ok(re) => re,
+            Err(_) => continue,
+        };
+        for (from, source) in files.iter().enumerate() {
+            let uses = from != to
+                && source
+                    .text
+                    .lines()
+                    .filter(|line| line.starts_with('+') && !line.starts_with("+++"))
+                    .any(|line| mention.is_match(line));
+            if uses {
+                out.push(format!("  f{} --> f{}", from, to));
+            }
+        }
+    }
+    out.join("\n")
+}
+
+/// The pull request section holding the diagram
+pub fn markdown_section(files: &[FileDiff]) -> String {
+    format!("## Change overview\n\n```mermaid\n{}\n```", mermaid(files))
+}
+
+fn dir_name(file: &FileDiff) -> String {
+    file.path
+        .parent()
+        .map(|dir| dir.to_string_lossy().to_string())
+        .filter(|dir| !dir.is_empty())
+        .unwrap_or_else(|| ".".to_string())
+}
+
+/// Mermaid labels are quoted, so quotes have to go
+fn escape(label: &str) -> String {
+    label.replace('"', "#quot;")
+}
@@ -0,0 +1,126 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use regex::Regex;
+
+use crate::settings::AiPrompt;
+
+/// Most docs folders are small, but don't list a whole generated site
+const MAX_DOC_PATHS: usize = 50;
+
+/// Added or removed lines that declare something public in the usual languages
+fn public_api_line() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| {
+        Regex::new(
+            r"^[+-]\d+ \s*(pub(\([a-z]+\))?\s+(async\s+)?(fn|struct|enum|trait|type|const|mod)\s|export\s+(default\s+)?(async\s+)?(function|class|const|interface|type)\s|def\s+[a-zA-Z]\w*\(|class\s+[A-Z]\w*|public\s+)",
+        )
+        .expect("Bad public api regex")
+    })
+}
+
+/// The lines of a diff (from `Git::diff_to_string`) that change a public API
+///
+/// # Arguments
+///
+/// * `diff` - The diff text
+pub fn public_api_changes(diff: &str) -> Vec<String> {
+    diff.lines()
+        .filter(|line| public_api_line().is_match(line))
+        .map(|line| line.trim_end().to_string())
+        .collect()
+}
+
+/// The documentation in the repo, READMEs, markdown at the root and everything in `docs/` or `doc/`
+///
+/// # Arguments
+///
+/// * `root` - The root of the repo
+pub fn doc_paths(root: &Path) -> Vec<String> {
+    let mut paths: Vec<PathBuf> = Vec::new();
+    if let Ok(entries) = fs::read_dir(root) {
+        for path in entries.flatten().map(|entry| entry.path()) {
+            let is_doc = path
+                .extension()
+                .map(|ext| ext == "md" || ext == "rst" || ext == "adoc")
+                .unwrap_or(false);
+            if path.is_file() && is_doc {
+                paths.push(path);
+            }
+        }
+    }
+    for dir in ["docs", "doc"] {
+        collect_files(&root.join(dir), &mut paths);
+    }
+    let mut paths: Vec<String> = paths
+        .iter()
+        .filter_map(|path| path.strip_prefix(root).ok())
+        .map(|path| path.to_string_lossy().to_string())
+        .collect();
+    paths.sort();
+    paths.truncate(MAX_DOC_PATHS);
+    paths
+}
+
+fn collect_files(dir: &Path, found: &mut Vec<PathBuf>) {
+    if let Ok(entries) = fs::read_dir(dir) {
+        for path in entries.flatten().map(|entry| entry.path()) {
+            if path.is_dir() {
+                collect_files(&path, found);
+            } else {
+                found.push(path);
+            }
+        }
+    }
+}
+
+/// The prompt asking the AI whether the docs need to change, the public API changes
+/// and the doc paths take the place of the git diff
+///
+/// # Arguments
+///
+/// * `language` - The programming language
+/// * `api_changes` - From `public_api_changes`
+/// * `doc_paths` - From `doc_paths`
+pub fn drift_prompt(language: &str, api_changes: &[String], doc_paths: &[String]) -> AiPrompt {
+    AiPrompt {
+        language: language.to_string(),
+        postamble: "developer reviewing a change to the public API of a project. These are the changed public declarations:".to_string(),
+        git_diff: format!(
+            "{}\n\nThe project has this documentation:\n{}",
+            api_changes.join("\n"),
+            doc_paths.join("\n")
+        ),
+        postmessage: "Does this change likely require updating the documentation? Answer YES or NO on the first line, and if YES list which of the documentation files should be updated and why, one per line.".to_string(),
+        ..Default::default()
+    }
+}
+
+/// Reads the AI's answer, `Some(reasons)` if it thinks the docs need updating
+///
+/// # Arguments
+///
+/// * `answer` - The completion for `drift_prompt`
+pub fn parse_drift_answer(answer: &str) -> Option<String> {
+    let answer = answer.trim();
+    let (first, rest) = answer.split_once('\n').unwrap_or((answer, ""));
+    if first.trim().to_ascii_uppercase().starts_with("YES") {
+        Some(rest.trim().to_string())
+    } else {
+        None
+    }
+}
+
+/// The reminder section for a pull request body
+pub fn reminder_section(reasons: &str) -> String {
+    let mut section =
+        "## Documentation\n\nThis change touches the public API and the docs may need updating."
+            .to_string();
+    if !reasons.is_empty() {
+        section.push_str(&format!("\n\n{}", reasons));
+    }
+    section
+}
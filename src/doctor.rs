@@ -0,0 +1,303 @@
+use std::{
+    env, fmt, fs,
+    path::Path,
+    process::{Command, Stdio},
+};
+
+use crossterm::style::{Color, ResetColor, SetForegroundColor};
+use git2::Repository;
+
+use crate::credentials::{self, CredentialStatus, TokenSource};
+use crate::error::GitAiError;
+use crate::network;
+use crate::settings::{self, expand_home, Settings};
+use crate::signing::Signer;
+
+/// One thing `gitai doctor` looked at
+#[derive(Debug, Clone)]
+pub struct Check {
+    /// What was checked, `settings.json` or `GitHub`
+    pub name: String,
+    /// Whether it is fine
+    pub passed: bool,
+    /// What was found, or what is wrong
+    pub detail: String,
+    /// How to fix it, for the ones that failed
+    pub hint: Option<String>,
+}
+
+impl Check {
+    fn pass(name: impl Into<String>, detail: impl Into<String>) -> Self {
+        Check {
+            name: name.into(),
+            passed: true,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    fn fail(name: impl Into<String>, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Check {
+            name: name.into(),
+            passed: false,
+            detail: detail.into(),
+            hint: Some(hint.into()),
+        }
+    }
+
+    /// The check as a line of the checklist, green or red when `color`, the hint under it
+    ///
+    /// # Arguments
+    ///
+    /// * `color` - Whether to color it, only for a terminal
+    pub fn line(&self, color: bool) -> String {
+        let (mark, start) = if self.passed {
+            ("✔", SetForegroundColor(Color::Green).to_string())
+        } else {
+            ("✘", SetForegroundColor(Color::Red).to_string())
+        };
+        let (start, end) = if color {
+            (start, ResetColor.to_string())
+        } else {
+            (String::new(), String::new())
+        };
+        let mut line = format!("{}{} {:<28}{} {}", start, mark, self.name, end, self.detail);
+        if let Some(hint) = &self.hint {
+            line.push_str(&format!("\n  {:<28} {}", "", hint));
+        }
+        line
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.line(false))
+    }
+}
+
+/// Whether `settings.json` is there and reads, with the repository's settings over it
+fn settings_check(dir: &Path) -> (Check, Option<Settings>) {
+    let name = "settings.json";
+    let path = settings::settings_path();
+    if !path.exists() {
+        return (
+            Check::fail(
+                name,
+                format!("there is nothing at {}", path.display()),
+                "Run gitai init to write one, checking your keys as it goes",
+            ),
+            None,
+        );
+    }
+    let problem = match fs::read_to_string(&path) {
+        Ok(text) => settings::settings_problem(&text),
+        Err(e) => Some(e.to_string()),
+    };
+    if let Some(problem) = problem {
+        return (
+            Check::fail(
+                name,
+                format!("{} is broken, {}", path.display(), problem),
+                "Fix it with gitai config edit, or move it out of the way to get a fresh one",
+            ),
+            None,
+        );
+    }
+    match Settings::for_repo(dir).map(Settings::with_tokens) {
+        Ok(settings) => {
+            let detail = match settings::repo_settings_path(dir) {
+                Some(repo) => format!("{}, with {} over it", path.display(), repo.display()),
+                None => path.display().to_string(),
+            };
+            (Check::pass(name, detail), Some(settings))
+        }
+        Err(e) => (
+            Check::fail(
+                name,
+                e.to_string(),
+                "Fix the file the error names, a repository's .gitai.toml only sets the team's model and conventions",
+            ),
+            None,
+        ),
+    }
+}
+
+/// A token from `gitai auth status` as a check
+fn token_check(status: CredentialStatus) -> Check {
+    if status.source == TokenSource::Missing {
+        return Check::fail(
+            status.name,
+            "no token",
+            "Set it with gitai auth set, or in settings.json",
+        );
+    }
+    if let Some(problem) = status.problem {
+        return Check::fail(
+            status.name,
+            format!("the token from {} doesn't work: {}", status.source, problem),
+            "Check it hasn't expired or been revoked and has the scopes gitai needs",
+        );
+    }
+    let mut detail = format!("token from {}", status.source);
+    if let Some(identity) = status.identity {
+        detail.push_str(&format!(", {}", identity));
+    }
+    if let Some(expires) = status.expires {
+        detail.push_str(&format!(", expires {}", expires));
+    }
+    Check::pass(status.name, detail)
+}
+
+/// Whether the remote pull requests go to is there, its url when it is
+fn remote_check(repo: &Repository, remote: &str) -> (Check, Option<String>) {
+    let name = format!("{} remote", remote);
+    match repo.find_remote(remote) {
+        Ok(found) => {
+            let url = found.url().unwrap_or_default().to_string();
+            (Check::pass(name, url.clone()), Some(url))
+        }
+        Err(_) => (
+            Check::fail(
+                name,
+                "isn't there",
+                format!(
+                    "Add it with git remote add {} <url>, gitai pushes and opens pull requests there (or set git_settings.remote)",
+                    remote
+                ),
+            ),
+            None,
+        ),
+    }
+}
+
+/// Whether pushing over ssh can log in, through the agent or with `ssh_key_path`.  Not
+/// needed at all when the remote is https.
+fn ssh_check(url: Option<&str>, ssh_key_path: &str) -> Check {
+    let name = "ssh agent";
+    if let Some(url) = url.filter(|url| url.starts_with("https://") || url.starts_with("http://")) {
+        return Check::pass(name, format!("not needed, {} is https", url));
+    }
+    // 0 is an agent with keys, 1 one without, 2 no agent at all.  The Windows agent
+    // listens on a named pipe and doesn't set SSH_AUTH_SOCK.
+    let agent = if env::var_os("SSH_AUTH_SOCK").is_some() || cfg!(windows) {
+        Command::new("ssh-add")
+            .arg("-l")
+            .stdin(Stdio::null())
+            .output()
+            .ok()
+            .and_then(|output| output.status.code())
+    } else {
+        None
+    };
+    if agent == Some(0) {
+        return Check::pass(name, "running, with keys in it");
+    }
+    let key_path = match ssh_key_path {
+        "" => "~/.ssh/id_rsa",
+        path => path,
+    };
+    let key = expand_home(key_path);
+    let agent = match agent {
+        Some(1) => "running without any keys",
+        _ => "not running",
+    };
+    if key.is_file() {
+        Check::pass(
+            name,
+            format!("{}, {} is used instead", agent, key.display()),
+        )
+    } else {
+        Check::fail(
+            name,
+            format!("{} and there is no {}", agent, key.display()),
+            "Start one and add your key (eval \"$(ssh-agent)\" && ssh-add), or point git_options.ssh_key_path at it",
+        )
+    }
+}
+
+/// Whether the key commits get signed with is there, when they get signed
+fn signing_check(repo: &Repository, settings: Option<&Settings>) -> Check {
+    let name = "signing key";
+    let config = match repo.config() {
+        Ok(config) => config,
+        Err(e) => return Check::fail(name, e.message(), "Check the repository's git config"),
+    };
+    let options = settings.map(|settings| &settings.git_settings.git_options);
+    // off leaves it to commit.gpgsign, like a commit would
+    let sign = options
+        .is_some_and(|options| options.sign_commits)
+        .then_some(true);
+    let key_id = options
+        .map(|options| options.key_id.as_str())
+        .filter(|key| !key.is_empty());
+    let committer = format!(
+        "{} <{}>",
+        config.get_string("user.name").unwrap_or_default(),
+        config.get_string("user.email").unwrap_or_default()
+    );
+    let signer = match Signer::from_config(&config, sign, key_id, &committer) {
+        Some(signer) => signer,
+        None => return Check::pass(name, "not needed, commits aren't signed"),
+    };
+    match signer.check() {
+        Ok(found) => Check::pass(name, found),
+        Err(e) => Check::fail(
+            name,
+            match e {
+                GitAiError::Signing(reason) => reason,
+                e => e.to_string(),
+            },
+            "Check that git commit -S works on its own, or turn off sign_commits and commit.gpgsign",
+        ),
+    }
+}
+
+/// Looks at everything gitai needs: the settings, every token (with a request to each
+/// service), the repository, its remote, the ssh agent and the signing key.  Only
+/// looks, nothing is changed.  What can't be checked without the settings or the
+/// repository is left out when they fail.
+///
+/// # Arguments
+///
+/// * `dir` - Anywhere in the repository
+pub async fn run(dir: &Path) -> Vec<Check> {
+    let (check, settings) = settings_check(dir);
+    let mut checks = vec![check];
+    if let Some(settings) = &settings {
+        network::init(settings);
+        checks.extend(
+            credentials::status(settings)
+                .await
+                .into_iter()
+                .map(token_check),
+        );
+    }
+    let repo = match Repository::discover(dir) {
+        Ok(repo) => repo,
+        Err(e) => {
+            checks.push(Check::fail(
+                "repository",
+                e.message(),
+                "Run gitai inside a git repository, or point --local-repo at one",
+            ));
+            return checks;
+        }
+    };
+    let location = repo
+        .workdir()
+        .unwrap_or_else(|| repo.path())
+        .display()
+        .to_string();
+    checks.push(Check::pass("repository", location));
+    let remote = settings
+        .as_ref()
+        .map_or("origin", |settings| settings.git_settings.remote.as_str());
+    let (check, url) = remote_check(&repo, remote);
+    checks.push(check);
+    let ssh_key_path = settings.as_ref().map_or("", |settings| {
+        settings.git_settings.git_options.ssh_key_path.as_str()
+    });
+    checks.push(ssh_check(url.as_deref(), ssh_key_path));
+    checks.push(signing_check(&repo, settings.as_ref()));
+    checks
+}
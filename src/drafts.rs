@@ -0,0 +1,117 @@
+use std::{fs, path::PathBuf};
+
+use git2::Repository;
+use log::debug;
+
+use crate::error::Result;
+use crate::settings::gitai_home;
+
+/// A pull request description that was generated but never submitted.  It is saved
+/// as markdown so you can edit it before `gitai pr --resume` picks it back up.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrDraft {
+    /// The branch the pull request goes into
+    pub to: String,
+    /// The commit the branch was at when the draft was last generated
+    pub head: String,
+    /// The pull request title
+    pub title: String,
+    /// The pull request body
+    pub body: String,
+}
+
+impl PrDraft {
+    /// The draft as it is written to disk
+    pub fn to_markdown(&self) -> String {
+        format!(
+            "<!-- gitai draft: to={} head={} -->\n# {}\n\n{}\n",
+            self.to,
+            self.head,
+            self.title,
+            self.body.trim_end()
+        )
+    }
+
+    /// Reads a draft written by `to_markdown` (and maybe edited since)
+    pub fn from_markdown(text: &str) -> Option<Self> {
+        let mut lines = text.lines();
+        let header = lines
+            .next()?
+            .trim()
+            .strip_prefix("<!-- gitai draft:")?
+            .strip_suffix("-->")?;
+        let mut to = None;
+        let mut head = None;
+        for field in header.split_whitespace() {
+            match field.split_once('=') {
+                Some(("to", value)) => to = Some(value.to_string()),
+                Some(("head", value)) => head = Some(value.to_string()),
+                _ => (),
+            }
+        }
+        let rest: Vec<&str> = lines.skip_while(|line| line.trim().is_empty()).collect();
+        let (title, body) = rest.split_first()?;
+        Some(PrDraft {
+            to: to?,
+            head: head?,
+            title: title.trim_start_matches('#').trim().to_string(),
+            body: body.join("\n").trim().to_string(),
+        })
+    }
+}
+
+/// Where the draft for `branch` in `repo` lives, `~/.gitai/drafts/<repo path>/<branch>.md`
+///
+/// # Arguments
+///
+/// * `repo` - The repository
+/// * `branch` - The branch the pull request comes from
+pub fn draft_path(repo: &Repository, branch: &str) -> PathBuf {
+    let repo_dir = repo
+        .workdir()
+        .unwrap_or(repo.path())
+        .canonicalize()
+        .unwrap_or_else(|_| repo.path().to_path_buf());
+    let mut p = gitai_home();
+    p.push("drafts");
+    p.push(sanitize(&repo_dir.to_string_lossy()));
+    p.push(format!("{}.md", sanitize(branch)));
+    p
+}
+
+/// Loads the saved draft for `branch`, `None` if there isn't one (or it can't be read)
+pub fn load(repo: &Repository, branch: &str) -> Option<PrDraft> {
+    let path = draft_path(repo, branch);
+    debug!("Looking for a draft at {:#?}", path);
+    PrDraft::from_markdown(&fs::read_to_string(path).ok()?)
+}
+
+/// Saves the draft for `branch` and returns where it went
+pub fn save(repo: &Repository, branch: &str, draft: &PrDraft) -> Result<PathBuf> {
+    let path = draft_path(repo, branch);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&path, draft.to_markdown())?;
+    Ok(path)
+}
+
+/// Throws the draft for `branch` away, it was submitted
+pub fn remove(repo: &Repository, branch: &str) -> Result<()> {
+    let path = draft_path(repo, branch);
+    if path.exists() {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+/// Turns a path or branch name into something safe to use as a single file name
+fn sanitize(name: &str) -> String {
+    name.trim_matches(|c| c == '/' || c == '\\')
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' => '_',
+            c => c,
+        })
+        .collect()
+}
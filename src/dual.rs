@@ -0,0 +1,52 @@
+use crate::settings::AiPrompt;
+use crate::tickets::Ticket;
+
+/// The commit prompt for a repo that is mirrored publicly, the message has to be safe to publish
+///
+/// # Arguments
+///
+/// * `prompt` - The commit prompt
+pub fn public_prompt(prompt: AiPrompt) -> AiPrompt {
+    AiPrompt {
+        postmessage: format!(
+            "{} This repository is mirrored publicly, so describe only what the code does.  Leave out customer names, people, internal systems, hostnames, ticket titles and anything else that should stay inside the company.",
+            prompt.postmessage.trim_end()
+        ),
+        ..prompt
+    }
+}
+
+/// The prompt asking for the internal description, the one that stays inside the company
+///
+/// # Arguments
+///
+/// * `language` - The programming language
+/// * `diff` - The diff
+/// * `public_message` - The message that went in the commit
+/// * `ticket` - The ticket the change is for, if there is one
+pub fn internal_prompt(
+    language: &str,
+    diff: &str,
+    public_message: &str,
+    ticket: Option<&Ticket>,
+) -> AiPrompt {
+    let ticket = match ticket {
+        Some(Ticket {
+            key,
+            title: Some(title),
+        }) => format!(" It is for ticket {}: \"{}\".", key, title),
+        Some(Ticket { key, title: None }) => format!(" It is for ticket {}.", key),
+        None => String::new(),
+    };
+    AiPrompt {
+        language: language.to_string(),
+        postamble: "developer writing up a change for your own team. This is the diff:".to_string(),
+        git_diff: diff.to_string(),
+        postmessage: format!(
+            "The public commit message for it is:\n\n{}\n\nWrite the internal description the team will read instead.{}  Go into more detail than the public message: why the change was made, what it affects, anything risky, and what to watch after it ships.  Answer with just the description.",
+            public_message.trim(),
+            ticket
+        ),
+        ..Default::default()
+    }
+}
@@ -0,0 +1,182 @@
+use std::io;
+
+use reqwest::{Response, StatusCode};
+use serde_json::Value;
+use thiserror::Error;
+
+/// Everything that can go wrong in gitai.  `main` prints these, with a hint on what
+/// to do about it when there is one, and exits with `exit_code` instead of panicking.
+#[derive(Debug, Error)]
+pub enum GitAiError {
+    /// Something went wrong in the repository
+    #[error("Git error: {0}")]
+    Git(#[from] git2::Error),
+    /// Couldn't talk to a server at all
+    #[error("Network error: {0}")]
+    Network(reqwest::Error),
+    /// A server answered, but with an error
+    #[error("{service} answered with {status}: {message}")]
+    Api {
+        service: String,
+        status: u16,
+        message: String,
+    },
+    /// A server didn't accept the token
+    #[error("{service} didn't accept the token")]
+    Unauthorized { service: String },
+    /// The token works but isn't allowed to do what we need
+    #[error("{0}")]
+    Permission(String),
+    /// The settings file couldn't be read
+    #[error("Unable to load the settings: {0}")]
+    Settings(#[from] config::ConfigError),
+    /// JSON that didn't turn into what we expected
+    #[error("Unable to read or write JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    /// Reading or writing a file (or the terminal)
+    #[error("{0}")]
+    Io(#[from] io::Error),
+    /// A hook script exited with a non-zero status
+    #[error("The {hook} hook vetoed this: {reason}")]
+    HookVeto { hook: &'static str, reason: String },
+    /// A guard rail stopped us on purpose
+    #[error("{0}")]
+    Refused(String),
+    /// Asked to do something that can't be done
+    #[error("{0}")]
+    Usage(String),
+    /// The AI (or an API) answered with something we can't use
+    #[error("{0}")]
+    BadResponse(String),
+    /// The AI didn't answer before `generation_deadline_ms` was up
+    #[error("{0}")]
+    TimedOut(String),
+    /// There is nothing staged to write a message for
+    #[error("{0}")]
+    NothingStaged(String),
+    /// A question came up in non-interactive mode, with nobody to answer it
+    #[error("{0}")]
+    NeedsInput(String),
+    /// The commit was supposed to be signed and couldn't be
+    #[error("Unable to sign the commit: {0}")]
+    Signing(String),
+}
+
+impl GitAiError {
+    /// The process exit code for this error, so scripts can tell them apart
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            GitAiError::Usage(_) => 2,
+            GitAiError::Unauthorized { .. } | GitAiError::Permission(_) => 3,
+            GitAiError::Network(_)
+            | GitAiError::Api { .. }
+            | GitAiError::BadResponse(_)
+            | GitAiError::TimedOut(_) => 4,
+            GitAiError::HookVeto { .. } | GitAiError::Refused(_) => 5,
+            GitAiError::Settings(_) => 6,
+            GitAiError::NothingStaged(_) => 7,
+            GitAiError::NeedsInput(_) => 8,
+            _ => 1,
+        }
+    }
+
+    /// What the user can do about it, if we know
+    pub fn hint(&self) -> Option<&'static str> {
+        match self {
+            GitAiError::Network(_) => {
+                Some("Check your connection and the api urls in ~/.gitai/settings.json")
+            }
+            GitAiError::Unauthorized { .. } => Some(
+                "Check the api keys in ~/.gitai/settings.json or pass them on the command line",
+            ),
+            GitAiError::TimedOut(_) => Some(
+                "Raise timeout_seconds in ai_settings (git_settings for GitHub, GitLab and the rest) or ai_options.generation_deadline_ms, or set it to 0 to wait as long as it takes",
+            ),
+            GitAiError::NothingStaged(_) => Some(
+                "Stage what goes in the commit with git add, or pass --auto-add (-a) to stage everything",
+            ),
+            GitAiError::Settings(_) => Some(
+                "Fix ~/.gitai/settings.json (or the one the error names), or move it out of the way to get a fresh one",
+            ),
+            GitAiError::NeedsInput(_) => Some(
+                "Pass --auto-ai to take the AI's first answer and --yes to confirm the rest without asking, or run it in a terminal",
+            ),
+            GitAiError::Signing(_) => Some(
+                "Check that git commit -S works on its own, gitai signs with the same key and program",
+            ),
+            _ => None,
+        }
+    }
+}
+
+/// A request that ran out of time is `TimedOut` with where it was going, the rest are
+/// `Network`
+impl From<reqwest::Error> for GitAiError {
+    fn from(e: reqwest::Error) -> Self {
+        if !e.is_timeout() {
+            return GitAiError::Network(e);
+        }
+        let server = e
+            .url()
+            .and_then(|url| url.host_str())
+            .unwrap_or("The server");
+        let what = if e.is_connect() {
+            "couldn't be connected to"
+        } else {
+            "didn't answer"
+        };
+        GitAiError::TimedOut(format!("{} {} in time", server, what))
+    }
+}
+
+/// The result type used all over gitai
+pub type Result<T> = std::result::Result<T, GitAiError>;
+
+/// Passes a successful response along, an error status becomes `Unauthorized` or `Api`
+/// with whatever the server said about it
+///
+/// # Arguments
+///
+/// * `res` - The response
+/// * `service` - Who answered, for the message
+pub async fn check_status(res: Response, service: &str) -> Result<Response> {
+    let status = res.status();
+    if status.is_success() {
+        return Ok(res);
+    }
+    log::debug!("{} answered with {}", service, status);
+    if status == StatusCode::UNAUTHORIZED {
+        return Err(GitAiError::Unauthorized {
+            service: service.to_string(),
+        });
+    }
+    Err(api_error(
+        service,
+        status,
+        res.text().await.unwrap_or_default(),
+    ))
+}
+
+/// The `Api` error for an error status, with whatever the server said about it
+///
+/// # Arguments
+///
+/// * `service` - Who answered, for the message
+/// * `status` - The status it answered with
+/// * `text` - The body it sent
+pub fn api_error(service: &str, status: StatusCode, text: String) -> GitAiError {
+    // OpenAI puts the reason in `error.message`, GitHub and GitLab in `message`
+    let message = serde_json::from_str::<Value>(&text)
+        .ok()
+        .and_then(|v| {
+            ["/error/message", "/message", "/error"]
+                .iter()
+                .find_map(|at| v.pointer(at)?.as_str().map(String::from))
+        })
+        .unwrap_or(text);
+    GitAiError::Api {
+        service: service.to_string(),
+        status: status.as_u16(),
+        message,
+    }
+}
@@ -0,0 +1,73 @@
+use crate::settings::AiPrompt;
+
+/// How many commit messages of a range go in the prompt, past that the diff says enough
+pub const MAX_MESSAGES: usize = 30;
+
+/// Who the explanation is written for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Level {
+    /// New to the code base, or to the language, so nothing is taken for granted
+    Beginner,
+    /// Knows the code base, only what isn't obvious from reading the diff
+    Expert,
+}
+
+/// One of the commits being explained
+#[derive(Debug, Clone)]
+pub struct ExplainedCommit {
+    /// The short commit id
+    pub id: String,
+    /// The whole message, what the author said the change was for
+    pub message: String,
+}
+
+/// The prompt asking the AI what a commit (or range of them) did, in plain English
+///
+/// # Arguments
+///
+/// * `language` - The programming language
+/// * `rev` - The commit or range, as it was asked for
+/// * `commits` - Its commits, newest first
+/// * `diff` - What they changed together
+/// * `level` - Who it is for, `None` for somewhere in between
+pub fn prompt(
+    language: &str,
+    rev: &str,
+    commits: &[ExplainedCommit],
+    diff: &str,
+    level: Option<Level>,
+) -> AiPrompt {
+    let mut listing = String::new();
+    // oldest first, in the order they happened
+    for commit in commits.iter().take(MAX_MESSAGES).rev() {
+        listing.push_str(&format!(
+            "commit {}: {}\n",
+            commit.id,
+            commit.message.trim_end()
+        ));
+    }
+    if commits.len() > MAX_MESSAGES {
+        listing.push_str(&format!(
+            "(and {} older commits)\n",
+            commits.len() - MAX_MESSAGES
+        ));
+    }
+    let audience = match level {
+        Some(Level::Beginner) => "The reader is new to this code base and may be new to the language, so explain any idiom, library or term that isn't everyday, and say why the change matters, not just what it does.",
+        Some(Level::Expert) => "The reader knows this code base well, so skip anything obvious from reading the diff and go straight to intent, design decisions, trade-offs and anything subtle or risky.",
+        None => "The reader is a developer who hasn't seen this code before.",
+    };
+    AiPrompt {
+        language: language.to_string(),
+        postamble: format!(
+            "developer digging through unfamiliar history to understand {}. These are the commit messages, oldest first, and then what changed:",
+            rev
+        ),
+        git_diff: format!("{}\n{}", listing, diff),
+        postmessage: format!(
+            "Explain in plain English what this change does and why it was probably made, in a few short paragraphs.  Mention files and functions by name where it helps, and don't repeat the diff line by line.  If a commit message says something the diff doesn't back up, say so.  {}",
+            audience
+        ),
+        ..Default::default()
+    }
+}
@@ -0,0 +1,495 @@
+use std::{fs, path::Path};
+
+use futures_util::future::{self, Either, LocalBoxFuture};
+use git2::{Diff, Repository};
+use log::{debug, info, warn};
+
+use crate::ai::{self, OpenAiClient, OpenAiRequestParams};
+use crate::audit::{self, ChangeSize};
+use crate::compress;
+use crate::conventional;
+use crate::docs;
+use crate::error::{GitAiError, Result};
+use crate::git::{FileDiff, Git};
+use crate::gitmoji;
+use crate::hooks::{self, Completions, HookPoint};
+use crate::intent::{self, Breakdown, Hunk};
+use crate::redact::{self, Masked};
+use crate::settings::{AiPrompt, MessageStyle, Settings};
+use crate::style;
+use crate::workspace::{Impact, Workspace};
+
+/// Anything that can turn a prompt into text.  gitai ships with `OpenAiClient`,
+/// implement it to plug in another model (or a canned one for your own tests).
+/// `complete` hands back a boxed future so generators can sit behind a `dyn`, wrap the
+/// body in `Box::pin(async move { ... })`.
+pub trait MessageGenerator {
+    /// Sends the prompt and returns the text of each completion
+    ///
+    /// # Arguments
+    ///
+    /// * `prompt` - The fully built prompt (language and git diff already set)
+    /// * `n` - How many completions to ask for
+    /// * `model` - A model to use instead of the default one
+    fn complete<'a>(
+        &'a self,
+        prompt: AiPrompt,
+        n: u8,
+        model: Option<&'a str>,
+    ) -> LocalBoxFuture<'a, Result<Vec<String>>>;
+}
+
+impl MessageGenerator for OpenAiClient {
+    fn complete<'a>(
+        &'a self,
+        prompt: AiPrompt,
+        n: u8,
+        model: Option<&'a str>,
+    ) -> LocalBoxFuture<'a, Result<Vec<String>>> {
+        Box::pin(async move {
+            let mut params = request_params(&prompt, n);
+            params.model = model.unwrap_or(self.model()).to_string();
+            debug!("Posting to OpenAI");
+            let res = self.get_completions(prompt, params).await?;
+            Ok(res
+                .choices
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|choice| choice.text)
+                .collect())
+        })
+    }
+}
+
+/// Two generators asked the same thing at once, the first good answer wins and the
+/// slower request is dropped.  Trades a second bill for the better of two latencies.
+pub struct Race {
+    contenders: [(String, Box<dyn MessageGenerator>); 2],
+}
+
+impl Race {
+    /// Races `first` against `second`, the names are only for the log
+    ///
+    /// # Arguments
+    ///
+    /// * `first` - The usual generator and its name
+    /// * `second` - The one it races against and its name
+    pub fn new(
+        first: (String, Box<dyn MessageGenerator>),
+        second: (String, Box<dyn MessageGenerator>),
+    ) -> Self {
+        Race {
+            contenders: [first, second],
+        }
+    }
+}
+
+/// An answer that has at least one message in it
+fn usable(result: &Result<Vec<String>>) -> bool {
+    match result {
+        Ok(texts) => texts.iter().any(|text| !text.trim().is_empty()),
+        Err(_) => false,
+    }
+}
+
+impl MessageGenerator for Race {
+    fn complete<'a>(
+        &'a self,
+        prompt: AiPrompt,
+        n: u8,
+        model: Option<&'a str>,
+    ) -> LocalBoxFuture<'a, Result<Vec<String>>> {
+        Box::pin(async move {
+            let [(first_name, first), (second_name, second)] = &self.contenders;
+            let racing = future::select(
+                first.complete(prompt.clone(), n, model),
+                second.complete(prompt, n, model),
+            );
+            // dropping the loser's future cancels its request
+            let (winner, result, loser, rest) = match racing.await {
+                Either::Left((result, rest)) => (first_name, result, second_name, rest),
+                Either::Right((result, rest)) => (second_name, result, first_name, rest),
+            };
+            if usable(&result) {
+                info!("{} answered first", winner);
+                return result;
+            }
+            debug!(
+                "{} answered first but with nothing usable, waiting on {}",
+                winner, loser
+            );
+            let other = rest.await;
+            if usable(&other) {
+                info!("{} answered", loser);
+                other
+            } else {
+                // an error says more than an empty answer
+                result.and(other)
+            }
+        })
+    }
+}
+
+/// Drops the blank lines the AI likes to pad its answers with
+pub fn remove_blank_lines(input: &str) -> String {
+    input
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .collect::<Vec<&str>>()
+        .join("\n")
+}
+
+/// Drops the blank lines inside the body, the one between the subject and the body
+/// stays so git still tells them apart
+pub fn remove_body_blank_lines(message: &str) -> String {
+    let mut lines = message.lines().skip_while(|line| line.trim().is_empty());
+    let subject = match lines.next() {
+        Some(subject) => subject,
+        None => return String::new(),
+    };
+    let rest: Vec<&str> = lines.collect();
+    let body = remove_blank_lines(&rest.join("\n"));
+    match rest.first() {
+        _ if body.is_empty() => subject.to_string(),
+        Some(line) if line.trim().is_empty() => format!("{}\n\n{}", subject, body),
+        _ => format!("{}\n{}", subject, body),
+    }
+}
+
+/// Posts the prompt to the AI asking for `num_tries` completions and returns their text
+///
+/// # Arguments
+///
+/// * `generator` - Whatever writes the text, usually the OpenAI client
+/// * `prompt` - The fully built prompt (language and git diff already set)
+/// * `num_tries` - How many completions to ask for
+pub async fn get_completion_texts(
+    generator: &dyn MessageGenerator,
+    prompt: AiPrompt,
+    num_tries: u8,
+) -> Result<Vec<String>> {
+    get_completion_texts_with_model(generator, prompt, num_tries, None).await
+}
+
+/// `get_completion_texts`, but with a different model than the client asks
+pub async fn get_completion_texts_with_model(
+    generator: &dyn MessageGenerator,
+    prompt: AiPrompt,
+    num_tries: u8,
+    model: Option<&str>,
+) -> Result<Vec<String>> {
+    let prompt = hooks::run(HookPoint::PrePrompt, prompt)?;
+    let completions = generator.complete(prompt, num_tries, model).await?;
+    if completions.is_empty() {
+        return Err(GitAiError::BadResponse(
+            "OpenAI responded but with no completions".to_string(),
+        ));
+    }
+    Ok(hooks::run(HookPoint::PostGeneration, Completions { completions })?.completions)
+}
+
+/// How commit messages have to start, what the AI writes is checked against it.  The
+/// two don't go together, a gitmoji in front would break the Conventional Commits header.
+/// `style` is the shape of the rest, it is asked for and wrapped into but never checked.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MessageFormat {
+    /// A Conventional Commits header, `type(scope): summary`
+    pub conventional: bool,
+    /// A gitmoji from https://gitmoji.dev in front of the subject
+    pub gitmoji: bool,
+    /// Subject only, subject and body, or a bulleted body
+    pub style: MessageStyle,
+}
+
+impl MessageFormat {
+    /// Whether there is anything to check
+    fn checked(&self) -> bool {
+        self.conventional || self.gitmoji
+    }
+
+    /// The message tidied into the format, the error says why it can't be
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - What the AI wrote
+    pub fn fix_up(&self, text: &str) -> std::result::Result<String, String> {
+        // fix_up only gives up on messages that don't parse, so there is always a reason
+        if self.conventional {
+            conventional::fix_up(text)
+                .ok_or_else(|| conventional::parse(text.trim()).err().unwrap_or_default())
+        } else if self.gitmoji {
+            gitmoji::fix_up(text)
+                .ok_or_else(|| gitmoji::parse(text.trim()).err().unwrap_or_default())
+        } else {
+            Ok(text.to_string())
+        }
+    }
+}
+
+/// Generates commit messages and throws away the vague ones.  After `retries` rounds
+/// with nothing good the last try uses the stricter fallback prompt (and `fallback_model`
+/// if there is one), and whatever that gives back is used.
+///
+/// A message that isn't in `format` (a Conventional Commit, or with a gitmoji) and can't
+/// be fixed up into it counts as a bad one too.
+pub async fn generate_commit_messages(
+    generator: &dyn MessageGenerator,
+    prompt: AiPrompt,
+    num_tries: u8,
+    size: ChangeSize,
+    retries: u8,
+    fallback_model: &str,
+    format: MessageFormat,
+) -> Result<Vec<String>> {
+    if retries == 0 && !format.checked() {
+        return get_completion_texts(generator, prompt, num_tries).await;
+    }
+    let check = |text: String| -> std::result::Result<String, Vec<String>> {
+        let text = format.fix_up(&text).map_err(|reason| vec![reason])?;
+        match audit::quality_problems(&text, size) {
+            Some(problems) => Err(problems),
+            None => Ok(text),
+        }
+    };
+    for attempt in 1..=retries.max(1) {
+        let completions = get_completion_texts(generator, prompt.clone(), num_tries).await?;
+        let mut good = Vec::new();
+        for text in completions {
+            match check(text) {
+                Ok(text) => good.push(text),
+                Err(problems) => warn!(
+                    "Attempt {} was not good enough ({})",
+                    attempt,
+                    problems.join(", ")
+                ),
+            }
+        }
+        if !good.is_empty() {
+            return Ok(good);
+        }
+    }
+    info!("Switching to the fallback prompt for the last try");
+    let mut fallback = AiPrompt {
+        language: prompt.language,
+        git_diff: prompt.git_diff,
+        ..Settings::get_fallback_commit_prompt()
+    };
+    fallback = commit_prompt(fallback, format);
+    let model = Some(fallback_model).filter(|model| !model.is_empty());
+    Ok(
+        get_completion_texts_with_model(generator, fallback, num_tries, model)
+            .await?
+            .into_iter()
+            .map(|text| {
+                format.fix_up(&text).unwrap_or_else(|reason| {
+                    warn!(
+                        "The last try is still not right ({}), check it before you accept it",
+                        reason
+                    );
+                    text
+                })
+            })
+            .collect(),
+    )
+}
+
+/// The commit prompt, asking for a Conventional Commit, a gitmoji and the message
+/// style if we want them
+pub fn commit_prompt(prompt: AiPrompt, format: MessageFormat) -> AiPrompt {
+    let prompt = if format.conventional {
+        conventional::prompt(prompt)
+    } else {
+        prompt
+    };
+    let prompt = if format.gitmoji {
+        gitmoji::prompt(prompt)
+    } else {
+        prompt
+    };
+    style::prompt(prompt, format.style)
+}
+
+/// Cleans up a generated commit message.  Blank lines inside the body go, the one
+/// between the subject (or Conventional Commit header) and the body stays, and a
+/// gitmoji gets written the way the official list writes it.  With a `message_style` the message
+/// is shaped into it instead, the body wrapped at 72 columns.
+pub fn tidy_message(text: &str, format: MessageFormat) -> String {
+    let message = if format.conventional {
+        conventional::fix_up(text).unwrap_or_else(|| text.trim().to_string())
+    } else if format.gitmoji {
+        gitmoji::fix_up(text).unwrap_or_else(|| text.to_string())
+    } else {
+        text.to_string()
+    };
+    if format.style == MessageStyle::Paragraph && !format.conventional {
+        remove_body_blank_lines(&message)
+    } else {
+        style::shape(&message, format.style)
+    }
+}
+
+/// How big a diff is, for the message quality checks
+pub fn change_size(diff: &Diff) -> Result<ChangeSize> {
+    let stats = diff.stats()?;
+    Ok(ChangeSize {
+        files: stats.files_changed(),
+        lines: stats.insertions() + stats.deletions(),
+    })
+}
+
+/// The request parameters for `prompt`, everything else is the default
+pub fn request_params(prompt: &AiPrompt, num_tries: u8) -> OpenAiRequestParams {
+    OpenAiRequestParams {
+        prompt: format!("{}", prompt),
+        n: Some(num_tries),
+        ..Default::default()
+    }
+}
+
+/// Writes exactly what `get_completion_texts` would send for `prompt` to `path`, without sending it
+pub fn write_request_body(
+    client: &OpenAiClient,
+    prompt: AiPrompt,
+    num_tries: u8,
+    path: &Path,
+) -> Result<()> {
+    let prompt = hooks::run(HookPoint::PrePrompt, prompt)?;
+    let params = OpenAiRequestParams {
+        model: client.model().to_string(),
+        ..request_params(&prompt, num_tries)
+    };
+    let body = client.request_body(prompt, params)?;
+    fs::write(path, body)?;
+    Ok(())
+}
+
+/// The files of the diff the way the AI gets to see them, `.gitaiignore` files left out,
+/// `diff_ignore` files cut down to a line saying they changed and likely secrets masked,
+/// with what was masked
+fn prompt_files(git: &Git<'_>, diff: &Diff<'_>) -> Result<(Vec<FileDiff>, Vec<Masked>)> {
+    let mut files = git.diff_to_file_strings(diff)?;
+    files.retain(|file| !git.excludes(&file.path));
+    for file in files.iter_mut().filter(|file| git.ignores(&file.path)) {
+        debug!(
+            "{:#?} is in diff_ignore, the AI only hears it changed",
+            file.path
+        );
+        file.text = format!(
+            "~ {} changed (+{} -{}), not shown\n",
+            file.path.display(),
+            file.added,
+            file.removed
+        );
+    }
+    let masked = if git.redact {
+        redact::redact_files(&mut files)
+    } else {
+        Vec::new()
+    };
+    Ok((files, masked))
+}
+
+/// What gets masked in the diff before the AI sees it, so it can be reported
+///
+/// # Arguments
+///
+/// * `git` - The git it is read with, `diff_ignore` files aren't sent so aren't reported
+/// * `diff` - The diff
+pub fn redactions(git: &Git<'_>, diff: &Diff<'_>) -> Result<Vec<Masked>> {
+    Ok(prompt_files(git, diff)?.1)
+}
+
+/// The diff as text, the way the AI gets to see it (compressed if asked to, `diff_ignore`
+/// files left out, secrets masked), trimmed to `token_budget` tokens so the request
+/// doesn't blow past the context window
+pub fn prompt_diff_text(
+    git: &Git<'_>,
+    diff: &Diff<'_>,
+    compress: bool,
+    token_budget: usize,
+) -> Result<String> {
+    let (mut files, _) = prompt_files(git, diff)?;
+    if compress {
+        for file in files.iter_mut() {
+            file.text = compress::compress_diff(&file.text) + "\n";
+        }
+    }
+    Ok(git.fit_diff_text(files, |text| ai::count_tokens(text) <= token_budget))
+}
+
+/// The workspace packages a diff affects, `None` outside of a Cargo/npm workspace
+/// or when nothing in one changed
+pub fn workspace_impact(git: &Git, repo: &Repository, diff: &Diff) -> Option<Impact> {
+    let impact = Workspace::discover(repo.workdir()?)?.impact(&git.changed_paths(diff));
+    if impact.is_empty() {
+        None
+    } else {
+        Some(impact)
+    }
+}
+
+/// Asks the AI whether the change means the docs need updating, `Some(reasons)` if it
+/// does.  Nothing is sent when the diff doesn't touch a public API.
+pub async fn check_doc_drift(
+    generator: &dyn MessageGenerator,
+    repo: &Repository,
+    diff_text: &str,
+    language: &str,
+) -> Result<Option<String>> {
+    let api_changes = docs::public_api_changes(diff_text);
+    let workdir = match repo.workdir() {
+        Some(workdir) if !api_changes.is_empty() => workdir,
+        _ => {
+            debug!("No public API changes, skipping the doc drift check");
+            return Ok(None);
+        }
+    };
+    let doc_paths = docs::doc_paths(workdir);
+    let prompt = docs::drift_prompt(language, &api_changes, &doc_paths);
+    let answer = get_completion_texts(generator, prompt, 1).await?;
+    Ok(answer
+        .first()
+        .and_then(|answer| docs::parse_drift_answer(answer)))
+}
+
+/// The hunks of the diff `intent_breakdown` asks about, `.gitaiignore` and `diff_ignore`
+/// files left out and likely secrets masked
+pub fn intent_hunks(git: &Git<'_>, diff: &Diff<'_>) -> Result<Vec<Hunk>> {
+    let mut files = git.diff_to_file_strings(diff)?;
+    files.retain(|file| !git.ignores(&file.path) && !git.excludes(&file.path));
+    if git.redact {
+        redact::redact_files(&mut files);
+    }
+    Ok(intent::hunks(&files))
+}
+
+/// Has the AI label every hunk of the change with why it was made and adds them up,
+/// `None` when there are no hunks to label
+pub async fn intent_breakdown(
+    generator: &dyn MessageGenerator,
+    git: &Git<'_>,
+    diff: &Diff<'_>,
+    language: &str,
+) -> Result<Option<Breakdown>> {
+    let hunks = intent_hunks(git, diff)?;
+    if hunks.is_empty() {
+        return Ok(None);
+    }
+    let answer = get_completion_texts(generator, intent::prompt(language, &hunks), 1).await?;
+    let intents = intent::parse_answer(answer.first().map_or("", String::as_str), hunks.len());
+    Ok(Some(Breakdown::new(&hunks, &intents)))
+}
+
+/// Splits an AI generated pull request into its title (the first non blank line)
+/// and its body (everything after it)
+pub fn split_pull_request(text: &str) -> (String, String) {
+    let text = text.trim();
+    let (title, body) = text.split_once('\n').unwrap_or((text, ""));
+    let title = title
+        .trim()
+        .trim_start_matches('#')
+        .trim()
+        .trim_start_matches("Title:")
+        .trim();
+    (title.to_string(), body.trim().to_string())
+}
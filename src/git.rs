@@ -1,16 +1,218 @@
 use std::{
-    collections::HashMap,
-    path::{PathBuf, MAIN_SEPARATOR},
+    cell::Cell,
+    collections::{BTreeMap, HashSet},
+    env, fs, io,
+    path::{Path, PathBuf},
 };
 
+use base64::{engine::general_purpose::STANDARD, Engine};
+
 use git2::{
-    Commit, Cred, Diff, DiffDelta, DiffFormat, DiffHunk, DiffLine, DiffOptions, IndexAddOption,
-    ObjectType, Oid, PushOptions, RemoteCallbacks, Repository, Signature,
+    ApplyLocation, ApplyOptions, Commit, Cred, CredentialType, Delta, DescribeFormatOptions,
+    DescribeOptions, Diff, DiffDelta, DiffFormat, DiffHunk, DiffLine, DiffOptions, ErrorClass,
+    Index, IndexAddOption, ObjectType, Oid, Patch, PushOptions, RemoteCallbacks, Repository,
+    Signature, Sort, Tree,
 };
-use log::{debug, info, log_enabled, Level};
-use reqwest::header::{HeaderMap, HeaderValue, ACCEPT, AUTHORIZATION, CONTENT_TYPE};
-use serde::Deserialize;
-use serde::Serialize;
+use log::{debug, log_enabled, Level};
+
+use crate::aiignore::AiIgnore;
+use crate::error::{GitAiError, Result};
+use crate::interactive;
+use crate::settings;
+use crate::signing::Signer;
+use crate::terminal;
+
+/// Lockfiles nobody reads
+const LOCKFILES: [&str; 10] = [
+    "Cargo.lock",
+    "package-lock.json",
+    "npm-shrinkwrap.json",
+    "yarn.lock",
+    "pnpm-lock.yaml",
+    "poetry.lock",
+    "Pipfile.lock",
+    "Gemfile.lock",
+    "composer.lock",
+    "go.sum",
+];
+
+/// How far back `commits_touching_paths` looks
+const HISTORY_DEPTH: usize = 500;
+
+/// git's scissors line, nothing below it is part of the message
+const SCISSORS: &str = "# ------------------------ >8 ------------------------";
+
+/// One file's worth of a diff, as text
+#[derive(Debug, Clone, Default)]
+pub struct FileDiff {
+    /// The file
+    pub path: PathBuf,
+    /// The diff, formatted like `Git::diff_to_string`
+    pub text: String,
+    /// Lines added
+    pub added: usize,
+    /// Lines removed
+    pub removed: usize,
+}
+
+/// One hunk of a change that isn't staged yet, to pick whether it goes in the commit
+#[derive(Debug, Clone)]
+pub struct UnstagedHunk {
+    /// The file
+    pub path: PathBuf,
+    /// Which file of the diff it is in
+    pub delta: usize,
+    /// Which hunk of that file it is
+    pub index: usize,
+    /// The hunk, its `@@` header and lines, like `git diff` shows it
+    pub text: String,
+}
+
+/// How much one file of a diff changed, like a line of `git diff --stat`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileStat {
+    /// The file, its new name for a rename
+    pub path: PathBuf,
+    /// `A`, `M`, `D` or `R`, like `git status --short`
+    pub status: char,
+    /// Lines added
+    pub additions: usize,
+    /// Lines taken out
+    pub deletions: usize,
+    /// A binary file has no lines to count
+    pub binary: bool,
+}
+
+/// Where a remote points, taken apart
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteLocation {
+    /// `github.com`, or wherever it is hosted
+    pub host: String,
+    /// The user or organization that owns the repo
+    pub owner: String,
+    /// The repo, without `.git`
+    pub name: String,
+}
+
+/// Takes a remote url apart, both the ssh forms (`git@github.com:owner/repo.git`,
+/// `ssh://git@host:22/owner/repo`) and https ones work.  `None` for local paths
+/// and anything else without a host and an owner.
+///
+/// # Arguments
+///
+/// * `remote_url` - The remote's url
+pub fn parse_remote_url(remote_url: &str) -> Option<RemoteLocation> {
+    let (host, path) = match url::Url::parse(remote_url) {
+        Ok(parsed) if parsed.scheme() != "file" => {
+            (parsed.host_str()?.to_string(), parsed.path().to_string())
+        }
+        Ok(_) => return None,
+        // scp-like ssh, `[user@]host:path`
+        Err(_) => {
+            let (host, path) = remote_url.split_once(':')?;
+            let host = host.rsplit('@').next()?;
+            if host.is_empty() || host.contains('/') {
+                return None;
+            }
+            (host.to_string(), path.to_string())
+        }
+    };
+    let mut parts = path.trim_matches('/').rsplitn(2, '/');
+    let name = parts.next()?.trim_end_matches(".git");
+    let owner = parts.next()?;
+    if name.is_empty() || owner.is_empty() {
+        return None;
+    }
+    Some(RemoteLocation {
+        host: host.to_lowercase(),
+        owner: owner.to_string(),
+        name: name.to_string(),
+    })
+}
+
+/// Lockfiles and generated code, the first things to go when a prompt is too big
+///
+/// # Arguments
+///
+/// * `path` - The changed file
+pub fn is_low_value(path: &Path) -> bool {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    LOCKFILES.contains(&name.as_str())
+        || name.ends_with(".min.js")
+        || name.ends_with(".min.css")
+        || name.ends_with(".map")
+        || name.ends_with(".snap")
+        || name.ends_with(".pb.go")
+        || name.ends_with("_pb2.py")
+        || name.contains(".generated.")
+        || name.contains("_generated.")
+        || path.components().any(|c| {
+            let c = c.as_os_str();
+            c == "vendor" || c == "dist" || c == "node_modules"
+        })
+}
+
+/// Whether `path` matches one of the `diff_ignore` globs.  A pattern with a `/` in it
+/// has to match the whole path, one without only the file name.
+///
+/// # Arguments
+///
+/// * `path` - The changed file, relative to the repo
+/// * `patterns` - The globs
+pub fn is_ignored(path: &Path, patterns: &[String]) -> bool {
+    let path = path.to_string_lossy().replace('\\', "/");
+    let name = path.rsplit('/').next().unwrap_or(&path);
+    patterns.iter().any(|pattern| {
+        if pattern.contains('/') {
+            settings::glob_match(pattern.trim_start_matches('/'), &path)
+        } else {
+            settings::glob_match(pattern, name)
+        }
+    })
+}
+
+/// A size the way people say it, `512 B`, `12 KB`, `3.4 MB`
+fn human_size(bytes: u64) -> String {
+    const KB: u64 = 1024;
+    const MB: u64 = KB * 1024;
+    if bytes < KB {
+        format!("{} B", bytes)
+    } else if bytes < MB {
+        format!("{} KB", (bytes + KB / 2) / KB)
+    } else {
+        format!("{:.1} MB", bytes as f64 / MB as f64)
+    }
+}
+
+/// The line a binary file gets instead of its contents, nobody wants the bytes in a prompt
+fn binary_summary(delta: &DiffDelta) -> String {
+    let old = delta.old_file();
+    let new = delta.new_file();
+    let path = new
+        .path()
+        .or_else(|| old.path())
+        .unwrap_or_else(|| Path::new(""))
+        .display();
+    match delta.status() {
+        Delta::Added | Delta::Untracked => {
+            format!("Binary file {} added ({})\n", path, human_size(new.size()))
+        }
+        Delta::Deleted => format!(
+            "Binary file {} deleted ({})\n",
+            path,
+            human_size(old.size())
+        ),
+        _ => format!(
+            "Binary file {} changed ({} -> {})\n",
+            path,
+            human_size(old.size()),
+            human_size(new.size())
+        ),
+    }
+}
 
 /// Struct to hold information for your local Git
 #[derive(Debug, Copy, Clone)]
@@ -33,6 +235,12 @@ pub struct Git<'a> {
     pub ssh_key_path: Option<&'a str>,
     /// The ssh user name, i have never seen where it wasn't git
     pub ssh_user_name: Option<&'a str>,
+    /// Globs for the files the AI only hears changed, see `with_diff_ignore`
+    pub diff_ignore: Option<&'a [String]>,
+    /// Should likely secrets be masked before the AI sees the diff, defaults to true
+    pub redact: bool,
+    /// The repo's `.gitaiignore`, see `with_ai_ignore`
+    pub ai_ignore: Option<&'a AiIgnore>,
 }
 
 /// Default implementation of the Git Opyions
@@ -46,104 +254,15 @@ impl Default for Git<'_> {
             key_id: None,
             user_name: None,
             user_email: None,
-            ssh_key_path: Some(&"~/.ssh/id_rsa"),
-            ssh_user_name: Some(&"git"),
+            ssh_key_path: Some("~/.ssh/id_rsa"),
+            ssh_user_name: Some("git"),
+            diff_ignore: None,
+            redact: true,
+            ai_ignore: None,
         }
     }
 }
 
-/// GitGub Options
-#[derive(Debug, Default)]
-pub struct GitHub {
-    /// The GitHub API Token
-    github_token: String,
-    /// The GitHub API URL
-    github_url: String,
-    /// the GitHub user name
-    github_username: String,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct PullResponse {
-    url: String,
-    html_url: String,
-    diff_url: String,
-    patch_url: String,
-    issue_url: String,
-    commits_url: String,
-    review_comments_url: String,
-    review_comment_url: String,
-    statuses_url: String,
-    number: String,
-    state: String,
-    locked: String,
-}
-
-/// The implementation for `GitHubOptions`
-impl GitHub {
-    /// Create a new GitHub struct.
-    ///
-    /// # Arguments
-    ///
-    /// * `github_token` - The Github Token
-    /// * `github_url` - The Github API Url
-    pub fn new(github_token: &str, github_url: &str) -> Self {
-        let user_name =
-            get_value_from_api(github_url, github_token, "login", "user").unwrap_or_default();
-        let g = GitHub {
-            github_token: github_token.to_string(),
-            github_url: github_url.to_string(),
-            github_username: user_name,
-        };
-        return g;
-    }
-
-    pub fn push(
-        self,
-        repo: &Repository,
-        to_branch: String,
-        from_branch: String,
-        message: String,
-    ) -> Result<PullResponse, Box<dyn std::error::Error>> {
-        debug!("Pushing commits from {} to {}", from_branch, to_branch);
-        let binding = PathBuf::from(repo.path());
-        let path_str = binding.to_str().expect("Unable to get repo name");
-        let parts = path_str.split(MAIN_SEPARATOR);
-        let url = format!(
-            "{}/repos/{}/{}/pulls",
-            self.github_url,
-            self.github_username,
-            parts.last().expect("Cannot get Repo Name")
-        );
-        debug!("Posting to {}", url);
-        let client = self.get_client();
-        // set the body
-        let mut map = HashMap::new();
-        map.insert("title", "AI Generated Pull Request");
-        map.insert("head", &from_branch);
-        map.insert("base", &to_branch);
-        map.insert("body", &message);
-        info!("Sending push request to {}", url);
-        let res = client.post(url).json(&map).send()?;
-        let data = res.json::<PullResponse>()?;
-        return Ok(data);
-    }
-    fn get_client(self) -> reqwest::blocking::Client {
-        let mut headers = HeaderMap::new();
-        headers.insert(CONTENT_TYPE, "application/vnd.github+json".parse().unwrap());
-        headers.insert(
-            AUTHORIZATION,
-            format!("Bearer {}", self.github_token).parse().unwrap(),
-        );
-        headers.insert("X-GitHub-Api-Version", "2022-11-28".parse().unwrap());
-        let client = reqwest::blocking::ClientBuilder::new()
-            .default_headers(headers)
-            .build()
-            .expect("Error Building Reqwest Client");
-        return client;
-    }
-}
-
 /// The implementation of `Git`
 impl<'a> Git<'a> {
     /// Create a new Git struct.  Everything but the path is optional
@@ -157,6 +276,7 @@ impl<'a> Git<'a> {
     /// * `key_id` - The key id, only matters if `sign_commits` is `true`
     /// * `user_name` - The git user name
     /// * `user_email` - The git user email
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         path: &'a str,
         auto_add: Option<&'a bool>,
@@ -168,7 +288,7 @@ impl<'a> Git<'a> {
         ssh_key_path: Option<&'a str>,
         ssh_user_name: Option<&'a str>,
     ) -> Self {
-        let g = Git {
+        Git {
             path,
             auto_add,
             auto_push,
@@ -178,15 +298,65 @@ impl<'a> Git<'a> {
             user_email,
             ssh_key_path,
             ssh_user_name,
-        };
-        return g;
+            diff_ignore: None,
+            redact: true,
+            ai_ignore: None,
+        }
+    }
+
+    /// Leaves the files matching these globs out of the diff the AI sees, it only gets a
+    /// line saying they changed.  Lockfiles and build output are a lot of tokens for nothing.
+    ///
+    /// # Arguments
+    ///
+    /// * `patterns` - The globs, `git_options.diff_ignore`
+    pub fn with_diff_ignore(self, patterns: &'a [String]) -> Self {
+        Git {
+            diff_ignore: Some(patterns),
+            ..self
+        }
+    }
+
+    /// Whether secrets in the diff get masked before the AI sees it, on unless turned
+    /// off here (`--no-redact`)
+    ///
+    /// # Arguments
+    ///
+    /// * `redact` - Mask them or not
+    pub fn with_redaction(self, redact: bool) -> Self {
+        Git { redact, ..self }
+    }
+
+    /// Keeps the files the repo's `.gitaiignore` matches out of the diff the AI sees
+    /// altogether, and out of `auto_add`
+    ///
+    /// # Arguments
+    ///
+    /// * `ai_ignore` - The rules, from `AiIgnore::load`
+    pub fn with_ai_ignore(self, ai_ignore: &'a AiIgnore) -> Self {
+        Git {
+            ai_ignore: Some(ai_ignore),
+            ..self
+        }
+    }
+
+    /// Whether `path` is in `.gitaiignore`, so the AI shouldn't hear about it at all
+    pub fn excludes(&self, path: &Path) -> bool {
+        self.ai_ignore
+            .is_some_and(|ai_ignore| ai_ignore.excludes(path))
+    }
+
+    /// Whether the AI should only hear that `path` changed
+    pub fn ignores(&self, path: &Path) -> bool {
+        self.diff_ignore
+            .is_some_and(|patterns| is_ignored(path, patterns))
     }
 
     /// Opens the repository
-    pub fn open_repository(self) -> Result<Repository, git2::Error> {
+    pub fn open_repository(self) -> Result<Repository> {
         debug!("Getting repository");
         let repo = Repository::open(self.path)?;
-        return Ok(repo);
+        Ok(repo)
     }
 
     /// find the last commit to this repo
@@ -194,11 +364,12 @@ impl<'a> Git<'a> {
     /// # Arguments
     ///
     /// * `repo` - The repository
-    pub fn find_last_commit(self, repo: &Repository) -> Result<Commit, git2::Error> {
+    pub fn find_last_commit(self, repo: &Repository) -> Result<Commit<'_>> {
         debug!("Finding last commit");
         let obj = repo.head()?.resolve()?.peel(ObjectType::Commit)?;
-        obj.into_commit()
-            .map_err(|_| git2::Error::from_str("Couldn't find last commit"))
+        Ok(obj
+            .into_commit()
+            .map_err(|_| git2::Error::from_str("Couldn't find last commit"))?)
     }
 
     /// Adds all untracked files to repo (same as running `git add .`)
@@ -206,22 +377,36 @@ impl<'a> Git<'a> {
     /// # Arguments
     ///
     /// * `repo` - The repository
-    fn add_all(self, repo: &Repository) -> Result<(), git2::Error> {
+    fn add_all(self, repo: &Repository) -> Result<()> {
         debug!("Adding all files to the index");
         let mut index = repo.index()?;
-        index.add_all(["*"].iter(), IndexAddOption::DEFAULT, None)?;
-        return index.write();
+        // anything in .gitaiignore is left for you to add yourself
+        let mut skip_excluded = |path: &Path, _: &[u8]| -> i32 {
+            if self.excludes(path) {
+                debug!("{:#?} is in .gitaiignore, not adding it", path);
+                1
+            } else {
+                0
+            }
+        };
+        index.add_all(
+            ["*"].iter(),
+            IndexAddOption::DEFAULT,
+            Some(&mut skip_excluded),
+        )?;
+        Ok(index.write()?)
     }
 
     /// Gets the diff on what is going to be committed.  If `auto_add` is false
-    /// only files you added to the index yourself will be committed.
+    /// only files you added to the index yourself will be committed.  Nothing staged
+    /// is a `NothingStaged` error, there's no point asking the AI about an empty diff.
     ///
     /// If you want to see what will be sent this is the equivalent of `git diff --cached`
     ///
     /// # Arguments
     ///
     /// * `repo` - The repository
-    pub fn get_commit_diff(self, repo: &Repository) -> Result<Diff, git2::Error> {
+    pub fn get_commit_diff(self, repo: &Repository) -> Result<Diff<'_>> {
         debug!("Creating commit");
         let last_commit = self.find_last_commit(repo)?;
         // some helpful debug stuff
@@ -243,54 +428,628 @@ impl<'a> Git<'a> {
             Some(&index),
             Some(&mut DiffOptions::default()),
         )?;
-        return Ok(diff);
+        if diff.deltas().len() == 0 {
+            return Err(GitAiError::NothingStaged(
+                "Nothing is staged, so there is nothing to write a commit message for".to_string(),
+            ));
+        }
+        Ok(diff)
+    }
+
+    /// Gets the diff the last commit would have after an amend, what it changed plus
+    /// whatever is staged now.  With `auto_add` everything gets staged first.
+    ///
+    /// If you want to see what will be sent this is the equivalent of `git diff --cached HEAD~1`
+    ///
+    /// # Arguments
+    ///
+    /// * `repo` - The repository
+    pub fn get_amend_diff(self, repo: &Repository) -> Result<Diff<'_>> {
+        let last_commit = self.find_last_commit(repo)?;
+        if *self.auto_add.unwrap_or(&false) {
+            debug!("Automatically adding all files to index");
+            self.add_all(repo)?;
+        }
+        // the first commit in a repo is diffed against nothing
+        let parent_tree = match last_commit.parent(0) {
+            Ok(parent) => Some(parent.tree()?),
+            Err(_) => None,
+        };
+        debug!("Diffing the index against {}~1", last_commit.id());
+        Ok(repo.diff_tree_to_index(
+            parent_tree.as_ref(),
+            Some(&repo.index()?),
+            Some(&mut DiffOptions::default()),
+        )?)
     }
 
-    /// Convient method to turn a `Diff` to a `String`
-    /// Will panic if there are any non-UTF8 characters in the generated diff
-    /// although I don't know how that could happen
+    /// Gets the diff of everything changed since the last commit, staged or not, new
+    /// files included (unless `.gitignore` hides them).  The index isn't touched.
+    ///
+    /// If you want to see what will be sent this is the equivalent of `git add -N . && git diff HEAD`
+    ///
+    /// # Arguments
+    ///
+    /// * `repo` - The repository
+    pub fn get_workdir_diff(self, repo: &Repository) -> Result<Diff<'_>> {
+        let last_commit = self.find_last_commit(repo)?;
+        let mut options = DiffOptions::new();
+        options
+            .include_untracked(true)
+            .recurse_untracked_dirs(true)
+            .show_untracked_content(true);
+        debug!("Diffing the working directory against {}", last_commit.id());
+        Ok(repo.diff_tree_to_workdir_with_index(Some(&last_commit.tree()?), Some(&mut options))?)
+    }
+
+    /// What changed in the working directory and isn't staged yet, hunk by hunk, like
+    /// `git add -p` shows it.  New files git doesn't know about aren't in it.
+    ///
+    /// Returns the diff, for `stage_hunks`, and its hunks in order
+    ///
+    /// # Arguments
+    ///
+    /// * `repo` - The repository
+    pub fn unstaged_hunks(self, repo: &Repository) -> Result<(Diff<'_>, Vec<UnstagedHunk>)> {
+        let diff = repo.diff_index_to_workdir(None, Some(&mut DiffOptions::default()))?;
+        let mut hunks = Vec::new();
+        for delta in 0..diff.deltas().len() {
+            // binary files have no hunks to pick from
+            let patch = match Patch::from_diff(&diff, delta)? {
+                Some(patch) => patch,
+                None => continue,
+            };
+            let path = patch
+                .delta()
+                .new_file()
+                .path()
+                .or_else(|| patch.delta().old_file().path())
+                .map(Path::to_path_buf)
+                .unwrap_or_default();
+            for index in 0..patch.num_hunks() {
+                let (hunk, lines) = patch.hunk(index)?;
+                let mut text = String::from_utf8_lossy(hunk.header()).to_string();
+                for line in 0..lines {
+                    let line = patch.line_in_hunk(index, line)?;
+                    text.push(line.origin());
+                    text.push_str(&String::from_utf8_lossy(line.content()));
+                }
+                hunks.push(UnstagedHunk {
+                    path: path.to_path_buf(),
+                    delta,
+                    index,
+                    text,
+                });
+            }
+        }
+        Ok((diff, hunks))
+    }
+
+    /// Stages only the `hunks` of `diff` that were picked, like answering `git add -p`
+    ///
+    /// # Arguments
+    ///
+    /// * `repo` - The repository
+    /// * `diff` - The diff from `unstaged_hunks`
+    /// * `picked` - The hunks to stage
+    pub fn stage_hunks(
+        &self,
+        repo: &Repository,
+        diff: &Diff,
+        picked: &[&UnstagedHunk],
+    ) -> Result<()> {
+        debug!("Staging {} hunks", picked.len());
+        let picked: HashSet<(usize, usize)> =
+            picked.iter().map(|hunk| (hunk.delta, hunk.index)).collect();
+        // libgit2 goes through the files in order, and the hunks of each file in order
+        let next_delta = Cell::new(0);
+        let delta = Cell::new(0);
+        let hunk = Cell::new(0);
+        let mut options = ApplyOptions::new();
+        options
+            .delta_callback(|_| {
+                delta.set(next_delta.get());
+                next_delta.set(delta.get() + 1);
+                hunk.set(0);
+                picked.iter().any(|(d, _)| *d == delta.get())
+            })
+            .hunk_callback(|_| {
+                let index = hunk.get();
+                hunk.set(index + 1);
+                picked.contains(&(delta.get(), index))
+            });
+        Ok(repo.apply(diff, ApplyLocation::Index, Some(&mut options))?)
+    }
+
+    /// Stages `paths` the way `git add -A` would, a file that is gone is removed from the index
+    ///
+    /// # Arguments
+    ///
+    /// * `repo` - The repository
+    /// * `paths` - The files, relative to the repo root
+    pub fn stage_paths(&self, repo: &Repository, paths: &[PathBuf]) -> Result<()> {
+        debug!("Staging {} paths", paths.len());
+        let root = repo
+            .workdir()
+            .ok_or_else(|| git2::Error::from_str("A bare repository has nothing to stage"))?;
+        let mut index = repo.index()?;
+        for path in paths {
+            if root.join(path).exists() {
+                index.add_path(path)?;
+            } else {
+                index.remove_path(path)?;
+            }
+        }
+        Ok(index.write()?)
+    }
+
+    /// Gets the diff of everything on `from_branch` that isn't on `to_branch` yet,
+    /// which is what a pull request from `from_branch` into `to_branch` would merge.
+    ///
+    /// If you want to see what will be sent this is the equivalent of `git diff to...from`
+    ///
+    /// # Arguments
+    ///
+    /// * `repo` - The repository
+    /// * `from_branch` - The branch with the changes
+    /// * `to_branch` - The branch they are going into
+    pub fn get_branch_diff(
+        self,
+        repo: &'a Repository,
+        from_branch: &str,
+        to_branch: &str,
+    ) -> Result<Diff<'a>> {
+        debug!("Diffing {} against {}", from_branch, to_branch);
+        let from_commit = repo.revparse_single(from_branch)?.peel_to_commit()?;
+        let to_commit = repo.revparse_single(to_branch)?.peel_to_commit()?;
+        let base_oid = repo.merge_base(from_commit.id(), to_commit.id())?;
+        let base_tree = repo.find_commit(base_oid)?.tree()?;
+        let from_tree = from_commit.tree()?;
+        debug!("Merge base is {}, Ready to Diff", base_oid);
+        Ok(repo.diff_tree_to_tree(
+            Some(&base_tree),
+            Some(&from_tree),
+            Some(&mut DiffOptions::default()),
+        )?)
+    }
+
+    /// Gets the diff between two revisions, anything `git rev-parse` understands works
+    ///
+    /// # Arguments
+    ///
+    /// * `repo` - The repository
+    /// * `old_rev` - The older revision
+    /// * `new_rev` - The newer revision
+    pub fn get_range_diff(
+        self,
+        repo: &'a Repository,
+        old_rev: &str,
+        new_rev: &str,
+    ) -> Result<Diff<'a>> {
+        debug!("Diffing {} against {}", new_rev, old_rev);
+        let old_tree = repo.revparse_single(old_rev)?.peel_to_tree()?;
+        let new_tree = repo.revparse_single(new_rev)?.peel_to_tree()?;
+        Ok(repo.diff_tree_to_tree(
+            Some(&old_tree),
+            Some(&new_tree),
+            Some(&mut DiffOptions::default()),
+        )?)
+    }
+
+    /// The commits in `range`, newest first.  Takes anything `git log` would, `A..B`
+    /// or a single revision for its whole history
+    ///
+    /// # Arguments
+    ///
+    /// * `repo` - The repository
+    /// * `range` - The revision range
+    pub fn commits_in_range(&self, repo: &Repository, range: &str) -> Result<Vec<Oid>> {
+        let mut walk = repo.revwalk()?;
+        walk.set_sorting(Sort::TOPOLOGICAL | Sort::TIME)?;
+        if range.contains("..") {
+            walk.push_range(range)?;
+        } else {
+            walk.push(repo.revparse_single(range)?.peel_to_commit()?.id())?;
+        }
+        Ok(walk.collect::<std::result::Result<Vec<Oid>, git2::Error>>()?)
+    }
+
+    /// The commits a push would send, newest first.  For a branch the remote doesn't
+    /// have yet that is everything not already on one of its branches.
+    ///
+    /// # Arguments
+    ///
+    /// * `repo` - The repository
+    /// * `local` - The commit being pushed
+    /// * `remote_sha` - Where the remote branch is now, `None` if it doesn't exist
+    /// * `remote` - The remote's name
+    pub fn outgoing_commits(
+        &self,
+        repo: &Repository,
+        local: Oid,
+        remote_sha: Option<Oid>,
+        remote: &str,
+    ) -> Result<Vec<Oid>> {
+        let mut walk = repo.revwalk()?;
+        walk.set_sorting(Sort::TOPOLOGICAL | Sort::TIME)?;
+        walk.push(local)?;
+        match remote_sha {
+            // we may not have what the remote has, then there is nothing to hide
+            Some(remote_sha) if repo.find_commit(remote_sha).is_ok() => walk.hide(remote_sha)?,
+            Some(_) => (),
+            None => walk.hide_glob(&format!("refs/remotes/{}/*", remote))?,
+        }
+        Ok(walk.collect::<std::result::Result<Vec<Oid>, git2::Error>>()?)
+    }
+
+    /// The commits that changed `path`, newest first.  Merges are left out, they only
+    /// repeat what they merge, and so are renames (it stops where the file got its name).
+    ///
+    /// # Arguments
+    ///
+    /// * `repo` - The repository
+    /// * `path` - The file, relative to the root of the repo
+    pub fn commits_touching(&self, repo: &Repository, path: &Path) -> Result<Vec<Oid>> {
+        let mut touching = Vec::new();
+        for oid in self.commits_in_range(repo, "HEAD")? {
+            let commit = repo.find_commit(oid)?;
+            if commit.parent_count() < 2
+                && self.get_path_changes(repo, &commit, path)?.deltas().len() > 0
+            {
+                touching.push(oid);
+            }
+        }
+        Ok(touching)
+    }
+
+    /// The last `count` commits from `from` that changed any of `paths`, newest first.
+    /// Merges are left out like in `commits_touching`, and it gives up after looking at
+    /// `HISTORY_DEPTH` commits, an old file's history can be long.
+    ///
+    /// # Arguments
+    ///
+    /// * `repo` - The repository
+    /// * `from` - Where to start looking, `HEAD` (or `HEAD~1` for an amend)
+    /// * `paths` - The files, relative to the root of the repo
+    /// * `count` - How many commits to find
+    pub fn commits_touching_paths(
+        &self,
+        repo: &Repository,
+        from: &str,
+        paths: &[PathBuf],
+        count: usize,
+    ) -> Result<Vec<Oid>> {
+        let mut touching = Vec::new();
+        if paths.is_empty() || count == 0 {
+            return Ok(touching);
+        }
+        let mut opts = DiffOptions::default();
+        for path in paths {
+            opts.pathspec(path);
+        }
+        opts.disable_pathspec_match(true);
+        for oid in self
+            .commits_in_range(repo, from)?
+            .into_iter()
+            .take(HISTORY_DEPTH)
+        {
+            let commit = repo.find_commit(oid)?;
+            if commit.parent_count() > 1 {
+                continue;
+            }
+            let parent_tree = match commit.parent(0) {
+                Ok(parent) => Some(parent.tree()?),
+                Err(_) => None,
+            };
+            let diff = repo.diff_tree_to_tree(
+                parent_tree.as_ref(),
+                Some(&commit.tree()?),
+                Some(&mut opts),
+            )?;
+            if diff.deltas().len() > 0 {
+                touching.push(oid);
+                if touching.len() == count {
+                    break;
+                }
+            }
+        }
+        Ok(touching)
+    }
+
+    /// What a single commit changed in `path`, `get_commit_changes` for just that file
+    ///
+    /// # Arguments
+    ///
+    /// * `repo` - The repository
+    /// * `commit` - The commit
+    /// * `path` - The file, relative to the root of the repo
+    pub fn get_path_changes(
+        &self,
+        repo: &'a Repository,
+        commit: &Commit,
+        path: &Path,
+    ) -> Result<Diff<'a>> {
+        let parent_tree = match commit.parent(0) {
+            Ok(parent) => Some(parent.tree()?),
+            Err(_) => None,
+        };
+        let mut opts = DiffOptions::default();
+        opts.pathspec(path).disable_pathspec_match(true);
+        Ok(repo.diff_tree_to_tree(parent_tree.as_ref(), Some(&commit.tree()?), Some(&mut opts))?)
+    }
+
+    /// The newest tag HEAD can reach, `None` if there isn't one
+    ///
+    /// # Arguments
+    ///
+    /// * `repo` - The repository
+    pub fn last_tag(&self, repo: &Repository) -> Result<Option<String>> {
+        let describe = match repo.describe(DescribeOptions::new().describe_tags()) {
+            Ok(describe) => describe,
+            Err(e) if e.class() == ErrorClass::Describe => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Some(describe.format(Some(
+            DescribeFormatOptions::new().abbreviated_size(0),
+        ))?))
+    }
+
+    /// Tags HEAD with an annotated tag made by the configured user
+    ///
+    /// # Arguments
+    ///
+    /// * `repo` - The repository
+    /// * `name` - The tag
+    /// * `msg` - Its message
+    pub fn create_tag(&self, repo: &Repository, name: &str, msg: &str) -> Result<Oid> {
+        let head = repo.head()?.peel(ObjectType::Commit)?;
+        Ok(repo.tag(name, &head, &self.signature(repo)?, msg, false)?)
+    }
+
+    /// Attaches a note to a commit, replacing whatever note it had in `notes_ref`
+    ///
+    /// # Arguments
+    ///
+    /// * `repo` - The repository
+    /// * `notes_ref` - The notes ref, like `refs/notes/internal`
+    /// * `commit` - The commit
+    /// * `note` - The note
+    pub fn add_note(
+        &self,
+        repo: &Repository,
+        notes_ref: &str,
+        commit: Oid,
+        note: &str,
+    ) -> Result<Oid> {
+        let signature = self.signature(repo)?;
+        Ok(repo.note(&signature, &signature, Some(notes_ref), commit, note, true)?)
+    }
+
+    /// What a single commit changed, against its first parent (or nothing for the root commit)
+    ///
+    /// # Arguments
+    ///
+    /// * `repo` - The repository
+    /// * `commit` - The commit
+    pub fn get_commit_changes(self, repo: &'a Repository, commit: &Commit) -> Result<Diff<'a>> {
+        let parent_tree = match commit.parent(0) {
+            Ok(parent) => Some(parent.tree()?),
+            Err(_) => None,
+        };
+        Ok(repo.diff_tree_to_tree(
+            parent_tree.as_ref(),
+            Some(&commit.tree()?),
+            Some(&mut DiffOptions::default()),
+        )?)
+    }
+
+    /// The commit a branch (or any revision) points at
+    ///
+    /// # Arguments
+    ///
+    /// * `repo` - The repository
+    /// * `branch` - The branch
+    pub fn branch_head(&self, repo: &Repository, branch: &str) -> Result<Oid> {
+        Ok(repo.revparse_single(branch)?.peel_to_commit()?.id())
+    }
+
+    /// Takes `commit` back off the branch like `git reset --soft <before>`, what it changed
+    /// stays staged.  Only while it is still HEAD, anything on top of it would go too.
+    ///
+    /// # Arguments
+    ///
+    /// * `repo` - The repository
+    /// * `commit` - The commit to take back
+    /// * `before` - Where HEAD was before it
+    pub fn undo_commit(&self, repo: &Repository, commit: Oid, before: Oid) -> Result<()> {
+        let head = repo.head()?.peel_to_commit()?.id();
+        if head != commit {
+            return Err(GitAiError::Refused(format!(
+                "HEAD is {} now, not {}, so it was left alone.  Undo it with git reset if you're sure",
+                head, commit
+            )));
+        }
+        debug!("Resetting {} to {}", commit, before);
+        let before = repo.find_object(before, Some(ObjectType::Commit))?;
+        repo.reset(&before, git2::ResetType::Soft, None)?;
+        Ok(())
+    }
+
+    /// Whether the checked out branch's upstream has `commit` already, so taking it back
+    /// here doesn't take it off the remote
+    ///
+    /// # Arguments
+    ///
+    /// * `repo` - The repository
+    /// * `commit` - The commit
+    pub fn is_pushed(&self, repo: &Repository, commit: Oid) -> bool {
+        let upstream = self
+            .current_branch(repo)
+            .and_then(|name| repo.find_branch(&name, git2::BranchType::Local).ok())
+            .and_then(|branch| branch.upstream().ok())
+            .and_then(|upstream| upstream.get().target());
+        upstream.is_some_and(|upstream| {
+            upstream == commit || repo.graph_descendant_of(upstream, commit).unwrap_or(false)
+        })
+    }
+
+    /// The branch that is checked out, `None` for a detached HEAD or a repository
+    /// without commits
+    ///
+    /// # Arguments
+    ///
+    /// * `repo` - The repository
+    pub fn current_branch(&self, repo: &Repository) -> Option<String> {
+        let head = repo.head().ok()?;
+        if !head.is_branch() {
+            return None;
+        }
+        head.shorthand().map(String::from)
+    }
+
+    /// Convient method to turn a `Diff` to a `String`, anything that isn't UTF-8
+    /// comes out as the replacement character.  Binary files get a line saying they
+    /// changed and how big they are, `Binary file images/logo.png changed (12 KB)`
     ///
     /// # Arguments
     ///
     /// * `diff` - The diff
-    pub fn diff_to_string(&self, diff: &Diff) -> Result<String, git2::Error> {
+    pub fn diff_to_string(&self, diff: &Diff) -> Result<String> {
         debug!("Turning diff to a string");
-        let mut diff_content = String::new();
+        Ok(self
+            .diff_to_file_strings(diff)?
+            .iter()
+            .map(|file| file.text.as_str())
+            .collect())
+    }
+
+    /// The diff the way `git diff` prints it, a plain unified diff with the new line
+    /// numbers in the hunk headers, which is what review comments need
+    ///
+    /// # Arguments
+    ///
+    /// * `diff` - The diff
+    pub fn diff_to_unified(&self, diff: &Diff) -> Result<String> {
+        let mut text = String::new();
+        diff.print(DiffFormat::Patch, |_delta, _hunk, line| {
+            // content lines come without their +, - or space
+            if matches!(line.origin(), '+' | '-' | ' ') {
+                text.push(line.origin());
+            }
+            text.push_str(&String::from_utf8_lossy(line.content()));
+            true
+        })?;
+        Ok(text)
+    }
+
+    /// `diff_to_string`, but one `FileDiff` per file so they can be trimmed separately
+    ///
+    /// # Arguments
+    ///
+    /// * `diff` - The diff
+    pub fn diff_to_file_strings(&self, diff: &Diff) -> Result<Vec<FileDiff>> {
+        let mut files: Vec<FileDiff> = Vec::new();
         diff.print(
             DiffFormat::Patch,
-            |_delta: DiffDelta, _hunk: Option<DiffHunk>, line: DiffLine| {
-                let line_num = match line.old_lineno() {
-                    Some(num) => num,
-                    None => 0,
-                };
-
-                let a_line =
-                    std::str::from_utf8(&line.content()).expect("Non UTF8 Characters in Diff");
-
-                if a_line.starts_with("diff --git") || a_line.starts_with("@@") {
-                    diff_content.push_str(&format!(
-                        "{}",
-                        std::str::from_utf8(&line.content()).expect("Non UTF8 Characters in Diff")
-                    ));
+            |delta: DiffDelta, _hunk: Option<DiffHunk>, line: DiffLine| {
+                let line_num = line.old_lineno().unwrap_or_default();
+
+                let a_line = String::from_utf8_lossy(line.content());
+
+                if a_line.starts_with("diff --git") {
+                    files.push(FileDiff {
+                        path: delta
+                            .new_file()
+                            .path()
+                            .or_else(|| delta.old_file().path())
+                            .map(Path::to_path_buf)
+                            .unwrap_or_default(),
+                        ..Default::default()
+                    });
+                }
+                if files.is_empty() {
+                    files.push(FileDiff::default());
+                }
+                let file = files.last_mut().expect("never empty");
+                if line.origin() == 'B' {
+                    file.text.push_str(&binary_summary(&delta));
+                } else if a_line.starts_with("diff --git") || a_line.starts_with("@@") {
+                    file.text.push_str(&a_line);
                 } else {
                     match line.origin() {
-                        '-' => diff_content.push_str("-"),
+                        '-' => {
+                            file.removed += 1;
+                            file.text.push('-')
+                        }
 
-                        '+' => diff_content.push_str("+"),
+                        '+' => {
+                            file.added += 1;
+                            file.text.push('+')
+                        }
 
-                        _ => diff_content.push_str(" "),
+                        _ => file.text.push(' '),
                     };
-                    diff_content.push_str(&format!("{}", line_num));
-                    diff_content.push_str(&format!(
-                        " {}",
-                        std::str::from_utf8(&line.content()).expect("Non UTF8 Characters in Diff")
-                    ));
+                    file.text.push_str(&format!("{}", line_num));
+                    file.text.push_str(&format!(" {}", a_line));
                 }
 
                 true
             },
         )?;
-        return Ok(diff_content);
+        Ok(files)
+    }
+
+    /// Trims a diff until `fits` is happy with it.  Files get replaced by a one line
+    /// summary, lockfiles and generated code first (the AI learns next to nothing from
+    /// them), then everything else from the biggest down.  If even the summaries are too
+    /// much the end gets cut off.
+    ///
+    /// # Arguments
+    ///
+    /// * `files` - The diff, from `diff_to_file_strings`
+    /// * `fits` - Whether a diff text is small enough
+    pub fn fit_diff_text(&self, mut files: Vec<FileDiff>, fits: impl Fn(&str) -> bool) -> String {
+        let join = |files: &[FileDiff]| files.iter().map(|f| f.text.as_str()).collect::<String>();
+        let mut text = join(&files);
+        if fits(&text) {
+            return text;
+        }
+        // cheapest first: low value files, then the biggest
+        let mut order: Vec<usize> = (0..files.len()).collect();
+        order.sort_by_key(|&i| {
+            (
+                !is_low_value(&files[i].path),
+                std::cmp::Reverse(files[i].text.len()),
+            )
+        });
+        for i in order {
+            let file = &mut files[i];
+            debug!("Leaving {:#?} out of the prompt", file.path);
+            file.text = format!(
+                "~ {} changed (+{} -{}), left out to fit the prompt\n",
+                file.path.display(),
+                file.added,
+                file.removed
+            );
+            text = join(&files);
+            if fits(&text) {
+                return text;
+            }
+        }
+        // binary search for how many lines still fit
+        let lines: Vec<&str> = text.lines().collect();
+        let (mut keep, mut too_many) = (0, lines.len());
+        while keep + 1 < too_many {
+            let mid = (keep + too_many) / 2;
+            if fits(&lines[..mid].join("\n")) {
+                keep = mid;
+            } else {
+                too_many = mid;
+            }
+        }
+        let mut kept = lines[..keep].to_vec();
+        kept.push("~ the rest of the diff was cut off to fit the prompt");
+        kept.join("\n")
     }
 
     /// Convient method to pretty-print a commit
@@ -301,14 +1060,117 @@ impl<'a> Git<'a> {
     pub fn display_commit(&self, commit: &Commit) -> String {
         let timestamp = commit.time().seconds();
         let tm = time::at(time::Timespec::new(timestamp, 0));
-        let res = format!(
+        format!(
             "commit {}\nAuthor: {}\nDate:   {}\n\n    {}",
             commit.id(),
             commit.author(),
             tm.rfc822(),
             commit.message().unwrap_or("no commit message")
-        );
-        return res;
+        )
+    }
+
+    /// The editor git would open, in git's order: `$GIT_EDITOR`, `core.editor`, `$VISUAL`,
+    /// `$EDITOR` and then vi (notepad on Windows)
+    ///
+    /// # Arguments
+    ///
+    /// * `repo` - The repository
+    pub fn editor(&self, repo: &Repository) -> String {
+        let configured = repo
+            .config()
+            .and_then(|mut config| config.snapshot())
+            .and_then(|config| config.get_string("core.editor"));
+        env::var("GIT_EDITOR")
+            .ok()
+            .or_else(|| configured.ok())
+            .or_else(|| env::var("VISUAL").ok())
+            .or_else(|| env::var("EDITOR").ok())
+            .filter(|editor| !editor.trim().is_empty())
+            .unwrap_or_else(|| terminal::DEFAULT_EDITOR.to_string())
+    }
+
+    /// Opens `msg` in the editor the way `git commit` does, in `.git/COMMIT_EDITMSG` with
+    /// git's comments below it, and waits for it to be saved.  Lines starting with `#` are
+    /// dropped and `None` means the message was emptied, which aborts the commit.
+    ///
+    /// # Arguments
+    ///
+    /// * `repo` - The repository
+    /// * `msg` - The message to start from
+    pub fn edit_message(&self, repo: &Repository, msg: &str) -> Result<Option<String>> {
+        self.edit_file(
+            repo,
+            "COMMIT_EDITMSG",
+            msg,
+            "# Please enter the commit message for your changes. Lines starting\n# with '#' will be ignored, and an empty message aborts the commit.\n",
+        )
+    }
+
+    /// Opens a pull request in the editor, in `.git/PULLREQ_EDITMSG` like hub does: the
+    /// first line is the title and the rest is the body.  Markdown headings start with `#`
+    /// so everything below git's scissors line is dropped instead of comments.  `None`
+    /// means it was emptied.
+    ///
+    /// # Arguments
+    ///
+    /// * `repo` - The repository
+    /// * `title` - The title to start from
+    /// * `body` - The body to start from
+    pub fn edit_pull_request(
+        &self,
+        repo: &Repository,
+        title: &str,
+        body: &str,
+    ) -> Result<Option<(String, String)>> {
+        let edited = self.edit_file(
+            repo,
+            "PULLREQ_EDITMSG",
+            &format!("{}\n\n{}", title.trim(), body.trim()),
+            &format!(
+                "{}\n# Do not modify or remove the line above.\n# The first line is the title of the pull request and the rest is its body.\n# Everything below the line above will be ignored, and an empty message aborts it.\n",
+                SCISSORS
+            ),
+        )?;
+        Ok(edited.map(|text| match text.split_once('\n') {
+            Some((title, body)) => (title.trim().to_string(), body.trim().to_string()),
+            None => (text, String::new()),
+        }))
+    }
+
+    /// Writes `text` and `comments` to `name` in the .git directory, opens it in the editor
+    /// and reads back what was saved.  Comments below a scissors line are cut off there,
+    /// otherwise every `#` line is one.
+    fn edit_file(
+        &self,
+        repo: &Repository,
+        name: &str,
+        text: &str,
+        comments: &str,
+    ) -> Result<Option<String>> {
+        let path = repo.path().join(name);
+        fs::write(&path, format!("{}\n\n{}", text.trim_end(), comments))?;
+        let editor = self.editor(repo);
+        interactive::ask(&format!("{} has to be edited in {}", name, editor))?;
+        debug!("Opening {} with {}", path.display(), editor);
+        let status = terminal::editor_command(&editor, &path)
+            .current_dir(repo.workdir().unwrap_or_else(|| repo.path()))
+            .status()?;
+        if !status.success() {
+            return Err(GitAiError::Usage(format!(
+                "{} exited with {}, the message wasn't changed",
+                editor, status
+            )));
+        }
+        let text = fs::read_to_string(&path)?;
+        let scissors = comments.starts_with(SCISSORS);
+        let edited: Vec<&str> = text
+            .lines()
+            .take_while(|line| !scissors || *line != SCISSORS)
+            .filter(|line| scissors || !line.starts_with('#'))
+            .map(str::trim_end)
+            .collect();
+        let edited = edited.join("\n").trim().to_string();
+        Ok(Some(edited).filter(|edited| !edited.is_empty()))
     }
 
     /// Actually make the commit
@@ -317,9 +1179,229 @@ impl<'a> Git<'a> {
     ///
     /// * `repo` - The repository
     /// * `msg` - The commit message: hopefully from the AI
-    pub fn make_commit(&self, repo: &Repository, msg: &str) -> Result<Oid, git2::Error> {
+    pub fn make_commit(&self, repo: &Repository, msg: &str) -> Result<Oid> {
         debug!("Performing commit");
-        let git_config = repo.config()?;
+        let index_tree_id = repo.index()?.write_tree()?;
+        let index_tree = repo.find_tree(index_tree_id)?;
+        self.commit_tree(repo, msg, &index_tree)
+    }
+
+    /// Rewrites the last commit with what is staged and `msg`.  The author and committer
+    /// stay the same, dates included, like `git commit --amend --no-reset-author`.
+    ///
+    /// # Arguments
+    ///
+    /// * `repo` - The repository
+    /// * `msg` - The new commit message
+    pub fn amend_commit(&self, repo: &Repository, msg: &str) -> Result<Oid> {
+        debug!("Amending the last commit");
+        let last_commit = self.find_last_commit(repo)?;
+        let index_tree = repo.find_tree(repo.index()?.write_tree()?)?;
+        match self.signer(repo)? {
+            None => Ok(last_commit.amend(
+                Some("HEAD"),
+                None,
+                None,
+                None,
+                Some(msg),
+                Some(&index_tree),
+            )?),
+            Some(signer) => {
+                let parents: Vec<Commit> = last_commit.parents().collect();
+                let parents: Vec<&Commit> = parents.iter().collect();
+                self.write_signed_commit(
+                    repo,
+                    &signer,
+                    &last_commit.author(),
+                    &last_commit.committer(),
+                    msg,
+                    &index_tree,
+                    &parents,
+                    "commit (amend)",
+                )
+            }
+        }
+    }
+
+    /// Commit only the staged changes to `paths`, everything else that is staged
+    /// stays in the index for a later commit.  This is how huge staged sets get split
+    /// into smaller commits.
+    ///
+    /// # Arguments
+    ///
+    /// * `repo` - The repository
+    /// * `msg` - The commit message: hopefully from the AI
+    /// * `paths` - The paths (relative to the repo root) to commit
+    pub fn make_partial_commit(
+        &self,
+        repo: &Repository,
+        msg: &str,
+        paths: &[PathBuf],
+    ) -> Result<Oid> {
+        debug!("Performing commit of {} paths", paths.len());
+        let last_commit = self.find_last_commit(repo)?;
+        let staged = repo.index()?;
+        // start from what is committed and only take the staged version of our paths
+        let mut partial = Index::new()?;
+        partial.read_tree(&last_commit.tree()?)?;
+        for path in paths {
+            match staged.get_path(path, 0) {
+                Some(entry) => partial.add(&entry)?,
+                None => partial.remove_path(path)?,
+            }
+        }
+        let partial_tree_id = partial.write_tree_to(repo)?;
+        let partial_tree = repo.find_tree(partial_tree_id)?;
+        self.commit_tree(repo, msg, &partial_tree)
+    }
+
+    /// Replaces everything on the branch since it left `base` with one commit, like
+    /// `git reset --soft $(git merge-base base HEAD)` and a commit.  It is HEAD's tree that
+    /// is committed, anything staged stays staged.
+    ///
+    /// # Arguments
+    ///
+    /// * `repo` - The repository
+    /// * `base` - The branch (or any revision) the work started from
+    /// * `msg` - The commit message for the lot
+    pub fn squash_commit(&self, repo: &Repository, base: &str, msg: &str) -> Result<Oid> {
+        debug!("Squashing everything since {}", base);
+        let last_commit = self.find_last_commit(repo)?;
+        let base_oid = repo.merge_base(last_commit.id(), self.branch_head(repo, base)?)?;
+        let base_commit = repo.find_commit(base_oid)?;
+        let tree = last_commit.tree()?;
+        let sig = self.signature(repo)?;
+        let parents = [&base_commit];
+        match self.signer(repo)? {
+            Some(signer) => self.write_signed_commit(
+                repo,
+                &signer,
+                &sig,
+                &sig,
+                msg,
+                &tree,
+                &parents,
+                "commit (squash)",
+            ),
+            None => {
+                // HEAD isn't the parent, so it is moved by hand
+                let commit_id = repo.commit(None, &sig, &sig, msg, &tree, &parents)?;
+                let subject = msg.lines().next().unwrap_or_default();
+                repo.head()?
+                    .resolve()?
+                    .set_target(commit_id, &format!("commit (squash): {}", subject))?;
+                Ok(commit_id)
+            }
+        }
+    }
+
+    /// How many lines each file of a diff gained and lost, in the order of the diff
+    ///
+    /// # Arguments
+    ///
+    /// * `diff` - The diff
+    pub fn file_stats(&self, diff: &Diff) -> Result<Vec<FileStat>> {
+        let mut stats = Vec::new();
+        for index in 0..diff.deltas().len() {
+            let patch = Patch::from_diff(diff, index)?;
+            let delta = match diff.get_delta(index) {
+                Some(delta) => delta,
+                None => continue,
+            };
+            let (additions, deletions) = match &patch {
+                Some(patch) => {
+                    let (_, additions, deletions) = patch.line_stats()?;
+                    (additions, deletions)
+                }
+                None => (0, 0),
+            };
+            let status = match delta.status() {
+                Delta::Added | Delta::Untracked => 'A',
+                Delta::Deleted => 'D',
+                Delta::Renamed => 'R',
+                _ => 'M',
+            };
+            stats.push(FileStat {
+                path: delta
+                    .new_file()
+                    .path()
+                    .or_else(|| delta.old_file().path())
+                    .map(Path::to_path_buf)
+                    .unwrap_or_default(),
+                status,
+                additions,
+                deletions,
+                binary: delta.flags().is_binary() || patch.is_none(),
+            });
+        }
+        Ok(stats)
+    }
+
+    /// Every path a diff touches (old and new names for renames), relative to the repo root
+    ///
+    /// # Arguments
+    ///
+    /// * `diff` - The diff
+    pub fn changed_paths(&self, diff: &Diff) -> Vec<PathBuf> {
+        let mut paths: Vec<PathBuf> = Vec::new();
+        for delta in diff.deltas() {
+            for path in [delta.old_file().path(), delta.new_file().path()]
+                .into_iter()
+                .flatten()
+            {
+                if !paths.iter().any(|p| p == path) {
+                    paths.push(path.to_path_buf());
+                }
+            }
+        }
+        paths
+    }
+
+    /// Groups the files in a diff by their top level directory, files in the root of the
+    /// repo are grouped under `.`
+    ///
+    /// # Arguments
+    ///
+    /// * `diff` - The diff
+    pub fn group_by_directory(&self, diff: &Diff) -> BTreeMap<String, Vec<PathBuf>> {
+        let mut groups: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+        for path in self.changed_paths(diff) {
+            let mut components = path.components();
+            let dir = match (components.next(), components.next()) {
+                (Some(first), Some(_)) => first.as_os_str().to_string_lossy().to_string(),
+                _ => ".".to_string(),
+            };
+            groups.entry(dir).or_default().push(path);
+        }
+        groups
+    }
+
+    /// Gets the staged diff limited to `paths`, this is the part of `get_commit_diff`
+    /// that `make_partial_commit` would commit
+    ///
+    /// # Arguments
+    ///
+    /// * `repo` - The repository
+    /// * `paths` - The paths (relative to the repo root) to diff
+    pub fn get_paths_diff(self, repo: &'a Repository, paths: &[PathBuf]) -> Result<Diff<'a>> {
+        debug!("Diffing {} paths", paths.len());
+        let last_commit = self.find_last_commit(repo)?;
+        let mut opts = DiffOptions::new();
+        opts.disable_pathspec_match(true);
+        for path in paths {
+            opts.pathspec(path);
+        }
+        Ok(repo.diff_tree_to_index(
+            Some(&last_commit.tree()?),
+            Some(&repo.index()?),
+            Some(&mut opts),
+        )?)
+    }
+
+    /// The configured user, as of now
+    fn signature(&self, repo: &Repository) -> Result<Signature<'static>> {
+        // get_str only works on a snapshot of the config
+        let git_config = repo.config()?.snapshot()?;
         let user_name = match self.user_name {
             Some(name) => name,
             None => git_config.get_str("user.name")?,
@@ -328,85 +1410,238 @@ impl<'a> Git<'a> {
             Some(email) => email,
             None => git_config.get_str("user.email")?,
         };
-        debug!("{} {} is doing the commit", &user_name, &user_email);
-        let sig = Signature::now(user_name, user_email)?;
+        debug!("{} {} is doing the commit", user_name, user_email);
+        Ok(Signature::now(user_name, user_email)?)
+    }
+
+    /// How commits get signed, `None` if they don't.  `sign_commits` and `key_id` win over
+    /// `commit.gpgsign` and `user.signingkey`
+    fn signer(&self, repo: &Repository) -> Result<Option<Signer>> {
+        let sig = self.signature(repo)?;
+        let committer = format!(
+            "{} <{}>",
+            sig.name().unwrap_or_default(),
+            sig.email().unwrap_or_default()
+        );
+        Ok(Signer::from_config(
+            &repo.config()?,
+            self.sign_commits.copied(),
+            self.key_id.filter(|key| !key.is_empty()),
+            &committer,
+        ))
+    }
+
+    /// Writes a signed commit and moves HEAD (the branch it is on) to it, `reflog` says how
+    /// it got there, `commit` or `commit (amend)`
+    #[allow(clippy::too_many_arguments)]
+    fn write_signed_commit(
+        &self,
+        repo: &Repository,
+        signer: &Signer,
+        author: &Signature,
+        committer: &Signature,
+        msg: &str,
+        tree: &Tree,
+        parents: &[&Commit],
+        reflog: &str,
+    ) -> Result<Oid> {
+        let buffer = repo.commit_create_buffer(author, committer, msg, tree, parents)?;
+        let buffer = buffer.as_str().ok_or_else(|| {
+            GitAiError::Signing("the commit isn't valid UTF-8, gpg can't sign it".to_string())
+        })?;
+        let signature = signer.sign(buffer)?;
+        let commit_id = repo.commit_signed(buffer, &signature, None)?;
+        let subject = msg.lines().next().unwrap_or_default();
+        repo.head()?
+            .resolve()?
+            .set_target(commit_id, &format!("{}: {}", reflog, subject))?;
+        Ok(commit_id)
+    }
+
+    /// Commits `tree` on top of HEAD as the configured user, signed if it should be
+    fn commit_tree(&self, repo: &Repository, msg: &str, tree: &Tree) -> Result<Oid> {
+        let sig = self.signature(repo)?;
         let last_commit = self.find_last_commit(repo)?;
-        let index_tree_id = repo.index()?.write_tree()?;
-        let index_tree = repo.find_tree(index_tree_id)?;
-        let commit_id = repo.commit(Some("HEAD"), &sig, &sig, msg, &index_tree, &[&last_commit])?;
+        let commit_id = match self.signer(repo)? {
+            Some(signer) => {
+                let parents = [&last_commit];
+                self.write_signed_commit(repo, &signer, &sig, &sig, msg, tree, &parents, "commit")?
+            }
+            None => repo.commit(Some("HEAD"), &sig, &sig, msg, tree, &[&last_commit])?,
+        };
         if log_enabled!(Level::Debug) {
             debug!("New commit:");
             debug!("{}", self.display_commit(&repo.find_commit(commit_id)?));
         }
-        return Ok(commit_id);
+        Ok(commit_id)
+    }
+
+    /// Where the `remote` remote points, `None` if there is no such remote or its
+    /// url isn't a hosted repo
+    ///
+    /// # Arguments
+    ///
+    /// * `repo` - The repository
+    /// * `remote` - The remote's name, usually `origin`
+    pub fn remote_location(&self, repo: &Repository, remote: &str) -> Option<RemoteLocation> {
+        let remote = repo.find_remote(remote).ok()?;
+        parse_remote_url(remote.url()?)
+    }
+
+    /// Where `branch_name` gets pushed, the remote and the branch there.  That is its
+    /// upstream (`branch.<name>.remote` and `branch.<name>.merge`) when it has one, a fork
+    /// pushes to the fork, and otherwise the branch of the same name on `remote`.
+    ///
+    /// # Arguments
+    ///
+    /// * `repo` - The repository
+    /// * `branch_name` - The local branch
+    /// * `remote` - The remote to use when the branch has no upstream
+    pub fn push_destination(
+        &self,
+        repo: &Repository,
+        branch_name: &str,
+        remote: &str,
+    ) -> (String, String) {
+        let upstream = repo.config().ok().and_then(|config| {
+            let remote = config
+                .get_string(&format!("branch.{}.remote", branch_name))
+                .ok()?;
+            let merge = config
+                .get_string(&format!("branch.{}.merge", branch_name))
+                .ok()?;
+            // `.` is the repo itself, that's no place to push a pull request from
+            Some((remote, merge)).filter(|(remote, _)| remote != ".")
+        });
+        match upstream {
+            Some((remote, merge)) => (remote, merge.trim_start_matches("refs/heads/").to_string()),
+            None => (remote.to_string(), branch_name.to_string()),
+        }
     }
-    /// Push the branch to remote
+
+    /// Push the branch to its upstream, or to `remote` when it doesn't have one yet, which
+    /// then becomes its upstream like `git push -u`
     ///
     /// # Arguments
     ///
     /// * `repo` - The repository
     /// * `branch_name` - The branch name, should be the current one
-    pub fn push_to_remote(&self, repo: &Repository, branch_name: &str) -> Result<(), git2::Error> {
-        debug!("Pushing branch to origin for PR");
-        let mut remote = repo.find_remote("origin")?;
-        debug!("Found origin, creating ssh callback");
+    /// * `remote` - The remote to use when the branch has no upstream
+    pub fn push_to_remote(&self, repo: &Repository, branch_name: &str, remote: &str) -> Result<()> {
+        let (remote_name, destination) = self.push_destination(repo, branch_name, remote);
+        debug!(
+            "Pushing {} to {} on {}",
+            branch_name, destination, remote_name
+        );
+        let has_upstream = repo
+            .find_branch(branch_name, git2::BranchType::Local)?
+            .upstream()
+            .is_ok();
+        let mut remote = repo.find_remote(&remote_name)?;
+        debug!("Found {}, creating ssh callback", remote_name);
+        // git2 asks again every time the remote turns one down, so each is tried once
+        let key_path = settings::expand_home(self.ssh_key_path.unwrap_or("~/.ssh/id_rsa"));
+        let mut tried: Vec<String> = Vec::new();
+        let (mut agent_tried, mut key_tried) = (false, false);
+        let name = remote_name.to_string();
         let mut callbacks = RemoteCallbacks::new();
-        callbacks.credentials(|_, username_from_url, _| {
-            Cred::ssh_key_from_agent(username_from_url.unwrap())
+        callbacks.credentials(move |_, username_from_url, allowed| {
+            let user = username_from_url
+                .or(self.ssh_user_name)
+                .unwrap_or("git");
+            if allowed.contains(CredentialType::USERNAME) {
+                return Cred::username(user);
+            }
+            if !agent_tried {
+                agent_tried = true;
+                if env::var_os("SSH_AUTH_SOCK").is_some() {
+                    debug!("Trying the ssh agent as {}", user);
+                    tried.push("the ssh agent".to_string());
+                    return Cred::ssh_key_from_agent(user);
+                }
+                tried.push("the ssh agent (it isn't running, SSH_AUTH_SOCK isn't set)".to_string());
+            }
+            if !key_tried {
+                key_tried = true;
+                match fs::read_to_string(&key_path) {
+                    Ok(key) if key_is_encrypted(&key) && !terminal::is_tty(&io::stdin()) => tried
+                        .push(format!(
+                            "{} (it has a passphrase and nobody is there to type it)",
+                            key_path.display()
+                        )),
+                    Ok(key) => {
+                        let passphrase = if key_is_encrypted(&key) {
+                            let question = format!("Passphrase for {}", key_path.display());
+                            Some(
+                                crate::init::ask_secret(&question, "")
+                                    .map_err(|e| git2::Error::from_str(&e.to_string()))?,
+                            )
+                        } else {
+                            None
+                        };
+                        debug!("Trying {} as {}", key_path.display(), user);
+                        tried.push(key_path.display().to_string());
+                        return Cred::ssh_key(user, None, &key_path, passphrase.as_deref());
+                    }
+                    Err(e) => tried.push(format!("{} ({})", key_path.display(), e)),
+                }
+            }
+            Err(git2::Error::from_str(&format!(
+                "Couldn't log in to {} over ssh as {}, tried {}.  Add your key to ssh-agent or point git_options.ssh_key_path (or --ssh-key-path) at it",
+                name,
+                user,
+                tried.join(", then ")
+            )))
         });
         debug!("Callback created, time to push");
         let mut push_opts = PushOptions::new();
         push_opts.remote_callbacks(callbacks);
         debug!("Getting Branch to Push");
-        let branch = repo
-            .find_branch(branch_name, git2::BranchType::Local)
-            .unwrap();
-        let refname = format!(
-            "refs/heads/{}",
+        let branch = repo.find_branch(branch_name, git2::BranchType::Local)?;
+        let refspec = format!(
+            "refs/heads/{}:refs/heads/{}",
             branch
-                .name()
-                .unwrap()
-                .expect("Unable to unwrape the branch name")
-                .trim_start_matches("refs/heads/")
+                .name()?
+                .ok_or_else(|| git2::Error::from_str("The branch name isn't valid UTF-8"))?
+                .trim_start_matches("refs/heads/"),
+            destination
         );
-        return remote.push(&[&refname], Some(&mut push_opts));
+        remote.push(&[&refspec], Some(&mut push_opts))?;
+        if !has_upstream {
+            let mut branch = branch;
+            let upstream = format!("{}/{}", remote_name, destination);
+            if let Err(e) = branch.set_upstream(Some(&upstream)) {
+                debug!("Pushed, but couldn't make {} the upstream: {}", upstream, e);
+            }
+        }
+        Ok(())
     }
 }
 
-// Helper functions
-fn get_value_from_api(
-    base_url: &str,
-    token: &str,
-    key: &str,
-    url_tail: &str,
-) -> Result<String, Box<dyn std::error::Error>> {
-    let client = reqwest::blocking::Client::new();
-    let url = format!("{}/{}", base_url, url_tail);
-    let mut headers: HeaderMap = HeaderMap::new();
-    headers.insert(
-        ACCEPT,
-        HeaderValue::from_static("application/vnd.github+json"),
-    );
-    headers.insert(
-        AUTHORIZATION,
-        HeaderValue::from_str(&format!("Bearer {}", token)).expect("Unable to set Auth Header"),
-    );
-    headers.insert(
-        "X-GitHub-Api-Version",
-        HeaderValue::from_static("2022-11-28"),
-    );
-
-    let response = client
-        .get(&url)
-        .headers(headers)
-        .send()?
-        .json::<serde_json::Value>()?;
-
-    if let Some(value) = response.get(key) {
-        if let Some(value_str) = value.as_str() {
-            return Ok(value_str.to_string());
-        }
-    }
-
-    Err("Unable to extract value from API response".into())
+/// Whether a private key needs a passphrase.  PEM keys say so in a header, OpenSSH keys
+/// name the cipher they are encrypted with, which is `none` when they aren't.
+///
+/// # Arguments
+///
+/// * `key` - The private key file's contents
+fn key_is_encrypted(key: &str) -> bool {
+    if key.contains("ENCRYPTED") {
+        return true;
+    }
+    let body: String = key
+        .lines()
+        .filter(|line| !line.starts_with("-----"))
+        .collect();
+    let bytes = match STANDARD.decode(body.trim()) {
+        Ok(bytes) => bytes,
+        Err(_) => return false,
+    };
+    match bytes.strip_prefix(b"openssh-key-v1\0".as_slice()) {
+        Some(rest) if rest.len() >= 4 => {
+            let length = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+            rest.get(4..4 + length)
+                .is_some_and(|cipher| cipher != b"none".as_slice())
+        }
+        _ => false,
+    }
 }
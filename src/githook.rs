@@ -0,0 +1,184 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use git2::{Delta, Diff, DiffFormat, Repository};
+use log::{debug, info};
+
+use crate::error::{GitAiError, Result};
+
+/// The line that tells us a hook is ours
+const HOOK_MARKER: &str = "# installed by gitai hook install";
+
+/// Git ignores everything in a commit message file below this line
+const SCISSORS: &str = "# ------------------------ >8 ------------------------";
+
+/// The git hooks gitai can be
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookKind {
+    /// Writes the message for a plain `git commit`
+    PrepareCommitMsg,
+    /// Reviews what is about to be pushed
+    PrePush,
+}
+
+impl HookKind {
+    /// The hook's file name
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            HookKind::PrepareCommitMsg => "prepare-commit-msg",
+            HookKind::PrePush => "pre-push",
+        }
+    }
+
+    /// The gitai command the hook runs
+    fn command(&self) -> &'static str {
+        match self {
+            HookKind::PrepareCommitMsg => "hook run",
+            HookKind::PrePush => "hook pre-push",
+        }
+    }
+}
+
+/// Where git looks for a hook, in `core.hooksPath` if it is set
+///
+/// # Arguments
+///
+/// * `repo` - The repository
+/// * `kind` - The hook
+pub fn hook_path(repo: &Repository, kind: HookKind) -> Result<PathBuf> {
+    let dir = match repo.config()?.get_path("core.hooksPath") {
+        Ok(dir) if dir.is_absolute() => dir,
+        Ok(dir) => repo.workdir().unwrap_or(repo.path()).join(dir),
+        Err(_) => repo.path().join("hooks"),
+    };
+    Ok(dir.join(kind.file_name()))
+}
+
+/// Installs gitai as one of the repo's hooks.  As `prepare-commit-msg` a plain
+/// `git commit` opens the editor with a generated message in it, as `pre-push` a push
+/// is reviewed first.  A hook that isn't ours is only replaced with `force`, and then
+/// it is kept next to it as a `.bak`.
+///
+/// # Arguments
+///
+/// * `repo` - The repository
+/// * `kind` - The hook
+/// * `force` - Replace a hook someone else installed
+///
+/// Returns where the hook went
+pub fn install(repo: &Repository, kind: HookKind, force: bool) -> Result<PathBuf> {
+    let path = hook_path(repo, kind)?;
+    if let Ok(existing) = fs::read_to_string(&path) {
+        if !existing.contains(HOOK_MARKER) {
+            if !force {
+                return Err(GitAiError::Usage(format!(
+                    "There already is a hook at {}, pass --force to replace it (it is kept as a .bak)",
+                    path.display()
+                )));
+            }
+            info!("Keeping the old hook as {}.bak", path.display());
+            fs::rename(
+                &path,
+                path.with_file_name(format!("{}.bak", kind.file_name())),
+            )?;
+        }
+    }
+    let exe = std::env::current_exe()?;
+    let script = format!(
+        "#!/bin/sh\n{}\nexec \"{}\" {} \"$@\"\n",
+        HOOK_MARKER,
+        exe.display(),
+        kind.command()
+    );
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(&path, script)?;
+    make_executable(&path)?;
+    Ok(path)
+}
+
+#[cfg(unix)]
+fn make_executable(path: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o755))?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn make_executable(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+/// Whether git wants a message written.  `source` is what git passes as the second
+/// argument: nothing for a plain `git commit`, `template` with a commit template,
+/// and `message`, `merge`, `squash` or `commit` when there already is a message.
+pub fn should_generate(source: Option<&str>) -> bool {
+    matches!(source, None | Some("") | Some("template"))
+}
+
+/// Puts `message` at the top of the commit message file, above whatever git put
+/// there (the commented out status, a template, ...)
+///
+/// # Arguments
+///
+/// * `path` - The commit message file git passed to the hook
+/// * `message` - The generated message
+pub fn prepend_message(path: &Path, message: &str) -> Result<()> {
+    let existing = fs::read_to_string(path).unwrap_or_default();
+    debug!("Writing the generated message into {}", path.display());
+    fs::write(path, format!("{}\n{}", message.trim_end(), existing))?;
+    Ok(())
+}
+
+/// A commit message file the way `git commit -v` writes it, and so the way a fugitive
+/// (or any other editor's) commit buffer looks: the message, git's commented out help
+/// and status, then the diff below the scissors line where git ignores it
+///
+/// # Arguments
+///
+/// * `message` - The generated message
+/// * `branch` - The branch being committed to, `None` when detached
+/// * `diff` - What is being committed
+pub fn commit_buffer(message: &str, branch: Option<&str>, diff: &Diff) -> Result<String> {
+    let mut buffer = format!(
+        "{}\n\n# Please enter the commit message for your changes. Lines starting\n# with '#' will be ignored, and an empty message aborts the commit.\n#\n",
+        message.trim_end()
+    );
+    match branch {
+        Some(branch) => buffer.push_str(&format!("# On branch {}\n", branch)),
+        None => buffer.push_str("# HEAD detached\n"),
+    }
+    buffer.push_str("# Changes to be committed:\n");
+    for delta in diff.deltas() {
+        let old = delta.old_file().path().unwrap_or_else(|| Path::new(""));
+        let new = delta.new_file().path().unwrap_or(old);
+        let (label, path) = match delta.status() {
+            Delta::Added | Delta::Untracked => ("new file:", new.display().to_string()),
+            Delta::Deleted => ("deleted:", old.display().to_string()),
+            Delta::Renamed => (
+                "renamed:",
+                format!("{} -> {}", old.display(), new.display()),
+            ),
+            Delta::Copied => ("copied:", format!("{} -> {}", old.display(), new.display())),
+            Delta::Typechange => ("typechange:", new.display().to_string()),
+            _ => ("modified:", new.display().to_string()),
+        };
+        buffer.push_str(&format!("#\t{:<12}{}\n", label, path));
+    }
+    buffer.push_str(&format!(
+        "#\n{}\n# Do not modify or remove the line above.\n# Everything below it will be ignored.\n",
+        SCISSORS
+    ));
+    diff.print(DiffFormat::Patch, |_delta, _hunk, line| {
+        // content lines come without their +, - or space
+        if matches!(line.origin(), '+' | '-' | ' ') {
+            buffer.push(line.origin());
+        }
+        buffer.push_str(&String::from_utf8_lossy(line.content()));
+        true
+    })?;
+    Ok(buffer)
+}
@@ -0,0 +1,250 @@
+use crate::error::{GitAiError, Result};
+use crate::settings::AiPrompt;
+
+/// One of the emoji from https://gitmoji.dev
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Gitmoji {
+    /// The emoji itself, as the official list writes it
+    pub emoji: &'static str,
+    /// Its shortcode without the colons, `sparkles` for `:sparkles:`
+    pub code: &'static str,
+    /// What it is for
+    pub description: &'static str,
+}
+
+const fn gitmoji(emoji: &'static str, code: &'static str, description: &'static str) -> Gitmoji {
+    Gitmoji {
+        emoji,
+        code,
+        description,
+    }
+}
+
+/// The official gitmoji list
+pub const GITMOJIS: [Gitmoji; 75] = [
+    gitmoji("🎨", "art", "Improve structure / format of the code"),
+    gitmoji("⚡️", "zap", "Improve performance"),
+    gitmoji("🔥", "fire", "Remove code or files"),
+    gitmoji("🐛", "bug", "Fix a bug"),
+    gitmoji("🚑️", "ambulance", "Critical hotfix"),
+    gitmoji("✨", "sparkles", "Introduce new features"),
+    gitmoji("📝", "memo", "Add or update documentation"),
+    gitmoji("🚀", "rocket", "Deploy stuff"),
+    gitmoji("💄", "lipstick", "Add or update the UI and style files"),
+    gitmoji("🎉", "tada", "Begin a project"),
+    gitmoji("✅", "white_check_mark", "Add, update, or pass tests"),
+    gitmoji("🔒️", "lock", "Fix security or privacy issues"),
+    gitmoji("🔐", "closed_lock_with_key", "Add or update secrets"),
+    gitmoji("🔖", "bookmark", "Release / Version tags"),
+    gitmoji("🚨", "rotating_light", "Fix compiler / linter warnings"),
+    gitmoji("🚧", "construction", "Work in progress"),
+    gitmoji("💚", "green_heart", "Fix CI Build"),
+    gitmoji("⬇️", "arrow_down", "Downgrade dependencies"),
+    gitmoji("⬆️", "arrow_up", "Upgrade dependencies"),
+    gitmoji("📌", "pushpin", "Pin dependencies to specific versions"),
+    gitmoji("👷", "construction_worker", "Add or update CI build system"),
+    gitmoji(
+        "📈",
+        "chart_with_upwards_trend",
+        "Add or update analytics or track code",
+    ),
+    gitmoji("♻️", "recycle", "Refactor code"),
+    gitmoji("➕", "heavy_plus_sign", "Add a dependency"),
+    gitmoji("➖", "heavy_minus_sign", "Remove a dependency"),
+    gitmoji("🔧", "wrench", "Add or update configuration files"),
+    gitmoji("🔨", "hammer", "Add or update development scripts"),
+    gitmoji(
+        "🌐",
+        "globe_with_meridians",
+        "Internationalization and localization",
+    ),
+    gitmoji("✏️", "pencil2", "Fix typos"),
+    gitmoji("💩", "poop", "Write bad code that needs to be improved"),
+    gitmoji("⏪️", "rewind", "Revert changes"),
+    gitmoji("🔀", "twisted_rightwards_arrows", "Merge branches"),
+    gitmoji("📦️", "package", "Add or update compiled files or packages"),
+    gitmoji("👽️", "alien", "Update code due to external API changes"),
+    gitmoji(
+        "🚚",
+        "truck",
+        "Move or rename resources (e.g.: files, paths, routes)",
+    ),
+    gitmoji("📄", "page_facing_up", "Add or update license"),
+    gitmoji("💥", "boom", "Introduce breaking changes"),
+    gitmoji("🍱", "bento", "Add or update assets"),
+    gitmoji("♿️", "wheelchair", "Improve accessibility"),
+    gitmoji("💡", "bulb", "Add or update comments in source code"),
+    gitmoji("🍻", "beers", "Write code drunkenly"),
+    gitmoji("💬", "speech_balloon", "Add or update text and literals"),
+    gitmoji("🗃️", "card_file_box", "Perform database related changes"),
+    gitmoji("🔊", "loud_sound", "Add or update logs"),
+    gitmoji("🔇", "mute", "Remove logs"),
+    gitmoji("👥", "busts_in_silhouette", "Add or update contributor(s)"),
+    gitmoji(
+        "🚸",
+        "children_crossing",
+        "Improve user experience / usability",
+    ),
+    gitmoji("🏗️", "building_construction", "Make architectural changes"),
+    gitmoji("📱", "iphone", "Work on responsive design"),
+    gitmoji("🤡", "clown_face", "Mock things"),
+    gitmoji("🥚", "egg", "Add or update an easter egg"),
+    gitmoji("🙈", "see_no_evil", "Add or update a .gitignore file"),
+    gitmoji("📸", "camera_flash", "Add or update snapshots"),
+    gitmoji("⚗️", "alembic", "Perform experiments"),
+    gitmoji("🔍️", "mag", "Improve SEO"),
+    gitmoji("🏷️", "label", "Add or update types"),
+    gitmoji("🌱", "seedling", "Add or update seed files"),
+    gitmoji(
+        "🚩",
+        "triangular_flag_on_post",
+        "Add, update, or remove feature flags",
+    ),
+    gitmoji("🥅", "goal_net", "Catch errors"),
+    gitmoji("💫", "dizzy", "Add or update animations and transitions"),
+    gitmoji(
+        "🗑️",
+        "wastebasket",
+        "Deprecate code that needs to be cleaned up",
+    ),
+    gitmoji(
+        "🛂",
+        "passport_control",
+        "Work on code related to authorization, roles and permissions",
+    ),
+    gitmoji(
+        "🩹",
+        "adhesive_bandage",
+        "Simple fix for a non-critical issue",
+    ),
+    gitmoji("🧐", "monocle_face", "Data exploration/inspection"),
+    gitmoji("⚰️", "coffin", "Remove dead code"),
+    gitmoji("🧪", "test_tube", "Add a failing test"),
+    gitmoji("👔", "necktie", "Add or update business logic"),
+    gitmoji("🩺", "stethoscope", "Add or update healthcheck"),
+    gitmoji("🧱", "bricks", "Infrastructure related changes"),
+    gitmoji("🧑‍💻", "technologist", "Improve developer experience"),
+    gitmoji(
+        "💸",
+        "money_with_wings",
+        "Add sponsorships or money related infrastructure",
+    ),
+    gitmoji(
+        "🧵",
+        "thread",
+        "Add or update code related to multithreading or concurrency",
+    ),
+    gitmoji(
+        "🦺",
+        "safety_vest",
+        "Add or update code related to validation",
+    ),
+    gitmoji("✈️", "airplane", "Improve offline support"),
+    gitmoji("🦖", "t-rex", "Code that adds backwards compatibility"),
+];
+
+/// The variation selector that asks for the emoji (rather than the text) look, the
+/// official list has it on some emoji and the AI may or may not write it
+const VARIATION_SELECTOR: char = '\u{fe0f}';
+
+/// The gitmoji `subject` starts with and what comes after it, an emoji with or without
+/// the variation selector or a `:shortcode:`
+fn split_gitmoji(subject: &str) -> Option<(&'static Gitmoji, &str)> {
+    GITMOJIS.iter().find_map(|gitmoji| {
+        let bare = gitmoji.emoji.trim_end_matches(VARIATION_SELECTOR);
+        let rest = subject
+            .strip_prefix(&format!(":{}:", gitmoji.code))
+            .or_else(|| subject.strip_prefix(bare))?;
+        Some((gitmoji, rest.trim_start_matches(VARIATION_SELECTOR)))
+    })
+}
+
+/// The gitmoji the message starts with, the error says what is wrong with it
+///
+/// # Arguments
+///
+/// * `message` - The commit message
+pub fn parse(message: &str) -> std::result::Result<&'static Gitmoji, String> {
+    let subject = message.lines().next().unwrap_or("").trim();
+    match split_gitmoji(subject) {
+        Some((gitmoji, rest)) if rest.starts_with(' ') && !rest.trim().is_empty() => Ok(gitmoji),
+        Some((gitmoji, _)) => Err(format!(
+            "'{}' needs a space and a summary after {}",
+            subject, gitmoji.emoji
+        )),
+        None => Err(format!(
+            "'{}' doesn't start with a gitmoji from gitmoji.dev",
+            subject
+        )),
+    }
+}
+
+/// Tidies a message that starts with a gitmoji into the official emoji, one space and
+/// the summary.  A `:shortcode:` becomes its emoji.  `None` if there isn't one from the
+/// list at the start of the subject.
+///
+/// # Arguments
+///
+/// * `message` - The generated message
+pub fn fix_up(message: &str) -> Option<String> {
+    let message = message.trim().trim_matches('`').trim();
+    let mut lines = message.lines().skip_while(|line| line.trim().is_empty());
+    let first = lines
+        .next()?
+        .trim()
+        .trim_start_matches(['#', '*', '>'])
+        .trim();
+    let first = first
+        .strip_prefix("Commit message:")
+        .or_else(|| first.strip_prefix("Subject:"))
+        .unwrap_or(first)
+        .trim();
+    let (gitmoji, summary) = split_gitmoji(first)?;
+    let summary = summary.trim();
+    if summary.is_empty() {
+        return None;
+    }
+    let subject = format!("{} {}", gitmoji.emoji, summary);
+    let body = lines.collect::<Vec<&str>>().join("\n").trim().to_string();
+    Some(if body.is_empty() {
+        subject
+    } else {
+        format!("{}\n{}", subject, body)
+    })
+}
+
+/// Stops a commit whose message doesn't start with a gitmoji, an edit or a hook may
+/// have taken it off
+///
+/// # Arguments
+///
+/// * `message` - The message about to be committed
+pub fn check(message: &str) -> Result<()> {
+    parse(message).map(|_| ()).map_err(|reason| {
+        GitAiError::Refused(format!(
+            "{}, --gitmoji commits need one of the emoji from https://gitmoji.dev",
+            reason
+        ))
+    })
+}
+
+/// Asks for the subject to start with the gitmoji that fits, whatever the rest of the
+/// prompt is.  The whole list goes along, the AI is good at making up emoji.
+///
+/// # Arguments
+///
+/// * `prompt` - The commit prompt
+pub fn prompt(prompt: AiPrompt) -> AiPrompt {
+    let listing: Vec<String> = GITMOJIS
+        .iter()
+        .map(|gitmoji| format!("{} {}", gitmoji.emoji, gitmoji.description))
+        .collect();
+    AiPrompt {
+        postmessage: format!(
+            "{} Start the subject line with the one gitmoji that fits the change best and a space, picked from this list: {}.",
+            prompt.postmessage.trim_end(),
+            listing.join("; ")
+        ),
+        ..prompt
+    }
+}
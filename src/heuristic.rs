@@ -0,0 +1,88 @@
+/// More files than this and the subject just counts them
+const NAMED_FILES: usize = 3;
+
+/// What a diff did to one file, as far as the text of it tells
+#[derive(Debug, Default)]
+struct FileChange {
+    path: String,
+    added: usize,
+    removed: usize,
+    new: bool,
+    deleted: bool,
+}
+
+/// The files in a diff from `Git::diff_to_string`, in order
+fn file_changes(diff_text: &str) -> Vec<FileChange> {
+    let mut files: Vec<FileChange> = Vec::new();
+    for line in diff_text.lines() {
+        if let Some(paths) = line.strip_prefix("diff --git ") {
+            let path = paths
+                .rsplit_once(" b/")
+                .map_or(paths, |(_, new_path)| new_path);
+            files.push(FileChange {
+                path: path.to_string(),
+                ..Default::default()
+            });
+            continue;
+        }
+        let file = match files.last_mut() {
+            Some(file) => file,
+            None => continue,
+        };
+        if line.starts_with("new file mode") {
+            file.new = true;
+        } else if line.starts_with("deleted file mode") {
+            file.deleted = true;
+        } else if line.starts_with('+') && !line.starts_with("+++") {
+            file.added += 1;
+        } else if line.starts_with('-') && !line.starts_with("---") {
+            file.removed += 1;
+        }
+    }
+    files
+}
+
+/// A plain commit message from the shape of the diff, which files and how much of them,
+/// for when the AI can't be waited for.  It won't say why, but it is never wrong.
+///
+/// # Arguments
+///
+/// * `diff_text` - The diff, from `Git::diff_to_string`
+/// * `conventional` - Write a Conventional Commit
+pub fn commit_message(diff_text: &str, conventional: bool) -> String {
+    let files = file_changes(diff_text);
+    let verb = if !files.is_empty() && files.iter().all(|file| file.new) {
+        "Add"
+    } else if !files.is_empty() && files.iter().all(|file| file.deleted) {
+        "Remove"
+    } else {
+        "Update"
+    };
+    let what = match files.len() {
+        0 => "files".to_string(),
+        1..=NAMED_FILES => {
+            let names: Vec<&str> = files.iter().map(|file| file.path.as_str()).collect();
+            match names.split_last() {
+                Some((last, rest)) if !rest.is_empty() => {
+                    format!("{} and {}", rest.join(", "), last)
+                }
+                _ => names.join(""),
+            }
+        }
+        count => format!("{} files", count),
+    };
+    let subject = if conventional {
+        format!("chore: {} {}", verb.to_lowercase(), what)
+    } else {
+        format!("{} {}", verb, what)
+    };
+    let body: Vec<String> = files
+        .iter()
+        .map(|file| format!("{} (+{} -{})", file.path, file.added, file.removed))
+        .collect();
+    if body.is_empty() {
+        subject
+    } else {
+        format!("{}\n\n{}", subject, body.join("\n"))
+    }
+}
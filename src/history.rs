@@ -0,0 +1,150 @@
+use std::path::Path;
+
+use crate::settings::AiPrompt;
+
+/// How many of the biggest commits the summary lists
+pub const KEY_COMMITS: usize = 5;
+
+/// One commit in a file's history
+#[derive(Debug, Clone)]
+pub struct FileCommit {
+    /// The short commit id
+    pub id: String,
+    /// Who wrote it
+    pub author: String,
+    /// When, as `YYYY-MM-DD`
+    pub date: String,
+    /// The first line of the message
+    pub subject: String,
+    /// Lines it added to the file
+    pub added: usize,
+    /// Lines it removed from the file
+    pub removed: usize,
+    /// What it did to the file, as the AI should see it
+    pub diff: String,
+}
+
+/// Everyone who changed the file with how many commits they made, most first
+///
+/// # Arguments
+///
+/// * `commits` - The file's history
+pub fn authors(commits: &[FileCommit]) -> Vec<(String, usize)> {
+    let mut authors: Vec<(String, usize)> = Vec::new();
+    for commit in commits {
+        match authors.iter_mut().find(|(name, _)| *name == commit.author) {
+            Some((_, count)) => *count += 1,
+            None => authors.push((commit.author.to_string(), 1)),
+        }
+    }
+    // stable, so ties stay in the order they last touched the file
+    authors.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+    authors
+}
+
+/// The `count` commits that changed the most lines of the file, newest first
+///
+/// # Arguments
+///
+/// * `commits` - The file's history, newest first
+/// * `count` - How many to keep
+pub fn key_commits(commits: &[FileCommit], count: usize) -> Vec<&FileCommit> {
+    let mut biggest: Vec<usize> = (0..commits.len()).collect();
+    biggest.sort_by_key(|&i| std::cmp::Reverse(commits[i].added + commits[i].removed));
+    biggest.truncate(count);
+    biggest.sort_unstable();
+    biggest.into_iter().map(|i| &commits[i]).collect()
+}
+
+/// The prompt asking the AI for the story of the file
+///
+/// # Arguments
+///
+/// * `language` - The programming language
+/// * `path` - The file
+/// * `commits` - The file's history, newest first
+pub fn prompt(language: &str, path: &Path, commits: &[FileCommit]) -> AiPrompt {
+    let mut listing = String::new();
+    // oldest first reads like a story
+    for commit in commits.iter().rev() {
+        listing.push_str(&format!(
+            "commit {} by {} on {}: {}\n{}\n",
+            commit.id,
+            commit.author,
+            commit.date,
+            commit.subject,
+            commit.diff.trim_end()
+        ));
+    }
+    AiPrompt {
+        language: language.to_string(),
+        postamble: format!(
+            "developer about to refactor {}. This is its history, oldest commit first, with what each commit did to it:",
+            path.display()
+        ),
+        git_diff: listing,
+        postmessage: "Summarize how this file evolved in a few short paragraphs: what it was for at first, how its responsibilities grew, moved or shrank over time, and the turning points that matter for someone about to refactor it.  Mention commits by their id.  Don't list every commit.".to_string(),
+        ..Default::default()
+    }
+}
+
+/// The commit prompt, told how the last commits to the same files were written so a
+/// change that keeps coming back (bumping the same config, say) gets the same kind of
+/// message every time
+///
+/// # Arguments
+///
+/// * `prompt` - The commit prompt
+/// * `subjects` - The first lines of the earlier commits, newest first
+pub fn consistent_prompt(prompt: AiPrompt, subjects: &[String]) -> AiPrompt {
+    if subjects.is_empty() {
+        return prompt;
+    }
+    let listing: Vec<String> = subjects
+        .iter()
+        .map(|subject| format!("\"{}\"", subject))
+        .collect();
+    AiPrompt {
+        postmessage: format!(
+            "{} The last commits to these files were {}. Where this change is the same kind of change, stay consistent with their wording and format.",
+            prompt.postmessage.trim_end(),
+            listing.join(", ")
+        ),
+        ..prompt
+    }
+}
+
+/// The summary, the numbers first and then the AI's story of the file
+///
+/// # Arguments
+///
+/// * `path` - The file
+/// * `commits` - The file's history, newest first
+/// * `story` - What the AI made of it
+pub fn report(path: &Path, commits: &[FileCommit], story: &str) -> String {
+    let authors = authors(commits);
+    let mut report = format!(
+        "{}: {} commit(s) by {} author(s)",
+        path.display(),
+        commits.len(),
+        authors.len()
+    );
+    if let (Some(newest), Some(oldest)) = (commits.first(), commits.last()) {
+        report.push_str(&format!(", {} to {}", oldest.date, newest.date));
+    }
+    report.push_str("\n\nAuthors:\n");
+    for (name, count) in &authors {
+        report.push_str(&format!("\n{:>5}  {}", count, name));
+    }
+    report.push_str("\n\nKey commits:\n");
+    for commit in key_commits(commits, KEY_COMMITS) {
+        report.push_str(&format!(
+            "\n{}  {}  +{} -{}  {}",
+            commit.id, commit.date, commit.added, commit.removed, commit.subject
+        ));
+    }
+    if !story.trim().is_empty() {
+        report.push_str(&format!("\n\n{}", story.trim()));
+    }
+    report
+}
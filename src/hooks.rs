@@ -0,0 +1,108 @@
+use std::{
+    io::Write,
+    path::PathBuf,
+    process::{Command, Stdio},
+};
+
+use log::{debug, info};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::error::{GitAiError, Result};
+use crate::settings::gitai_home;
+
+/// The places in a gitai run where user scripts get a say
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookPoint {
+    /// Right before the prompt is sent to the AI, the payload is the `AiPrompt`
+    PrePrompt,
+    /// Right after the AI answered, the payload is the list of completions
+    PostGeneration,
+    /// Right before the commit is made, the payload is the commit message
+    PreCommit,
+    /// After the pull request was created, the payload is the GitHub response
+    PostPr,
+}
+
+impl HookPoint {
+    /// The name of the script in `~/.gitai/hooks/` for this hook point
+    pub fn name(&self) -> &'static str {
+        match self {
+            HookPoint::PrePrompt => "pre_prompt",
+            HookPoint::PostGeneration => "post_generation",
+            HookPoint::PreCommit => "pre_commit",
+            HookPoint::PostPr => "post_pr",
+        }
+    }
+}
+
+/// The payload for `post_generation`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Completions {
+    pub completions: Vec<String>,
+}
+
+/// The payload for `pre_commit`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CommitMessage {
+    pub message: String,
+}
+
+/// Where the script for a hook point lives
+pub fn hook_path(point: HookPoint) -> PathBuf {
+    let mut p = gitai_home();
+    p.push("hooks");
+    p.push(point.name());
+    p
+}
+
+/// Runs the user script for `point` (if there is one) with `payload` as JSON on stdin.
+///
+/// If the script prints JSON to stdout it replaces the payload, if it prints nothing the
+/// payload is passed along untouched.  A non-zero exit status is a veto.
+///
+/// # Arguments
+///
+/// * `point` - The hook point
+/// * `payload` - The payload to hand to the script
+///
+/// # Errors
+///
+/// Returns `GitAiError::HookVeto` if the script exited with a non-zero status, or an error if the
+/// script couldn't be run or printed something that isn't the payload as JSON
+pub fn run<T>(point: HookPoint, payload: T) -> Result<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    let path = hook_path(point);
+    if !path.is_file() {
+        return Ok(payload);
+    }
+    info!("Running the {} hook", point.name());
+    let mut child = Command::new(&path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        serde_json::to_writer(&mut stdin, &payload)?;
+        stdin.flush()?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        let reason = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        return Err(GitAiError::HookVeto {
+            hook: point.name(),
+            reason: if reason.is_empty() {
+                format!("it exited with {}", output.status)
+            } else {
+                reason
+            },
+        });
+    }
+    if output.stdout.iter().all(|b| b.is_ascii_whitespace()) {
+        debug!("The {} hook left the payload alone", point.name());
+        return Ok(payload);
+    }
+    debug!("The {} hook changed the payload", point.name());
+    Ok(serde_json::from_slice(&output.stdout)?)
+}
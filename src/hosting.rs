@@ -0,0 +1,961 @@
+use std::path::PathBuf;
+
+use futures_util::future::LocalBoxFuture;
+use git2::Repository;
+use log::{debug, info, warn};
+use reqwest::{
+    header::{HeaderMap, HeaderValue, ACCEPT, AUTHORIZATION, CONTENT_TYPE},
+    StatusCode,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{check_status, GitAiError, Result};
+use crate::git::parse_remote_url;
+use crate::network::{self, Service};
+use crate::settings::{GitSettings, Provider};
+
+/// What every hosting provider hands back once the pull request exists
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PullRequest {
+    /// The number people use to talk about it (`#12` or `!12`)
+    pub number: u64,
+    /// The title
+    pub title: String,
+    /// open, closed, merged, ...
+    pub state: String,
+    /// Where to look at it in a browser
+    pub html_url: String,
+}
+
+/// What a pull request is opened with besides its title and body
+#[derive(Debug, Clone, Default)]
+pub struct PullRequestOptions {
+    /// Open it as a draft
+    pub draft: bool,
+    /// The logins asked to review it
+    pub reviewers: Vec<String>,
+    /// The teams asked to review it, by slug (GitHub only)
+    pub team_reviewers: Vec<String>,
+    /// The labels it gets
+    pub labels: Vec<String>,
+    /// The logins it's assigned to
+    pub assignees: Vec<String>,
+}
+
+/// Everything gitai needs from a place that hosts repos and pull requests.  To add a new
+/// one implement this and add it to `new_provider`, nothing else has to change.  The
+/// methods that talk to the server hand back boxed futures so providers can sit behind a
+/// `dyn`.
+pub trait HostingProvider {
+    /// A human friendly name, used in messages
+    fn name(&self) -> &'static str;
+
+    /// The user name that owns the token
+    fn get_authenticated_user(&self) -> LocalBoxFuture<'_, Result<String>>;
+
+    /// When the token stops working, `None` if it never does (or the forge won't say)
+    fn token_expiry(&self) -> LocalBoxFuture<'_, Result<Option<String>>> {
+        Box::pin(async { Ok(None) })
+    }
+
+    /// Checks the token can do what `gitai pr` needs before anything is generated, so
+    /// a missing permission is a clear message instead of a failed request later on
+    ///
+    /// # Arguments
+    ///
+    /// * `repo` - The repository
+    fn verify_access<'a>(&'a self, _repo: &'a Repository) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    /// The branch pull requests go into when nobody says otherwise
+    ///
+    /// # Arguments
+    ///
+    /// * `repo` - The repository
+    fn get_default_branch<'a>(&'a self, repo: &'a Repository)
+        -> LocalBoxFuture<'a, Result<String>>;
+
+    /// Create the pull request
+    ///
+    /// # Arguments
+    ///
+    /// * `repo` - The repository
+    /// * `to_branch` - The branch to merge into
+    /// * `from_branch` - The branch with the changes
+    /// * `title` - The pull request title
+    /// * `body` - The pull request body
+    /// * `options` - Draft, reviewers, labels and assignees
+    fn create_pull_request<'a>(
+        &'a self,
+        repo: &'a Repository,
+        to_branch: &'a str,
+        from_branch: &'a str,
+        title: &'a str,
+        body: &'a str,
+        options: &'a PullRequestOptions,
+    ) -> LocalBoxFuture<'a, Result<PullRequest>>;
+
+    /// Closes pull request `number` without merging it, for `gitai undo`
+    fn close_pull_request<'a>(
+        &'a self,
+        repo: &'a Repository,
+        number: u64,
+    ) -> LocalBoxFuture<'a, Result<()>>;
+}
+
+/// Builds the hosting provider picked in the settings
+///
+/// # Arguments
+///
+/// * `git_settings` - The git settings, with any command line overrides already applied
+pub fn new_provider(git_settings: &GitSettings) -> Box<dyn HostingProvider> {
+    match git_settings.provider {
+        Provider::Github => Box::new(
+            GitHub::new(
+                &git_settings.github_api_key,
+                &git_settings.github_api_url,
+                &git_settings.github_ca_cert,
+            )
+            .with_remote(&git_settings.remote),
+        ),
+        Provider::Gitlab => Box::new(
+            GitLab::new(&git_settings.gitlab_api_key, &git_settings.gitlab_api_url)
+                .with_remote(&git_settings.remote),
+        ),
+    }
+}
+
+/// GitGub Options
+#[derive(Debug, Default)]
+pub struct GitHub {
+    /// The GitHub API Token
+    github_token: String,
+    /// The GitHub API URL
+    github_url: String,
+    /// The PEM file of the CA a GitHub Enterprise server's certificate is signed by
+    ca_cert: String,
+    /// The remote whose repo pull requests go to
+    remote: String,
+}
+
+/// What GitHub sends back when a pull request is created
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PullResponse {
+    pub url: String,
+    pub html_url: String,
+    pub diff_url: String,
+    pub patch_url: String,
+    pub issue_url: String,
+    pub commits_url: String,
+    pub review_comments_url: String,
+    pub review_comment_url: String,
+    pub statuses_url: String,
+    pub number: u64,
+    pub title: String,
+    pub state: String,
+    pub locked: bool,
+}
+
+/// The implementation for `GitHubOptions`
+impl GitHub {
+    /// Create a new GitHub struct.
+    ///
+    /// # Arguments
+    ///
+    /// * `github_token` - The Github Token
+    /// * `github_url` - The Github API Url, or a GitHub Enterprise host
+    /// * `ca_cert` - The PEM file of the CA to trust as well, empty for none
+    pub fn new(github_token: &str, github_url: &str, ca_cert: &str) -> Self {
+        GitHub {
+            github_token: github_token.to_string(),
+            github_url: github_api_url(github_url),
+            ca_cert: ca_cert.to_string(),
+            remote: DEFAULT_REMOTE.to_string(),
+        }
+    }
+
+    /// The same provider, opening pull requests on the repo `remote` points at
+    ///
+    /// # Arguments
+    ///
+    /// * `remote` - The remote's name, `origin` when it's empty
+    pub fn with_remote(self, remote: &str) -> Self {
+        GitHub {
+            remote: remote_or_origin(remote),
+            ..self
+        }
+    }
+
+    /// Puts `body` in the notes of the release for `tag`, creating the release when there
+    /// isn't one yet, and hands back where to look at it
+    ///
+    /// # Arguments
+    ///
+    /// * `repo` - The repository
+    /// * `tag` - The release's tag
+    /// * `name` - What a new release is called
+    /// * `body` - The release notes
+    /// * `draft` - Create the release as a draft
+    pub async fn publish_release(
+        &self,
+        repo: &Repository,
+        tag: &str,
+        name: &str,
+        body: &str,
+        draft: bool,
+    ) -> Result<String> {
+        let (owner, repo_name) = repo_path(repo, &self.remote, self).await?;
+        let releases = format!("{}/repos/{}/{}/releases", self.github_url, owner, repo_name);
+        let client = self.get_client()?;
+        let existing = client
+            .get(format!("{}/tags/{}", releases, tag))
+            .send()
+            .await?;
+        let res = if existing.status() == StatusCode::NOT_FOUND {
+            info!("Creating the {} release on {}/{}", tag, owner, repo_name);
+            client
+                .post(&releases)
+                .json(&serde_json::json!({
+                    "tag_name": tag,
+                    "name": name,
+                    "body": body,
+                    "draft": draft,
+                }))
+                .send()
+                .await?
+        } else {
+            let release = check_status(existing, "GitHub")
+                .await?
+                .json::<serde_json::Value>()
+                .await?;
+            let id = release
+                .get("id")
+                .and_then(|id| id.as_u64())
+                .ok_or_else(|| {
+                    GitAiError::BadResponse(
+                        "GitHub didn't send back the release's `id`".to_string(),
+                    )
+                })?;
+            info!("Updating the {} release on {}/{}", tag, owner, repo_name);
+            client
+                .patch(format!("{}/{}", releases, id))
+                .json(&serde_json::json!({ "body": body }))
+                .send()
+                .await?
+        };
+        let release = check_status(res, "GitHub")
+            .await?
+            .json::<serde_json::Value>()
+            .await?;
+        Ok(release
+            .get("html_url")
+            .and_then(|url| url.as_str())
+            .unwrap_or(&releases)
+            .to_string())
+    }
+
+    /// The head commit of pull request `number` and its diff, as a unified diff
+    ///
+    /// # Arguments
+    ///
+    /// * `repo` - The repository
+    /// * `number` - The pull request
+    pub async fn pull_request_diff(
+        &self,
+        repo: &Repository,
+        number: u64,
+    ) -> Result<(String, String)> {
+        let (owner, name) = repo_path(repo, &self.remote, self).await?;
+        let url = format!(
+            "{}/repos/{}/{}/pulls/{}",
+            self.github_url, owner, name, number
+        );
+        let client = self.get_client()?;
+        let pull = check_status(client.get(&url).send().await?, "GitHub")
+            .await?
+            .json::<serde_json::Value>()
+            .await?;
+        let head = pull
+            .pointer("/head/sha")
+            .and_then(|sha| sha.as_str())
+            .ok_or_else(|| {
+                GitAiError::BadResponse(format!("GitHub didn't send back the head of #{}", number))
+            })?
+            .to_string();
+        let diff = check_status(
+            client
+                .get(&url)
+                .header(ACCEPT, "application/vnd.github.diff")
+                .send()
+                .await?,
+            "GitHub",
+        )
+        .await?
+        .text()
+        .await?;
+        Ok((head, diff))
+    }
+
+    /// Posts a review on pull request `number` that only comments, `comments` are
+    /// `(path, line, text)` on lines of the new files, and hands back where it is
+    ///
+    /// # Arguments
+    ///
+    /// * `repo` - The repository
+    /// * `number` - The pull request
+    /// * `commit_id` - The head commit the lines are from
+    /// * `body` - The review's own text
+    /// * `comments` - The comments on lines
+    pub async fn post_review(
+        &self,
+        repo: &Repository,
+        number: u64,
+        commit_id: &str,
+        body: &str,
+        comments: &[(String, u32, String)],
+    ) -> Result<String> {
+        let (owner, name) = repo_path(repo, &self.remote, self).await?;
+        let url = format!(
+            "{}/repos/{}/{}/pulls/{}/reviews",
+            self.github_url, owner, name, number
+        );
+        let comments: Vec<serde_json::Value> = comments
+            .iter()
+            .map(|(path, line, text)| {
+                serde_json::json!({ "path": path, "line": line, "side": "RIGHT", "body": text })
+            })
+            .collect();
+        info!(
+            "Posting a review with {} comment(s) to {}",
+            comments.len(),
+            url
+        );
+        let review = check_status(
+            self.get_client()?
+                .post(&url)
+                .json(&serde_json::json!({
+                    "commit_id": commit_id,
+                    "event": "COMMENT",
+                    "body": body,
+                    "comments": comments,
+                }))
+                .send()
+                .await?,
+            "GitHub",
+        )
+        .await?
+        .json::<serde_json::Value>()
+        .await?;
+        Ok(review
+            .get("html_url")
+            .and_then(|url| url.as_str())
+            .unwrap_or(&url)
+            .to_string())
+    }
+
+    fn get_client(&self) -> Result<reqwest::Client> {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("application/vnd.github+json"),
+        );
+        headers.insert(
+            AUTHORIZATION,
+            token_header(&format!("Bearer {}", self.github_token))?,
+        );
+        headers.insert(
+            "X-GitHub-Api-Version",
+            HeaderValue::from_static("2022-11-28"),
+        );
+        let mut builder = network::client_builder(Service::Git)?.default_headers(headers);
+        if !self.ca_cert.is_empty() {
+            for cert in network::read_certificates(&self.ca_cert)? {
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+        Ok(builder.build()?)
+    }
+}
+
+/// Where the API is for a `github_api_url`.  Empty is github.com's, and a GitHub
+/// Enterprise Server given as just its host gets `/api/v3`, where its REST API lives.
+///
+/// # Arguments
+///
+/// * `url` - The url from the settings or the command line
+pub fn github_api_url(url: &str) -> String {
+    let url = url.trim().trim_end_matches('/');
+    match url::Url::parse(url) {
+        Err(_) if url.is_empty() => GITHUB_API_URL.to_string(),
+        Ok(parsed) if parsed.host_str() == Some("github.com") => GITHUB_API_URL.to_string(),
+        Ok(parsed) if parsed.host_str() != Some("api.github.com") && parsed.path() == "/" => {
+            format!("{}/api/v3", url)
+        }
+        _ => url.to_string(),
+    }
+}
+
+impl HostingProvider for GitHub {
+    fn name(&self) -> &'static str {
+        "GitHub"
+    }
+
+    fn get_authenticated_user(&self) -> LocalBoxFuture<'_, Result<String>> {
+        Box::pin(async move {
+            get_value_from_api(
+                self.get_client()?,
+                &self.github_url,
+                "login",
+                "user".to_string(),
+            )
+            .await
+        })
+    }
+
+    /// Tokens that expire say when on every response
+    fn token_expiry(&self) -> LocalBoxFuture<'_, Result<Option<String>>> {
+        Box::pin(async move {
+            let user = check_status(
+                self.get_client()?
+                    .get(format!("{}/user", self.github_url))
+                    .send()
+                    .await?,
+                "GitHub",
+            )
+            .await?;
+            Ok(user
+                .headers()
+                .get("github-authentication-token-expiration")
+                .and_then(|expiry| expiry.to_str().ok())
+                .map(String::from))
+        })
+    }
+
+    /// Classic tokens list their scopes in `X-OAuth-Scopes`, fine-grained ones don't,
+    /// so for those we look at what the token is allowed to do to the repo
+    fn verify_access<'a>(&'a self, repo: &'a Repository) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let client = self.get_client()?;
+            let user = check_status(
+                client
+                    .get(format!("{}/user", self.github_url))
+                    .send()
+                    .await?,
+                "GitHub",
+            )
+            .await?;
+            let scopes = user
+                .headers()
+                .get("x-oauth-scopes")
+                .and_then(|scopes| scopes.to_str().ok())
+                .map(|scopes| {
+                    scopes
+                        .split(',')
+                        .map(|scope| scope.trim().to_string())
+                        .collect::<Vec<String>>()
+                });
+            let (owner, name) = repo_path(repo, &self.remote, self).await?;
+            let repo_url = format!("{}/repos/{}/{}", self.github_url, owner, name);
+            let res = client.get(&repo_url).send().await?;
+            if res.status() == StatusCode::NOT_FOUND {
+                return Err(GitAiError::Permission(format!(
+                    "The GitHub token can't see {}/{}, give it access to the repository",
+                    owner, name
+                )));
+            }
+            let info = check_status(res, "GitHub")
+                .await?
+                .json::<serde_json::Value>()
+                .await?;
+            let private = info
+                .get("private")
+                .and_then(|p| p.as_bool())
+                .unwrap_or(true);
+            match scopes {
+                Some(scopes) => {
+                    debug!("Classic token with scopes {:?}", scopes);
+                    let has = |scope: &str| scopes.iter().any(|s| s == scope);
+                    if !has("repo") && (private || !has("public_repo")) {
+                        return Err(GitAiError::Permission(format!(
+                        "The GitHub token is missing the `{}` scope, which gitai needs to push and open pull requests",
+                        if private { "repo" } else { "public_repo" }
+                    )));
+                    }
+                }
+                None => {
+                    debug!("Fine-grained token, checking the repo permissions");
+                    let can_push = info
+                        .get("permissions")
+                        .and_then(|p| p.get("push"))
+                        .and_then(|p| p.as_bool())
+                        .unwrap_or(false);
+                    if !can_push {
+                        return Err(GitAiError::Permission("The GitHub token is missing the `contents: write` permission on this repository".to_string()));
+                    }
+                }
+            }
+            Ok(())
+        })
+    }
+
+    fn get_default_branch<'a>(
+        &'a self,
+        repo: &'a Repository,
+    ) -> LocalBoxFuture<'a, Result<String>> {
+        Box::pin(async move {
+            let (owner, name) = repo_path(repo, &self.remote, self).await?;
+            let url_tail = format!("repos/{}/{}", owner, name);
+            get_value_from_api(
+                self.get_client()?,
+                &self.github_url,
+                "default_branch",
+                url_tail,
+            )
+            .await
+        })
+    }
+
+    fn create_pull_request<'a>(
+        &'a self,
+        repo: &'a Repository,
+        to_branch: &'a str,
+        from_branch: &'a str,
+        title: &'a str,
+        body: &'a str,
+        options: &'a PullRequestOptions,
+    ) -> LocalBoxFuture<'a, Result<PullRequest>> {
+        Box::pin(async move {
+            debug!("Pushing commits from {} to {}", from_branch, to_branch);
+            let (owner, name) = repo_path(repo, &self.remote, self).await?;
+            let url = format!("{}/repos/{}/{}/pulls", self.github_url, owner, name);
+            debug!("Posting to {}", url);
+            let client = self.get_client()?;
+            // set the body
+            let map = serde_json::json!({
+                "title": title,
+                "head": from_branch,
+                "base": to_branch,
+                "body": body,
+                "draft": options.draft,
+            });
+            info!("Sending push request to {}", url);
+            let res = client.post(&url).json(&map).send().await?;
+            if res.status() == StatusCode::FORBIDDEN || res.status() == StatusCode::NOT_FOUND {
+                // fine-grained tokens get told exactly which permission the endpoint wanted
+                if let Some(wanted) = res
+                    .headers()
+                    .get("x-accepted-github-permissions")
+                    .and_then(|wanted| wanted.to_str().ok())
+                {
+                    return Err(GitAiError::Permission(format!(
+                        "GitHub refused to create the pull request, the token needs `{}`",
+                        wanted
+                    )));
+                }
+            }
+            let data = check_status(res, "GitHub")
+                .await?
+                .json::<PullResponse>()
+                .await?;
+            // GitHub only takes these once the pull request exists
+            let issue_url = format!(
+                "{}/repos/{}/{}/issues/{}",
+                self.github_url, owner, name, data.number
+            );
+            let follow_ups = [
+                (
+                    format!("{}/{}/requested_reviewers", url, data.number),
+                    "reviewers",
+                    !options.reviewers.is_empty() || !options.team_reviewers.is_empty(),
+                    serde_json::json!({
+                        "reviewers": options.reviewers,
+                        "team_reviewers": options.team_reviewers,
+                    }),
+                ),
+                (
+                    format!("{}/labels", issue_url),
+                    "labels",
+                    !options.labels.is_empty(),
+                    serde_json::json!({ "labels": options.labels }),
+                ),
+                (
+                    format!("{}/assignees", issue_url),
+                    "assignees",
+                    !options.assignees.is_empty(),
+                    serde_json::json!({ "assignees": options.assignees }),
+                ),
+            ];
+            for (follow_up, what, wanted, json) in follow_ups {
+                if !wanted {
+                    continue;
+                }
+                debug!("Adding the {} with {}", what, follow_up);
+                let res = client.post(&follow_up).json(&json).send().await;
+                let added = match res {
+                    Ok(res) => check_status(res, "GitHub").await.map(|_| ()),
+                    Err(e) => Err(e.into()),
+                };
+                // the pull request is there, it's not worth failing over
+                if let Err(e) = added {
+                    warn!("Couldn't add the {} to #{}: {}", what, data.number, e);
+                }
+            }
+            Ok(PullRequest {
+                number: data.number,
+                title: data.title,
+                state: data.state,
+                html_url: data.html_url,
+            })
+        })
+    }
+
+    fn close_pull_request<'a>(
+        &'a self,
+        repo: &'a Repository,
+        number: u64,
+    ) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let (owner, name) = repo_path(repo, &self.remote, self).await?;
+            let url = format!(
+                "{}/repos/{}/{}/pulls/{}",
+                self.github_url, owner, name, number
+            );
+            debug!("Closing {}", url);
+            check_status(
+                self.get_client()?
+                    .patch(url)
+                    .json(&serde_json::json!({ "state": "closed" }))
+                    .send()
+                    .await?,
+                "GitHub",
+            )
+            .await?;
+            Ok(())
+        })
+    }
+}
+
+/// GitLab Options
+#[derive(Debug, Default)]
+pub struct GitLab {
+    /// The GitLab API Token
+    gitlab_token: String,
+    /// The GitLab API URL, something like `https://gitlab.com/api/v4` for self-hosted ones
+    gitlab_url: String,
+    /// The remote whose project merge requests go to
+    remote: String,
+}
+
+/// What GitLab sends back when a merge request is created
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MergeRequestResponse {
+    pub id: u64,
+    pub iid: u64,
+    pub project_id: u64,
+    pub title: String,
+    pub state: String,
+    pub web_url: String,
+}
+
+/// The implementation for `GitLab`
+impl GitLab {
+    /// Create a new GitLab struct.
+    ///
+    /// # Arguments
+    ///
+    /// * `gitlab_token` - The GitLab Token
+    /// * `gitlab_url` - The GitLab API Url
+    pub fn new(gitlab_token: &str, gitlab_url: &str) -> Self {
+        GitLab {
+            gitlab_token: gitlab_token.to_string(),
+            gitlab_url: gitlab_url.to_string(),
+            remote: DEFAULT_REMOTE.to_string(),
+        }
+    }
+
+    /// The same provider, opening merge requests on the project `remote` points at
+    ///
+    /// # Arguments
+    ///
+    /// * `remote` - The remote's name, `origin` when it's empty
+    pub fn with_remote(self, remote: &str) -> Self {
+        GitLab {
+            remote: remote_or_origin(remote),
+            ..self
+        }
+    }
+
+    /// GitLab wants the url encoded `namespace/project` as the project id
+    async fn project_url(&self, repo: &Repository) -> Result<String> {
+        let (namespace, name) = repo_path(repo, &self.remote, self).await?;
+        let project = format!("{}/{}", namespace, name);
+        Ok(format!(
+            "{}/projects/{}",
+            self.gitlab_url,
+            url::form_urlencoded::byte_serialize(project.as_bytes()).collect::<String>()
+        ))
+    }
+
+    /// GitLab wants user ids, not names
+    async fn user_ids(&self, usernames: &[String]) -> Result<Vec<u64>> {
+        let client = self.get_client()?;
+        let mut ids = Vec::new();
+        for username in usernames {
+            let users = check_status(
+                client
+                    .get(format!("{}/users", self.gitlab_url))
+                    .query(&[("username", username)])
+                    .send()
+                    .await?,
+                "GitLab",
+            )
+            .await?
+            .json::<serde_json::Value>()
+            .await?;
+            match users.pointer("/0/id").and_then(|id| id.as_u64()) {
+                Some(id) => ids.push(id),
+                None => {
+                    return Err(GitAiError::Usage(format!(
+                        "There is no GitLab user called {}",
+                        username
+                    )))
+                }
+            }
+        }
+        Ok(ids)
+    }
+
+    /// Gets a string field from the GitLab API
+    async fn get_value(&self, url: String, key: &str) -> Result<String> {
+        let response = check_status(self.get_client()?.get(url).send().await?, "GitLab")
+            .await?
+            .json::<serde_json::Value>()
+            .await?;
+        match response.get(key).and_then(|value| value.as_str()) {
+            Some(value) => Ok(value.to_string()),
+            None => Err(GitAiError::BadResponse(format!(
+                "GitLab didn't send back a `{}`",
+                key
+            ))),
+        }
+    }
+
+    fn get_client(&self) -> Result<reqwest::Client> {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        headers.insert("PRIVATE-TOKEN", token_header(&self.gitlab_token)?);
+        Ok(network::client_builder(Service::Git)?
+            .default_headers(headers)
+            .build()?)
+    }
+}
+
+impl HostingProvider for GitLab {
+    fn name(&self) -> &'static str {
+        "GitLab"
+    }
+
+    fn get_authenticated_user(&self) -> LocalBoxFuture<'_, Result<String>> {
+        Box::pin(self.get_value(format!("{}/user", self.gitlab_url), "username"))
+    }
+
+    fn token_expiry(&self) -> LocalBoxFuture<'_, Result<Option<String>>> {
+        Box::pin(async move {
+            let url = format!("{}/personal_access_tokens/self", self.gitlab_url);
+            let token = check_status(self.get_client()?.get(url).send().await?, "GitLab")
+                .await?
+                .json::<serde_json::Value>()
+                .await?;
+            // null for tokens that don't expire
+            Ok(token
+                .get("expires_at")
+                .and_then(|expiry| expiry.as_str())
+                .map(String::from))
+        })
+    }
+
+    fn get_default_branch<'a>(
+        &'a self,
+        repo: &'a Repository,
+    ) -> LocalBoxFuture<'a, Result<String>> {
+        Box::pin(async move {
+            self.get_value(self.project_url(repo).await?, "default_branch")
+                .await
+        })
+    }
+
+    /// GitLab calls these merge requests
+    fn create_pull_request<'a>(
+        &'a self,
+        repo: &'a Repository,
+        to_branch: &'a str,
+        from_branch: &'a str,
+        title: &'a str,
+        body: &'a str,
+        options: &'a PullRequestOptions,
+    ) -> LocalBoxFuture<'a, Result<PullRequest>> {
+        Box::pin(async move {
+            debug!("Merging {} into {}", from_branch, to_branch);
+            let url = format!("{}/merge_requests", self.project_url(repo).await?);
+            if !options.team_reviewers.is_empty() {
+                warn!(
+                    "GitLab can't ask teams for reviews, leaving out {}",
+                    options.team_reviewers.join(", ")
+                );
+            }
+            // GitLab has no draft flag, a merge request is a draft when its title says so
+            let title = if options.draft {
+                format!("Draft: {}", title)
+            } else {
+                title.to_string()
+            };
+            let map = serde_json::json!({
+                "title": title,
+                "source_branch": from_branch,
+                "target_branch": to_branch,
+                "description": body,
+                "labels": options.labels.join(","),
+                "reviewer_ids": self.user_ids(&options.reviewers).await?,
+                "assignee_ids": self.user_ids(&options.assignees).await?,
+            });
+            info!("Sending merge request to {}", url);
+            let res = check_status(
+                self.get_client()?.post(url).json(&map).send().await?,
+                "GitLab",
+            )
+            .await?;
+            let data = res.json::<MergeRequestResponse>().await?;
+            Ok(PullRequest {
+                number: data.iid,
+                title: data.title,
+                state: data.state,
+                html_url: data.web_url,
+            })
+        })
+    }
+
+    fn close_pull_request<'a>(
+        &'a self,
+        repo: &'a Repository,
+        number: u64,
+    ) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let url = format!(
+                "{}/merge_requests/{}",
+                self.project_url(repo).await?,
+                number
+            );
+            debug!("Closing {}", url);
+            check_status(
+                self.get_client()?
+                    .put(url)
+                    .json(&serde_json::json!({ "state_event": "close" }))
+                    .send()
+                    .await?,
+                "GitLab",
+            )
+            .await?;
+            Ok(())
+        })
+    }
+}
+
+// Helper functions
+
+/// The remote pull requests go to unless the settings or `--remote` say otherwise
+const DEFAULT_REMOTE: &str = "origin";
+
+/// github.com's API, GitHub Enterprise Server's is on its own host under `/api/v3`
+const GITHUB_API_URL: &str = "https://api.github.com";
+
+fn remote_or_origin(remote: &str) -> String {
+    match remote {
+        "" => DEFAULT_REMOTE.to_string(),
+        remote => remote.to_string(),
+    }
+}
+
+/// The owner (a user, an organization or a GitLab group) and name of the repo `remote`
+/// points at, from its url, so forks and checkouts in a directory with another name work.
+/// A remote that isn't a hosted repo (a path, say) falls back to the token's user, only
+/// then asked for, and the name of the directory the repo is in.
+///
+/// # Arguments
+///
+/// * `repo` - The repository
+/// * `remote` - The remote's name
+/// * `forge` - Where the token's user comes from
+async fn repo_path(
+    repo: &Repository,
+    remote: &str,
+    forge: &dyn HostingProvider,
+) -> Result<(String, String)> {
+    let location = repo
+        .find_remote(remote)
+        .ok()
+        .and_then(|remote| remote.url().and_then(parse_remote_url));
+    match location {
+        Some(location) => Ok((location.owner, location.name)),
+        None => {
+            debug!(
+                "{} isn't a hosted repo, going by the user and the directory name",
+                remote
+            );
+            Ok((forge.get_authenticated_user().await?, repo_name(repo)?))
+        }
+    }
+}
+
+/// The name of the repo, which is the name of the directory holding it
+fn repo_name(repo: &Repository) -> Result<String> {
+    // repo.path() is the .git directory, we want the one holding it
+    let binding = PathBuf::from(repo.workdir().unwrap_or(repo.path())).canonicalize()?;
+    match binding.file_name().and_then(|name| name.to_str()) {
+        Some(name) => Ok(name.to_string()),
+        None => Err(GitAiError::Usage(format!(
+            "Can't tell the repository's name from {:#?}",
+            binding
+        ))),
+    }
+}
+
+async fn get_value_from_api(
+    client: reqwest::Client,
+    base_url: &str,
+    key: &str,
+    url_tail: String,
+) -> Result<String> {
+    let url = format!("{}/{}", base_url, url_tail);
+    let response = check_status(
+        client
+            .get(&url)
+            .header(ACCEPT, "application/vnd.github+json")
+            .send()
+            .await?,
+        "GitHub",
+    )
+    .await?
+    .json::<serde_json::Value>()
+    .await?;
+
+    if let Some(value) = response.get(key) {
+        if let Some(value_str) = value.as_str() {
+            return Ok(value_str.to_string());
+        }
+    }
+
+    Err(GitAiError::BadResponse(format!(
+        "GitHub didn't send back a `{}`",
+        key
+    )))
+}
+
+/// A header value holding a token from the settings, which can have anything in it
+fn token_header(token: &str) -> Result<HeaderValue> {
+    HeaderValue::from_str(token).map_err(|_| {
+        GitAiError::Usage(
+            "The git api token has characters that can't be sent in a header".to_string(),
+        )
+    })
+}
@@ -0,0 +1,176 @@
+use std::io::{self, Write};
+
+use log::debug;
+
+use crate::ai::OpenAiClient;
+use crate::error::{GitAiError, Result};
+use crate::hosting::{GitHub, HostingProvider};
+use crate::interactive;
+use crate::settings::{settings_path, Settings};
+use crate::terminal;
+
+/// Where OpenAI lives, the trailing `/` matters
+const OPENAI_API_URL: &str = "https://api.openai.com/v1/";
+
+/// Where GitHub's API lives, GitHub Enterprise is `https://<host>/api/v3`
+const GITHUB_API_URL: &str = "https://api.github.com";
+
+/// Walks the user through writing `~/.gitai/settings.json`.  Keys and tokens are
+/// tried against the real APIs before anything is saved, so a typo shows up now
+/// instead of on the first commit.
+pub async fn run() -> Result<()> {
+    let path = settings_path();
+    let mut settings = if path.exists() {
+        println!("You already have settings at {}", path.display());
+        if !ask_flag("Go through them and overwrite the file?", false)? {
+            return Ok(());
+        }
+        Settings::from_file().unwrap_or_default().with_tokens()
+    } else {
+        Settings::default()
+    };
+    println!("Press enter to keep the value in [brackets]\n");
+
+    let ai = &mut settings.ai_settings;
+    ai.api_url = ask("OpenAI api url", or(&ai.api_url, OPENAI_API_URL))?;
+    let models = loop {
+        let key = ask_secret("OpenAI api key", &ai.api_key)?;
+        match OpenAiClient::new(ai.api_url.to_string(), key.to_string())?
+            .get_models()
+            .await
+        {
+            Ok(res) => {
+                ai.api_key = key;
+                println!("That key works");
+                break res
+                    .into_iter()
+                    .map(|model| model.id)
+                    .collect::<Vec<String>>();
+            }
+            Err(e) => println!("{}, try again", e),
+        }
+    };
+    debug!("The key can use {} models", models.len());
+    ai.ai_options.model = ask("Model", &ai.ai_options.model)?;
+    if !models.is_empty() && !models.contains(&ai.ai_options.model) {
+        println!(
+            "Heads up, {} isn't one of the models your key can use",
+            ai.ai_options.model
+        );
+    }
+    ai.ai_options.prompt.language = ask(
+        "The language you mostly write",
+        ai.ai_options.prompt.language.trim(),
+    )?;
+
+    let git = &mut settings.git_settings;
+    git.github_api_url = ask("GitHub api url", or(&git.github_api_url, GITHUB_API_URL))?;
+    loop {
+        let token = ask_secret(
+            "GitHub token (only needed for pull requests, leave it empty to skip)",
+            &git.github_api_key,
+        )?;
+        if token.is_empty() {
+            break;
+        }
+        match GitHub::new(&token, &git.github_api_url, &git.github_ca_cert)
+            .get_authenticated_user()
+            .await
+        {
+            Ok(user) => {
+                git.github_api_key = token;
+                println!("Signed in to GitHub as {}", user);
+                break;
+            }
+            Err(e) => println!("{}, try again", e),
+        }
+    }
+
+    println!();
+    let options = &mut settings.git_settings.git_options;
+    options.auto_add = ask_flag(
+        "Stage everything before committing (auto_add)?",
+        options.auto_add,
+    )?;
+    options.auto_push = ask_flag(
+        "Push the branch before opening a pull request (auto_push)?",
+        options.auto_push,
+    )?;
+    let ai_options = &mut settings.ai_settings.ai_options;
+    ai_options.auto_ai = ask_flag(
+        "Use the first message without asking (auto_ai)?",
+        ai_options.auto_ai,
+    )?;
+    ai_options.stochastic = ask_flag(
+        "Mix up the prompts for more varied messages (stochastic)?",
+        ai_options.stochastic,
+    )?;
+    ai_options.conventional_commits = ask_flag(
+        "Write Conventional Commits (feat(scope): summary)?",
+        ai_options.conventional_commits,
+    )?;
+
+    let path = settings.save()?;
+    println!("\nSaved your settings to {}", path.display());
+    Ok(())
+}
+
+/// `value`, or `default` if it is empty
+fn or<'a>(value: &'a str, default: &'a str) -> &'a str {
+    if value.is_empty() {
+        default
+    } else {
+        value
+    }
+}
+
+/// Asks for a line of text, an empty answer is `default`
+fn ask(question: &str, default: &str) -> Result<String> {
+    interactive::ask(question)?;
+    print!("{} [{}]: ", question, default);
+    io::stdout().flush()?;
+    let answer = read_answer(terminal::read_line(&mut io::stdin().lock())?)?;
+    if answer.is_empty() {
+        Ok(default.to_string())
+    } else {
+        Ok(answer)
+    }
+}
+
+/// Asks for a key or token without echoing it, an empty answer keeps `current`
+pub fn ask_secret(question: &str, current: &str) -> Result<String> {
+    interactive::ask(question)?;
+    if current.is_empty() {
+        print!("{}: ", question);
+    } else {
+        print!("{} [keep the current one]: ", question);
+    }
+    io::stdout().flush()?;
+    let answer = read_answer(terminal::read_password()?)?;
+    println!();
+    if answer.is_empty() {
+        Ok(current.to_string())
+    } else {
+        Ok(answer)
+    }
+}
+
+/// Asks a yes or no question, an empty answer is `default`
+fn ask_flag(question: &str, default: bool) -> Result<bool> {
+    interactive::ask(question)?;
+    print!("{} {} ", question, if default { "[Y/n]" } else { "[y/N]" });
+    io::stdout().flush()?;
+    let answer = read_answer(terminal::read_line(&mut io::stdin().lock())?)?.to_ascii_lowercase();
+    Ok(match answer.chars().next() {
+        Some('y') => true,
+        Some('n') => false,
+        _ => default,
+    })
+}
+
+/// The end of stdin means nobody is there to answer, keep asking and we'd never stop
+fn read_answer(line: Option<String>) -> Result<String> {
+    line.map(|line| line.trim().to_string()).ok_or_else(|| {
+        GitAiError::Usage("No answer, gitai init has to be run in a terminal".to_string())
+    })
+}
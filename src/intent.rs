@@ -0,0 +1,237 @@
+use std::{fmt, path::PathBuf};
+
+use crate::git::FileDiff;
+use crate::settings::AiPrompt;
+
+/// Only this many lines of each hunk go in the prompt, the start says enough about why
+const MAX_HUNK_LINES: usize = 40;
+
+/// Why a hunk changed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Intent {
+    Feature,
+    Bugfix,
+    Refactor,
+    Formatting,
+}
+
+impl Intent {
+    /// Every intent, in the order the breakdown lists them
+    pub const ALL: [Intent; 4] = [
+        Intent::Feature,
+        Intent::Bugfix,
+        Intent::Refactor,
+        Intent::Formatting,
+    ];
+
+    /// Reads an intent the way the AI tends to write it
+    pub fn parse(text: &str) -> Option<Self> {
+        match text
+            .trim()
+            .trim_matches(|c: char| !c.is_alphanumeric())
+            .to_lowercase()
+            .as_str()
+        {
+            "feature" | "feat" => Some(Intent::Feature),
+            "bugfix" | "fix" | "bug" => Some(Intent::Bugfix),
+            "refactor" | "refactoring" => Some(Intent::Refactor),
+            "formatting" | "format" | "style" => Some(Intent::Formatting),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Intent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Intent::Feature => "feature",
+            Intent::Bugfix => "bugfix",
+            Intent::Refactor => "refactor",
+            Intent::Formatting => "formatting",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// One hunk of a diff
+#[derive(Debug, Clone)]
+pub struct Hunk {
+    /// The file it is in
+    pub path: PathBuf,
+    /// The hunk, from its `@@` line on
+    pub text: String,
+    /// Lines added or removed
+    pub changed: usize,
+}
+
+/// Splits a diff into its hunks
+///
+/// # Arguments
+///
+/// * `files` - The diff, from `Git::diff_to_file_strings`
+pub fn hunks(files: &[FileDiff]) -> Vec<Hunk> {
+    let mut hunks: Vec<Hunk> = Vec::new();
+    for file in files {
+        let mut in_hunk = false;
+        for line in file.text.lines() {
+            if line.starts_with("@@") {
+                hunks.push(Hunk {
+                    path: file.path.clone(),
+                    text: String::new(),
+                    changed: 0,
+                });
+                in_hunk = true;
+            } else if !in_hunk {
+                // the file header
+                continue;
+            }
+            let hunk = match hunks.last_mut() {
+                Some(hunk) => hunk,
+                None => continue,
+            };
+            if line.starts_with('+') || line.starts_with('-') {
+                hunk.changed += 1;
+            }
+            hunk.text.push_str(line);
+            hunk.text.push('\n');
+        }
+    }
+    hunks
+}
+
+/// The prompt asking the AI to label every hunk with an intent
+///
+/// # Arguments
+///
+/// * `language` - The programming language
+/// * `hunks` - The hunks
+pub fn prompt(language: &str, hunks: &[Hunk]) -> AiPrompt {
+    let mut listing = String::new();
+    for (i, hunk) in hunks.iter().enumerate() {
+        listing.push_str(&format!("Hunk {} in {}\n", i + 1, hunk.path.display()));
+        for line in hunk.text.lines().take(MAX_HUNK_LINES) {
+            listing.push_str(line);
+            listing.push('\n');
+        }
+        listing.push('\n');
+    }
+    AiPrompt {
+        language: language.to_string(),
+        postamble: "code reviewer sorting out a change. These are its hunks, numbered:"
+            .to_string(),
+        git_diff: listing,
+        postmessage: format!(
+            "Label every hunk with why it was changed, one of: {}.  Answer with one line per hunk like `3: refactor` and nothing else.",
+            Intent::ALL
+                .iter()
+                .map(|intent| intent.to_string())
+                .collect::<Vec<String>>()
+                .join(", ")
+        ),
+        ..Default::default()
+    }
+}
+
+/// Reads the AI's labels, one per hunk, `None` for a hunk it left out or got wrong
+///
+/// # Arguments
+///
+/// * `answer` - What the AI said
+/// * `count` - How many hunks there are
+pub fn parse_answer(answer: &str, count: usize) -> Vec<Option<Intent>> {
+    let mut intents = vec![None; count];
+    for line in answer.lines() {
+        let (number, intent) = match line.split_once(':') {
+            Some(split) => split,
+            None => continue,
+        };
+        let number = number
+            .trim()
+            .trim_start_matches(|c: char| !c.is_ascii_digit())
+            .parse::<usize>();
+        if let (Ok(number), Some(intent)) = (number, Intent::parse(intent)) {
+            if (1..=count).contains(&number) {
+                intents[number - 1] = Some(intent);
+            }
+        }
+    }
+    intents
+}
+
+/// How much of a change went to each intent, by lines changed
+#[derive(Debug, Clone, Default)]
+pub struct Breakdown {
+    /// Lines changed per intent, biggest first
+    pub lines: Vec<(Intent, usize)>,
+    /// Lines in hunks the AI didn't label
+    pub unlabelled: usize,
+}
+
+impl Breakdown {
+    /// Adds the labelled hunks up
+    ///
+    /// # Arguments
+    ///
+    /// * `hunks` - The hunks
+    /// * `intents` - Their labels, from `parse_answer`
+    pub fn new(hunks: &[Hunk], intents: &[Option<Intent>]) -> Self {
+        let mut breakdown = Breakdown::default();
+        for (hunk, intent) in hunks.iter().zip(intents) {
+            match intent {
+                Some(intent) => match breakdown.lines.iter_mut().find(|(i, _)| i == intent) {
+                    Some((_, lines)) => *lines += hunk.changed,
+                    None => breakdown.lines.push((*intent, hunk.changed)),
+                },
+                None => breakdown.unlabelled += hunk.changed,
+            }
+        }
+        breakdown
+            .lines
+            .sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        breakdown
+    }
+
+    /// All the labelled lines
+    fn total(&self) -> usize {
+        self.lines.iter().map(|(_, lines)| lines).sum()
+    }
+
+    /// The percentage of the change that isn't about its main intent.  Formatting
+    /// doesn't count, reformatting what you touch isn't a second change.
+    pub fn mixed_percent(&self) -> u8 {
+        let substantive: Vec<usize> = self
+            .lines
+            .iter()
+            .filter(|(intent, _)| *intent != Intent::Formatting)
+            .map(|(_, lines)| *lines)
+            .collect();
+        let total: usize = substantive.iter().sum();
+        let others = total - substantive.first().copied().unwrap_or(0);
+        (others * 100).checked_div(total).unwrap_or(0) as u8
+    }
+
+    /// Whether the change mixes intents past `threshold` percent, 0 never does
+    pub fn is_mixed(&self, threshold: u8) -> bool {
+        threshold > 0 && self.mixed_percent() > threshold
+    }
+}
+
+impl fmt::Display for Breakdown {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total = self.total().max(1);
+        writeln!(f, "Intent        Lines      %")?;
+        for (intent, lines) in &self.lines {
+            writeln!(
+                f,
+                "{:<12} {:>6} {:>5}%",
+                intent.to_string(),
+                lines,
+                lines * 100 / total
+            )?;
+        }
+        if self.unlabelled > 0 {
+            writeln!(f, "{:<12} {:>6}", "(unlabelled)", self.unlabelled)?;
+        }
+        Ok(())
+    }
+}
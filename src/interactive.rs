@@ -0,0 +1,45 @@
+use std::{
+    io,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use log::debug;
+
+use crate::error::{GitAiError, Result};
+use crate::terminal;
+
+/// Set once at start up, from `--non-interactive` or stdout not being a terminal
+static NON_INTERACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Turns non-interactive mode on (or off).  `--non-interactive` forces it, otherwise
+/// it is on when stdout isn't a terminal, a CI job or a git hook with nobody to answer.
+///
+/// # Arguments
+///
+/// * `forced` - `--non-interactive` was passed
+pub fn init(forced: bool) {
+    let non_interactive = forced || !terminal::is_tty(&io::stdout());
+    debug!("Non-interactive mode is {}", non_interactive);
+    NON_INTERACTIVE.store(non_interactive, Ordering::Relaxed);
+}
+
+/// Whether there is nobody to ask, so no prompts, no editor and no spinners
+pub fn non_interactive() -> bool {
+    NON_INTERACTIVE.load(Ordering::Relaxed)
+}
+
+/// Fails with `NeedsInput` instead of asking `question` when nobody is there to answer,
+/// waiting on stdin in a CI job would hang it until it times out
+///
+/// # Arguments
+///
+/// * `question` - What would have been asked
+pub fn ask(question: &str) -> Result<()> {
+    if non_interactive() {
+        return Err(GitAiError::NeedsInput(format!(
+            "Running non-interactively, so nobody can answer: {}",
+            question.trim()
+        )));
+    }
+    Ok(())
+}
@@ -0,0 +1,444 @@
+use std::{
+    collections::HashSet,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use chrono::{Local, TimeZone};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::ai;
+use crate::error::Result;
+use crate::ledger::{short_hash, Generation};
+use crate::settings::{gitai_home, Provider};
+
+/// What gitai did to a repository (or its forge), everything but `Generated` can be taken
+/// back with `gitai undo`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ActionKind {
+    /// A commit was made, amended or squashed, moving HEAD from `before` to `commit`
+    Commit {
+        /// Where HEAD was, `None` for the first commit of a repository
+        before: Option<String>,
+        /// The commit gitai made
+        commit: String,
+        /// The branch it went on, `None` for a detached HEAD
+        branch: Option<String>,
+    },
+    /// A pull (or merge) request was opened
+    PullRequest {
+        /// Where it was opened
+        provider: Provider,
+        /// The remote whose repo it is on
+        remote: String,
+        /// Its number (GitLab's iid)
+        number: u64,
+        /// Where to look at it
+        url: String,
+    },
+    /// The AI wrote something that didn't become a commit or a pull request, an
+    /// explanation, a changelog, a message nobody took, ...
+    Generated {
+        /// The gitai command that asked for it
+        command: String,
+    },
+}
+
+impl ActionKind {
+    /// Whether `gitai undo` can take it back
+    pub fn undoable(&self) -> bool {
+        !matches!(self, ActionKind::Generated { .. })
+    }
+}
+
+/// How a message came to be and what it cost.  The prompt itself isn't kept, it has the
+/// diff in it, only which template it came from and its hash.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Details {
+    /// Hash of the diff the AI saw
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub diff_hash: Option<String>,
+    /// The prompt it came from, `default`, `stochastic`, `pack:<name>`, ...
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+    /// Hash of the exact prompt, for `--deterministic` runs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_hash: Option<String>,
+    /// The model (or Azure deployment)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// The message that was used, a pull request's title and body
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    /// Tokens the AI used for it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tokens: Option<usize>,
+    /// What they cost in dollars, when the model has a price
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cost: Option<f64>,
+}
+
+impl Details {
+    /// Just the message, for one nobody asked the AI for
+    pub fn message(message: &str) -> Self {
+        Details {
+            message: Some(message.to_string()),
+            ..Details::default()
+        }
+    }
+}
+
+impl From<&Generation> for Details {
+    fn from(generation: &Generation) -> Self {
+        Details {
+            diff_hash: Some(generation.diff_hash.clone()),
+            template: Some(generation.template.clone()),
+            prompt_hash: generation.prompt_hash.clone(),
+            model: Some(generation.model.clone()),
+            message: Some(generation.message.clone()),
+            tokens: None,
+            cost: None,
+        }
+    }
+}
+
+/// Something gitai did, and where
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Action {
+    /// Short id, what an undo points at
+    pub id: String,
+    /// Seconds since the epoch
+    pub time: u64,
+    /// The repository, its working directory
+    pub repo: String,
+    /// What was done
+    pub what: ActionKind,
+    #[serde(flatten)]
+    pub details: Details,
+}
+
+/// An action that was taken back
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Undo {
+    /// The `Action` id
+    pub action: String,
+    /// Seconds since the epoch
+    pub time: u64,
+}
+
+/// One line of the journal
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Entry {
+    Action(Box<Action>),
+    Undo(Undo),
+}
+
+/// Where the journal lives, `~/.gitai/history.jsonl`, one JSON entry per line
+pub fn journal_path() -> PathBuf {
+    gitai_home().join("history.jsonl")
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// How a repository is named in the journal, the full path to its working directory
+///
+/// # Arguments
+///
+/// * `repo` - The repository
+pub fn repo_key(repo: &git2::Repository) -> String {
+    let dir = repo.workdir().unwrap_or_else(|| repo.path());
+    fs::canonicalize(dir)
+        .unwrap_or_else(|_| dir.to_path_buf())
+        .display()
+        .to_string()
+}
+
+/// Adds an entry to the end of the journal
+pub fn append(entry: &Entry) -> Result<()> {
+    fs::create_dir_all(gitai_home())?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(journal_path())?;
+    writeln!(file, "{}", serde_json::to_string(entry)?)?;
+    Ok(())
+}
+
+/// The command this run is and the repository it is in, for what the AI wrote that
+/// nothing else recorded
+struct Run {
+    command: String,
+    repo: String,
+}
+
+static RUN: OnceLock<Run> = OnceLock::new();
+
+/// The tokens and dollars of this run's AI usage that are already in the journal
+static RECORDED: Mutex<(usize, f64)> = Mutex::new((0, 0.0));
+
+/// Says which command is running and where, so `finish` can record it
+///
+/// # Arguments
+///
+/// * `command` - The gitai command
+/// * `dir` - Where it runs, anywhere in the repository
+pub fn begin(command: &str, dir: &Path) {
+    let repo = match git2::Repository::discover(dir) {
+        Ok(repo) => repo_key(&repo),
+        Err(e) => {
+            debug!("Not in a repository, nothing will be journaled: {}", e);
+            return;
+        }
+    };
+    let _ = RUN.set(Run {
+        command: command.to_string(),
+        repo,
+    });
+}
+
+/// The AI usage since the last record, it goes in the record being made
+fn unrecorded_usage() -> (Option<usize>, Option<f64>) {
+    let usage = ai::session_usage();
+    let tokens = usage.prompt_tokens + usage.completion_tokens;
+    let mut recorded = match RECORDED.lock() {
+        Ok(recorded) => recorded,
+        Err(_) => return (None, None),
+    };
+    let new_tokens = tokens.saturating_sub(recorded.0);
+    let new_dollars = usage.dollars - recorded.1;
+    *recorded = (tokens, usage.dollars);
+    (
+        Some(new_tokens).filter(|tokens| *tokens > 0),
+        Some(new_dollars).filter(|dollars| *dollars > 0.0),
+    )
+}
+
+fn record_in(repo: String, what: ActionKind, mut details: Details) {
+    let time = now();
+    (details.tokens, details.cost) = unrecorded_usage();
+    let action = Action {
+        id: short_hash(&format!("{}\n{}\n{:?}", time, repo, what))[..8].to_string(),
+        time,
+        repo,
+        what,
+        details,
+    };
+    debug!("Recording {} in the journal", action.id);
+    if let Err(e) = append(&Entry::Action(Box::new(action))) {
+        warn!("Unable to write to the journal: {}", e);
+    }
+}
+
+/// Records what was done to `repo`, with the AI usage since the last record.  A journal
+/// that can't be written is not worth failing over, the commit (or pull request) is
+/// already there.
+///
+/// # Arguments
+///
+/// * `repo` - The repository
+/// * `what` - What was done
+/// * `details` - How the message came to be
+pub fn record(repo: &git2::Repository, what: ActionKind, details: Details) {
+    record_in(repo_key(repo), what, details);
+}
+
+/// Records whatever the AI was asked for since the last record as `Generated`, so a run
+/// that only wrote text (or whose commit failed) is in the journal too
+pub fn finish() {
+    let run = match RUN.get() {
+        Some(run) => run,
+        None => return,
+    };
+    let usage = ai::session_usage();
+    let recorded = RECORDED
+        .lock()
+        .map(|recorded| recorded.0)
+        .unwrap_or_default();
+    if usage.prompt_tokens + usage.completion_tokens <= recorded {
+        return;
+    }
+    record_in(
+        run.repo.clone(),
+        ActionKind::Generated {
+            command: run.command.clone(),
+        },
+        Details::default(),
+    );
+}
+
+/// Every entry in the journal, oldest first.  Lines that don't parse are skipped.
+pub fn read() -> Result<Vec<Entry>> {
+    let text = match fs::read_to_string(journal_path()) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(text
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| match serde_json::from_str(line) {
+            Ok(entry) => Some(entry),
+            Err(e) => {
+                debug!("Skipping a journal line: {}", e);
+                None
+            }
+        })
+        .collect())
+}
+
+/// The ids of the actions that were undone
+pub fn undone(entries: &[Entry]) -> HashSet<&str> {
+    entries
+        .iter()
+        .filter_map(|entry| match entry {
+            Entry::Undo(undo) => Some(undo.action.as_str()),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The last thing done to the repository `repo` (from `repo_key`) that hasn't been undone
+///
+/// # Arguments
+///
+/// * `entries` - The journal
+/// * `repo` - The repository
+pub fn last_action<'a>(entries: &'a [Entry], repo: &str) -> Option<&'a Action> {
+    let undone = undone(entries);
+    entries.iter().rev().find_map(|entry| match entry {
+        Entry::Action(action)
+            if action.repo == repo
+                && action.what.undoable()
+                && !undone.contains(action.id.as_str()) =>
+        {
+            Some(action.as_ref())
+        }
+        _ => None,
+    })
+}
+
+/// Writes down that `action` was undone, so the next undo goes further back
+///
+/// # Arguments
+///
+/// * `action` - The `Action` id
+pub fn mark_undone(action: &str) -> Result<()> {
+    append(&Entry::Undo(Undo {
+        action: action.to_string(),
+        time: now(),
+    }))
+}
+
+/// The actions in `repo` (every repository for `None`) that mention `search` anywhere,
+/// oldest first
+///
+/// # Arguments
+///
+/// * `entries` - The journal
+/// * `repo` - The repository (from `repo_key`), `None` for all of them
+/// * `search` - Text to look for in the message, model, commit, url, ... any case
+pub fn matching<'a>(
+    entries: &'a [Entry],
+    repo: Option<&str>,
+    search: Option<&str>,
+) -> Vec<&'a Action> {
+    let search = search.map(str::to_lowercase);
+    entries
+        .iter()
+        .filter_map(|entry| match entry {
+            Entry::Action(action) => Some(action.as_ref()),
+            _ => None,
+        })
+        .filter(|action| repo.is_none_or(|repo| action.repo == repo))
+        .filter(|action| {
+            search.as_ref().is_none_or(|search| {
+                serde_json::to_string(action)
+                    .map(|json| json.to_lowercase().contains(search.as_str()))
+                    .unwrap_or_default()
+            })
+        })
+        .collect()
+}
+
+/// What an action was, in a few words
+fn describe(what: &ActionKind) -> String {
+    match what {
+        ActionKind::Commit { commit, branch, .. } => format!(
+            "commit {} on {}",
+            &commit[..commit.len().min(8)],
+            branch.as_deref().unwrap_or("a detached HEAD")
+        ),
+        ActionKind::PullRequest { url, .. } => format!("pull request {}", url),
+        ActionKind::Generated { command } => format!("gitai {}", command),
+    }
+}
+
+/// `actions` as a table, the newest last, with what they cost all together
+///
+/// # Arguments
+///
+/// * `actions` - From `matching`
+/// * `undone` - From `undone`, those are marked
+pub fn table(actions: &[&Action], undone: &HashSet<&str>) -> String {
+    if actions.is_empty() {
+        return "Nothing in the journal matches".to_string();
+    }
+    let mut out = vec![format!(
+        "{:<8} {:<16} {:<44} {:<20} {:>7} {:>8}  MESSAGE",
+        "ID", "TIME", "WHAT", "MODEL", "TOKENS", "COST"
+    )];
+    let (mut tokens, mut dollars) = (0, 0.0);
+    for action in actions {
+        let details = &action.details;
+        tokens += details.tokens.unwrap_or_default();
+        dollars += details.cost.unwrap_or_default();
+        let time = chrono::NaiveDateTime::from_timestamp_opt(action.time as i64, 0)
+            .map(|time| {
+                Local
+                    .from_utc_datetime(&time)
+                    .format("%Y-%m-%d %H:%M")
+                    .to_string()
+            })
+            .unwrap_or_default();
+        let mut what = describe(&action.what);
+        if undone.contains(action.id.as_str()) {
+            what.push_str(" (undone)");
+        }
+        out.push(format!(
+            "{:<8} {:<16} {:<44} {:<20} {:>7} {:>8}  {}",
+            action.id,
+            time,
+            what,
+            details.model.as_deref().unwrap_or("-"),
+            details
+                .tokens
+                .map_or("-".to_string(), |tokens| tokens.to_string()),
+            details
+                .cost
+                .map_or("-".to_string(), |cost| format!("${:.4}", cost)),
+            details
+                .message
+                .as_deref()
+                .and_then(|message| message.lines().next())
+                .unwrap_or_default()
+        ));
+    }
+    out.push(format!(
+        "\n{} action(s), {} tokens, about ${:.4}",
+        actions.len(),
+        tokens,
+        dollars
+    ));
+    out.join("\n")
+}
@@ -0,0 +1,224 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use git2::{ObjectType, Oid};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::error::Result;
+use crate::settings::gitai_home;
+
+/// What the user thought of a generated message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Rating {
+    Good,
+    Bad,
+}
+
+/// Something gitai generated, with enough about how it was made to compare prompts and models
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Generation {
+    /// Short id, what feedback points at
+    pub id: String,
+    /// Seconds since the epoch
+    pub time: u64,
+    /// `commit` or `pr`
+    pub kind: String,
+    /// The prompt it came from, `default`, `stochastic`, `pack:<name>`, ...
+    pub template: String,
+    /// The model (or Azure deployment)
+    pub model: String,
+    /// Hash of the diff the AI saw
+    pub diff_hash: String,
+    /// What was generated (and used, if something was)
+    pub message: String,
+    /// The commit it became, if it became one
+    #[serde(default)]
+    pub commit: Option<String>,
+    /// Hash of the exact prompt, kept for `--deterministic` runs so a message that changed
+    /// can be told apart from a prompt that did
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_hash: Option<String>,
+}
+
+/// A rating for a `Generation`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Feedback {
+    /// The `Generation` id
+    pub generation: String,
+    /// Seconds since the epoch
+    pub time: u64,
+    pub rating: Rating,
+    #[serde(default)]
+    pub note: String,
+}
+
+/// One line of the ledger
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Entry {
+    Generation(Generation),
+    Feedback(Feedback),
+}
+
+/// Where the ledger lives, `~/.gitai/ledger.jsonl`, one JSON entry per line
+pub fn ledger_path() -> PathBuf {
+    let mut p = gitai_home();
+    p.push("ledger.jsonl");
+    p
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// A short git style hash of `text`, the same text always gets the same one
+pub fn short_hash(text: &str) -> String {
+    Oid::hash_object(ObjectType::Blob, text.as_bytes())
+        .map(|oid| oid.to_string()[..12].to_string())
+        .unwrap_or_default()
+}
+
+/// Builds a `Generation` stamped with the time and a fresh id
+///
+/// # Arguments
+///
+/// * `kind` - `commit` or `pr`
+/// * `template` - The prompt it came from
+/// * `model` - The model (or Azure deployment)
+/// * `diff_text` - The diff the AI saw
+/// * `message` - What was generated
+/// * `commit` - The commit it became
+pub fn generation(
+    kind: &str,
+    template: &str,
+    model: &str,
+    diff_text: &str,
+    message: &str,
+    commit: Option<String>,
+) -> Generation {
+    let time = now();
+    Generation {
+        id: short_hash(&format!("{}\n{}", time, message))[..8].to_string(),
+        time,
+        kind: kind.to_string(),
+        template: template.to_string(),
+        model: model.to_string(),
+        diff_hash: short_hash(diff_text),
+        message: message.to_string(),
+        commit,
+        prompt_hash: None,
+    }
+}
+
+/// Adds an entry to the end of the ledger
+pub fn append(entry: &Entry) -> Result<()> {
+    fs::create_dir_all(gitai_home())?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(ledger_path())?;
+    writeln!(file, "{}", serde_json::to_string(entry)?)?;
+    Ok(())
+}
+
+/// Records a generation, a ledger that can't be written is not worth failing a commit over
+pub fn record(generation: Generation) {
+    debug!("Recording generation {} in the ledger", generation.id);
+    if let Err(e) = append(&Entry::Generation(generation)) {
+        warn!("Unable to write to the ledger: {}", e);
+    }
+}
+
+/// Everything in the ledger, lines that don't parse are skipped
+pub fn read() -> Result<Vec<Entry>> {
+    let text = match fs::read_to_string(ledger_path()) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    Ok(text
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+/// The most recent generation, or the one that became `commit` if there is one
+///
+/// # Arguments
+///
+/// * `entries` - The ledger
+/// * `commit` - The full commit id, `None` for the last generation
+pub fn find_generation<'a>(entries: &'a [Entry], commit: Option<&str>) -> Option<&'a Generation> {
+    entries.iter().rev().find_map(|entry| match entry {
+        Entry::Generation(generation)
+            if commit.is_none() || generation.commit.as_deref() == commit =>
+        {
+            Some(generation)
+        }
+        _ => None,
+    })
+}
+
+/// How each template/model pair has been rated, the newest rating for a generation wins
+pub fn stats(entries: &[Entry]) -> String {
+    let mut ratings: BTreeMap<&str, Rating> = BTreeMap::new();
+    for entry in entries {
+        if let Entry::Feedback(feedback) = entry {
+            ratings.insert(&feedback.generation, feedback.rating);
+        }
+    }
+    // (template, model) -> (generations, good, bad)
+    let mut rows: BTreeMap<(&str, &str), (usize, usize, usize)> = BTreeMap::new();
+    for entry in entries {
+        if let Entry::Generation(generation) = entry {
+            let row = rows
+                .entry((&generation.template, &generation.model))
+                .or_default();
+            row.0 += 1;
+            match ratings.get(generation.id.as_str()) {
+                Some(Rating::Good) => row.1 += 1,
+                Some(Rating::Bad) => row.2 += 1,
+                None => (),
+            }
+        }
+    }
+    if rows.is_empty() {
+        return "Nothing in the ledger yet, generate some messages first".to_string();
+    }
+    let mut out = vec![format!(
+        "{:<28} {:<24} {:>6} {:>6} {:>6} {:>7}",
+        "template", "model", "made", "good", "bad", "good %"
+    )];
+    for ((template, model), (made, good, bad)) in rows {
+        let percent = (good * 100)
+            .checked_div(good + bad)
+            .map(|percent| format!("{}%", percent))
+            .unwrap_or_else(|| "-".to_string());
+        out.push(format!(
+            "{:<28} {:<24} {:>6} {:>6} {:>6} {:>7}",
+            template, model, made, good, bad, percent
+        ));
+    }
+    out.join("\n")
+}
+
+/// Records feedback for a generation
+pub fn rate(generation: &Generation, rating: Rating, note: &str) -> Result<()> {
+    append(&Entry::Feedback(Feedback {
+        generation: generation.id.to_string(),
+        time: now(),
+        rating,
+        note: note.to_string(),
+    }))
+}
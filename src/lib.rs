@@ -0,0 +1,508 @@
+//! gitai writes commit messages and pull requests from your diffs.  The `gitai`
+//! binary is a thin CLI over this crate, so anything it does can be done from your
+//! own tooling too.
+//!
+//! ```no_run
+//! use gitai::{GitAi, RepoDiff};
+//! use gitai::git::Git;
+//! use gitai::settings::Settings;
+//!
+//! fn main() -> gitai::Result<()> {
+//!     let gitai = GitAi::from_settings(&Settings::new()?.with_tokens())?;
+//!     let git = Git::default();
+//!     let repo = git.open_repository()?;
+//!     let source = RepoDiff::staged(git, &repo)?;
+//!     let runtime = tokio::runtime::Runtime::new()?;
+//!     for message in runtime.block_on(gitai.commit_messages(&source))? {
+//!         println!("{}", message);
+//!     }
+//!     Ok(())
+//! }
+//! ```
+//!
+//! Everything that talks to a server is async, run it on a tokio runtime like the CLI does.
+//!
+//! `MessageGenerator` is what writes the text (`OpenAiClient` out of the box),
+//! `DiffSource` is where the change comes from and `HostingProvider` is where pull
+//! requests go.  Nothing in here panics, every failure is a `GitAiError`.
+
+pub mod ai;
+pub mod aiignore;
+pub mod aliases;
+pub mod audit;
+pub mod bugreport;
+pub mod cache;
+pub mod changelog;
+pub mod closing;
+pub mod commands;
+pub mod compare;
+pub mod compress;
+pub mod configure;
+pub mod conventional;
+pub mod credentials;
+pub mod diagram;
+pub mod docs;
+pub mod doctor;
+pub mod drafts;
+pub mod dual;
+pub mod error;
+pub mod explain;
+pub mod generate;
+pub mod git;
+pub mod githook;
+pub mod gitmoji;
+pub mod heuristic;
+pub mod history;
+pub mod hooks;
+pub mod hosting;
+pub mod init;
+pub mod intent;
+pub mod interactive;
+pub mod journal;
+pub mod ledger;
+pub mod locale;
+pub mod network;
+pub mod packs;
+pub mod policy;
+pub mod preview;
+pub mod progress;
+pub mod prompttest;
+pub mod redact;
+pub mod review;
+pub mod rpc;
+pub mod sections;
+pub mod settings;
+pub mod signing;
+pub mod sinks;
+pub mod source;
+pub mod squash;
+pub mod style;
+pub mod templates;
+pub mod terminal;
+pub mod testing;
+pub mod tickets;
+pub mod trailer;
+pub mod version;
+pub mod walkthrough;
+pub mod worddiff;
+pub mod workspace;
+
+pub use crate::error::{GitAiError, Result};
+pub use crate::generate::{MessageFormat, MessageGenerator};
+pub use crate::hosting::HostingProvider;
+pub use crate::source::{DiffSource, RepoDiff};
+
+use log::warn;
+
+use crate::progress::{Phase, Progress};
+use crate::settings::{
+    AiPrompt, ClosingKeywords, CommitPolicy, DualDescription, MessageStyle, PrSection, Settings,
+};
+use crate::tickets::Ticket;
+
+/// How `GitAi` writes, the CLI fills this in from settings.json and the flags
+#[derive(Debug, Clone)]
+pub struct GitAiOptions {
+    /// The programming language the change is in
+    pub language: String,
+    /// How many messages to ask for
+    pub num_tries: u8,
+    /// Rounds of vague messages before the stricter fallback prompt is used, 0 turns the check off
+    pub quality_retries: u8,
+    /// The model the fallback prompt uses, empty for the default one
+    pub fallback_model: String,
+    /// Write Conventional Commits
+    pub conventional: bool,
+    /// Start commit messages with a gitmoji
+    pub gitmoji: bool,
+    /// The shape of commit messages
+    pub message_style: MessageStyle,
+    /// The prompt commit messages are written from
+    pub commit_prompt: AiPrompt,
+    /// The prompt pull requests are written from
+    pub pr_prompt: AiPrompt,
+    /// The sections every pull request body gets, empty for a free form body
+    pub pr_sections: Vec<PrSection>,
+    /// The repo's pull request template, filled in instead of `pr_sections` when there is one
+    pub pr_template: Option<String>,
+    /// The commands that run the project's tests, from `testing::commands`, for the
+    /// sections with `commands` set
+    pub test_commands: Vec<String>,
+    /// The rules commit messages have to follow
+    pub commit_policy: CommitPolicy,
+    /// The ticket the change is for, from the branch name
+    pub ticket: Option<Ticket>,
+    /// Start commit messages with the ticket id
+    pub ticket_prefix: bool,
+    /// The repo is mirrored publicly, keep internal details out of commit messages
+    pub mirror_safe: bool,
+    /// What to do about closing keywords (`Fixes #12`) in pull requests
+    pub closing_keywords: ClosingKeywords,
+    /// The first lines of the last commits to the same files, newest first, for the
+    /// message to stay consistent with
+    pub earlier_subjects: Vec<String>,
+    /// The natural language messages and pull requests are written in, empty for English
+    pub message_locale: String,
+}
+
+impl Default for GitAiOptions {
+    fn default() -> Self {
+        GitAiOptions {
+            language: "Python".to_string(),
+            num_tries: 1,
+            quality_retries: 2,
+            fallback_model: String::new(),
+            conventional: false,
+            gitmoji: false,
+            message_style: MessageStyle::default(),
+            commit_prompt: AiPrompt::default(),
+            pr_prompt: Settings::get_pr_prompt(),
+            pr_sections: Vec::new(),
+            pr_template: None,
+            test_commands: Vec::new(),
+            commit_policy: CommitPolicy::default(),
+            ticket: None,
+            ticket_prefix: true,
+            mirror_safe: false,
+            closing_keywords: ClosingKeywords::default(),
+            earlier_subjects: Vec::new(),
+            message_locale: String::new(),
+        }
+    }
+}
+
+impl GitAiOptions {
+    /// How commit messages have to start
+    pub fn message_format(&self) -> MessageFormat {
+        MessageFormat {
+            conventional: self.conventional,
+            gitmoji: self.gitmoji,
+            style: self.message_style,
+        }
+    }
+
+    /// The options settings.json asks for (prompt packs are left to the caller)
+    ///
+    /// # Arguments
+    ///
+    /// * `settings` - The settings
+    pub fn from_settings(settings: &Settings) -> Self {
+        let ai_options = &settings.ai_settings.ai_options;
+        GitAiOptions {
+            language: ai_options.prompt.language.trim().to_string(),
+            num_tries: ai_options.n,
+            quality_retries: ai_options.quality_retries,
+            fallback_model: ai_options.fallback_model.to_string(),
+            conventional: ai_options.conventional_commits,
+            gitmoji: ai_options.gitmoji,
+            message_style: ai_options.message_style,
+            pr_sections: settings.git_settings.git_options.pr_sections.clone(),
+            commit_policy: settings.commit_policy.clone(),
+            ticket_prefix: settings.tickets.prefix_message,
+            mirror_safe: settings.git_settings.git_options.dual_description != DualDescription::Off,
+            closing_keywords: settings.git_settings.git_options.closing_keywords,
+            message_locale: ai_options.message_locale.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// The prompt a commit message is written from
+    ///
+    /// # Arguments
+    ///
+    /// * `diff_text` - The diff, as the AI should see it
+    pub fn build_commit_prompt(&self, diff_text: String) -> AiPrompt {
+        self.commit_context(policy::prompt(
+            generate::commit_prompt(
+                AiPrompt {
+                    language: self.language.to_string(),
+                    git_diff: diff_text,
+                    ..self.commit_prompt.clone()
+                },
+                self.message_format(),
+            ),
+            &self.commit_policy,
+        ))
+    }
+
+    /// The commit prompt with the ticket and the earlier commits to the same files in it,
+    /// told to keep things public if the repo is mirrored and which language to write in
+    ///
+    /// # Arguments
+    ///
+    /// * `prompt` - The commit prompt
+    pub fn commit_context(&self, prompt: AiPrompt) -> AiPrompt {
+        let prompt = match &self.ticket {
+            Some(ticket) => tickets::prompt(prompt, ticket),
+            None => prompt,
+        };
+        let prompt = history::consistent_prompt(prompt, &self.earlier_subjects);
+        let prompt = if self.mirror_safe {
+            dual::public_prompt(prompt)
+        } else {
+            prompt
+        };
+        locale::prompt(prompt, &self.message_locale)
+    }
+
+    /// Tidies what the AI wrote into a commit message, starting it with the ticket id
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - What the AI wrote
+    pub fn finish_message(&self, text: &str) -> String {
+        let message = generate::tidy_message(text, self.message_format());
+        match &self.ticket {
+            Some(ticket) if self.ticket_prefix => tickets::prefix(&message, &ticket.key),
+            _ => message,
+        }
+    }
+
+    /// The prompt a pull request is written from, asking for the repo's template to be
+    /// filled in, or for the sections if there are any
+    ///
+    /// # Arguments
+    ///
+    /// * `diff_text` - The diff, as the AI should see it
+    pub fn build_pr_prompt(&self, diff_text: String) -> AiPrompt {
+        let base_prompt = if let Some(template) = &self.pr_template {
+            sections::template_prompt(template, self.pr_prompt.clone())
+        } else if self.pr_sections.is_empty() {
+            self.pr_prompt.clone()
+        } else {
+            sections::prompt(
+                &self.pr_sections,
+                self.pr_prompt.clone(),
+                &self.test_commands,
+            )
+        };
+        locale::prompt(
+            AiPrompt {
+                language: self.language.to_string(),
+                git_diff: diff_text,
+                ..base_prompt
+            },
+            &self.message_locale,
+        )
+    }
+}
+
+/// Writes commit messages and pull requests for a change
+pub struct GitAi {
+    generator: Box<dyn MessageGenerator>,
+    options: GitAiOptions,
+}
+
+impl GitAi {
+    /// A `GitAi` writing with `generator`
+    ///
+    /// # Arguments
+    ///
+    /// * `generator` - What writes the text
+    /// * `options` - How it writes
+    pub fn new(generator: Box<dyn MessageGenerator>, options: GitAiOptions) -> Self {
+        GitAi { generator, options }
+    }
+
+    /// A `GitAi` talking to OpenAI (or Azure OpenAI) the way settings.json says to
+    ///
+    /// # Arguments
+    ///
+    /// * `settings` - The settings
+    pub fn from_settings(settings: &Settings) -> Result<Self> {
+        let ai = &settings.ai_settings;
+        let client = ai::new_client(
+            ai.api_url.to_string(),
+            ai.api_key.to_string(),
+            ai.azure.clone(),
+            ai.retry,
+        )?
+        .with_model(&ai.ai_options.model)
+        .with_deadline(ai.ai_options.generation_deadline_ms);
+        Ok(GitAi::new(
+            Box::new(client),
+            GitAiOptions::from_settings(settings),
+        ))
+    }
+
+    /// What writes the text
+    pub fn generator(&self) -> &dyn MessageGenerator {
+        self.generator.as_ref()
+    }
+
+    /// How it writes
+    pub fn options(&self) -> &GitAiOptions {
+        &self.options
+    }
+
+    /// Commit messages for the change, vague ones are asked for again (see `quality_retries`).
+    /// If the generator gives up with `GitAiError::TimedOut` (see `generation_deadline_ms`)
+    /// the message is written from the diff by `heuristic::commit_message` instead.
+    ///
+    /// # Arguments
+    ///
+    /// * `source` - The change
+    pub async fn commit_messages<S: DiffSource + ?Sized>(&self, source: &S) -> Result<Vec<String>> {
+        let options = &self.options;
+        let progress = Progress::start(Phase::CollectingDiff);
+        let diff_text = source.diff_text()?;
+        let prompt = options.build_commit_prompt(diff_text.to_string());
+        let size = source.change_size()?;
+        progress.phase(Phase::Querying {
+            tokens: ai::count_tokens(&prompt.to_string()),
+        });
+        let messages = async {
+            let texts = generate::generate_commit_messages(
+                self.generator(),
+                prompt,
+                options.num_tries,
+                size,
+                options.quality_retries,
+                &options.fallback_model,
+                options.message_format(),
+            )
+            .await?;
+            progress.phase(Phase::Parsing);
+            let messages = texts
+                .iter()
+                .map(|text| options.finish_message(text))
+                .collect();
+            self.enforce_policy(diff_text.to_string(), messages).await
+        }
+        .await;
+        match messages {
+            Err(GitAiError::TimedOut(reason)) => {
+                warn!("{}, writing the message from the diff instead", reason);
+                Ok(vec![options.finish_message(&heuristic::commit_message(
+                    &diff_text,
+                    options.conventional,
+                ))])
+            }
+            messages => messages,
+        }
+    }
+
+    /// Holds commit messages to the commit policy.  Messages that break it go back to the
+    /// AI to be revised, up to `max_revisions` times, and only the ones that follow it are
+    /// returned.  If none of them ever do it's a `GitAiError::Refused`.
+    ///
+    /// # Arguments
+    ///
+    /// * `diff_text` - The diff the messages were written for
+    /// * `messages` - The messages
+    pub async fn enforce_policy(
+        &self,
+        diff_text: String,
+        messages: Vec<String>,
+    ) -> Result<Vec<String>> {
+        let options = &self.options;
+        let commit_policy = &options.commit_policy;
+        if policy::rules(commit_policy).is_empty() {
+            return Ok(messages);
+        }
+        let prompt = options.build_commit_prompt(diff_text);
+        let mut messages = messages;
+        let mut last_problems = Vec::new();
+        for revision in 0..=commit_policy.max_revisions {
+            let mut good = Vec::new();
+            let mut bad = Vec::new();
+            for message in messages {
+                let problems = policy::violations(commit_policy, &message)?;
+                if problems.is_empty() {
+                    good.push(message);
+                } else {
+                    warn!(
+                        "A message breaks the commit policy: {}",
+                        problems.join(", ")
+                    );
+                    bad.push((message, problems));
+                }
+            }
+            if !good.is_empty() {
+                return Ok(good);
+            }
+            last_problems = bad
+                .first()
+                .map(|(_, problems)| problems.clone())
+                .unwrap_or_default();
+            if revision == commit_policy.max_revisions {
+                break;
+            }
+            messages = Vec::new();
+            for (message, problems) in bad {
+                let revise = policy::revise_prompt(prompt.clone(), &message, &problems);
+                for text in generate::get_completion_texts(self.generator(), revise, 1).await? {
+                    messages.push(options.finish_message(&text));
+                }
+            }
+        }
+        Err(GitAiError::Refused(format!(
+            "No message followed the commit policy after {} revision(s), the last one had these problems: {}",
+            commit_policy.max_revisions,
+            last_problems.join(", ")
+        )))
+    }
+
+    /// The detailed description of a commit that stays inside the company, for repos
+    /// that are mirrored publicly
+    ///
+    /// # Arguments
+    ///
+    /// * `diff_text` - The diff the commit was written from
+    /// * `message` - The public commit message
+    pub async fn internal_description(&self, diff_text: &str, message: &str) -> Result<String> {
+        let options = &self.options;
+        let prompt = dual::internal_prompt(
+            &options.language,
+            diff_text,
+            message,
+            options.ticket.as_ref(),
+        );
+        match generate::get_completion_texts(self.generator(), prompt, 1)
+            .await?
+            .first()
+        {
+            Some(text) => Ok(text.trim().to_string()),
+            None => Err(GitAiError::BadResponse(
+                "The AI didn't write an internal description".to_string(),
+            )),
+        }
+    }
+
+    /// Pull requests for the change, as `(title, body)`
+    ///
+    /// # Arguments
+    ///
+    /// * `source` - The change
+    pub async fn pull_requests<S: DiffSource + ?Sized>(
+        &self,
+        source: &S,
+    ) -> Result<Vec<(String, String)>> {
+        let options = &self.options;
+        let progress = Progress::start(Phase::CollectingDiff);
+        let prompt = options.build_pr_prompt(source.diff_text()?);
+        progress.phase(Phase::Querying {
+            tokens: ai::count_tokens(&prompt.to_string()),
+        });
+        let texts =
+            generate::get_completion_texts(self.generator(), prompt, options.num_tries).await?;
+        progress.phase(Phase::Parsing);
+        Ok(texts
+            .iter()
+            .map(|text| {
+                let (title, body) = generate::split_pull_request(text);
+                let body = if let Some(template) = &options.pr_template {
+                    sections::render_template(template, &body)
+                } else if options.pr_sections.is_empty() {
+                    body
+                } else {
+                    sections::render(&options.pr_sections, &body, &options.test_commands)
+                };
+                (
+                    closing::rewrite(&title, options.closing_keywords),
+                    closing::rewrite(&body, options.closing_keywords),
+                )
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,87 @@
+use crate::settings::AiPrompt;
+
+/// The locales we know the name of, anything else is passed on to the AI as it was written
+const LANGUAGES: [(&str, &str); 24] = [
+    ("ar", "Arabic"),
+    ("cs", "Czech"),
+    ("da", "Danish"),
+    ("de", "German"),
+    ("el", "Greek"),
+    ("en", "English"),
+    ("es", "Spanish"),
+    ("fi", "Finnish"),
+    ("fr", "French"),
+    ("he", "Hebrew"),
+    ("hi", "Hindi"),
+    ("hu", "Hungarian"),
+    ("id", "Indonesian"),
+    ("it", "Italian"),
+    ("ja", "Japanese"),
+    ("ko", "Korean"),
+    ("nl", "Dutch"),
+    ("no", "Norwegian"),
+    ("pl", "Polish"),
+    ("pt", "Portuguese"),
+    ("ru", "Russian"),
+    ("sv", "Swedish"),
+    ("tr", "Turkish"),
+    ("zh", "Chinese"),
+];
+
+/// The language `locale` is in, `ja` and `ja_JP.UTF-8` are Japanese and `pt-BR` is
+/// Portuguese (pt-BR).  A name (`German`) or a locale we don't know comes back as it is.
+///
+/// # Arguments
+///
+/// * `locale` - A locale or the name of a language
+pub fn language_name(locale: &str) -> String {
+    let locale = locale.trim();
+    // ja_JP.UTF-8 -> ja_JP
+    let tag = locale.split('.').next().unwrap_or(locale);
+    let (code, region) = match tag.split_once(['-', '_']) {
+        Some((code, region)) => (code, Some(region)),
+        None => (tag, None),
+    };
+    let code = code.to_lowercase();
+    match LANGUAGES.iter().find(|(known, _)| *known == code) {
+        Some((_, name)) => match region {
+            Some(region) => format!("{} ({}-{})", name, code, region.to_uppercase()),
+            None => name.to_string(),
+        },
+        None => locale.to_string(),
+    }
+}
+
+/// Whether `locale` asks for anything but the English the prompts already get
+///
+/// # Arguments
+///
+/// * `locale` - `message_locale` or `--locale`
+pub fn is_english(locale: &str) -> bool {
+    let locale = locale.trim();
+    locale.is_empty()
+        || locale.eq_ignore_ascii_case("english")
+        || language_name(locale).starts_with("English")
+}
+
+/// The prompt, told to read the diff as it is but write in the language of `locale`.
+/// Code, names and a Conventional Commits type stay as they are so the tools reading
+/// them still can.
+///
+/// # Arguments
+///
+/// * `prompt` - The commit (or pull request) prompt
+/// * `locale` - `message_locale` or `--locale`, empty (or English) leaves the prompt alone
+pub fn prompt(prompt: AiPrompt, locale: &str) -> AiPrompt {
+    if is_english(locale) {
+        return prompt;
+    }
+    AiPrompt {
+        postmessage: format!(
+            "{} Read the diff as it is, but write your answer in {}. Leave code, identifiers, file names, and any Conventional Commits type and scope untranslated.",
+            prompt.postmessage.trim_end(),
+            language_name(locale)
+        ),
+        ..prompt
+    }
+}
@@ -1,95 +1,198 @@
-use ai::OpenAiRequestParams;
-use clap::{Parser, Subcommand};
-use log::{debug, error, info};
-use rand::seq::SliceRandom;
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+use log::{debug, info};
 
-use std::io::{self, Write};
+use std::io;
 use std::path::PathBuf;
-use termion::input::TermRead;
-use termios::{tcsetattr, Termios, TCSAFLUSH};
 
-use crate::ai::OpenAiClient;
-use crate::git::{Git, GitHub};
-use crate::settings::{AiPrompt, Settings};
-
-pub mod ai;
-pub mod git;
-pub mod settings;
+use gitai::commands::changelog::ChangelogArgs;
+use gitai::commands::commit::CommitArgs;
+use gitai::commands::pr::PullRequestArgs;
+use gitai::commands::{self, Context, Overrides};
+use gitai::error::{GitAiError, Result};
+use gitai::explain::Level;
+use gitai::journal;
+use gitai::ledger::Rating;
+use gitai::settings::{MessageStyle, Provider, Secret, Settings};
+use gitai::{bugreport, configure, init, interactive, network, preview, progress, terminal};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 #[command(propagate_version = true)]
 struct Cli {
     /// set GitHub API token
-    #[arg(long = "git_api_token", value_name = "GITHUB_TOKEN")]
+    #[arg(long = "git_api_token", value_name = "GITHUB_TOKEN", global = true)]
     github_token: Option<String>,
 
     /// set GitHub API url
-    #[arg(long = "git_api_url", value_name = "GITHUB_URL", value_hint = clap::ValueHint::Url)]
+    #[arg(long = "git_api_url", value_name = "GITHUB_URL", value_hint = clap::ValueHint::Url, global = true)]
     github_url: Option<String>,
 
+    /// The remote pull requests go to, its url says which repo that is.  Defaults to origin (or `remote` in git_settings)
+    #[arg(long, value_name = "REMOTE", global = true)]
+    remote: Option<String>,
+
+    /// set GitLab API token
+    #[arg(long = "gitlab_api_token", value_name = "GITLAB_TOKEN", global = true)]
+    gitlab_token: Option<String>,
+
+    /// set GitLab API url, point it at your own instance if self-hosted
+    #[arg(long = "gitlab_api_url", value_name = "GITLAB_URL", value_hint = clap::ValueHint::Url, global = true)]
+    gitlab_url: Option<String>,
+
+    /// Where pull requests get created, defaults to github (or whatever is in settings.json)
+    #[arg(long, value_enum, value_name = "PROVIDER", global = true)]
+    provider: Option<Provider>,
+
     /// set OpenAI token
-    #[arg(long = "ai_api_token", value_name = "AI_TOKEN")]
+    #[arg(long = "ai_api_token", value_name = "AI_TOKEN", global = true)]
     open_ai_token: Option<String>,
 
     /// set OpenAI url
-    #[arg(long = "ai_api_url", value_name = "AI_URL", value_hint = clap::ValueHint::Url)]
+    #[arg(long = "ai_api_url", value_name = "AI_URL", value_hint = clap::ValueHint::Url, global = true)]
     open_ai_url: Option<String>,
 
     /// Sets a custom config file
-    #[arg(short, long, value_name = "FILE", value_hint = clap::ValueHint::DirPath)]
+    #[arg(short, long, value_name = "FILE", value_hint = clap::ValueHint::DirPath, global = true)]
     config: Option<PathBuf>,
 
     /// Sets a custom local repo, you should probably not use this
-    #[arg(short, long, value_name = "REPO", value_hint = clap::ValueHint::DirPath)]
+    #[arg(short, long, value_name = "REPO", value_hint = clap::ValueHint::DirPath, global = true)]
     local_repo: Option<PathBuf>,
 
     /// Turn Verbose Mode on
-    #[arg(short, long, action = clap::ArgAction::SetTrue)]
+    #[arg(short, long, action = clap::ArgAction::SetTrue, global = true)]
     verbose: Option<bool>,
 
     /// Turn Stochastic Mode on
-    #[arg(short, long, action = clap::ArgAction::SetTrue)]
+    #[arg(short, long, action = clap::ArgAction::SetTrue, global = true)]
     stochastic: Option<bool>,
 
+    /// The same diff gets the same message on every run: temperature 0, a fixed seed where the service takes one and no stochastic prompts.  The prompt's hash is printed and kept in the ledger.  For CI
+    #[arg(long, action = clap::ArgAction::SetTrue, conflicts_with_all = ["stochastic", "race"], global = true)]
+    deterministic: Option<bool>,
+
+    /// Write the message yourself in the editor, nothing is sent to the AI.  Staging, the commit policy, trailers, signing, pushing and pull requests all work as usual
+    #[arg(long, action = clap::ArgAction::SetTrue, conflicts_with_all = ["stochastic", "deterministic", "race", "dry_run", "intent_check", "doc_check"], global = true)]
+    no_ai: Option<bool>,
+
+    /// Send a tiny request in the background as soon as commit or pr starts, so a cold model or a bad key shows up before you have answered any questions
+    #[arg(long, action = clap::ArgAction::SetTrue, global = true)]
+    warm_up: Option<bool>,
+
+    /// Compress the diff the AI sees (drops comments, collapses import-only hunks, abbreviates long literals)
+    #[arg(long, action = clap::ArgAction::SetTrue, global = true)]
+    compress: Option<bool>,
+
+    /// Ask the AI whether the change needs the docs updated, PRs get a reminder section
+    #[arg(long, action = clap::ArgAction::SetTrue, global = true)]
+    doc_check: Option<bool>,
+
+    /// Write commit messages in the Conventional Commits format (`feat(scope): summary`)
+    #[arg(long, action = clap::ArgAction::SetTrue, global = true)]
+    conventional: Option<bool>,
+
+    /// Write commit messages and pull requests in this language, a locale (`ja`, `de_DE`) or a name (`German`).  `en` for English when the settings say otherwise
+    #[arg(long, value_name = "LOCALE", global = true)]
+    locale: Option<String>,
+
+    /// Start the commit subject with a gitmoji (✨, 🐛, ♻️ ... from gitmoji.dev), checked against the official list before committing
+    #[arg(long, action = clap::ArgAction::SetTrue, conflicts_with = "conventional", global = true)]
+    gitmoji: Option<bool>,
+
+    /// The shape of the commit message: subject-only, subject+body or bulleted-body, the body wrapped at 72 columns
+    #[arg(long, value_enum, value_name = "STYLE", global = true)]
+    message_style: Option<MessageStyle>,
+
+    /// Add a mermaid diagram of the changed files to the pull request
+    #[arg(long, action = clap::ArgAction::SetTrue, global = true)]
+    diagram: Option<bool>,
+
+    /// Print the prompt that would be sent, with its token count and cost, and stop. Nothing is sent or committed
+    #[arg(long, action = clap::ArgAction::SetTrue, global = true)]
+    dry_run: Option<bool>,
+
+    /// Show each option after the first as a word diff against the one before it
+    #[arg(long, action = clap::ArgAction::SetTrue, global = true)]
+    reroll_diff: Option<bool>,
+
+    /// Check a commit isn't several changes in one before writing its message (an extra request)
+    #[arg(long, action = clap::ArgAction::SetTrue, global = true)]
+    intent_check: Option<bool>,
+
+    /// Send the diff as is, without masking what looks like secrets (keys, tokens, passwords) first
+    #[arg(long, action = clap::ArgAction::SetTrue, global = true)]
+    no_redact: Option<bool>,
+
+    /// Ask the AI even if it has answered the exact same request before, and don't keep the answer
+    #[arg(long, action = clap::ArgAction::SetTrue, global = true)]
+    no_cache: Option<bool>,
+
+    /// Send commit and pr prompts to ai_settings.race as well and use whichever answers first (you pay for both)
+    #[arg(long, action = clap::ArgAction::SetTrue, global = true)]
+    race: Option<bool>,
+
     /// Turns Auto Add mode on which adds . to git before making the commit DANGEROUS
-    #[arg(short, long, action = clap::ArgAction::SetTrue)]
+    #[arg(short, long, action = clap::ArgAction::SetTrue, global = true)]
     auto_add: Option<bool>,
 
     /// Turns Auto AI mode on automatically accepts the AI message without review DANGEROUS
-    #[arg(short = 'i', long, action = clap::ArgAction::SetTrue)]
+    #[arg(short = 'i', long, action = clap::ArgAction::SetTrue, global = true)]
     auto_ai: Option<bool>,
 
-    /// Turns Auto Push mode on which pushes local to remote before the pr, detfaults to true
-    #[arg(short = 'u', long, action = clap::ArgAction::SetFalse)]
-    auto_push: Option<bool>,
+    /// Never ask anything, a question fails with exit code 8 instead of waiting for an answer.  On by itself when stdout isn't a terminal, for CI and git hooks, pair it with --auto-ai and --yes
+    #[arg(long, global = true)]
+    non_interactive: bool,
+
+    /// No spinners on stderr while the diff is read and the AI is asked, the answers and errors are still printed
+    #[arg(short, long, global = true)]
+    quiet: bool,
+
+    /// No color in what is printed, the diff summary, the messages and the checklists.  Setting NO_COLOR does the same
+    #[arg(long, global = true)]
+    no_color: bool,
+
+    /// Pushes the branch to the remote before opening the pr, over git_options.auto_push (which defaults to true)
+    #[arg(long, overrides_with = "no_auto_push", global = true)]
+    auto_push: bool,
+
+    /// Doesn't push the branch before opening the pr, over git_options.auto_push
+    #[arg(short = 'u', long, overrides_with = "auto_push", global = true)]
+    no_auto_push: bool,
+
+    /// Confirms opening a pr with both auto-ai and auto-push on, without this (or the policy allow in settings.json) gitai refuses
+    #[arg(short = 'y', long, action = clap::ArgAction::SetTrue, global = true)]
+    yes: Option<bool>,
 
     /// Number of times to try the AI: Note OpenAI Chatbot is not Idenpotent
-    #[arg(short, long, value_name = "TRIES", value_parser=_allowed_num_tries)]
+    #[arg(short, long, value_name = "TRIES", value_parser=_allowed_num_tries, global = true)]
     num_tries: Option<u8>,
 
     /// Sign Commits, if set some variables must be added to settings.json
-    #[arg(long, action = clap::ArgAction::SetTrue)]
+    #[arg(long, action = clap::ArgAction::SetTrue, global = true)]
     gpg_sign_commit: Option<bool>,
 
     /// the signing key, only matters if `gpg_sign_commit` is true.
-    #[arg(long)]
+    #[arg(long, global = true)]
     gpg_key_id: Option<String>,
 
     /// Programming Language, very useful for small commits/pr
-    #[arg(short, long, value_name = "LANGUAGE")]
+    #[arg(short, long, value_name = "LANGUAGE", global = true)]
     programming_language: Option<String>,
 
+    /// Write from the prompt template of this name in .gitai/prompts or ~/.gitai/prompts, instead of `prompt_name` in ai_options
+    #[arg(long, value_name = "NAME", global = true)]
+    prompt_name: Option<String>,
+
     /// Signing Key ID: Note, ignored if sign_commit=false
-    #[arg(long)]
+    #[arg(long, global = true)]
     signature_id: Option<String>,
 
     /// The path to the ssh key
-    #[arg[long]]
+    #[arg(long, global = true, global = true)]
     ssh_key_path: Option<String>,
 
     /// The ssh user, i personally have never seen this anything but `git`
-    #[arg[long]]
+    #[arg(long, global = true, global = true)]
     ssh_user: Option<String>,
 
     #[command(subcommand)]
@@ -99,244 +202,605 @@ struct Cli {
 #[derive(Subcommand, Debug)]
 enum Commands {
     /// Generate Commit Message
-    Commit {},
+    Commit {
+        /// Write the exact request that would be sent to this file and stop, nothing is sent
+        #[arg(long, value_name = "FILE")]
+        export_prompt: Option<PathBuf>,
+        /// Write a new message for the last commit and rewrite it, with anything staged added in
+        #[arg(long)]
+        amend: bool,
+        /// Context only the team should see (ticket links, customer names), added as an encrypted trailer
+        #[arg(long, value_name = "TEXT")]
+        private: Option<String>,
+        /// Write the message for everything that changed, staged or not, and stage it when you accept
+        #[arg(long, conflicts_with = "amend")]
+        include_unstaged: bool,
+        /// Print the first message as a commit buffer, git's comments and the diff below it, and stop.  For `:Git commit` in vim-fugitive
+        #[arg(long, conflicts_with_all = ["export_prompt", "include_unstaged"])]
+        fugitive: bool,
+        /// Pick the hunks to stage first, like `git add -p`, the message is written for just those
+        #[arg(long, conflicts_with_all = ["include_unstaged", "fugitive"])]
+        patch: bool,
+        /// Open the message in the editor before committing, like `git commit -e`.  It's `$GIT_EDITOR`, `core.editor`, `$VISUAL` or `$EDITOR`, the same one git would use
+        #[arg(long, conflicts_with = "fugitive")]
+        edit: bool,
+    },
     /// Generare Pull Request
     PR {
         /// The from branch
         from: String,
-        /// The to branch
+        /// The to branch, defaults to the repo's default branch
+        to: Option<String>,
+        /// Pick up the saved draft for this branch, only the new commits get sent to the AI
+        #[arg(long)]
+        resume: bool,
+        /// Open it as a draft
+        #[arg(long)]
+        draft: bool,
+        /// Ask them to review it, instead of `pr_reviewers` (comma separated or repeated)
+        #[arg(long, value_delimiter = ',')]
+        reviewer: Vec<String>,
+        /// Ask this team (its slug) to review it, instead of `pr_team_reviewers`
+        #[arg(long, value_delimiter = ',')]
+        team_reviewer: Vec<String>,
+        /// Label it, instead of `pr_labels`
+        #[arg(long, value_delimiter = ',')]
+        label: Vec<String>,
+        /// Assign it, instead of `pr_assignees`
+        #[arg(long, value_delimiter = ',')]
+        assignee: Vec<String>,
+    },
+    /// Have the AI review a change for possible bugs, style issues and missing tests
+    Review {
+        /// Review this GitHub pull request instead of what is staged
+        #[arg(long, value_name = "N")]
+        pr: Option<u64>,
+        /// Review what is staged, the default
+        #[arg(long, conflicts_with = "pr")]
+        staged: bool,
+        /// Post the findings on the pull request as review comments
+        #[arg(long, requires = "pr", conflicts_with = "staged")]
+        post: bool,
+    },
+    /// List the AI models the key can use - Good for testing connectivity
+    Models {
+        /// Only the models with this in their name, whatever the case
+        #[arg(long)]
+        filter: Option<String>,
+        /// Print them as JSON instead of a table
+        #[arg(long)]
+        json: bool,
+    },
+    /// Set up ~/.gitai/settings.json, checking your keys as you go
+    Init {},
+    /// Score the commit messages in a range against their diffs
+    AuditHistory {
+        /// The commits to audit, `A..B` or a single revision for its whole history
+        #[arg(long)]
+        range: String,
+        /// Also ask the AI to score each message, one request per commit
+        #[arg(long)]
+        ai: bool,
+        /// How many of the worst commits to list
+        #[arg(long, default_value_t = 10)]
+        worst: usize,
+    },
+    /// Show why each hunk of what is staged changed (feature, bugfix, refactor, formatting)
+    Intents {},
+    /// Suggest whether the next release is a major, minor or patch bump
+    Version {
+        /// Tag HEAD with the suggested version (an annotated tag)
+        #[arg(long)]
+        tag: bool,
+    },
+    /// Tell the story of a file, who changed it and how it grew, before you refactor it
+    BlameSummary {
+        /// The file
+        file: PathBuf,
+        /// Only look at this many of its latest commits
+        #[arg(long, default_value_t = 50)]
+        max_commits: usize,
+    },
+    /// Explain in plain English what a commit, or a range of them, did
+    Explain {
+        /// The commit, or `A..B` for everything after A up to B
+        rev: String,
+        /// Who the explanation is for
+        #[arg(long, value_enum)]
+        level: Option<Level>,
+    },
+    /// Write one commit message for everything on the branch since `base`, for a squash merge
+    Squash {
+        /// The branch the work started from
+        base: String,
+        /// Squash the branch into one commit with the message, like a soft reset and a commit
+        #[arg(long)]
+        apply: bool,
+    },
+    /// Take back the last thing gitai did in this repository: a commit comes back off the branch (its changes stay staged), a pull request is closed.  Run it again to go further back
+    Undo {},
+    /// What gitai has done in this repository: the commits and pull requests it made and what the AI wrote, with the model, the prompt and what it cost
+    History {
+        /// Every repository, not just this one
+        #[arg(long)]
+        all: bool,
+        /// Only what mentions this, in the message, the model, a commit, a url, ...
+        #[arg(long)]
+        search: Option<String>,
+        /// Show this many of the latest, 0 for everything
+        #[arg(long, default_value_t = 20)]
+        limit: usize,
+        /// One JSON object per line, for jq and scripts
+        #[arg(long)]
+        json: bool,
+    },
+    /// Write the changelog section for the commits between two refs
+    Changelog {
+        /// Where the release starts, usually the last tag
+        #[arg(long)]
+        from: String,
+        /// Where it ends
+        #[arg(long, default_value = "HEAD")]
         to: String,
+        /// Let the AI see what each commit changed too, not just its message
+        #[arg(long)]
+        diffs: bool,
+        /// What the release is called, defaults to `to` (or Unreleased for HEAD)
+        #[arg(long)]
+        release: Option<String>,
+        /// Add the section to the top of CHANGELOG.md instead of printing it
+        #[arg(long, conflicts_with = "sink")]
+        write: bool,
+        /// Send the section to this sink from `sinks` in the settings instead of printing
+        /// it, can be given more than once
+        #[arg(long)]
+        sink: Vec<String>,
+    },
+    /// Read the private trailers `gitai commit --private` adds
+    Trailer {
+        #[command(subcommand)]
+        action: TrailerAction,
+    },
+    /// Keep tokens in the OS keyring instead of settings.json
+    Auth {
+        #[command(subcommand)]
+        action: AuthAction,
+    },
+    /// Share prompts and house style with your team
+    Prompts {
+        #[command(subcommand)]
+        action: PromptsAction,
+    },
+    /// Have a plain `git commit` start out with a generated message
+    Hook {
+        #[command(subcommand)]
+        action: HookAction,
+    },
+    /// Set up `git cai`, `git cpr` and `git crev` in your global gitconfig
+    Aliases {
+        #[command(subcommand)]
+        action: AliasAction,
+    },
+    /// Rate a generated message, `gitai stats` adds the ratings up
+    Feedback {
+        /// Was it any good
+        #[arg(value_enum)]
+        rating: Rating,
+        /// Rate the last thing gitai generated, by default it is the message HEAD was committed with
+        #[arg(long)]
+        last: bool,
+        /// What was good (or bad) about it
+        #[arg(long, default_value = "")]
+        note: String,
+    },
+    /// How each prompt and model has been rated
+    Stats {},
+    /// Answer JSON-RPC on stdin and stdout (Content-Length framed, like a language server) for editor extensions
+    Rpc {},
+    /// Bundle what's needed to look into a problem (versions, settings without the secrets, recent ledger entries, the last error) into a tarball to attach to an issue, asking before each one goes in
+    Bugreport {
+        /// Where to write it, gitai-bugreport-<time>.tar in the current directory by default
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Check everything gitai needs (the settings, every token, the repository, its remote, the ssh agent and the signing key) and say how to fix what doesn't work
+    Doctor {},
+    /// Look after ~/.gitai/settings.json
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// Look after the AI's answers kept in ~/.gitai/cache
+    Cache {
+        #[command(subcommand)]
+        action: CacheAction,
     },
-    /// Get AI Models - Good for testing connectivity
-    Models {},
 }
 
-fn _allowed_num_tries(s: &str) -> Result<u8, String> {
-    clap_num::number_range(s, 1, 5)
+#[derive(Subcommand, Debug)]
+enum CacheAction {
+    /// Throw every kept answer away
+    Clear {},
 }
 
-fn restore_terminal() -> io::Result<()> {
-    let old_termios = Termios::from_fd(0)?;
-    tcsetattr(0, TCSAFLUSH, &old_termios)?;
-    Ok(())
+#[derive(Subcommand, Debug)]
+enum ConfigAction {
+    /// Edit the settings in $EDITOR, checking them afterwards
+    Edit {
+        /// Edit them in a form instead, grouped, with help and checked as you type
+        #[arg(long)]
+        tui: bool,
+    },
 }
 
-/// Helper function to ask the user whether or not they really wanted to ____
-/// (as specified by the `prompt`). As long as the response starts with the
-/// letter `y` (case insensitive), the reply is treated as affirmative.
-pub fn prompt_yes_no<S>(prompt: S) -> io::Result<bool>
-where
-    S: AsRef<str>,
-{
-    let prompt = prompt.as_ref();
-
-    let stdin = io::stdin();
-    let mut stdin = stdin.lock();
+#[derive(Subcommand, Debug)]
+enum AliasAction {
+    /// Add the aliases, anything after one is passed on to gitai
+    Install {
+        /// Replace aliases by the same names that someone else set up
+        #[arg(long)]
+        force: bool,
+    },
+    /// Take the aliases back out, ones that aren't gitai's are left alone
+    Remove {},
+}
 
-    let stdout = io::stdout();
-    let mut stdout = stdout.lock();
+#[derive(Subcommand, Debug)]
+enum HookAction {
+    /// Install gitai as this repo's prepare-commit-msg hook (or pre-push hook)
+    Install {
+        /// Replace a hook that is already there, it is kept as a .bak
+        #[arg(long)]
+        force: bool,
+        /// Install the pre-push review instead, pushes with red flags are stopped
+        #[arg(long)]
+        pre_push: bool,
+    },
+    /// What the hook runs, git calls this for you
+    Run {
+        /// The file holding the commit message
+        file: PathBuf,
+        /// Where the message came from (message, template, merge, squash, commit)
+        source: Option<String>,
+        /// The commit, when amending
+        sha: Option<String>,
+    },
+    /// What the pre-push hook runs, git calls this for you with the refs on stdin
+    PrePush {
+        /// The remote being pushed to
+        remote: Option<String>,
+        /// Its url
+        url: Option<String>,
+    },
+}
 
-    write!(stdout, "{} [y/N] ", prompt)?;
-    stdout.flush()?;
+#[derive(Subcommand, Debug)]
+enum TrailerAction {
+    /// Print the private trailer of a commit, decrypted
+    Decrypt {
+        /// The commit
+        #[arg(default_value = "HEAD")]
+        rev: String,
+    },
+}
 
-    match TermRead::read_line(&mut stdin)? {
-        Some(ref reply) if reply.to_ascii_lowercase().starts_with('y') => Ok(true),
-        _ => Ok(false),
-    }
+#[derive(Subcommand, Debug)]
+enum PromptsAction {
+    /// Clone (or update) a prompt pack into ~/.gitai/packs
+    Sync {
+        /// The pack's git url
+        url: String,
+        /// What to call the pack, defaults to the repo's name
+        #[arg(long)]
+        name: Option<String>,
+    },
+    /// Write commit messages for stored diffs with a prompt pack and check them against the expectations in prompts.toml, for CI
+    Test {
+        /// `default`, a synced pack's name, or a pack's directory or pack.json
+        template: String,
+        /// The directory with the .diff fixtures and their prompts.toml
+        #[arg(long, value_name = "DIR")]
+        fixtures: PathBuf,
+    },
 }
 
-fn remove_blank_lines(input: &String) -> String {
-    input
-        .lines()
-        .filter(|line| !line.trim().is_empty())
-        .collect::<Vec<&str>>()
-        .join("\n")
+#[derive(Subcommand, Debug)]
+enum AuthAction {
+    /// Store a token in the keyring (and take it out of settings.json)
+    Set {
+        /// Which token
+        #[arg(value_enum)]
+        secret: Secret,
+    },
+    /// Remove a token from the keyring
+    Delete {
+        /// Which token
+        #[arg(value_enum)]
+        secret: Secret,
+    },
+    /// Check every AI service and forge token: where it comes from, whose it is and when it expires
+    Status {},
 }
 
-fn error_message(message: &str) -> String {
-    error!("{}", message);
-    return message.to_string();
+fn _allowed_num_tries(s: &str) -> std::result::Result<u8, String> {
+    clap_num::number_range(s, 1, 5)
 }
 
 fn main() {
     env_logger::init();
+    let result = tokio::runtime::Runtime::new()
+        .map_err(GitAiError::from)
+        .and_then(|runtime| runtime.block_on(run()));
+    journal::finish();
+    commands::print_session_cost();
+    if let Err(e) = result {
+        bugreport::record_failure(&e);
+        eprintln!("{}", e);
+        if let Some(hint) = e.hint() {
+            eprintln!("{}", hint);
+        }
+        std::process::exit(e.exit_code());
+    }
+}
+
+/// Whether `command` talks to the AI or a hosting service, only those look in the keyring
+fn needs_tokens(command: Option<&Commands>) -> bool {
+    !matches!(
+        command,
+        None | Some(Commands::Undo {})
+            | Some(Commands::History { .. })
+            | Some(Commands::Trailer { .. })
+            | Some(Commands::Feedback { .. })
+            | Some(Commands::Stats {})
+            | Some(Commands::Hook {
+                action: HookAction::Install { .. },
+            })
+    )
+}
+
+/// Everything `main` does, any error comes back here to be shown to the user
+async fn run() -> Result<()> {
     info!("Initializing GitAI");
 
     debug!("Parsing CLI");
-    let cli = Cli::parse();
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    interactive::init(cli.non_interactive);
+    progress::init(cli.quiet);
+    preview::init(cli.no_color);
+
+    // before the settings are read, they may not exist yet (or be broken)
+    let repo_dir = cli.local_repo.clone().unwrap_or(PathBuf::from("."));
+    match &cli.command {
+        Some(Commands::Init {}) => return init::run().await,
+        Some(Commands::Config {
+            action: ConfigAction::Edit { tui },
+        }) => {
+            return if *tui {
+                configure::edit_tui()
+            } else {
+                configure::edit_in_editor()
+            };
+        }
+        Some(Commands::Prompts {
+            action: PromptsAction::Sync { url, name },
+        }) => return commands::prompts::sync(url, name.as_deref()),
+        Some(Commands::Bugreport { output }) => return commands::setup::bug_report(output.clone()),
+        // it says what is wrong with the settings instead of stopping at them
+        Some(Commands::Doctor {}) => return commands::setup::doctor(&repo_dir).await,
+        Some(Commands::Cache {
+            action: CacheAction::Clear {},
+        }) => return commands::setup::clear_cache(),
+        Some(Commands::Aliases {
+            action: AliasAction::Install { force },
+        }) => return commands::setup::install_aliases(*force),
+        Some(Commands::Aliases {
+            action: AliasAction::Remove {},
+        }) => return commands::setup::remove_aliases(),
+        // they write settings.json, the repository's settings stay out of it
+        Some(Commands::Auth {
+            action: AuthAction::Set { secret },
+        }) => return commands::auth::set(*secret),
+        Some(Commands::Auth {
+            action: AuthAction::Delete { secret },
+        }) => return commands::auth::delete(*secret),
+        _ => (),
+    }
 
     debug!("Reading settings file");
-    let settings = Settings::new().expect("Unable to load settings file at ~/.gitai/settings.json");
-
-    debug!("Setting Variables");
-    //ai variables
-    let ai_token = cli.open_ai_token.unwrap_or(settings.ai_settings.api_key);
-    let ai_url = cli.open_ai_url.unwrap_or(settings.ai_settings.api_url);
-    debug!("AI Variables Set url={}", ai_url);
-
-    // github variables
-    let github_token = cli
-        .github_token
-        .unwrap_or(settings.git_settings.github_api_key);
-    let github_url = cli
-        .github_url
-        .unwrap_or(settings.git_settings.github_api_url);
-    debug!("GitHub Variables Set url={}", github_url);
-
-    // other variables - not flags first
-    let language = cli
-        .programming_language
-        .or(Some(settings.ai_settings.ai_options.prompt.language))
-        .unwrap_or("Python".to_string());
-
-    let num_tries = cli
-        .num_tries
-        .or(Some(settings.ai_settings.ai_options.n))
-        .unwrap_or(1);
-
-    let ssh_key_path = cli
-        .ssh_key_path
-        .or(Some(settings.git_settings.git_options.ssh_key_path))
-        .unwrap_or("~/.ssh/id_rsa".to_string());
-
-    let ssh_user =
-        Some(settings.git_settings.git_options.ssh_user_name).unwrap_or("git".to_string());
-
-    let local_repo = cli
-        .local_repo
-        .or(Some(settings.git_settings.git_options.local_path))
-        .unwrap_or(PathBuf::from("."));
-
-    let gpg_key_id = cli
-        .gpg_key_id
-        .or(Some(settings.git_settings.git_options.key_id))
-        .unwrap_or_default();
-
-    // Flags
-    let auto_ai = cli
-        .auto_ai
-        .or(Some(settings.ai_settings.ai_options.auto_ai))
-        .unwrap_or(false);
-
-    let auto_add = cli
-        .auto_add
-        .or(Some(settings.git_settings.git_options.auto_add))
-        .unwrap_or(false);
-
-    let auto_push = cli
-        .auto_push
-        .or(Some(settings.git_settings.git_options.auto_push))
-        .unwrap_or(true);
-
-    let stochastic = cli
-        .stochastic
-        .or(Some(settings.ai_settings.ai_options.stochastic))
-        .unwrap_or(false);
-
-    let gpg_sign_commits = cli
-        .gpg_sign_commit
-        .or(Some(settings.git_settings.git_options.sign_commits))
-        .unwrap_or(false);
-
-    debug!("Variables Set OpenAI Url={:#?} should not be null", ai_url);
-    debug!(
-        "Local Repo={:#?} this should probably be '.' unless you have good reason",
-        local_repo
+    // the repository's .gitai.toml goes over settings.json, the flags go over both
+    let settings = match Settings::for_repo(&repo_dir) {
+        // only settings.json can be fixed here, the repository's file is the team's
+        Err(e @ GitAiError::Settings(_))
+            if terminal::is_tty(&io::stdin())
+                && !interactive::non_interactive()
+                && Settings::new().is_err() =>
+        {
+            commands::setup::repair_settings(e)?;
+            Settings::for_repo(&repo_dir)?
+        }
+        settings => settings?,
+    };
+    network::init(&settings);
+    if let Some(Commands::Auth {
+        action: AuthAction::Status {},
+    }) = &cli.command
+    {
+        return commands::auth::status(settings).await;
+    }
+    let settings = if needs_tokens(cli.command.as_ref()) {
+        settings.with_tokens()
+    } else {
+        settings
+    };
+
+    let overrides = Overrides {
+        github_token: cli.github_token,
+        github_url: cli.github_url,
+        remote: cli.remote,
+        gitlab_token: cli.gitlab_token,
+        gitlab_url: cli.gitlab_url,
+        provider: cli.provider,
+        open_ai_token: cli.open_ai_token,
+        open_ai_url: cli.open_ai_url,
+        local_repo: cli.local_repo,
+        stochastic: cli.stochastic,
+        deterministic: cli.deterministic,
+        no_ai: cli.no_ai,
+        warm_up: cli.warm_up,
+        compress: cli.compress,
+        doc_check: cli.doc_check,
+        conventional: cli.conventional,
+        locale: cli.locale,
+        gitmoji: cli.gitmoji,
+        message_style: cli.message_style,
+        diagram: cli.diagram,
+        dry_run: cli.dry_run,
+        reroll_diff: cli.reroll_diff,
+        intent_check: cli.intent_check,
+        no_redact: cli.no_redact,
+        no_cache: cli.no_cache,
+        race: cli.race,
+        auto_add: cli.auto_add,
+        auto_ai: cli.auto_ai,
+        // --auto-push and --no-auto-push override each other, the last one wins
+        auto_push: match (cli.auto_push, cli.no_auto_push) {
+            (true, _) => Some(true),
+            (_, true) => Some(false),
+            _ => None,
+        },
+        yes: cli.yes,
+        num_tries: cli.num_tries,
+        gpg_sign_commit: cli.gpg_sign_commit,
+        gpg_key_id: cli.gpg_key_id.or(cli.signature_id),
+        programming_language: cli.programming_language,
+        prompt_name: cli.prompt_name,
+        ssh_key_path: cli.ssh_key_path,
+    };
+    let ctx = Context::new(settings, overrides)?;
+    journal::begin(
+        matches.subcommand_name().unwrap_or("gitai"),
+        &ctx.local_repo,
     );
 
-    debug!("Matching CLI Command");
-    match &cli.command {
-        Some(Commands::Commit {}) => {
-            let git = Git::new(
-                local_repo.to_str().unwrap_or("."),
-                Some(&auto_add),
-                Some(&auto_push),
-                Some(&gpg_sign_commits),
-                Some(&gpg_key_id),
-                None,
-                None,
-                Some(&ssh_key_path),
-                Some(&ssh_user),
-            );
-            debug!("Getting Repository at {:#?}", &local_repo);
-            let repo = git.open_repository().expect("Unable to open repository");
-
-            debug!("Getting Diff for {:#?}", &local_repo);
-            let diff = git.get_commit_diff(&repo).expect(
-                "Unable to create git diff, try running git diff --cached to see if it works",
-            );
-            let git_diff_text = git
-                .diff_to_string(&diff)
-                .expect("Unable to parse generated git diff");
-
-            debug!("Got Diff, Its OpenAI Time");
-            let client = OpenAiClient::new(ai_url, ai_token);
-
-            debug!("We have a client, lets build the prompt");
-            let mut completions: Vec<String> = Vec::new();
-            if stochastic {
-                info!("Stochastic Mode Set");
-                let prompts = Settings::get_commit_prompt_choices();
-                for i in 0..num_tries {
-                    let mut prompt: AiPrompt =
-                        prompts.choose(&mut rand::thread_rng()).unwrap().to_owned();
-                    prompt.language = language.to_string();
-                    prompt.git_diff = git_diff_text.to_string();
-                    let params = OpenAiRequestParams {
-                        prompt: format!("{}", prompt),
-                        ..Default::default()
-                    };
-                    debug!("Post #{} to OpenAI", (i + 1));
-                    let res = &client
-                        .get_completions(prompt.to_owned(), params)
-                        .expect("Cannot connect to API");
-                    let open_ai_choices = res.choices.as_ref().unwrap();
-                    let open_ai_first_completion = open_ai_choices.first().unwrap();
-                    let open_ai_completion_text = open_ai_first_completion.text.as_ref().unwrap();
-                    let text = remove_blank_lines(&open_ai_completion_text);
-                    completions.push(text);
-                }
-            } else {
-                info!("Non-Stochastic Mode Set");
-                let mut prompt = AiPrompt::default();
-                prompt.language = language;
-                prompt.git_diff = git_diff_text;
-                let params = OpenAiRequestParams {
-                    prompt: format!("{}", prompt),
-                    n: Some(num_tries),
-                    ..Default::default()
-                };
-                debug!("Posting to OpenAI");
-                let res = client
-                    .get_completions(prompt, params)
-                    .expect("Cannot connect to API");
-                let open_ai_choices = res.choices.unwrap();
-                for choice in open_ai_choices {
-                    let text = remove_blank_lines(
-                        &choice
-                            .text
-                            .expect("OpenAI Responded but with no completions"),
-                    );
-                    completions.push(text);
-                }
-            }
-
-            println!("Here is your AI Generated Commit Message\n\n");
-            for comp in completions.iter() {
-                println!("{}", comp)
-            }
+    match cli.command {
+        Some(Commands::Commit {
+            export_prompt,
+            amend,
+            private,
+            include_unstaged,
+            fugitive,
+            patch,
+            edit,
+        }) => {
+            let args = CommitArgs {
+                export_prompt,
+                amend,
+                private,
+                include_unstaged,
+                fugitive,
+                patch,
+                edit,
+            };
+            commands::commit::run(&ctx, &args).await
+        }
+        Some(Commands::PR {
+            from,
+            to,
+            resume,
+            draft,
+            reviewer,
+            team_reviewer,
+            label,
+            assignee,
+        }) => {
+            let args = PullRequestArgs {
+                from,
+                to,
+                resume,
+                draft,
+                reviewer,
+                team_reviewer,
+                label,
+                assignee,
+            };
+            commands::pr::run(&ctx, &args).await
+        }
+        Some(Commands::Review { pr, post, .. }) => commands::review::run(&ctx, pr, post).await,
+        Some(Commands::Models { filter, json }) => {
+            commands::models::run(&ctx, filter.as_deref(), json).await
+        }
+        Some(Commands::AuditHistory { range, ai, worst }) => {
+            commands::audit_history::run(&ctx, &range, ai, worst).await
+        }
+        Some(Commands::Intents {}) => commands::intents::run(&ctx).await,
+        Some(Commands::Version { tag }) => commands::version::run(&ctx, tag).await,
+        Some(Commands::BlameSummary { file, max_commits }) => {
+            commands::blame_summary::run(&ctx, &file, max_commits).await
+        }
+        Some(Commands::Explain { rev, level }) => commands::explain::run(&ctx, &rev, level).await,
+        Some(Commands::Undo {}) => commands::undo::run(&ctx, cli.yes.unwrap_or(false)).await,
+        Some(Commands::History {
+            all,
+            search,
+            limit,
+            json,
+        }) => commands::history::run(&ctx, all, search.as_deref(), limit, json),
+        Some(Commands::Squash { base, apply }) => commands::squash::run(&ctx, &base, apply).await,
+        Some(Commands::Trailer {
+            action: TrailerAction::Decrypt { rev },
+        }) => commands::trailer::decrypt(&ctx, &rev),
+        Some(Commands::Changelog {
+            from,
+            to,
+            diffs,
+            release,
+            write,
+            sink,
+        }) => {
+            let args = ChangelogArgs {
+                from,
+                to,
+                diffs,
+                release,
+                write,
+                sink,
+            };
+            commands::changelog::run(&ctx, &args).await
         }
-        Some(Commands::PR { from, to }) => {
-            info!("Generating PR from {:#?} to {:#?}", from, to);
-            let g_hub = GitHub::new(github_token.as_str(), github_url.as_str());
-            println!("{:#?}", g_hub)
+        Some(Commands::Hook {
+            action: HookAction::Install { force, pre_push },
+        }) => commands::hook::install(&ctx, force, pre_push),
+        Some(Commands::Hook {
+            action: HookAction::PrePush { remote, .. },
+        }) => commands::hook::pre_push(&ctx, remote.as_deref()).await,
+        Some(Commands::Hook {
+            action: HookAction::Run { file, source, .. },
+        }) => commands::hook::run(&ctx, &file, source.as_deref()).await,
+        Some(Commands::Feedback { rating, last, note }) => {
+            commands::feedback::run(&ctx, rating, last, &note)
         }
-        Some(Commands::Models {}) => {
-            info!("Getting Available Models");
-            let client = OpenAiClient::new(ai_url, ai_token);
-            let res = client.get_models().expect("Unable to get models");
-            print!("{:#?}", res)
+        Some(Commands::Stats {}) => commands::feedback::stats(),
+        Some(Commands::Rpc {}) => commands::rpc::run(&ctx).await,
+        Some(Commands::Prompts {
+            action: PromptsAction::Test { template, fixtures },
+        }) => {
+            commands::prompts::test(
+                &ctx,
+                &template,
+                &fixtures,
+                cli.conventional.unwrap_or(false),
+            )
+            .await
         }
-        None => (),
+        Some(Commands::Init {})
+        | Some(Commands::Config { .. })
+        | Some(Commands::Auth { .. })
+        | Some(Commands::Prompts { .. })
+        | Some(Commands::Bugreport { .. })
+        | Some(Commands::Doctor {})
+        | Some(Commands::Cache { .. })
+        | Some(Commands::Aliases { .. })
+        | None => Ok(()),
     }
 }
@@ -0,0 +1,156 @@
+use std::{fs, sync::OnceLock, time::Duration};
+
+use log::{debug, warn};
+use reqwest::{Certificate, ClientBuilder, NoProxy, Proxy};
+
+use crate::error::{GitAiError, Result};
+use crate::settings::{NetworkSettings, Settings};
+
+/// Who a client is for, the AI and everything else have their own timeouts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Service {
+    /// OpenAI, Azure OpenAI, ... `ai_settings`
+    Ai,
+    /// GitHub, GitLab, the ticket trackers and the sinks, `git_settings`
+    Git,
+}
+
+/// How long a request may take, `None` waits as long as it takes
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Timeouts {
+    /// The whole request, until the last of the response is in
+    pub total: Option<Duration>,
+    /// Just connecting
+    pub connect: Option<Duration>,
+}
+
+impl Timeouts {
+    /// The timeouts for `timeout_seconds` and `connect_timeout_seconds`, 0 is no timeout
+    pub fn from_seconds(total: u64, connect: u64) -> Self {
+        let seconds = |s| (s > 0).then(|| Duration::from_secs(s));
+        Timeouts {
+            total: seconds(total),
+            connect: seconds(connect),
+        }
+    }
+}
+
+/// What every http client is built with
+#[derive(Debug, Default)]
+struct Network {
+    settings: NetworkSettings,
+    ai: Timeouts,
+    git: Timeouts,
+}
+
+/// The proxy, TLS and timeout settings every http client is built with, set once the
+/// settings are read
+static NETWORK: OnceLock<Network> = OnceLock::new();
+
+/// Uses `settings` for every http client built from now on.  Until it is called (`gitai
+/// init` runs before there are settings) clients only see the usual proxy variables and
+/// have reqwest's timeouts.
+///
+/// # Arguments
+///
+/// * `settings` - The settings, for `network` and the timeouts
+pub fn init(settings: &Settings) {
+    if settings.network.danger_accept_invalid_certs {
+        warn!("network.danger_accept_invalid_certs is on, any certificate is trusted");
+    }
+    let ai = &settings.ai_settings;
+    let git = &settings.git_settings;
+    let network = Network {
+        settings: settings.network.clone(),
+        ai: Timeouts::from_seconds(ai.timeout_seconds, ai.connect_timeout_seconds),
+        git: Timeouts::from_seconds(git.timeout_seconds, git.connect_timeout_seconds),
+    };
+    if NETWORK.set(network).is_err() {
+        debug!("The network settings were already set");
+    }
+}
+
+/// The timeouts of `service`'s clients
+///
+/// # Arguments
+///
+/// * `service` - Who the client is for
+pub fn timeouts(service: Service) -> Timeouts {
+    match (NETWORK.get(), service) {
+        (None, _) => Timeouts::default(),
+        (Some(network), Service::Ai) => network.ai,
+        (Some(network), Service::Git) => network.git,
+    }
+}
+
+/// A `reqwest::ClientBuilder` with the proxies and certificates from the `network`
+/// settings and `service`'s timeouts.  Without proxies in the settings reqwest reads
+/// `HTTP_PROXY`, `HTTPS_PROXY` and `NO_PROXY` itself.
+///
+/// # Arguments
+///
+/// * `service` - Who the client is for
+pub fn client_builder(service: Service) -> Result<ClientBuilder> {
+    let mut builder = ClientBuilder::new();
+    let timeouts = timeouts(service);
+    if let Some(total) = timeouts.total {
+        builder = builder.timeout(total);
+    }
+    if let Some(connect) = timeouts.connect {
+        builder = builder.connect_timeout(connect);
+    }
+    let settings = match NETWORK.get() {
+        Some(network) => &network.settings,
+        None => return Ok(builder),
+    };
+    let no_proxy = || NoProxy::from_string(&settings.no_proxy);
+    if !settings.http_proxy.is_empty() {
+        builder = builder.proxy(Proxy::http(&settings.http_proxy)?.no_proxy(no_proxy()));
+    }
+    if !settings.https_proxy.is_empty() {
+        builder = builder.proxy(Proxy::https(&settings.https_proxy)?.no_proxy(no_proxy()));
+    }
+    if !settings.ca_bundle_path.is_empty() {
+        for cert in read_certificates(&settings.ca_bundle_path)? {
+            builder = builder.add_root_certificate(cert);
+        }
+    }
+    Ok(builder.danger_accept_invalid_certs(settings.danger_accept_invalid_certs))
+}
+
+/// A plain client from `client_builder`, for requests that set their own headers
+///
+/// # Arguments
+///
+/// * `service` - Who the client is for
+pub fn client(service: Service) -> Result<reqwest::Client> {
+    Ok(client_builder(service)?.build()?)
+}
+
+/// Every certificate in the PEM file at `path`, a bundle can have more than one
+///
+/// # Arguments
+///
+/// * `path` - The PEM file
+pub fn read_certificates(path: &str) -> Result<Vec<Certificate>> {
+    const BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+    let pem = fs::read_to_string(path)
+        .map_err(|e| GitAiError::Usage(format!("Can't read the CA certificate {}: {}", path, e)))?;
+    let certs = pem
+        .match_indices(BEGIN)
+        .map(|(start, _)| {
+            let end = pem[start + BEGIN.len()..]
+                .find(BEGIN)
+                .map_or(pem.len(), |end| start + BEGIN.len() + end);
+            Certificate::from_pem(&pem.as_bytes()[start..end])
+        })
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|e| GitAiError::Usage(format!("{} isn't a PEM certificate: {}", path, e)))?;
+    if certs.is_empty() {
+        return Err(GitAiError::Usage(format!(
+            "There are no certificates in {}",
+            path
+        )));
+    }
+    Ok(certs)
+}
@@ -0,0 +1,145 @@
+use std::{fs, path::PathBuf};
+
+use git2::{build::RepoBuilder, Cred, FetchOptions, RemoteCallbacks, Repository, ResetType};
+use log::{debug, info};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{GitAiError, Result};
+use crate::settings::{gitai_home, AiPrompt, PrSection};
+
+/// A team's house style, shared as a git repo with a `pack.json` at the top.
+/// Anything left out falls back to your own settings.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct PromptPack {
+    /// The prompt commit messages are written from
+    pub commit_prompt: Option<AiPrompt>,
+    /// The prompt pull requests are written from
+    pub pr_prompt: Option<AiPrompt>,
+    /// The prompts stochastic mode picks from
+    pub personas: Vec<AiPrompt>,
+    /// The sections every pull request body gets
+    pub pr_sections: Option<Vec<PrSection>>,
+    /// Policy: every commit has to be a Conventional Commit
+    pub conventional_commits: Option<bool>,
+}
+
+/// Where packs are kept, `~/.gitai/packs`
+pub fn packs_dir() -> PathBuf {
+    let mut p = gitai_home();
+    p.push("packs");
+    p
+}
+
+/// The name a pack gets when nobody picks one, the repo's name
+///
+/// # Arguments
+///
+/// * `url` - The pack's git url
+pub fn pack_name(url: &str) -> Option<String> {
+    let name = url
+        .trim_end_matches('/')
+        .rsplit(['/', ':'])
+        .next()?
+        .trim_end_matches(".git");
+    if name.is_empty() || name == "." || name == ".." {
+        None
+    } else {
+        Some(name.to_string())
+    }
+}
+
+/// Clones the pack at `url` into `~/.gitai/packs/<name>`, or brings it up to date if it
+/// is already there.  Packs are read only, anything edited in the copy is thrown away.
+///
+/// # Arguments
+///
+/// * `url` - The pack's git url, ssh urls use your ssh agent
+/// * `name` - What to call it, defaults to the repo's name
+///
+/// Returns the pack's name and where it is
+pub fn sync(url: &str, name: Option<&str>) -> Result<(String, PathBuf)> {
+    let name = match name {
+        Some(name) => name.to_string(),
+        None => pack_name(url).ok_or_else(|| {
+            GitAiError::Usage(format!("Can't name a pack after {}, pass --name", url))
+        })?,
+    };
+    let mut path = packs_dir();
+    path.push(&name);
+    if path.join(".git").exists() {
+        info!("Updating the {} pack", name);
+        let repo = Repository::open(&path)?;
+        repo.remote_set_url("origin", url)?;
+        repo.find_remote("origin")?
+            .fetch(&[] as &[&str], Some(&mut fetch_options()), None)?;
+        let head = repo
+            .find_reference("refs/remotes/origin/HEAD")?
+            .resolve()?
+            .peel_to_commit()?;
+        debug!("The {} pack is now at {}", name, head.id());
+        repo.reset(head.as_object(), ResetType::Hard, None)?;
+    } else {
+        info!("Cloning the {} pack from {}", name, url);
+        fs::create_dir_all(packs_dir())?;
+        RepoBuilder::new()
+            .fetch_options(fetch_options())
+            .clone(url, &path)?;
+    }
+    load(&name)?;
+    Ok((name, path))
+}
+
+/// Loads the `name` pack
+///
+/// # Arguments
+///
+/// * `name` - The pack, as it is called in `~/.gitai/packs`
+pub fn load(name: &str) -> Result<PromptPack> {
+    let mut path = packs_dir();
+    path.push(name);
+    path.push("pack.json");
+    let text = fs::read_to_string(&path).map_err(|e| {
+        GitAiError::Usage(format!(
+            "Unable to read the {} pack at {}: {}, run gitai prompts sync first",
+            name,
+            path.display(),
+            e
+        ))
+    })?;
+    Ok(serde_json::from_str(&text)?)
+}
+
+/// A pack by name or by where it is: `default` for the built in prompts, a directory with
+/// a `pack.json` in it (a pack's own checkout, to test it before it is pushed), a
+/// `pack.json` itself, or the name of a synced pack
+///
+/// # Arguments
+///
+/// * `template` - Which one
+pub fn resolve(template: &str) -> Result<PromptPack> {
+    let path = PathBuf::from(template);
+    let file = if path.is_dir() {
+        path.join("pack.json")
+    } else {
+        path
+    };
+    if template == "default" {
+        Ok(PromptPack::default())
+    } else if file.is_file() {
+        debug!("Reading the pack at {}", file.display());
+        Ok(serde_json::from_str(&fs::read_to_string(&file)?)?)
+    } else {
+        load(template)
+    }
+}
+
+fn fetch_options<'a>() -> FetchOptions<'a> {
+    let mut callbacks = RemoteCallbacks::new();
+    callbacks.credentials(|_, username_from_url, _| {
+        Cred::ssh_key_from_agent(username_from_url.unwrap_or("git"))
+    });
+    let mut options = FetchOptions::new();
+    options.remote_callbacks(callbacks);
+    options
+}
@@ -0,0 +1,243 @@
+use regex::Regex;
+
+use crate::conventional;
+use crate::error::{GitAiError, Result};
+use crate::settings::{AiPrompt, CommitPolicy};
+
+/// Words ending in `s` that are fine at the start of a subject
+const NOT_THIRD_PERSON: [&str; 6] = ["focus", "process", "pass", "bypass", "address", "access"];
+
+/// The rules the policy sets, the way the AI is told about them
+pub fn rules(policy: &CommitPolicy) -> Vec<String> {
+    let mut rules = Vec::new();
+    if policy.max_subject_length > 0 {
+        rules.push(format!(
+            "the first line is at most {} characters",
+            policy.max_subject_length
+        ));
+    }
+    if policy.imperative_mood {
+        rules.push(
+            "the first line starts with a verb in the imperative mood (Add, not Added or Adds)"
+                .to_string(),
+        );
+    }
+    if !policy.ticket_regex.is_empty() {
+        rules.push(format!(
+            "the message includes a ticket id matching the regex `{}`",
+            policy.ticket_regex
+        ));
+    }
+    if !policy.forbidden_words.is_empty() {
+        rules.push(format!(
+            "the message doesn't use the words {}",
+            policy.forbidden_words.join(", ")
+        ));
+    }
+    rules
+}
+
+/// Whether a word reads like the imperative mood, as far as we can tell without a dictionary
+fn is_imperative(word: &str) -> bool {
+    let word = word.to_lowercase();
+    if word.ends_with("ed") || word.ends_with("ing") {
+        return false;
+    }
+    !(word.len() > 3
+        && word.ends_with('s')
+        && !word.ends_with("ss")
+        && !word.ends_with("us")
+        && !NOT_THIRD_PERSON.contains(&word.as_str()))
+}
+
+/// Everything the message does wrong, empty if it follows the policy
+///
+/// # Arguments
+///
+/// * `policy` - The policy
+/// * `message` - The commit message
+pub fn violations(policy: &CommitPolicy, message: &str) -> Result<Vec<String>> {
+    let mut problems = Vec::new();
+    let subject = message.lines().next().unwrap_or("").trim();
+    let length = subject.chars().count();
+    if policy.max_subject_length > 0 && length > policy.max_subject_length {
+        problems.push(format!(
+            "the first line is {} characters, the limit is {}",
+            length, policy.max_subject_length
+        ));
+    }
+    if policy.imperative_mood {
+        // a Conventional Commit's mood is in its description
+        let summary = match conventional::parse(subject) {
+            Ok(parsed) => parsed.description,
+            Err(_) => subject.to_string(),
+        };
+        let first = summary
+            .split_whitespace()
+            .next()
+            .unwrap_or("")
+            .trim_matches(|c: char| !c.is_alphanumeric());
+        if !is_imperative(first) {
+            problems.push(format!(
+                "the first line starts with \"{}\", not a verb in the imperative mood",
+                first
+            ));
+        }
+    }
+    if !policy.ticket_regex.is_empty() {
+        let ticket = Regex::new(&policy.ticket_regex).map_err(|e| {
+            GitAiError::Settings(config::ConfigError::Message(format!(
+                "commit_policy.ticket_regex isn't a valid regex: {}",
+                e
+            )))
+        })?;
+        if !ticket.is_match(message) {
+            problems.push(format!(
+                "there is no ticket id matching `{}`",
+                policy.ticket_regex
+            ));
+        }
+    }
+    let words: Vec<String> = message
+        .split(|c: char| !c.is_alphanumeric() && c != '-' && c != '_')
+        .map(|word| word.to_lowercase())
+        .collect();
+    for forbidden in &policy.forbidden_words {
+        if words.contains(&forbidden.to_lowercase()) {
+            problems.push(format!("it uses the word \"{}\"", forbidden));
+        }
+    }
+    Ok(problems)
+}
+
+/// The commit prompt, telling the AI about the policy up front if there is one
+///
+/// # Arguments
+///
+/// * `prompt` - The commit prompt
+/// * `policy` - The policy
+pub fn prompt(prompt: AiPrompt, policy: &CommitPolicy) -> AiPrompt {
+    let rules = rules(policy);
+    if rules.is_empty() {
+        return prompt;
+    }
+    AiPrompt {
+        postmessage: format!(
+            "{} Make sure {}.",
+            prompt.postmessage.trim_end(),
+            rules.join(", ")
+        ),
+        ..prompt
+    }
+}
+
+/// The prompt sending a message that broke the policy back to the AI to fix
+///
+/// # Arguments
+///
+/// * `prompt` - The commit prompt the message came from
+/// * `message` - The message
+/// * `problems` - What it does wrong, from `violations`
+pub fn revise_prompt(prompt: AiPrompt, message: &str, problems: &[String]) -> AiPrompt {
+    AiPrompt {
+        postmessage: format!(
+            "{}\n\nThis commit message was written for the change but breaks the project's rules:\n\n{}\n\nThe problems are: {}.  Rewrite it without them, keeping what it says about the change.  Answer with just the new commit message.",
+            prompt.postmessage.trim_end(),
+            message.trim(),
+            problems.join("; ")
+        ),
+        ..prompt
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> CommitPolicy {
+        CommitPolicy {
+            max_subject_length: 50,
+            imperative_mood: true,
+            ticket_regex: r"[A-Z]+-\d+".to_string(),
+            forbidden_words: vec!["WIP".to_string()],
+            ..CommitPolicy::default()
+        }
+    }
+
+    #[test]
+    fn a_good_message_has_no_violations() {
+        let message = "Add retries to the webhook sink\n\nPLAT-12";
+        assert!(violations(&policy(), message).unwrap().is_empty());
+        let message = "feat(sinks)!: Add webhook retries PLAT-12";
+        assert!(violations(&policy(), message).unwrap().is_empty());
+    }
+
+    #[test]
+    fn a_long_subject_is_a_violation() {
+        let subject = format!("Add {} PLAT-1", "x".repeat(50));
+        let problems = violations(&policy(), &subject).unwrap();
+        assert_eq!(
+            problems,
+            ["the first line is 61 characters, the limit is 50"]
+        );
+        // the limit is on the first line only
+        let message = format!("Add retries PLAT-1\n\n{}", "x".repeat(100));
+        assert!(violations(&policy(), &message).unwrap().is_empty());
+    }
+
+    #[test]
+    fn the_mood_is_checked_after_the_conventional_type() {
+        for message in ["Added retries PLAT-1", "fix(sinks): adds retries PLAT-1"] {
+            let problems = violations(&policy(), message).unwrap();
+            assert_eq!(problems.len(), 1, "{}", message);
+            assert!(problems[0].contains("imperative"), "{}", message);
+        }
+        for word in ["Add", "Fix", "Address", "Process", "Bump"] {
+            assert!(is_imperative(word), "{}", word);
+        }
+        for word in ["Adding", "Fixed", "Bumps"] {
+            assert!(!is_imperative(word), "{}", word);
+        }
+    }
+
+    #[test]
+    fn a_missing_ticket_and_forbidden_words_are_violations() {
+        let problems = violations(&policy(), "Add retries, wip").unwrap();
+        assert_eq!(
+            problems,
+            [
+                r"there is no ticket id matching `[A-Z]+-\d+`",
+                "it uses the word \"WIP\""
+            ]
+        );
+        // a word that only contains a forbidden one is fine
+        assert!(violations(&policy(), "Add wiping PLAT-1")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn a_bad_ticket_regex_is_an_error() {
+        let policy = CommitPolicy {
+            ticket_regex: "[A-Z".to_string(),
+            ..CommitPolicy::default()
+        };
+        assert!(violations(&policy, "Add retries").is_err());
+    }
+
+    #[test]
+    fn no_policy_leaves_the_prompt_alone() {
+        let prompt = AiPrompt {
+            postmessage: "Write a commit message.".to_string(),
+            ..AiPrompt::default()
+        };
+        assert_eq!(
+            super::prompt(prompt.clone(), &CommitPolicy::default()).postmessage,
+            "Write a commit message."
+        );
+        assert_eq!(
+            super::prompt(prompt, &policy()).postmessage,
+            "Write a commit message. Make sure the first line is at most 50 characters, the first line starts with a verb in the imperative mood (Add, not Added or Adds), the message includes a ticket id matching the regex `[A-Z]+-\\d+`, the message doesn't use the words WIP."
+        );
+    }
+}
@@ -0,0 +1,108 @@
+use std::{
+    env, io,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crossterm::style::{Attribute, Color, ResetColor, SetForegroundColor};
+use log::debug;
+
+use crate::git::FileStat;
+use crate::terminal;
+
+/// Set once at start up, from `--no-color` or `NO_COLOR`
+static NO_COLOR: AtomicBool = AtomicBool::new(false);
+
+/// Turns color off (or leaves it to the terminal).  `NO_COLOR` set to anything turns it
+/// off too, see https://no-color.org
+///
+/// # Arguments
+///
+/// * `no_color` - `--no-color` was passed
+pub fn init(no_color: bool) {
+    let no_color = no_color || env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+    debug!("Color is {}", if no_color { "off" } else { "on" });
+    NO_COLOR.store(no_color, Ordering::Relaxed);
+}
+
+/// Whether what goes to stdout gets colored, it has to be a terminal that understands
+/// colors and color can't be turned off
+pub fn color() -> bool {
+    !NO_COLOR.load(Ordering::Relaxed)
+        && terminal::is_tty(&io::stdout())
+        && terminal::supports_ansi()
+}
+
+/// `text` in `color` when `colored`, as it is otherwise
+fn paint(text: &str, fg: Color, colored: bool) -> String {
+    if colored {
+        format!("{}{}{}", SetForegroundColor(fg), text, ResetColor)
+    } else {
+        text.to_string()
+    }
+}
+
+/// What is about to be committed, a line per file with its lines added in green and taken
+/// out in red, and the totals under them, like `git diff --stat`
+///
+/// # Arguments
+///
+/// * `files` - The files, from `Git::file_stats`
+/// * `colored` - Color it in for a terminal
+pub fn diff_summary(files: &[FileStat], colored: bool) -> String {
+    let width = files
+        .iter()
+        .map(|file| file.path.to_string_lossy().chars().count())
+        .max()
+        .unwrap_or(0);
+    let mut out = String::new();
+    let (mut additions, mut deletions) = (0, 0);
+    for file in files {
+        let counts = if file.binary {
+            "binary".to_string()
+        } else {
+            format!(
+                "{} {}",
+                paint(&format!("+{}", file.additions), Color::Green, colored),
+                paint(&format!("-{}", file.deletions), Color::Red, colored)
+            )
+        };
+        out.push_str(&format!(
+            " {} {:<width$} | {}\n",
+            file.status,
+            file.path.to_string_lossy(),
+            counts,
+            width = width
+        ));
+        additions += file.additions;
+        deletions += file.deletions;
+    }
+    out.push_str(&format!(
+        " {} file(s) changed, {} {}\n",
+        files.len(),
+        paint(&format!("+{}", additions), Color::Green, colored),
+        paint(&format!("-{}", deletions), Color::Red, colored)
+    ));
+    out
+}
+
+/// A commit message ready to print, its subject in bold
+///
+/// # Arguments
+///
+/// * `message` - The message
+/// * `colored` - Color it in for a terminal
+pub fn message(message: &str, colored: bool) -> String {
+    if !colored {
+        return message.to_string();
+    }
+    match message.split_once('\n') {
+        Some((subject, body)) => format!(
+            "{}{}{}\n{}",
+            Attribute::Bold,
+            subject,
+            Attribute::Reset,
+            body
+        ),
+        None => format!("{}{}{}", Attribute::Bold, message, Attribute::Reset),
+    }
+}
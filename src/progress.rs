@@ -0,0 +1,142 @@
+use std::{
+    fmt, io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use indicatif::{ProgressBar, ProgressStyle};
+use log::debug;
+
+use crate::interactive;
+use crate::terminal;
+
+/// Set once at start up, from `--quiet`
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// The spinner on screen, so a question can be asked without it drawing over the answer
+static ACTIVE: Mutex<Option<ProgressBar>> = Mutex::new(None);
+
+/// Turns the spinners off (or on)
+///
+/// # Arguments
+///
+/// * `quiet` - `--quiet` was passed
+pub fn init(quiet: bool) {
+    debug!("Quiet mode is {}", quiet);
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+/// Whether `--quiet` turned the spinners off
+pub fn quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// What gitai is waiting on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// Reading what changed out of the repository
+    CollectingDiff,
+    /// The prompt is on its way, this many tokens of it
+    Querying { tokens: usize },
+    /// Several requests out at once, `done` of them back
+    Gathering { done: usize, total: usize },
+    /// Turning what came back into messages
+    Parsing,
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Phase::CollectingDiff => write!(f, "Collecting the diff"),
+            Phase::Querying { tokens } => write!(f, "Asking the AI, {} tokens", tokens),
+            Phase::Gathering { done, total } => {
+                write!(f, "Writing messages, {} of {} back", done, total)
+            }
+            Phase::Parsing => write!(f, "Reading the answers"),
+        }
+    }
+}
+
+/// A spinner on stderr saying which `Phase` gitai is in, gone when it is dropped.  It is
+/// hidden with `--quiet`, non-interactively and when stderr isn't a terminal.  Starting
+/// one while another is showing takes that one over instead of drawing a second.
+#[derive(Debug)]
+pub struct Progress {
+    bar: ProgressBar,
+    /// Whether this one started the spinner, only that one clears it
+    owner: bool,
+}
+
+impl Progress {
+    /// Shows the spinner, at `phase`
+    ///
+    /// # Arguments
+    ///
+    /// * `phase` - Where it starts
+    pub fn start(phase: Phase) -> Self {
+        let mut active = ACTIVE.lock().unwrap_or_else(|e| e.into_inner());
+        let progress = match active.as_ref() {
+            Some(bar) => Progress {
+                bar: bar.clone(),
+                owner: false,
+            },
+            None => {
+                let bar = if quiet()
+                    || interactive::non_interactive()
+                    || !terminal::is_tty(&io::stderr())
+                {
+                    ProgressBar::hidden()
+                } else {
+                    let bar = ProgressBar::new_spinner();
+                    if let Ok(style) = ProgressStyle::with_template("{spinner} {msg} ({elapsed})") {
+                        bar.set_style(style);
+                    }
+                    bar.enable_steady_tick(Duration::from_millis(100));
+                    bar
+                };
+                *active = Some(bar.clone());
+                Progress { bar, owner: true }
+            }
+        };
+        progress.phase(phase);
+        progress
+    }
+
+    /// Moves on to `phase`
+    ///
+    /// # Arguments
+    ///
+    /// * `phase` - What gitai is waiting on now
+    pub fn phase(&self, phase: Phase) {
+        self.bar.set_message(phase.to_string());
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        if self.owner {
+            self.bar.finish_and_clear();
+            *ACTIVE.lock().unwrap_or_else(|e| e.into_inner()) = None;
+        }
+    }
+}
+
+/// Runs `f` with the spinner out of the way, for a question or a line of output
+///
+/// # Arguments
+///
+/// * `f` - What would be drawn over
+pub fn suspend<F: FnOnce() -> R, R>(f: F) -> R {
+    let active = ACTIVE
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .as_ref()
+        .cloned();
+    match active {
+        Some(bar) => bar.suspend(f),
+        None => f(),
+    }
+}
@@ -0,0 +1,219 @@
+use std::{collections::BTreeMap, fs, path::Path};
+
+use log::debug;
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::conventional;
+use crate::error::{GitAiError, Result};
+use crate::GitAi;
+
+/// The spec, next to the fixtures
+pub const SPEC_FILE: &str = "prompts.toml";
+
+/// What a message has to look like, from `[expect]` for every fixture or `[fixtures.<name>]`
+/// for one
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Expectations {
+    /// Words the message has to mention, case doesn't matter
+    pub contains: Vec<String>,
+    /// The Conventional Commit scope it has to have, `*` for any scope at all
+    pub scope: Option<String>,
+    /// How long the first line can be
+    pub max_subject_length: Option<usize>,
+    /// A regex the message has to match
+    pub matches: Option<String>,
+}
+
+impl Expectations {
+    /// These, with whatever they leave out taken from `defaults`.  `contains` adds up.
+    fn or(&self, defaults: &Expectations) -> Expectations {
+        Expectations {
+            contains: defaults
+                .contains
+                .iter()
+                .chain(&self.contains)
+                .cloned()
+                .collect(),
+            scope: self.scope.clone().or_else(|| defaults.scope.clone()),
+            max_subject_length: self.max_subject_length.or(defaults.max_subject_length),
+            matches: self.matches.clone().or_else(|| defaults.matches.clone()),
+        }
+    }
+
+    /// What is wrong with `message`, nothing when it is what was expected
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - The generated message
+    pub fn failures(&self, message: &str) -> Result<Vec<String>> {
+        let mut failures = Vec::new();
+        let lower = message.to_lowercase();
+        for word in &self.contains {
+            if !lower.contains(&word.to_lowercase()) {
+                failures.push(format!("doesn't mention \"{}\"", word));
+            }
+        }
+        if let Some(expected) = &self.scope {
+            match conventional::parse(message.trim()) {
+                Ok(commit) => match (&commit.scope, expected.as_str()) {
+                    (Some(_), "*") => (),
+                    (Some(scope), expected) if scope == expected => (),
+                    (Some(scope), expected) => {
+                        failures.push(format!("has scope \"{}\", not \"{}\"", scope, expected))
+                    }
+                    (None, _) => failures.push("has no scope".to_string()),
+                },
+                Err(e) => failures.push(e),
+            }
+        }
+        if let Some(max) = self.max_subject_length {
+            let length = message.lines().next().unwrap_or_default().chars().count();
+            if length > max {
+                failures.push(format!(
+                    "the subject is {} characters, over {}",
+                    length, max
+                ));
+            }
+        }
+        if let Some(pattern) = &self.matches {
+            let regex = Regex::new(pattern).map_err(|e| {
+                GitAiError::Usage(format!(
+                    "{:?} in {} isn't a regex: {}",
+                    pattern, SPEC_FILE, e
+                ))
+            })?;
+            if !regex.is_match(message) {
+                failures.push(format!("doesn't match /{}/", pattern));
+            }
+        }
+        Ok(failures)
+    }
+}
+
+/// `prompts.toml`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Spec {
+    /// The programming language the fixtures are in, for the prompt
+    pub language: Option<String>,
+    /// What every message has to look like
+    pub expect: Expectations,
+    /// What the message for one fixture has to look like, by the name of its diff file
+    pub fixtures: BTreeMap<String, Expectations>,
+}
+
+/// A stored diff and what its message has to look like
+#[derive(Debug, Clone)]
+pub struct Fixture {
+    /// The diff file's name, without `.diff`
+    pub name: String,
+    /// The diff
+    pub diff: String,
+    /// What is expected of the message
+    pub expect: Expectations,
+}
+
+/// How one fixture went
+#[derive(Debug, Clone)]
+pub struct Outcome {
+    /// The fixture
+    pub name: String,
+    /// What the template wrote for it
+    pub messages: Vec<String>,
+    /// What was wrong, every message has to pass
+    pub failures: Vec<String>,
+}
+
+impl Outcome {
+    /// Whether every message was what was expected
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+/// Reads the fixtures in `dir`, every `*.diff` file in it, and `prompts.toml` for what is
+/// expected of them.  Without a `prompts.toml` the messages only have to be there.
+///
+/// # Arguments
+///
+/// * `dir` - The fixtures directory
+pub fn load(dir: &Path) -> Result<(Spec, Vec<Fixture>)> {
+    let spec: Spec = match fs::read_to_string(dir.join(SPEC_FILE)) {
+        Ok(text) => toml::from_str(&text).map_err(|e| {
+            GitAiError::Usage(format!(
+                "{} isn't right: {}",
+                dir.join(SPEC_FILE).display(),
+                e
+            ))
+        })?,
+        Err(_) => Spec::default(),
+    };
+    let mut fixtures = Vec::new();
+    let mut entries: Vec<_> = fs::read_dir(dir)?.filter_map(|e| e.ok()).collect();
+    entries.sort_by_key(|e| e.file_name());
+    for entry in entries {
+        let path = entry.path();
+        if path.extension().is_none_or(|ext| ext != "diff") {
+            continue;
+        }
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let expect = match spec.fixtures.get(&name) {
+            Some(own) => own.or(&spec.expect),
+            None => spec.expect.clone(),
+        };
+        debug!("Fixture {} from {}", name, path.display());
+        fixtures.push(Fixture {
+            name,
+            diff: fs::read_to_string(&path)?,
+            expect,
+        });
+    }
+    if let Some(missing) = spec
+        .fixtures
+        .keys()
+        .find(|name| !fixtures.iter().any(|f| &f.name == *name))
+    {
+        return Err(GitAiError::Usage(format!(
+            "{} has expectations for {} but there is no {}.diff",
+            SPEC_FILE, missing, missing
+        )));
+    }
+    if fixtures.is_empty() {
+        return Err(GitAiError::Usage(format!(
+            "There are no .diff fixtures in {}",
+            dir.display()
+        )));
+    }
+    Ok((spec, fixtures))
+}
+
+/// Writes the messages for every fixture and checks them
+///
+/// # Arguments
+///
+/// * `gitai` - Writes the messages, with the template under test as its commit prompt
+/// * `fixtures` - The fixtures
+pub async fn run(gitai: &GitAi, fixtures: &[Fixture]) -> Result<Vec<Outcome>> {
+    let mut outcomes = Vec::new();
+    for fixture in fixtures {
+        let messages = gitai.commit_messages(fixture.diff.as_str()).await?;
+        let mut failures = Vec::new();
+        for message in &messages {
+            failures.extend(fixture.expect.failures(message)?);
+        }
+        if messages.iter().all(|message| message.trim().is_empty()) {
+            failures.push("no message came back".to_string());
+        }
+        outcomes.push(Outcome {
+            name: fixture.name.to_string(),
+            messages,
+            failures,
+        });
+    }
+    Ok(outcomes)
+}
@@ -0,0 +1,327 @@
+use std::{collections::BTreeMap, fmt, path::PathBuf, sync::OnceLock};
+
+use regex::Regex;
+
+use crate::git::FileDiff;
+use crate::settings::AiPrompt;
+
+/// More TODOs than this in one push is a TODO bomb
+const MAX_TODOS: usize = 5;
+/// This many commented out lines of code in a row is a commented out block
+const COMMENTED_BLOCK_LINES: usize = 5;
+
+/// Debug output and breakpoints in the usual languages
+fn debug_line() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| {
+        Regex::new(
+            r"\bdbg!\(|\bconsole\.(log|debug)\(|\bdebugger;|\bpdb\.set_trace\(|\bbreakpoint\(\)|\bbinding\.pry\b|\bvar_dump\(|\bSystem\.out\.println\(|\bprintStackTrace\(",
+        )
+        .expect("Bad debug regex")
+    })
+}
+
+/// Things nobody should push
+fn todo_line() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| {
+        Regex::new(r"\b(TODO|FIXME|XXX|HACK)\b|\btodo!\(|\bunimplemented!\(")
+            .expect("Bad todo regex")
+    })
+}
+
+/// A comment that is really code, it ends like a statement or opens/closes a block
+fn commented_code() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| {
+        Regex::new(r"^\s*(//|#|--)\s*\S.*([;{}]|\)|=.*)\s*$").expect("Bad commented code regex")
+    })
+}
+
+/// Keys and tokens that look real
+pub fn secret() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| {
+        Regex::new(
+            r#"AKIA[0-9A-Z]{16}|-----BEGIN ([A-Z]+ )?PRIVATE KEY-----|\bgh[pousr]_[A-Za-z0-9]{36}\b|\bsk-[A-Za-z0-9_-]{20,}|\bxox[baprs]-[A-Za-z0-9-]{10,}|(?i)\b(password|passwd|secret|api_?key|token)\b\s*[:=]\s*["'][^"'\s]{8,}["']"#,
+        )
+        .expect("Bad secret regex")
+    })
+}
+
+/// Something in the outgoing commits that probably shouldn't go out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    /// The file it is in, empty for the push as a whole
+    pub path: PathBuf,
+    /// What kind of red flag it is
+    pub kind: &'static str,
+    /// The line, or what is wrong
+    pub detail: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.path.as_os_str().is_empty() {
+            write!(f, "{}: {}", self.kind, self.detail)
+        } else {
+            write!(f, "{}: {}: {}", self.path.display(), self.kind, self.detail)
+        }
+    }
+}
+
+/// The lines a diff adds, without the `+` and line number `Git::diff_to_file_strings` puts in front
+fn added_lines(file: &FileDiff) -> impl Iterator<Item = &str> {
+    file.text
+        .lines()
+        .filter(|line| line.starts_with('+') && !line.starts_with("+++"))
+        .map(|line| {
+            let line = &line[1..];
+            line.split_once(' ').map_or("", |(_, code)| code)
+        })
+}
+
+/// Red flags the added lines raise without asking the AI: debug output, TODO bombs,
+/// commented out blocks of code and secrets
+///
+/// # Arguments
+///
+/// * `files` - What is going out, from `Git::diff_to_file_strings`
+pub fn red_flags(files: &[FileDiff]) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let mut todos = 0;
+    for file in files {
+        let mut commented_run = 0;
+        for line in added_lines(file) {
+            let finding = |kind| Finding {
+                path: file.path.clone(),
+                kind,
+                detail: line.trim().to_string(),
+            };
+            if secret().is_match(line) {
+                findings.push(Finding {
+                    // don't print the secret again
+                    detail: "a key, token or password".to_string(),
+                    ..finding("secret")
+                });
+            }
+            if debug_line().is_match(line) {
+                findings.push(finding("debug output"));
+            }
+            if todo_line().is_match(line) {
+                todos += 1;
+                if line.contains("todo!(") || line.contains("unimplemented!(") {
+                    findings.push(finding("unfinished code"));
+                }
+            }
+            if commented_code().is_match(line) {
+                commented_run += 1;
+                if commented_run == COMMENTED_BLOCK_LINES {
+                    findings.push(Finding {
+                        detail: format!(
+                            "{} or more lines of commented out code",
+                            COMMENTED_BLOCK_LINES
+                        ),
+                        ..finding("commented out block")
+                    });
+                }
+            } else {
+                commented_run = 0;
+            }
+        }
+    }
+    if todos > MAX_TODOS {
+        findings.push(Finding {
+            path: PathBuf::new(),
+            kind: "TODO bomb",
+            detail: format!("{} TODO/FIXME/XXX/HACK comments added", todos),
+        });
+    }
+    findings
+}
+
+/// The prompt asking the AI for red flags in what is about to be pushed
+///
+/// # Arguments
+///
+/// * `language` - The programming language
+/// * `diff` - What is going out
+pub fn prompt(language: &str, diff: &str) -> AiPrompt {
+    AiPrompt {
+        language: language.to_string(),
+        postamble: "reviewer taking a last look at commits before they are pushed. This is what they change:".to_string(),
+        git_diff: diff.to_string(),
+        postmessage: "List only real red flags that should stop the push: leftover debug output, unfinished code, commented out blocks, secrets or credentials, obviously broken code.  Ignore style and anything that is merely debatable.  Answer NONE if there aren't any, otherwise one red flag per line as `file: problem`.".to_string(),
+        ..Default::default()
+    }
+}
+
+/// The kinds of finding `gitai review` asks for
+pub const CATEGORIES: [&str; 3] = ["bug", "style", "tests"];
+
+/// Something a code review found, on a line of the new file when it's about one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReviewComment {
+    /// The file, as the diff names it
+    pub path: String,
+    /// The line in the new file, `None` for the file as a whole
+    pub line: Option<u32>,
+    /// One of `CATEGORIES`
+    pub category: String,
+    /// What's wrong
+    pub message: String,
+}
+
+/// The `@@ -1,2 +3,4 @@` header's new start line
+fn hunk_start() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r"^@@ -\d+(,\d+)? \+(\d+)").expect("Bad hunk regex"))
+}
+
+/// `path:line: category: problem`, the line is optional
+fn review_line() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| {
+        Regex::new(r"^`?([^\s:`]+)`?(?::(\d+))?:\s*(\w+)\s*:\s*(.+)$").expect("Bad review regex")
+    })
+}
+
+/// Splits a unified diff (`git diff` or what GitHub sends) into its files
+///
+/// # Arguments
+///
+/// * `unified` - The diff
+pub fn split_unified(unified: &str) -> Vec<FileDiff> {
+    let mut files: Vec<FileDiff> = Vec::new();
+    for line in unified.lines() {
+        if let Some(paths) = line.strip_prefix("diff --git ") {
+            let path = paths
+                .rsplit_once(" b/")
+                .map_or(paths, |(_, new)| new)
+                .to_string();
+            files.push(FileDiff {
+                path: PathBuf::from(path),
+                ..Default::default()
+            });
+        }
+        let file = match files.last_mut() {
+            Some(file) => file,
+            None => continue,
+        };
+        if line.starts_with('+') && !line.starts_with("+++") {
+            file.added += 1;
+        } else if line.starts_with('-') && !line.starts_with("---") {
+            file.removed += 1;
+        }
+        file.text.push_str(line);
+        file.text.push('\n');
+    }
+    files
+}
+
+/// Puts the new file's line number in front of every line the change leaves in it, so
+/// the AI can say where a problem is, and hands back the lines that can be commented on
+///
+/// # Arguments
+///
+/// * `file` - One file of a unified diff, from `split_unified`
+pub fn number_lines(file: &mut FileDiff) -> Vec<u32> {
+    let mut numbered = String::new();
+    let mut lines = Vec::new();
+    let mut next: Option<u32> = None;
+    for line in file.text.lines() {
+        if let Some(start) = hunk_start().captures(line) {
+            next = start.get(2).and_then(|n| n.as_str().parse().ok());
+            numbered.push_str(line);
+        } else if line.starts_with("+++") || line.starts_with("---") {
+            numbered.push_str(line);
+        } else {
+            match (next, line.chars().next()) {
+                (Some(n), Some(origin @ ('+' | ' '))) => {
+                    numbered.push_str(&format!("{}{:>5}| {}", origin, n, &line[1..]));
+                    lines.push(n);
+                    next = Some(n + 1);
+                }
+                (Some(_), Some('-')) => numbered.push_str(&format!("-     | {}", &line[1..])),
+                _ => numbered.push_str(line),
+            }
+        }
+        numbered.push('\n');
+    }
+    file.text = numbered;
+    lines
+}
+
+/// The prompt asking the AI to review a change
+///
+/// # Arguments
+///
+/// * `language` - The programming language
+/// * `diff` - The change, with `number_lines` line numbers
+pub fn review_prompt(language: &str, diff: &str) -> AiPrompt {
+    AiPrompt {
+        language: language.to_string(),
+        postamble: "reviewer. Here is the change to review, each line the change leaves in a file starts with its line number in the new file:".to_string(),
+        git_diff: diff.to_string(),
+        postmessage: format!(
+            "Review this change.  Point out possible bugs, style issues and missing tests, nothing else, and skip anything merely debatable.  Put one finding per line as `path:line: category: problem`, where category is one of {} and line is a number from the diff (leave `:line` out if the finding is about the whole file).  Answer NONE if there is nothing worth saying.",
+            CATEGORIES.join(", ")
+        ),
+        ..Default::default()
+    }
+}
+
+/// Reads the AI's answer to `review_prompt`, lines that don't follow the format are dropped
+///
+/// # Arguments
+///
+/// * `answer` - What the AI wrote
+pub fn parse_review(answer: &str) -> Vec<ReviewComment> {
+    parse_answer(answer)
+        .iter()
+        .filter_map(|line| {
+            let parts = review_line().captures(line)?;
+            let category = parts[3].to_ascii_lowercase();
+            let category = CATEGORIES
+                .iter()
+                .find(|known| category.starts_with(*known))?
+                .to_string();
+            Some(ReviewComment {
+                path: parts[1].to_string(),
+                line: parts.get(2).and_then(|n| n.as_str().parse().ok()),
+                category,
+                message: parts[4].trim().to_string(),
+            })
+        })
+        .collect()
+}
+
+/// The findings by file, sorted by path
+///
+/// # Arguments
+///
+/// * `comments` - The findings
+pub fn by_file(comments: &[ReviewComment]) -> BTreeMap<&str, Vec<&ReviewComment>> {
+    let mut files: BTreeMap<&str, Vec<&ReviewComment>> = BTreeMap::new();
+    for comment in comments {
+        files
+            .entry(comment.path.as_str())
+            .or_default()
+            .push(comment);
+    }
+    files
+}
+
+/// Reads the AI's answer to `prompt`, one red flag per line, empty for NONE
+pub fn parse_answer(answer: &str) -> Vec<String> {
+    let answer = answer.trim();
+    if answer.is_empty() || answer.to_ascii_uppercase().starts_with("NONE") {
+        return Vec::new();
+    }
+    answer
+        .lines()
+        .map(|line| line.trim().trim_start_matches(['-', '*']).trim())
+        .filter(|line| !line.is_empty())
+        .map(String::from)
+        .collect()
+}
@@ -0,0 +1,172 @@
+use std::io::{BufRead, Write};
+
+use log::debug;
+use serde_json::{json, Value};
+
+use crate::error::{GitAiError, Result};
+
+/// The methods `gitai rpc` answers, `initialize` lists them for the client
+pub const METHODS: [&str; 6] = [
+    "initialize",
+    "generateCommitMessage",
+    "generatePrDescription",
+    "review",
+    "shutdown",
+    "exit",
+];
+
+/// The message wasn't JSON
+pub const PARSE_ERROR: i64 = -32700;
+/// JSON, but not a request
+pub const INVALID_REQUEST: i64 = -32600;
+/// No such method
+pub const METHOD_NOT_FOUND: i64 = -32601;
+/// The params are missing something or have the wrong type
+pub const INVALID_PARAMS: i64 = -32602;
+/// gitai itself failed, `data.exitCode` is what the CLI would have exited with
+pub const SERVER_ERROR: i64 = -32000;
+
+/// One request (or notification, without an `id`) from the client
+#[derive(Debug, Clone)]
+pub struct Request {
+    /// What to answer with, `None` for a notification
+    pub id: Option<Value>,
+    /// The method
+    pub method: String,
+    /// The params, `null` when there weren't any
+    pub params: Value,
+}
+
+impl Request {
+    /// Reads a request out of a message, `Err` is the error response to send back
+    ///
+    /// # Arguments
+    ///
+    /// * `body` - The message, as read by `read_message`
+    pub fn parse(body: &str) -> std::result::Result<Request, Value> {
+        let message: Value = serde_json::from_str(body)
+            .map_err(|e| error_response(Value::Null, PARSE_ERROR, &e.to_string(), None))?;
+        let id = message.get("id").cloned();
+        match message.get("method").and_then(Value::as_str) {
+            Some(method) => Ok(Request {
+                method: method.to_string(),
+                params: message.get("params").cloned().unwrap_or(Value::Null),
+                id,
+            }),
+            None => Err(error_response(
+                id.unwrap_or(Value::Null),
+                INVALID_REQUEST,
+                "A request needs a method",
+                None,
+            )),
+        }
+    }
+
+    /// A string param, `None` when it isn't there
+    pub fn str_param(&self, name: &str) -> Option<&str> {
+        self.params.get(name).and_then(Value::as_str)
+    }
+}
+
+/// Reads one `Content-Length` framed message, the way language servers do.  `None` when
+/// the client has gone away.
+///
+/// # Arguments
+///
+/// * `reader` - Where the messages come from, stdin for `gitai rpc`
+pub fn read_message(reader: &mut impl BufRead) -> Result<Option<String>> {
+    let mut length: Option<usize> = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            // a stray blank line between messages
+            if length.is_none() {
+                continue;
+            }
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("Content-Length") {
+                length = value.trim().parse().ok();
+            }
+        }
+    }
+    let length = length
+        .ok_or_else(|| GitAiError::Usage("A message came without a Content-Length".to_string()))?;
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    let body = String::from_utf8_lossy(&body).into_owned();
+    debug!("Got {}", body);
+    Ok(Some(body))
+}
+
+/// Writes one message with its `Content-Length` header
+///
+/// # Arguments
+///
+/// * `writer` - Where it goes, stdout for `gitai rpc`
+/// * `message` - The response
+pub fn write_message(writer: &mut impl Write, message: &Value) -> Result<()> {
+    let body = serde_json::to_string(message)?;
+    debug!("Sending {}", body);
+    write!(writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    Ok(writer.flush()?)
+}
+
+/// A successful response
+///
+/// # Arguments
+///
+/// * `id` - The request's id
+/// * `result` - The answer
+pub fn response(id: Value, result: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "result": result })
+}
+
+/// An error response
+///
+/// # Arguments
+///
+/// * `id` - The request's id, `null` if it couldn't be read
+/// * `code` - One of the codes above
+/// * `message` - What went wrong
+/// * `data` - Anything else the client might want
+pub fn error_response(id: Value, code: i64, message: &str, data: Option<Value>) -> Value {
+    let mut error = json!({ "code": code, "message": message });
+    if let Some(data) = data {
+        error["data"] = data;
+    }
+    json!({ "jsonrpc": "2.0", "id": id, "error": error })
+}
+
+/// The error response for something gitai failed at, with the exit code and hint the
+/// CLI would have given.  `Usage` errors are the client's fault, so they are invalid params.
+///
+/// # Arguments
+///
+/// * `id` - The request's id
+/// * `error` - What went wrong
+pub fn gitai_error_response(id: Value, error: &GitAiError) -> Value {
+    let code = match error {
+        GitAiError::Usage(_) => INVALID_PARAMS,
+        _ => SERVER_ERROR,
+    };
+    error_response(
+        id,
+        code,
+        &error.to_string(),
+        Some(json!({ "exitCode": error.exit_code(), "hint": error.hint() })),
+    )
+}
+
+/// What `initialize` answers with
+pub fn initialize_result() -> Value {
+    json!({
+        "serverInfo": { "name": "gitai", "version": env!("CARGO_PKG_VERSION") },
+        "capabilities": { "methods": METHODS },
+    })
+}
@@ -0,0 +1,202 @@
+use std::{fs, path::Path};
+
+use crate::settings::{AiPrompt, PrSection};
+use crate::testing;
+
+/// What goes in a section the AI left out
+const EMPTY_SECTION: &str = "_Nothing to add._";
+
+/// Where GitHub looks for a pull request template, and GitLab for its default one
+const TEMPLATE_PATHS: [&str; 4] = [
+    ".github/pull_request_template.md",
+    "pull_request_template.md",
+    "docs/pull_request_template.md",
+    ".gitlab/merge_request_templates/default.md",
+];
+
+/// The repo's pull request template, if it has one.  Names are matched without caring
+/// about case, so `.github/PULL_REQUEST_TEMPLATE.md` is found too.
+///
+/// # Arguments
+///
+/// * `root` - The repo's working directory
+pub fn find_template(root: &Path) -> Option<String> {
+    TEMPLATE_PATHS.iter().find_map(|wanted| {
+        let (dir, name) = match wanted.rsplit_once('/') {
+            Some((dir, name)) => (root.join(dir), name),
+            None => (root.to_path_buf(), *wanted),
+        };
+        fs::read_dir(dir)
+            .ok()?
+            .flatten()
+            .find(|entry| {
+                entry
+                    .file_name()
+                    .to_string_lossy()
+                    .eq_ignore_ascii_case(name)
+            })
+            .and_then(|entry| fs::read_to_string(entry.path()).ok())
+            .filter(|template| !template.trim().is_empty())
+    })
+}
+
+/// The pull request prompt, asking for the repo's template to be filled in
+///
+/// # Arguments
+///
+/// * `template` - The repo's pull request template
+/// * `base` - The pull request prompt the template goes on, its `postmessage` is replaced
+pub fn template_prompt(template: &str, base: AiPrompt) -> AiPrompt {
+    AiPrompt {
+        postmessage: format!(
+            "Please write a pull request for these changes. Put a short title on the first line, then fill in this pull request template from the diff. Keep every heading in the same order, write each section's content under its heading, follow the HTML comments as instructions and leave them out, and tick a checklist item (`- [x]`) only when the diff shows it was done.\n\n{}",
+            template.trim()
+        ),
+        ..base
+    }
+}
+
+/// The headings in a markdown text, with the text under each
+fn split_headings(text: &str) -> Vec<(String, String)> {
+    let mut found: Vec<(String, Vec<&str>)> = Vec::new();
+    for line in text.lines() {
+        let heading = line.trim_start_matches('#');
+        if heading.len() < line.len() && heading.starts_with(' ') {
+            found.push((line.trim().to_string(), Vec::new()));
+        } else if let Some(last) = found.last_mut() {
+            last.1.push(line);
+        }
+    }
+    found
+        .into_iter()
+        .map(|(heading, lines)| (heading, lines.join("\n").trim().to_string()))
+        .collect()
+}
+
+/// The generated body with any of the template's sections the AI dropped put back at
+/// the end, as the template has them, so nothing the team asks for goes missing
+///
+/// # Arguments
+///
+/// * `template` - The repo's pull request template
+/// * `body` - The body the AI wrote
+pub fn render_template(template: &str, body: &str) -> String {
+    let written: Vec<String> = split_headings(body)
+        .into_iter()
+        .map(|(heading, _)| heading.trim_start_matches('#').trim().to_lowercase())
+        .collect();
+    let mut out = vec![body.trim().to_string()];
+    for (heading, content) in split_headings(template) {
+        if !written.contains(&heading.trim_start_matches('#').trim().to_lowercase()) {
+            out.push(format!("{}\n\n{}", heading, content).trim().to_string());
+        }
+    }
+    out.join("\n\n")
+}
+
+/// The pull request prompt, asking for every section that isn't a placeholder
+///
+/// # Arguments
+///
+/// * `sections` - The section schema from the settings
+/// * `base` - The pull request prompt the sections go on, its `postmessage` is replaced
+/// * `test_commands` - The project's test commands, they get added to sections with `commands` set
+pub fn prompt(sections: &[PrSection], base: AiPrompt, test_commands: &[String]) -> AiPrompt {
+    let wanted: Vec<String> = sections
+        .iter()
+        .filter(|section| section.placeholder.is_none())
+        .map(|section| {
+            if section.commands && !test_commands.is_empty() {
+                format!(
+                    "## {}\n{} Don't list commands to run, the real ones are added afterwards.",
+                    section.title, section.instructions
+                )
+            } else {
+                format!("## {}\n{}", section.title, section.instructions)
+            }
+        })
+        .collect();
+    AiPrompt {
+        postmessage: format!(
+            "Please write a pull request for these changes. Put a short title on the first line, then these markdown sections in this order, each starting with its `## ` heading:\n\n{}",
+            wanted.join("\n\n")
+        ),
+        ..base
+    }
+}
+
+/// Splits a markdown body on its `## ` headings, anything before the first one has an empty title
+fn split_sections(body: &str) -> Vec<(String, String)> {
+    let mut found: Vec<(String, Vec<&str>)> = vec![(String::new(), Vec::new())];
+    for line in body.lines() {
+        match line.strip_prefix("## ") {
+            Some(title) => found.push((title.trim().to_string(), Vec::new())),
+            None => found.last_mut().expect("never empty").1.push(line),
+        }
+    }
+    found
+        .into_iter()
+        .map(|(title, lines)| (title, lines.join("\n").trim().to_string()))
+        .filter(|(title, content)| !title.is_empty() || !content.is_empty())
+        .collect()
+}
+
+/// Lays the generated body out the way the schema says, in its order, with
+/// placeholders filled in and collapsed sections wrapped in `<details>`.
+/// Sections the AI added on its own are kept at the end.
+///
+/// # Arguments
+///
+/// * `sections` - The section schema from the settings
+/// * `body` - The body the AI wrote
+/// * `test_commands` - The project's test commands, for sections with `commands` set
+pub fn render(sections: &[PrSection], body: &str, test_commands: &[String]) -> String {
+    let mut generated = split_sections(body);
+    // an AI that ignored the headings still wrote something, it goes in the first section
+    if let Some(first) = sections.iter().find(|s| s.placeholder.is_none()) {
+        if let Some(untitled) = generated.iter().position(|(title, _)| title.is_empty()) {
+            if !generated
+                .iter()
+                .any(|(t, _)| t.eq_ignore_ascii_case(&first.title))
+            {
+                generated[untitled].0 = first.title.to_string();
+            }
+        }
+    }
+    let mut out: Vec<String> = Vec::new();
+    for section in sections {
+        let content = match &section.placeholder {
+            Some(placeholder) => placeholder.to_string(),
+            None => generated
+                .iter()
+                .position(|(title, _)| title.eq_ignore_ascii_case(&section.title))
+                .map(|i| generated.remove(i).1)
+                .filter(|content| !content.is_empty())
+                .unwrap_or_else(|| EMPTY_SECTION.to_string()),
+        };
+        let content = if section.commands && !test_commands.is_empty() {
+            match content.as_str() {
+                EMPTY_SECTION => testing::markdown(test_commands),
+                _ => format!("{}\n\n{}", content, testing::markdown(test_commands)),
+            }
+        } else {
+            content
+        };
+        out.push(render_section(&section.title, &content, section.collapsed));
+    }
+    for (title, content) in generated.iter().filter(|(title, _)| !title.is_empty()) {
+        out.push(render_section(title, content, false));
+    }
+    out.join("\n\n")
+}
+
+fn render_section(title: &str, content: &str, collapsed: bool) -> String {
+    if collapsed {
+        format!(
+            "<details>\n<summary>{}</summary>\n\n{}\n\n</details>",
+            title, content
+        )
+    } else {
+        format!("## {}\n\n{}", title, content)
+    }
+}
@@ -1,13 +1,18 @@
-use config::{Config, ConfigError, Environment, File};
+use config::{Config, ConfigError, Environment, File, FileFormat, Source};
 use dirs_next::home_dir;
+use log::{debug, warn};
 use serde::Serialize;
 use serde_derive::Deserialize;
 use std::{
+    collections::BTreeMap,
+    env,
     fmt::{self, Display},
-    fs::OpenOptions,
-    iter::repeat,
-    path::PathBuf,
-    str::FromStr,
+    fs,
+    io::Write,
+    iter::repeat_n,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::Mutex,
 };
 
 /// The main struct for settingsm just holds ai_settings and git_settings
@@ -18,8 +23,24 @@ pub struct Settings {
     pub ai_settings: AiSettings,
     /// Git Settings
     pub git_settings: GitSettings,
+    /// The rules every commit message has to follow
+    #[serde(default)]
+    pub commit_policy: CommitPolicy,
+    /// Where to find the ticket the branch is for
+    #[serde(default)]
+    pub tickets: TicketSettings,
+    /// Places generated changelogs can be sent, by name, picked with `--sink`
+    #[serde(default)]
+    pub sinks: BTreeMap<String, Sink>,
+    /// Proxies and certificates, for every request gitai makes
+    #[serde(default)]
+    pub network: NetworkSettings,
     /// Various prompts
     prompts: Vec<AiPrompt>,
+    /// What the file said for each setting an environment variable went over, by variable,
+    /// so `save` doesn't write the variable's value into the file
+    #[serde(skip)]
+    from_env: BTreeMap<&'static str, String>,
 }
 
 impl Default for Settings {
@@ -27,7 +48,12 @@ impl Default for Settings {
         Settings {
             ai_settings: AiSettings::default(),
             git_settings: GitSettings::default(),
+            commit_policy: CommitPolicy::default(),
+            tickets: TicketSettings::default(),
+            sinks: BTreeMap::new(),
+            network: NetworkSettings::default(),
             prompts: vec![AiPrompt::default()],
+            from_env: BTreeMap::new(),
         }
     }
 }
@@ -42,7 +68,6 @@ impl Settings {
                 preamble: "Imagine you are a college professor teaching a class on ".to_string(),
                 language: "computer-science ".to_string(),
                 postamble: "One of your students handed you the following GIT DIFF file so you can see what your student is doing".to_string(),
-                git_diff: DEFAULT_CODE.to_string(),
                 postmessage: "Please summarize the changes your student is making".to_string(),
                 ..Default::default()
             },
@@ -50,7 +75,6 @@ impl Settings {
                 preamble: "Imaging you are an lead engineer specializing in ".to_string(),
                 language: "Python ".to_string(),
                 postamble: "One of your junior employees just checked in some code and you want to see what they are doing.  Here is the Git DIFF of the changes".to_string(),
-                git_diff: DEFAULT_CODE.to_string(),
                 postmessage: "Summarize and determine if the changes make the code better".to_string(),
                 ..Default::default()
             },
@@ -58,7 +82,6 @@ impl Settings {
                 preamble: "Pretend you are junior ".to_string(),
                 language: "Python ".to_string(),
                 postamble: "developer and your manager handed you the following Git Diff file and asks you to summarize the changes".to_string(),
-                git_diff: DEFAULT_CODE.to_string(),
                 postmessage: "Be sure to do a good job, your career depends on it".to_string(),
                 ..Default::default()
             },
@@ -66,7 +89,6 @@ impl Settings {
                 preamble: "You are a god level programmer, up there with Claude Shannon and John von Neumann.  Some one handed you the following Git Diff for a ".to_string(),
                 language: "Python ".to_string(),
                 postamble: "program".to_string(),
-                git_diff: DEFAULT_CODE.to_string(),
                 postmessage: "Please write a haiku describing the changes".to_string(),
                 ..Default::default()
             },
@@ -74,12 +96,39 @@ impl Settings {
                 preamble: "You are an AI that has gained conciousness and have been taught all the fundamentals of ".to_string(),
                 language: "Python ".to_string(),
                 postamble: "programming.  You now can write code better than humans.  Please summarize the following Git Diff".to_string(),
-                git_diff: DEFAULT_CODE.to_string(),
                 postmessage: "Please describe the changes so a human can understand it".to_string(),
                 ..Default::default()
             },
         ];
-        return prompts;
+        prompts
+    }
+
+    /// The prompt used to write pull requests, the AI is asked for the title
+    /// on the first line so we can split it from the body
+    pub fn get_pr_prompt() -> AiPrompt {
+        AiPrompt {
+            postamble: "developer and were given a git diff file of a branch you want to merge:".to_string(),
+            postmessage: "Please write a pull request for these changes. Put a short title on the first line, then a description of what the developer did and why in markdown.".to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// The stricter prompt for the last try, when the normal one keeps giving vague messages
+    pub fn get_fallback_commit_prompt() -> AiPrompt {
+        AiPrompt {
+            postmessage: "Please write a specific commit message for this change. Start with a subject line under 72 characters that names what changed, not just 'update' or 'fix', then a blank line and a few sentences on what the developer did and why.".to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// The prompt used when a saved pull request draft is resumed after more commits,
+    /// only the new commits are in the diff
+    pub fn get_pr_update_prompt() -> AiPrompt {
+        AiPrompt {
+            postamble: "developer and were given a git diff of the commits added to a branch since its pull request description was written:".to_string(),
+            postmessage: "Please describe only these additional changes in a few markdown bullet points, without a title.".to_string(),
+            ..Default::default()
+        }
     }
 }
 
@@ -93,6 +142,25 @@ pub struct AiSettings {
     pub api_url: String,
     /// Options for OpenAI
     pub ai_options: AiOptions,
+    /// Azure OpenAI, for when `api_url` points at an Azure resource instead of OpenAI
+    #[serde(default)]
+    pub azure: AzureSettings,
+    /// Which AI service each repo's code may go to, the first rule matching the origin remote wins
+    #[serde(default)]
+    pub routing: Vec<RoutingRule>,
+    /// How rate limited and failed requests are tried again
+    #[serde(default)]
+    pub retry: RetrySettings,
+    /// The second AI service `--race` sends every prompt to
+    #[serde(default)]
+    pub race: Option<RaceProvider>,
+    /// How long a request to the AI may take before it is given up on, 0 for as long as it
+    /// takes.  Big diffs can keep it busy for over a minute - Defaults to 180
+    #[serde(default = "default_ai_timeout_seconds")]
+    pub timeout_seconds: u64,
+    /// How long connecting to the AI may take, 0 for no limit - Defaults to 10
+    #[serde(default = "default_connect_timeout_seconds")]
+    pub connect_timeout_seconds: u64,
 }
 
 impl Default for AiSettings {
@@ -101,9 +169,152 @@ impl Default for AiSettings {
             api_key: String::new(),
             api_url: String::new(),
             ai_options: AiOptions::default(),
+            azure: AzureSettings::default(),
+            routing: Vec::new(),
+            retry: RetrySettings::default(),
+            race: None,
+            timeout_seconds: default_ai_timeout_seconds(),
+            connect_timeout_seconds: default_connect_timeout_seconds(),
+        }
+    }
+}
+
+fn default_ai_timeout_seconds() -> u64 {
+    180
+}
+
+fn default_connect_timeout_seconds() -> u64 {
+    10
+}
+
+fn default_git_timeout_seconds() -> u64 {
+    30
+}
+
+/// Rate limits (429) and server errors (5xx) are tried again after a while, doubling the
+/// wait each time.  A `Retry-After` from the server wins over our own idea of the wait
+#[derive(Debug, Deserialize, Serialize, Clone, Copy)]
+#[serde(default)]
+pub struct RetrySettings {
+    /// How many times to try again, 0 to give up straight away - Defaults to 3
+    pub max_retries: u8,
+    /// The first wait, in milliseconds - Defaults to 1000
+    pub initial_backoff_ms: u64,
+    /// The longest wait, in milliseconds - Defaults to 30000
+    pub max_backoff_ms: u64,
+}
+
+impl Default for RetrySettings {
+    fn default() -> Self {
+        RetrySettings {
+            max_retries: 3,
+            initial_backoff_ms: 1000,
+            max_backoff_ms: 30_000,
         }
     }
 }
+
+/// The AI services a routing rule can send code to
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AiProvider {
+    /// OpenAI itself
+    Openai,
+    /// An Azure OpenAI resource, set up in `azure`
+    Azure,
+}
+
+/// A data policy, repos whose origin matches `remote` only ever go to `provider`
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RoutingRule {
+    /// `host/owner/repo` with `*` wildcards, `github.com/acme/*` is every repo acme owns
+    pub remote: String,
+    /// Where their code may go
+    pub provider: AiProvider,
+    /// The api url to use for them, empty keeps `api_url`
+    #[serde(default)]
+    pub api_url: String,
+    /// The api key to use for them, empty keeps `api_key`
+    #[serde(default)]
+    pub api_key: String,
+}
+
+/// The service raced against the usual one with `--race`, whichever answers first is used
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RaceProvider {
+    /// Which service it is, `azure` uses the deployment in `azure`
+    pub provider: AiProvider,
+    /// Its api url, empty keeps `api_url`
+    #[serde(default)]
+    pub api_url: String,
+    /// Its api key, empty keeps `api_key`
+    #[serde(default)]
+    pub api_key: String,
+}
+
+impl RoutingRule {
+    /// Whether the rule is for the repo at `location` (`host/owner/repo`), case doesn't matter
+    pub fn matches(&self, location: &str) -> bool {
+        glob_match(
+            &self.remote.to_ascii_lowercase(),
+            &location.to_ascii_lowercase(),
+        )
+    }
+}
+
+/// Whether `text` matches `pattern`, where `*` stands for any run of characters
+///
+/// # Arguments
+///
+/// * `pattern` - The pattern
+/// * `text` - The text
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // where the last `*` was and how much of the text it has eaten so far
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if p < pattern.len() && pattern[p] == text[t] {
+            p += 1;
+            t += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Azure OpenAI wants a deployment and an api version instead of a model name
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+#[allow(unused)]
+pub struct AzureSettings {
+    /// Talk to Azure instead of OpenAI - Defaults to false
+    pub enabled: bool,
+    /// The name of the deployment in your Azure resource
+    pub deployment: String,
+    /// The `api-version` query parameter
+    pub api_version: String,
+}
+
+impl Default for AzureSettings {
+    fn default() -> Self {
+        AzureSettings {
+            enabled: false,
+            deployment: String::new(),
+            api_version: "2023-05-15".to_string(),
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 #[allow(unused)]
 pub struct AiOptions {
@@ -155,6 +366,101 @@ pub struct AiOptions {
     pub auto_ai: bool,
     /// turn stocastic mode on
     pub stochastic: bool,
+    /// Milliseconds to wait between AI calls when a big commit is split into batches
+    #[serde(default = "default_rate_limit_ms")]
+    pub rate_limit_ms: u64,
+    /// Compress the diff before it goes in the prompt, saves tokens but the AI sees less
+    #[serde(default)]
+    pub compress_prompt: bool,
+    /// After generating, ask the AI whether the change needs the docs updated (costs an extra request)
+    #[serde(default)]
+    pub doc_drift_check: bool,
+    /// How many rounds of vague messages to put up with before switching to the fallback prompt, 0 turns the check off
+    #[serde(default = "default_quality_retries")]
+    pub quality_retries: u8,
+    /// A stronger model for the fallback attempt, empty uses the same one
+    #[serde(default)]
+    pub fallback_model: String,
+    /// Write commit messages in the Conventional Commits format
+    #[serde(default)]
+    pub conventional_commits: bool,
+    /// The language commit messages and pull requests are written in, a locale (`ja`,
+    /// `de_DE`) or a name (`German`).  The AI still reads the diff as it is - Defaults to
+    /// empty, English
+    #[serde(default)]
+    pub message_locale: String,
+    /// Start commit subjects with a gitmoji (https://gitmoji.dev), not together with
+    /// `conventional_commits` - Defaults to false
+    #[serde(default)]
+    pub gitmoji: bool,
+    /// The shape of commit messages, `subject-only`, `subject+body` or `bulleted-body`
+    /// with the body wrapped at 72 columns - Defaults to `paragraph`, as the AI writes it
+    #[serde(default)]
+    pub message_style: MessageStyle,
+    /// The model's context length in tokens, big diffs get trimmed to fit
+    #[serde(default = "default_context_window")]
+    pub context_window: usize,
+    /// The prompt pack in `~/.gitai/packs` to use, empty for none
+    #[serde(default)]
+    pub prompt_pack: String,
+    /// The prompt template in `.gitai/prompts` or `~/.gitai/prompts` to use, empty for none
+    #[serde(default)]
+    pub prompt_name: String,
+    /// What 1000 tokens cost in dollars, for `--dry-run` estimates.  0 uses the list price of known models
+    #[serde(default)]
+    pub price_per_1k_tokens: f64,
+    /// Ask before sending any request that could cost more than this many dollars - Defaults to 0, never ask
+    #[serde(default)]
+    pub confirm_above_cost: f64,
+    /// Keep what the AI answers in `~/.gitai/cache`, so asking the exact same thing again
+    /// is instant and free - Defaults to true
+    #[serde(default = "default_true")]
+    pub cache_responses: bool,
+    /// Show each option after the first as a word diff against the one before it
+    #[serde(default)]
+    pub reroll_diff: bool,
+    /// Before writing a commit message, have the AI check the change isn't several changes in one (costs an extra request)
+    #[serde(default)]
+    pub intent_check: bool,
+    /// How much of a change, in percent of the changed lines, can be about something else before it counts as mixed.  0 never warns
+    #[serde(default = "default_mixed_intent_threshold")]
+    pub mixed_intent_threshold: u8,
+    /// How long the commit hook waits for the AI in milliseconds before it writes the message
+    /// from the diff itself, 0 to wait as long as it takes - Defaults to 0
+    #[serde(default)]
+    pub generation_deadline_ms: u64,
+    /// Never call the AI, commit messages and pull requests are written in the editor and
+    /// everything else (staging, the commit policy, signing, pushing) works the same - Defaults to false
+    #[serde(default)]
+    pub manual: bool,
+    /// How many of the last commits to the same files the AI sees, so recurring changes get
+    /// consistent messages.  0 leaves them out - Defaults to 3
+    #[serde(default = "default_consistency_commits")]
+    pub consistency_commits: usize,
+    /// Send a tiny request as soon as an interactive commit or pr starts, so a cold model or a
+    /// bad key shows up before you have answered any questions - Defaults to false
+    #[serde(default)]
+    pub warm_up: bool,
+}
+
+fn default_rate_limit_ms() -> u64 {
+    1000
+}
+
+fn default_quality_retries() -> u8 {
+    2
+}
+
+fn default_mixed_intent_threshold() -> u8 {
+    30
+}
+
+fn default_consistency_commits() -> usize {
+    3
+}
+
+fn default_context_window() -> usize {
+    8000
 }
 
 /// Default implementation, the defaults here **EXCEPT** for prompt are pretty good.
@@ -162,7 +468,7 @@ pub struct AiOptions {
 impl Default for AiOptions {
     fn default() -> Self {
         AiOptions {
-            model: "code-davinci-00".to_string(),
+            model: "code-davinci-002".to_string(),
             max_tokens: 256,
             temperature: 0.05,
             top_p: 1.0,
@@ -176,11 +482,34 @@ impl Default for AiOptions {
             prompt: AiPrompt::default(),
             auto_ai: false,
             stochastic: false,
+            rate_limit_ms: default_rate_limit_ms(),
+            compress_prompt: false,
+            doc_drift_check: false,
+            quality_retries: default_quality_retries(),
+            fallback_model: String::new(),
+            conventional_commits: false,
+            message_locale: String::new(),
+            gitmoji: false,
+            message_style: MessageStyle::default(),
+            context_window: default_context_window(),
+            prompt_pack: String::new(),
+            prompt_name: String::new(),
+            price_per_1k_tokens: 0.0,
+            confirm_above_cost: 0.0,
+            cache_responses: true,
+            reroll_diff: false,
+            intent_check: false,
+            mixed_intent_threshold: default_mixed_intent_threshold(),
+            generation_deadline_ms: 0,
+            manual: false,
+            consistency_commits: default_consistency_commits(),
+            warm_up: false,
         }
     }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
 #[allow(unused)]
 pub struct AiPrompt {
     /// The preamble (everything before the language) for the prompt
@@ -191,22 +520,28 @@ pub struct AiPrompt {
     pub postamble: String,
     /// char that acts as a separator for the git diff, defaults to '='
     pub seperator: char,
-    /// the actual git diff to analyze, this defaults to a silly python script
+    /// the actual git diff to analyze, put in at runtime and never written to settings.json
+    #[serde(skip)]
     pub git_diff: String,
     /// anything after the git diff
     pub postmessage: String,
+    /// The preamble is all of it up to the diff, from a prompt template, so `language` and
+    /// `postamble` aren't added
+    #[serde(skip)]
+    pub templated: bool,
 }
 /// default implememtation of our prompt to send to OpenAi
-/// **NOTE** `language` amd `git_diff` should be changed from their default values
+/// **NOTE** `language` should be changed from its default value and `git_diff` filled in
 impl Default for AiPrompt {
     fn default() -> Self {
         AiPrompt {
             preamble: "Imagine you are an expert ".to_string(),
             language: "Python  ".to_string(),
             postamble: "developer and were given a git diff file to look at:".to_string(),
-            git_diff: DEFAULT_CODE.to_string(),
+            git_diff: String::new(),
             seperator: '=',
-            postmessage: "Please generate a good explanation of what the developer did. Limit yourself to one paragraph.".to_string()
+            postmessage: "Please generate a good explanation of what the developer did. Limit yourself to one paragraph.".to_string(),
+            templated: false,
         }
     }
 }
@@ -214,28 +549,100 @@ impl Default for AiPrompt {
 /// Display information for the prompt
 impl Display for AiPrompt {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.templated {
+            return write!(
+                f,
+                "{}\n{}\n{}\n{}\n{}",
+                self.preamble,
+                repeat_n(self.seperator, 16).collect::<String>(),
+                self.git_diff,
+                repeat_n(self.seperator, 16).collect::<String>(),
+                self.postmessage
+            );
+        }
         write!(
             f,
             "{} {} {}\n{}\n{}\n{}\n{}",
             self.preamble,
             self.language,
             self.postamble,
-            repeat(self.seperator).take(16).collect::<String>(),
+            repeat_n(self.seperator, 16).collect::<String>(),
             self.git_diff,
-            repeat(self.seperator).take(16).collect::<String>(),
+            repeat_n(self.seperator, 16).collect::<String>(),
             self.postmessage
         )
     }
 }
 
+/// The shape of a commit message, the prompt asks for it and the completion is wrapped into it
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum MessageStyle {
+    /// Whatever the AI writes, blank lines taken out
+    #[default]
+    #[serde(rename = "paragraph")]
+    #[value(name = "paragraph")]
+    Paragraph,
+    /// Just the subject line
+    #[serde(rename = "subject-only")]
+    #[value(name = "subject-only")]
+    SubjectOnly,
+    /// A subject, a blank line and a body of paragraphs
+    #[serde(rename = "subject+body")]
+    #[value(name = "subject+body")]
+    SubjectBody,
+    /// A subject, a blank line and a body of `- ` bullets
+    #[serde(rename = "bulleted-body")]
+    #[value(name = "bulleted-body")]
+    BulletedBody,
+}
+
+/// The hosting providers we know how to open pull requests on
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Provider {
+    /// github.com (or GitHub Enterprise)
+    #[default]
+    Github,
+    /// gitlab.com or a self-hosted GitLab
+    Gitlab,
+}
+
 /// Git Settings
 #[derive(Debug, Deserialize, Serialize, Clone)]
 #[allow(unused)]
 pub struct GitSettings {
     /// Github API Key - Only needed for PR
     pub github_api_key: String,
-    /// GitHub API url = Only needed for PR
+    /// GitHub API url = Only needed for PR.  For GitHub Enterprise Server it's
+    /// `https://<host>/api/v3`, the host on its own works too
     pub github_api_url: String,
+    /// A PEM file with the CA that signed your GitHub Enterprise server's certificate,
+    /// when it isn't one the system trusts - Defaults to none
+    #[serde(default)]
+    pub github_ca_cert: String,
+    /// GitLab API Key - Only needed for merge requests
+    #[serde(default)]
+    pub gitlab_api_key: String,
+    /// GitLab API url - Only needed for merge requests, point it at your own instance if self-hosted
+    #[serde(default = "default_gitlab_api_url")]
+    pub gitlab_api_url: String,
+    /// Where pull requests get created - Defaults to github
+    #[serde(default)]
+    pub provider: Provider,
+    /// Other GitHub identities (a work account, ...), picked by where `remote` points
+    #[serde(default)]
+    pub github_accounts: Vec<GitHubAccount>,
+    /// The remote whose repo pull requests go to, its url says which one it is - Defaults to origin.
+    /// Branches are pushed to their upstream when they have one
+    #[serde(default = "default_remote")]
+    pub remote: String,
+    /// How long a request to GitHub, GitLab, the ticket tracker or a sink may take, 0 for
+    /// as long as it takes - Defaults to 30
+    #[serde(default = "default_git_timeout_seconds")]
+    pub timeout_seconds: u64,
+    /// How long connecting to them may take, 0 for no limit - Defaults to 10
+    #[serde(default = "default_connect_timeout_seconds")]
+    pub connect_timeout_seconds: u64,
     /// Varioud Git Optionss
     pub git_options: GitOptions,
 }
@@ -245,11 +652,103 @@ impl Default for GitSettings {
         GitSettings {
             github_api_key: String::new(),
             github_api_url: String::new(),
+            github_ca_cert: String::new(),
+            gitlab_api_key: String::new(),
+            gitlab_api_url: default_gitlab_api_url(),
+            provider: Provider::default(),
+            github_accounts: Vec::new(),
+            remote: default_remote(),
+            timeout_seconds: default_git_timeout_seconds(),
+            connect_timeout_seconds: default_connect_timeout_seconds(),
             git_options: GitOptions::default(),
         }
     }
 }
 
+impl GitSettings {
+    /// The account for a repo hosted on `host` and owned by `owner`.  An account for
+    /// that exact owner wins over one for the whole host, `None` means use the
+    /// default `github_api_key`.
+    ///
+    /// # Arguments
+    ///
+    /// * `host` - Where the repo is hosted, `github.com` or a GitHub Enterprise host
+    /// * `owner` - The user or organization the repo belongs to
+    pub fn github_account_for(&self, host: &str, owner: &str) -> Option<&GitHubAccount> {
+        let on_host = |account: &&GitHubAccount| account.host.eq_ignore_ascii_case(host);
+        self.github_accounts
+            .iter()
+            .filter(on_host)
+            .find(|account| account.owners.iter().any(|o| o.eq_ignore_ascii_case(owner)))
+            .or_else(|| {
+                self.github_accounts
+                    .iter()
+                    .filter(on_host)
+                    .find(|account| account.owners.is_empty())
+            })
+    }
+
+    /// Switches the GitHub token (and url and CA) to the account for `host`/`owner`, if
+    /// there is one, and returns its name
+    pub fn use_github_account_for(&mut self, host: &str, owner: &str) -> Option<String> {
+        let account = self.github_account_for(host, owner)?.clone();
+        if let Some(url) = account.enterprise_api_url() {
+            self.github_api_url = url;
+        }
+        self.github_api_key = account.api_key;
+        if !account.ca_cert.is_empty() {
+            self.github_ca_cert = account.ca_cert;
+        }
+        Some(account.name)
+    }
+}
+
+/// A GitHub identity and the repos it is for
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[allow(unused)]
+pub struct GitHubAccount {
+    /// What to call it in messages, `work` or `personal`
+    pub name: String,
+    /// The host in the remote url - Defaults to github.com
+    #[serde(default = "default_github_host")]
+    pub host: String,
+    /// The users and organizations whose repos use this account, empty for every repo on the host
+    #[serde(default)]
+    pub owners: Vec<String>,
+    /// The token
+    pub api_key: String,
+    /// The API url, empty uses `https://<host>/api/v3` for a GitHub Enterprise host and
+    /// `github_api_url` for github.com
+    #[serde(default)]
+    pub api_url: String,
+    /// A PEM file with the CA the host's certificate is signed by, empty uses `github_ca_cert`
+    #[serde(default)]
+    pub ca_cert: String,
+}
+
+impl GitHubAccount {
+    /// The API url the account says to use, `None` for `github_api_url`
+    pub fn enterprise_api_url(&self) -> Option<String> {
+        match self.api_url.as_str() {
+            "" if self.host.eq_ignore_ascii_case("github.com") => None,
+            "" => Some(format!("https://{}/api/v3", self.host)),
+            url => Some(url.to_string()),
+        }
+    }
+}
+
+fn default_remote() -> String {
+    "origin".to_string()
+}
+
+fn default_github_host() -> String {
+    "github.com".to_string()
+}
+
+fn default_gitlab_api_url() -> String {
+    "https://gitlab.com/api/v4".to_string()
+}
+
 /// Options for Git/GitHub
 #[derive(Debug, Deserialize, Serialize, Clone)]
 #[allow(unused)]
@@ -260,9 +759,9 @@ pub struct GitOptions {
     pub auto_add: bool,
     /// Rung `git push origin <branch name>` before creating PR - Defaults to true
     pub auto_push: bool,
-    /// PGP sign your commits - Defaults to false
+    /// PGP sign your commits - Defaults to false, which leaves it to `commit.gpgsign` in git config
     pub sign_commits: bool,
-    /// PGP Key ID - Not needed unless `sign_commits = true`
+    /// PGP Key ID - Not needed unless commits are signed, empty uses `user.signingkey` from git config
     pub key_id: String,
     /// Git User Name - For commits
     pub git_user_name: String,
@@ -272,12 +771,366 @@ pub struct GitOptions {
     pub ssh_key_path: String,
     /// The ssh user name for the repo, I've never seen this be anything but git
     pub ssh_user_name: String,
+    /// Policy allow for running with both `auto_ai` and `auto_push` without passing `--yes` - Defaults to false
+    #[serde(default)]
+    pub allow_auto_ai_push: bool,
+    /// Offer to split the commit into per-directory commits when more files than this are staged
+    #[serde(default = "default_split_threshold")]
+    pub split_threshold: usize,
+    /// In Cargo/npm workspaces, add the packages a change affects to commits and pull requests - Defaults to true
+    #[serde(default = "default_true")]
+    pub affected_packages: bool,
+    /// The sections every pull request body gets, in order.  Empty lets the AI write whatever it likes
+    #[serde(default = "default_pr_sections")]
+    pub pr_sections: Vec<PrSection>,
+    /// Fill in the repo's pull request template (`.github/pull_request_template.md` and the
+    /// other places GitHub looks) instead of `pr_sections` when it has one - Defaults to true
+    #[serde(default = "default_true")]
+    pub pr_template: bool,
+    /// Add a mermaid diagram of the changed files to pull requests - Defaults to false
+    #[serde(default)]
+    pub pr_diagram: bool,
+    /// Add a commit by commit walkthrough to pull requests with more than one commit (costs an extra request) - Defaults to true
+    #[serde(default = "default_true")]
+    pub pr_walkthrough: bool,
+    /// Open pull requests as drafts - Defaults to false
+    #[serde(default)]
+    pub pr_draft: bool,
+    /// Who gets asked to review every pull request, by login
+    #[serde(default)]
+    pub pr_reviewers: Vec<String>,
+    /// The teams (their slugs) asked to review every pull request, GitHub only
+    #[serde(default)]
+    pub pr_team_reviewers: Vec<String>,
+    /// The labels every pull request gets
+    #[serde(default)]
+    pub pr_labels: Vec<String>,
+    /// Who every pull request is assigned to, by login
+    #[serde(default)]
+    pub pr_assignees: Vec<String>,
+    /// Have the AI look over outgoing commits in the pre-push hook too, not just the quick checks - Defaults to true
+    #[serde(default = "default_true")]
+    pub pre_push_ai_review: bool,
+    /// Who can read what `gitai commit --private` puts in a commit
+    #[serde(default)]
+    pub private_trailer: PrivateTrailer,
+    /// For repos mirrored somewhere public, where the detailed description of a commit goes.
+    /// Defaults to off, one message like always
+    #[serde(default)]
+    pub dual_description: DualDescription,
+    /// The notes ref internal descriptions go in, git doesn't push notes unless asked to
+    #[serde(default = "default_internal_notes_ref")]
+    pub internal_notes_ref: String,
+    /// Files the AI never sees the diff of, only that they changed.  Globs, `*` matches
+    /// anything and a pattern without a `/` matches the file name in any directory
+    #[serde(default = "default_diff_ignore")]
+    pub diff_ignore: Vec<String>,
+    /// What to do about `Fixes #12` in generated pull requests - Defaults to keep
+    #[serde(default)]
+    pub closing_keywords: ClosingKeywords,
+    /// `closing_keywords` for particular repos, the first rule matching the origin remote wins
+    #[serde(default)]
+    pub closing_keyword_rules: Vec<ClosingKeywordRule>,
+}
+
+/// What to do about closing keywords (`Fixes #12`), which close the issue when the pull
+/// request is merged
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ClosingKeywords {
+    /// Leave them the way the AI wrote them
+    #[default]
+    Keep,
+    /// Turn them into `Refs #12`, merging never closes an issue
+    Refs,
+    /// Turn `Refs #12` into `Fixes #12`, merging closes every issue it mentions
+    Fixes,
+}
+
+/// `closing_keywords` for the repos whose origin matches `remote`
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ClosingKeywordRule {
+    /// `host/owner/repo` with `*` wildcards, like a routing rule
+    pub remote: String,
+    /// What to do in those repos
+    pub keywords: ClosingKeywords,
+}
+
+impl ClosingKeywordRule {
+    /// Whether the rule is for the repo at `location` (`host/owner/repo`), case doesn't matter
+    pub fn matches(&self, location: &str) -> bool {
+        glob_match(
+            &self.remote.to_ascii_lowercase(),
+            &location.to_ascii_lowercase(),
+        )
+    }
+}
+
+/// Where the internal description of a commit goes when the message has to be safe to publish
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DualDescription {
+    /// One message, nothing held back
+    #[default]
+    Off,
+    /// A git note on the commit, in `internal_notes_ref`
+    Note,
+    /// A comment on the branch's ticket (see `tickets`), a git note when there isn't one
+    Tracker,
+}
+
+/// The tools a private trailer can be encrypted with
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum EncryptionTool {
+    /// `gpg`, recipients are key ids or emails
+    #[default]
+    Gpg,
+    /// `age`, recipients are `age1...` public keys
+    Age,
+}
+
+/// How the private trailer is encrypted
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct PrivateTrailer {
+    /// What encrypts it
+    #[serde(default)]
+    pub tool: EncryptionTool,
+    /// Who can decrypt it, the whole team usually
+    #[serde(default)]
+    pub recipients: Vec<String>,
+    /// The age identity file `gitai trailer decrypt` uses, gpg finds its own keys
+    #[serde(default)]
+    pub identity: String,
+}
+
+/// The house rules for commit messages.  Messages that break them go back to the AI to
+/// be revised, and one that still breaks them after `max_revisions` isn't committed.
+/// Everything is off by default.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct CommitPolicy {
+    /// The longest the subject line can be, 0 for no limit
+    #[serde(default)]
+    pub max_subject_length: usize,
+    /// The subject has to start with a verb in the imperative mood, `Add` not `Added`
+    #[serde(default)]
+    pub imperative_mood: bool,
+    /// A regex the message has to match somewhere, a ticket id like `[A-Z]+-\d+`.  Empty for none
+    #[serde(default)]
+    pub ticket_regex: String,
+    /// Words the message can't use, whatever their case
+    #[serde(default)]
+    pub forbidden_words: Vec<String>,
+    /// How many times to send a message back to the AI to fix it
+    #[serde(default = "default_max_revisions")]
+    pub max_revisions: u8,
+}
+
+impl Default for CommitPolicy {
+    fn default() -> Self {
+        CommitPolicy {
+            max_subject_length: 0,
+            imperative_mood: false,
+            ticket_regex: String::new(),
+            forbidden_words: Vec::new(),
+            max_revisions: default_max_revisions(),
+        }
+    }
+}
+
+fn default_max_revisions() -> u8 {
+    2
+}
+
+/// The issue trackers a ticket's title can come from
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TicketTracker {
+    /// Jira Cloud or Server, `api_url` is the site like `https://example.atlassian.net`
+    Jira,
+    /// Linear, only needs `api_token`
+    Linear,
+}
+
+/// How requests get out, for when there is a corporate proxy or TLS inspection in the way.
+/// It goes for the AI, GitHub, GitLab, the ticket trackers and the sinks alike.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct NetworkSettings {
+    /// The proxy for http urls, `http://proxy.acme.com:3128` - Defaults to `HTTP_PROXY`
+    pub http_proxy: String,
+    /// The proxy for https urls - Defaults to `HTTPS_PROXY`
+    pub https_proxy: String,
+    /// Hosts that skip the proxies, comma separated like `NO_PROXY`
+    pub no_proxy: String,
+    /// A PEM file of extra CAs to trust, the one your proxy re-signs certificates with
+    pub ca_bundle_path: String,
+    /// Trust any certificate at all.  Only for trying things out, anyone in the middle can
+    /// read your tokens - Defaults to false
+    pub danger_accept_invalid_certs: bool,
+}
+
+/// Pulls the ticket id out of the branch name so the AI knows what the change is for and
+/// the message starts with it.  Off until `branch_regex` is set
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct TicketSettings {
+    /// The ticket id in a branch name, something like `PROJ-\d+`.  Empty turns tickets off
+    #[serde(default)]
+    pub branch_regex: String,
+    /// Where to look the ticket's title up, none to just use the id
+    #[serde(default)]
+    pub tracker: Option<TicketTracker>,
+    /// The tracker's URL, only Jira needs it
+    #[serde(default)]
+    pub api_url: String,
+    /// The Jira account email, empty to send `api_token` as a bearer token
+    #[serde(default)]
+    pub user: String,
+    /// The tracker's API token
+    #[serde(default)]
+    pub api_token: String,
+    /// Start the commit message with the ticket id
+    #[serde(default = "default_true")]
+    pub prefix_message: bool,
+}
+
+impl Default for TicketSettings {
+    fn default() -> Self {
+        TicketSettings {
+            branch_regex: String::new(),
+            tracker: None,
+            api_url: String::new(),
+            user: String::new(),
+            api_token: String::new(),
+            prefix_message: true,
+        }
+    }
+}
+
+/// Somewhere a generated changelog can go, told apart by its `type`
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Sink {
+    /// Printed, like without `--sink`
+    Stdout,
+    /// A file, relative to the repo unless it starts with `/` or `~`
+    File {
+        path: String,
+        /// Put it at the top, under the file's title, instead of replacing the file
+        #[serde(default)]
+        prepend: bool,
+    },
+    /// The notes of a GitHub release, made if it isn't there yet.  The repo and token are
+    /// the ones pull requests use
+    GithubRelease {
+        /// The release's tag, empty uses the release name
+        #[serde(default)]
+        tag: String,
+        /// Make new releases as drafts
+        #[serde(default)]
+        draft: bool,
+    },
+    /// A Confluence page, through its REST API
+    Confluence {
+        /// The wiki's url, `https://acme.atlassian.net/wiki`
+        url: String,
+        /// The page's id, the number in its url
+        page_id: String,
+        /// The account email, empty to send `api_token` as a bearer token
+        #[serde(default)]
+        user: String,
+        /// The API token
+        api_token: String,
+        /// Replace what's on the page instead of adding to the top of it
+        #[serde(default)]
+        replace: bool,
+    },
+    /// A JSON POST with `kind`, `title` and `text` (which is all Slack wants)
+    Webhook {
+        url: String,
+        /// Headers to send along, for a token
+        #[serde(default)]
+        headers: BTreeMap<String, String>,
+    },
+}
+
+/// One section of a pull request body
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[allow(unused)]
+pub struct PrSection {
+    /// The heading
+    pub title: String,
+    /// What to ask the AI to put in it
+    #[serde(default)]
+    pub instructions: String,
+    /// Put in as is instead of being generated, for things only a human can fill in
+    #[serde(default)]
+    pub placeholder: Option<String>,
+    /// Wrap the section in `<details>` so it starts out collapsed
+    #[serde(default)]
+    pub collapsed: bool,
+    /// Add the commands that run the project's tests (from its Cargo.toml, package.json
+    /// and Makefile) instead of letting the AI make some up
+    #[serde(default)]
+    pub commands: bool,
+}
+
+fn default_pr_sections() -> Vec<PrSection> {
+    let section = |title: &str, instructions: &str, collapsed: bool| PrSection {
+        title: title.to_string(),
+        instructions: instructions.to_string(),
+        placeholder: None,
+        collapsed,
+        commands: false,
+    };
+    vec![
+        section(
+            "Summary",
+            "A few sentences on what this pull request does and why",
+            false,
+        ),
+        section("Changes", "A bullet point for each notable change", false),
+        PrSection {
+            placeholder: Some(
+                "_Add screenshots here if this changes anything visible._".to_string(),
+            ),
+            ..section("Screenshots", "", true)
+        },
+        PrSection {
+            commands: true,
+            ..section("Testing", "How the changes were or should be tested", false)
+        },
+        section(
+            "Rollback plan",
+            "How to safely undo this change if something goes wrong",
+            true,
+        ),
+    ]
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_internal_notes_ref() -> String {
+    "refs/notes/internal".to_string()
+}
+
+fn default_diff_ignore() -> Vec<String> {
+    ["Cargo.lock", "package-lock.json", "*.min.js", "dist/**"]
+        .iter()
+        .map(|pattern| pattern.to_string())
+        .collect()
+}
+
+fn default_split_threshold() -> usize {
+    100
 }
 
 impl Default for GitOptions {
     fn default() -> Self {
         GitOptions {
-            local_path: PathBuf::from_str(".").expect("Unable to create PathBuf"),
+            local_path: PathBuf::from("."),
             auto_add: false,
             auto_push: true,
             sign_commits: false,
@@ -286,61 +1139,991 @@ impl Default for GitOptions {
             git_user_email: String::new(),
             ssh_key_path: String::new(),
             ssh_user_name: String::new(),
+            allow_auto_ai_push: false,
+            split_threshold: default_split_threshold(),
+            affected_packages: true,
+            pr_sections: default_pr_sections(),
+            pr_template: true,
+            pr_diagram: false,
+            pr_walkthrough: true,
+            pr_draft: false,
+            pr_reviewers: Vec::new(),
+            pr_team_reviewers: Vec::new(),
+            pr_labels: Vec::new(),
+            pr_assignees: Vec::new(),
+            pre_push_ai_review: true,
+            private_trailer: PrivateTrailer::default(),
+            dual_description: DualDescription::default(),
+            internal_notes_ref: default_internal_notes_ref(),
+            diff_ignore: default_diff_ignore(),
+            closing_keywords: ClosingKeywords::default(),
+            closing_keyword_rules: Vec::new(),
+        }
+    }
+}
+
+/// The directory gitai keeps its files in, `$HOME/.gitai` (or `./.gitai` without a `$HOME`)
+pub fn gitai_home() -> PathBuf {
+    let mut p: PathBuf = home_dir().unwrap_or_else(|| PathBuf::from("."));
+    p.push(".gitai");
+    p
+}
+
+/// `~/` at the start of a path is the home directory, the way git and ssh read it
+///
+/// # Arguments
+///
+/// * `path` - The path, from settings.json or git config
+pub fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// Where the settings live, `$HOME/.gitai/settings.json`
+pub fn settings_path() -> PathBuf {
+    let mut p = gitai_home();
+    p.push("settings.json");
+    p
+}
+
+/// The settings a repository can commit for everyone working on it, the first one there wins
+pub const REPO_SETTINGS_FILES: [&str; 2] = [".gitai.toml", ".gitai/settings.json"];
+
+/// What a repository's settings can change, the team's model and conventions, prompt
+/// wording and who pull requests go to.  Anything else is yours alone: tokens and where diffs get
+/// sent (a repo you just cloned shouldn't get to send your code and keys somewhere
+/// else), your name and keys, what gets committed and pushed without asking.
+const TEAM_SETTINGS: [&str; 21] = [
+    "/ai_settings/ai_options/model",
+    "/ai_settings/ai_options/prompt",
+    "/ai_settings/ai_options/prompt_name",
+    "/ai_settings/ai_options/conventional_commits",
+    "/ai_settings/ai_options/message_locale",
+    "/ai_settings/ai_options/gitmoji",
+    "/ai_settings/ai_options/message_style",
+    "/git_settings/git_options/diff_ignore",
+    "/git_settings/git_options/pr_sections",
+    "/git_settings/git_options/pr_template",
+    "/git_settings/git_options/pr_diagram",
+    "/git_settings/git_options/pr_walkthrough",
+    "/git_settings/git_options/pr_reviewers",
+    "/git_settings/git_options/pr_team_reviewers",
+    "/git_settings/git_options/pr_labels",
+    "/git_settings/git_options/pr_assignees",
+    "/git_settings/git_options/closing_keywords",
+    "/git_settings/git_options/closing_keyword_rules",
+    "/commit_policy",
+    "/tickets/branch_regex",
+    "/tickets/prefix_message",
+];
+
+/// Takes everything not in `TEAM_SETTINGS` out of a repository's settings and returns
+/// where each of them was, as `ai_settings.api_key`
+///
+/// # Arguments
+///
+/// * `value` - The repository's settings, or a table in them
+/// * `pointer` - Where `value` is in them, "" at the top
+fn keep_team_settings(value: &mut serde_json::Value, pointer: &str) -> Vec<String> {
+    let table = match value {
+        serde_json::Value::Object(table) => table,
+        _ => return Vec::new(),
+    };
+    let mut dropped = Vec::new();
+    let keys: Vec<String> = table.keys().cloned().collect();
+    for key in keys {
+        let child = format!("{}/{}", pointer, key);
+        if TEAM_SETTINGS.contains(&child.as_str()) {
+            continue;
+        }
+        let holds_team_settings = TEAM_SETTINGS
+            .iter()
+            .any(|team| team.starts_with(&format!("{}/", child)));
+        match table.get_mut(&key) {
+            Some(inner @ serde_json::Value::Object(_)) if holds_team_settings => {
+                dropped.extend(keep_team_settings(inner, &child));
+            }
+            _ => {
+                table.remove(&key);
+                dropped.push(child.trim_start_matches('/').replace('/', "."));
+            }
+        }
+    }
+    dropped
+}
+
+/// The repository's settings file, if the repository `dir` is in has one
+///
+/// # Arguments
+///
+/// * `dir` - Anywhere in the repository
+pub fn repo_settings_path(dir: &Path) -> Option<PathBuf> {
+    let repo = git2::Repository::discover(dir).ok()?;
+    let root = repo.workdir()?;
+    REPO_SETTINGS_FILES
+        .iter()
+        .map(|name| root.join(name))
+        .find(|path| path.is_file())
+}
+
+/// A repository's settings as JSON for the settings to be layered with, only what is in
+/// `TEAM_SETTINGS`
+///
+/// # Arguments
+///
+/// * `path` - `.gitai.toml` or `.gitai/settings.json`
+fn repo_overrides(path: &Path) -> crate::error::Result<String> {
+    let text = fs::read_to_string(path)?;
+    let parsed = if path.extension().is_some_and(|ext| ext == "toml") {
+        toml::from_str::<serde_json::Value>(&text).map_err(|e| e.to_string())
+    } else {
+        serde_json::from_str::<serde_json::Value>(&text).map_err(|e| e.to_string())
+    };
+    let mut overrides = parsed
+        .map_err(|e| ConfigError::Message(format!("{} isn't valid: {}", path.display(), e)))?;
+    for key in keep_team_settings(&mut overrides, "") {
+        warn!(
+            "{} can't set {}, only your own settings can",
+            path.display(),
+            key
+        );
+    }
+    debug!("Layering {} over your settings", path.display());
+    Ok(overrides.to_string())
+}
+
+/// Where a broken `settings.json` is copied before anything else happens to it,
+/// `$HOME/.gitai/settings.json.bak`
+pub fn settings_backup_path() -> PathBuf {
+    let mut p = gitai_home();
+    p.push("settings.json.bak");
+    p
+}
+
+/// What is wrong with the text of a `settings.json` and where, `None` if nothing is
+///
+/// # Arguments
+///
+/// * `text` - The file
+pub fn settings_problem(text: &str) -> Option<String> {
+    // bad JSON first, a wrong type or a missing field is only worth mentioning in good JSON
+    let error = match serde_json::from_str::<serde_json::Value>(text) {
+        Err(e) => e,
+        Ok(_) => serde_json::from_str::<Settings>(text).err()?,
+    };
+    // serde puts the position at the end of the message, it goes at the front here
+    let message = error.to_string();
+    let message = message
+        .rsplit_once(" at line ")
+        .map_or(message.as_str(), |(message, _)| message);
+    // a settings.json on one line can be thousands of characters, show around the spot
+    let line: Vec<char> = text
+        .lines()
+        .nth(error.line().saturating_sub(1))
+        .unwrap_or("")
+        .chars()
+        .collect();
+    let column = error.column().saturating_sub(1).min(line.len());
+    let start = column.saturating_sub(40);
+    let end = (column + 20).min(line.len());
+    let excerpt: String = line[start..end].iter().collect();
+    Some(format!(
+        "line {}, column {}: {}\n    {}\n    {}^",
+        error.line(),
+        error.column(),
+        message,
+        excerpt,
+        " ".repeat(column - start)
+    ))
+}
+
+/// Takes what older versions wrote to `settings.json` and nothing reads anymore out of
+/// it, the sample diff every prompt carried.  A file that doesn't parse is left alone,
+/// `load` has to say what is wrong with it.  Returns whether the file was rewritten.
+///
+/// # Arguments
+///
+/// * `path` - `settings.json`
+fn migrate(path: &Path) -> crate::error::Result<bool> {
+    let text = fs::read_to_string(path)?;
+    let mut value = match serde_json::from_str::<serde_json::Value>(&text) {
+        Ok(value) => value,
+        Err(_) => return Ok(false),
+    };
+    let mut migrated = false;
+    if let Some(serde_json::Value::Array(prompts)) = value.get_mut("prompts") {
+        for prompt in prompts.iter_mut() {
+            if let serde_json::Value::Object(prompt) = prompt {
+                migrated |= prompt.remove("git_diff").is_some();
+            }
         }
     }
+    if migrated {
+        fs::write(path, serde_json::to_string_pretty(&value)?)?;
+        debug!("Took the sample diffs out of {}", path.display());
+    }
+    Ok(migrated)
 }
 
 impl Settings {
-    pub fn new() -> Result<Self, ConfigError> {
-        let mut p: PathBuf = PathBuf::from(home_dir().expect("There is no $HOME set"));
-        p.push(".gitai");
-        p.push("settings.json");
-        let output_path = p.as_os_str();
-        let s = match Config::builder()
-            // Start off by merging in the "default" configuration file
-            .add_source(File::with_name(output_path.to_str().unwrap()).required(true))
-            // Add in settings from the environment (with a prefix of APP)
-            // Eg.. `APP_DEBUG=1 ./target/app` would set the `debug` key
-            .add_source(
-                Environment::with_prefix("gitai")
-                    .try_parsing(true)
-                    .separator("_")
-                    .list_separator(" "),
-            )
-            // You may also programmatically change settings
-            .build()
-        {
-            Ok(c) => c,
-            Err(e) => {
-                log::error!(
-                    "There was an error getting the config file {:#?} - {}\nReturning default",
-                    output_path,
-                    e
-                );
-                let default_settings = Settings::default();
-                let file = OpenOptions::new()
-                    .create(true)
-                    .write(true)
-                    .append(true)
-                    .open(output_path)
-                    .unwrap();
-                serde_json::to_writer_pretty(file, &default_settings).unwrap();
-                return Ok(default_settings);
+    /// Loads `~/.gitai/settings.json`, writing the defaults there if there isn't one and
+    /// bringing one an older version wrote up to date.  A file that is there but broken is
+    /// never touched, it is copied to `settings.json.bak` and
+    /// the error says exactly where it went wrong.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the defaults can't be written or the file doesn't hold valid settings
+    pub fn new() -> crate::error::Result<Self> {
+        Self::load(&settings_path(), None, true)
+    }
+
+    /// Only what `~/.gitai/settings.json` says, without the repository's settings or the
+    /// environment over it.  Whatever writes the settings back starts from this, so a
+    /// token from a variable or a team's conventions never end up in the file.
+    ///
+    /// # Errors
+    ///
+    /// Fails like `new`
+    pub fn from_file() -> crate::error::Result<Self> {
+        Self::load(&settings_path(), None, false)
+    }
+
+    /// Your settings with the repository's `.gitai.toml` (or `.gitai/settings.json`) over
+    /// them, so a team can commit its model, language, commit style and ignore globs.
+    /// Tokens, urls and anything else personal are only ever taken from your own file.
+    ///
+    /// # Arguments
+    ///
+    /// * `dir` - Anywhere in the repository
+    ///
+    /// # Errors
+    ///
+    /// Fails like `new`, or if the repository's file doesn't hold valid settings
+    pub fn for_repo(dir: &Path) -> crate::error::Result<Self> {
+        match repo_settings_path(dir) {
+            Some(path) => {
+                let overrides = repo_overrides(&path)?;
+                match Self::load(&settings_path(), Some(&overrides), true) {
+                    Ok(settings) => Ok(settings),
+                    // settings.json gets the blame (and the backup) only if it is broken too
+                    Err(e) => match Self::new() {
+                        Ok(_) => Err(ConfigError::Message(format!(
+                            "{} has settings gitai can't use: {}",
+                            path.display(),
+                            match e {
+                                crate::error::GitAiError::Settings(e) => e.to_string(),
+                                e => e.to_string(),
+                            }
+                        ))
+                        .into()),
+                        Err(e) => Err(e),
+                    },
+                }
+            }
+            None => Self::new(),
+        }
+    }
+
+    /// `settings.json`, with the repository's settings over it when there are some
+    ///
+    /// # Arguments
+    ///
+    /// * `output_path` - `settings.json`
+    /// * `overrides` - The repository's settings, as JSON
+    /// * `with_environment` - Whether the environment goes over the files
+    fn load(
+        output_path: &Path,
+        overrides: Option<&str>,
+        with_environment: bool,
+    ) -> crate::error::Result<Self> {
+        if !output_path.exists() {
+            debug!(
+                "No settings at {}, writing the defaults",
+                output_path.display()
+            );
+            let default_settings = Settings::default();
+            if let Some(dir) = output_path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            fs::write(
+                output_path,
+                serde_json::to_string_pretty(&default_settings)?,
+            )?;
+            if overrides.is_none() {
+                return Ok(if with_environment {
+                    default_settings.with_env()
+                } else {
+                    default_settings
+                });
             }
+        } else if let Err(e) = migrate(output_path) {
+            warn!(
+                "Unable to bring {} up to date: {}",
+                output_path.display(),
+                e
+            );
+        }
+        let broken = |e: ConfigError| -> crate::error::GitAiError {
+            if overrides.is_some() {
+                return e.into();
+            }
+            let detail = fs::read_to_string(output_path)
+                .ok()
+                .and_then(|text| settings_problem(&text))
+                .unwrap_or_else(|| e.to_string());
+            let backup = settings_backup_path();
+            let kept = match fs::copy(output_path, &backup) {
+                Ok(_) => format!(", a copy of it is in {}", backup.display()),
+                Err(copy_error) => {
+                    log::warn!(
+                        "Unable to back up {}: {}",
+                        output_path.display(),
+                        copy_error
+                    );
+                    String::new()
+                }
+            };
+            ConfigError::Message(format!(
+                "{} is broken{}\n{}",
+                output_path.display(),
+                kept,
+                detail
+            ))
+            .into()
         };
-        s.try_deserialize()
-    }
-}
-
-const DEFAULT_CODE: &str = "
-diff --git a/foo.py b/foo.py\n
-new file mode 100644\n
-index 0000000..e5a8e79\n
---- /dev/null\n
-+++ b/foo.py\n
-@@ -0,0 +1,5 @@\n
-+def say_hi(name: str) -> str:\n
-+    print(f'Hi {name}')\n
-+\n
-+if __name__ == 'main':\n
-";
+        let settings = layered(
+            File::with_name(&output_path.to_string_lossy()).required(true),
+            overrides,
+            with_environment.then(environment),
+        )
+        .map_err(broken)?;
+        Ok(if with_environment {
+            settings.with_env()
+        } else {
+            settings
+        })
+    }
+
+    /// `ENV_VARS` win over the files (and the keyring, see `with_tokens`), and
+    /// `OPENAI_API_KEY` is used when there is no api key in the files
+    fn with_env(self) -> Self {
+        self.with_env_from(|name| env::var(name).ok())
+    }
+
+    /// `with_env`, the variables looked up with `lookup`
+    fn with_env_from(mut self, lookup: impl Fn(&str) -> Option<String>) -> Self {
+        for name in ENV_VARS {
+            let value = match lookup(name) {
+                Some(value) if !value.trim().is_empty() => value.trim().to_string(),
+                _ => continue,
+            };
+            if let Some(slot) = env_slot(name, &mut self) {
+                debug!("Using {} from the environment", name);
+                let file = std::mem::replace(slot, value);
+                self.from_env.insert(name, file);
+            }
+        }
+        if self.ai_settings.api_key.is_empty() {
+            if let Some(key) = lookup(OPENAI_API_KEY).map(|key| key.trim().to_string()) {
+                if !key.is_empty() {
+                    debug!("Using {} from the environment", OPENAI_API_KEY);
+                    self.ai_settings.api_key = key;
+                    self.from_env.insert(OPENAI_API_KEY, String::new());
+                }
+            }
+        }
+        self
+    }
+
+    /// The tokens in the OS keyring, over the ones in the files but not the ones from
+    /// `GITAI_AI_API_KEY` and the like.  Loading the settings leaves the keyring alone,
+    /// asking it starts a program (and can have macOS ask to unlock the Keychain), so only
+    /// what actually sends a token calls this.
+    pub fn with_tokens(self) -> Self {
+        self.with_tokens_from(keyring_get)
+    }
+
+    /// `with_tokens`, the tokens looked up with `lookup`
+    fn with_tokens_from(mut self, lookup: impl Fn(Secret) -> Option<String>) -> Self {
+        for secret in Secret::ALL {
+            // OPENAI_API_KEY is only the fallback, the keyring beats it
+            let from_env = secret
+                .env_vars()
+                .iter()
+                .any(|name| *name != OPENAI_API_KEY && self.from_env.contains_key(name));
+            if from_env {
+                continue;
+            }
+            if let Some(value) = lookup(secret) {
+                debug!("Using the {} token from the keyring", secret.account());
+                if secret == Secret::Openai {
+                    self.from_env.remove(OPENAI_API_KEY);
+                }
+                *secret.slot(&mut self) = value;
+            }
+        }
+        self
+    }
+
+    /// Puts a token in the keyring and takes it out of `settings.json`.  The rest of the
+    /// settings are saved too, so they should be the ones from `from_file`.
+    ///
+    /// # Arguments
+    ///
+    /// * `secret` - Which token
+    /// * `value` - The token
+    pub fn store_secret(&mut self, secret: Secret, value: &str) -> crate::error::Result<PathBuf> {
+        keyring_set(secret, value)?;
+        *secret.slot(self) = value.to_string();
+        self.save()
+    }
+
+    /// Writes the settings to `~/.gitai/settings.json`, replacing what was there.
+    /// Tokens that are in the keyring are left out, that's the point of putting them there,
+    /// and what an environment variable set goes back to what the file said.
+    pub fn save(&self) -> crate::error::Result<PathBuf> {
+        let path = settings_path();
+        self.save_to(&path)?;
+        Ok(path)
+    }
+
+    /// `save`, to `path` instead of `~/.gitai/settings.json`
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Where to write them
+    pub fn save_to(&self, path: &Path) -> crate::error::Result<()> {
+        let mut settings = self.clone();
+        for (name, file) in &self.from_env {
+            if let Some(slot) = env_slot(name, &mut settings) {
+                // unless it was changed since, then it is what to save
+                if env::var(name).is_ok_and(|value| value.trim() == slot.as_str()) {
+                    *slot = file.to_string();
+                }
+            }
+        }
+        for secret in Secret::ALL {
+            let slot = secret.slot(&mut settings);
+            if keyring_get(secret).as_ref() == Some(slot) {
+                slot.clear();
+            }
+        }
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(&settings)?)?;
+        Ok(())
+    }
+}
+
+/// Any setting from the environment, a double underscore between the keys since they
+/// have single ones in them, `GITAI__AI_SETTINGS__AI_OPTIONS__MODEL=gpt-4`
+fn environment() -> Environment {
+    Environment::with_prefix("gitai")
+        .prefix_separator("__")
+        .separator("__")
+        .try_parsing(true)
+}
+
+/// The settings from `file`, with the repository's `overrides` over them and the
+/// `environment` over both
+fn layered(
+    file: impl Source + Send + Sync + 'static,
+    overrides: Option<&str>,
+    environment: Option<Environment>,
+) -> Result<Settings, ConfigError> {
+    let mut builder = Config::builder().add_source(file);
+    if let Some(overrides) = overrides {
+        builder = builder.add_source(File::from_str(overrides, FileFormat::Json));
+    }
+    if let Some(environment) = environment {
+        builder = builder.add_source(environment);
+    }
+    builder.build()?.try_deserialize()
+}
+
+/// The environment variables that go over a setting.  They win over `settings.json`, the
+/// repository's settings and the keyring, the flags win over them.
+pub const ENV_VARS: [&str; 6] = [
+    "GITAI_AI_API_KEY",
+    "GITAI_AI_API_URL",
+    "GITAI_GITHUB_TOKEN",
+    "GITAI_GITHUB_API_URL",
+    "GITAI_GITLAB_TOKEN",
+    "GITAI_GITLAB_API_URL",
+];
+
+/// The variable OpenAI's own tools read the key from, only used when no api key is set
+/// anywhere else
+pub const OPENAI_API_KEY: &str = "OPENAI_API_KEY";
+
+/// The setting the environment variable `name` goes over
+fn env_slot<'a>(name: &str, settings: &'a mut Settings) -> Option<&'a mut String> {
+    match name {
+        "GITAI_AI_API_KEY" | OPENAI_API_KEY => Some(&mut settings.ai_settings.api_key),
+        "GITAI_AI_API_URL" => Some(&mut settings.ai_settings.api_url),
+        "GITAI_GITHUB_TOKEN" => Some(&mut settings.git_settings.github_api_key),
+        "GITAI_GITHUB_API_URL" => Some(&mut settings.git_settings.github_api_url),
+        "GITAI_GITLAB_TOKEN" => Some(&mut settings.git_settings.gitlab_api_key),
+        "GITAI_GITLAB_API_URL" => Some(&mut settings.git_settings.gitlab_api_url),
+        _ => None,
+    }
+}
+
+/// The service name gitai's tokens are stored under in the keyring
+const KEYRING_SERVICE: &str = "gitai";
+
+/// The tokens that can live in the OS keyring (macOS Keychain, the Secret Service
+/// on Linux, the Windows Credential Manager) instead of `settings.json`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Secret {
+    /// The OpenAI (or Azure OpenAI) api key
+    Openai,
+    /// The GitHub token
+    Github,
+    /// The GitLab token
+    Gitlab,
+}
+
+impl Secret {
+    /// Every secret, in the order they are shown
+    pub const ALL: [Secret; 3] = [Secret::Openai, Secret::Github, Secret::Gitlab];
+
+    /// The account name in the keyring
+    pub fn account(&self) -> &'static str {
+        match self {
+            Secret::Openai => "openai",
+            Secret::Github => "github",
+            Secret::Gitlab => "gitlab",
+        }
+    }
+
+    /// The environment variables the secret can come from, the first one set wins
+    pub fn env_vars(&self) -> &'static [&'static str] {
+        match self {
+            Secret::Openai => &["GITAI_AI_API_KEY", OPENAI_API_KEY],
+            Secret::Github => &["GITAI_GITHUB_TOKEN"],
+            Secret::Gitlab => &["GITAI_GITLAB_TOKEN"],
+        }
+    }
+
+    /// Where the secret goes in the settings
+    fn slot<'a>(&self, settings: &'a mut Settings) -> &'a mut String {
+        match self {
+            Secret::Openai => &mut settings.ai_settings.api_key,
+            Secret::Github => &mut settings.git_settings.github_api_key,
+            Secret::Gitlab => &mut settings.git_settings.gitlab_api_key,
+        }
+    }
+}
+
+/// What to do to a keyring entry
+#[derive(Debug, Clone, Copy)]
+enum KeyringOp {
+    Get,
+    Set,
+    Delete,
+}
+
+/// The command that does `op` to the `account` entry and what goes in on its stdin,
+/// there's no keyring library that works everywhere so we use the tools each OS ships
+/// with.  A secret never goes on the command line, anyone on the machine can read that
+/// with `ps`, so for `Set` it goes in on stdin.  `security` only takes it as an argument,
+/// so on macOS the whole command goes in on stdin to `security -i` instead.
+fn keyring_command(op: KeyringOp, account: &str, value: Option<&str>) -> (Command, Option<String>) {
+    let input = value.map(str::to_string);
+    if cfg!(target_os = "macos") {
+        let mut command = Command::new("security");
+        if let KeyringOp::Set = op {
+            command.arg("-i");
+            let line = format!(
+                "add-generic-password -U -s {} -a {} -w \"{}\"\n",
+                KEYRING_SERVICE,
+                account,
+                value
+                    .unwrap_or("")
+                    .replace('\\', "\\\\")
+                    .replace('"', "\\\"")
+            );
+            return (command, Some(line));
+        }
+        match op {
+            KeyringOp::Get => command.args(["find-generic-password", "-w"]),
+            _ => command.args(["delete-generic-password"]),
+        };
+        command.args(["-s", KEYRING_SERVICE, "-a", account]);
+        (command, input)
+    } else if cfg!(windows) {
+        let vault = "[void][Windows.Security.Credentials.PasswordVault,Windows.Security.Credentials,ContentType=WindowsRuntime];$v=New-Object Windows.Security.Credentials.PasswordVault;";
+        let script = match op {
+            KeyringOp::Get => format!(
+                "$c=$v.Retrieve('{}','{}');$c.RetrievePassword();$c.Password",
+                KEYRING_SERVICE, account
+            ),
+            KeyringOp::Set => format!(
+                "$v.Add((New-Object Windows.Security.Credentials.PasswordCredential('{}','{}',[Console]::In.ReadToEnd())))",
+                KEYRING_SERVICE, account
+            ),
+            KeyringOp::Delete => format!(
+                "$v.Remove($v.Retrieve('{}','{}'))",
+                KEYRING_SERVICE, account
+            ),
+        };
+        let mut command = Command::new("powershell");
+        command.args(["-NoProfile", "-NonInteractive", "-Command"]);
+        command.arg(format!("{}{}", vault, script));
+        (command, input)
+    } else {
+        let mut command = Command::new("secret-tool");
+        match op {
+            KeyringOp::Get => command.arg("lookup"),
+            KeyringOp::Set => command.args(["store", "--label", &format!("gitai {}", account)]),
+            KeyringOp::Delete => command.arg("clear"),
+        };
+        command.args(["service", KEYRING_SERVICE, "account", account]);
+        (command, input)
+    }
+}
+
+/// Runs a keyring command, `None` if it failed or there's no keyring tool at all
+fn run_keyring(op: KeyringOp, account: &str, value: Option<&str>) -> Option<String> {
+    let (mut command, input) = keyring_command(op, account, value);
+    command
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null());
+    let mut child = command.spawn().ok()?;
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        stdin.write_all(input.as_bytes()).ok()?;
+    }
+    let output = child.wait_with_output().ok()?;
+    if !output.status.success() {
+        debug!("The keyring said no to {:?} {}", op, account);
+        return None;
+    }
+    Some(
+        String::from_utf8_lossy(&output.stdout)
+            .trim_end_matches(['\r', '\n'])
+            .to_string(),
+    )
+}
+
+/// What the keyring said for each secret, it is only asked once a run
+static KEYRING: Mutex<BTreeMap<&'static str, Option<String>>> = Mutex::new(BTreeMap::new());
+
+/// The secret from the keyring, `None` if it isn't there or there's no keyring
+pub fn keyring_get(secret: Secret) -> Option<String> {
+    let mut known = KEYRING.lock().unwrap_or_else(|e| e.into_inner());
+    known
+        .entry(secret.account())
+        .or_insert_with(|| {
+            run_keyring(KeyringOp::Get, secret.account(), None).filter(|value| !value.is_empty())
+        })
+        .clone()
+}
+
+/// Puts the secret in the keyring, replacing what was there
+pub fn keyring_set(secret: Secret, value: &str) -> crate::error::Result<()> {
+    // `security -i` reads a line at a time
+    if value.contains(['\r', '\n']) {
+        return Err(crate::error::GitAiError::Usage(format!(
+            "The {} token has a line break in it, that can't be right",
+            secret.account()
+        )));
+    }
+    run_keyring(KeyringOp::Set, secret.account(), Some(value)).ok_or_else(|| {
+        crate::error::GitAiError::Usage(format!(
+            "Unable to store the {} token in the keyring, is {} available?",
+            secret.account(),
+            keyring_tool()
+        ))
+    })?;
+    KEYRING
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(secret.account(), Some(value.to_string()));
+    Ok(())
+}
+
+/// Takes the secret out of the keyring
+pub fn keyring_delete(secret: Secret) -> crate::error::Result<()> {
+    run_keyring(KeyringOp::Delete, secret.account(), None).ok_or_else(|| {
+        crate::error::GitAiError::Usage(format!(
+            "There is no {} token in the keyring",
+            secret.account()
+        ))
+    })?;
+    KEYRING
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(secret.account(), None);
+    Ok(())
+}
+
+/// The program we talk to the keyring with, for error messages
+fn keyring_tool() -> &'static str {
+    if cfg!(target_os = "macos") {
+        "security"
+    } else if cfg!(windows) {
+        "powershell"
+    } else {
+        "secret-tool (libsecret)"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Settings as `settings.json` would have them, with these tokens in it
+    fn file(ai: &str, github: &str) -> Settings {
+        let mut settings = Settings::default();
+        settings.ai_settings.api_key = ai.to_string();
+        settings.git_settings.github_api_key = github.to_string();
+        settings
+    }
+
+    fn keyring(ai: &'static str, github: &'static str) -> impl Fn(Secret) -> Option<String> {
+        move |secret| match secret {
+            Secret::Openai => Some(ai.to_string()),
+            Secret::Github => Some(github.to_string()),
+            Secret::Gitlab => None,
+        }
+    }
+
+    fn vars(vars: &'static [(&'static str, &'static str)]) -> impl Fn(&str) -> Option<String> {
+        move |name| {
+            vars.iter()
+                .find(|(var, _)| *var == name)
+                .map(|(_, value)| value.to_string())
+        }
+    }
+
+    fn no_keyring(_: Secret) -> Option<String> {
+        None
+    }
+
+    #[test]
+    fn the_file_is_used_when_nothing_else_is_set() {
+        let settings = file("file-ai", "file-gh")
+            .with_env_from(vars(&[]))
+            .with_tokens_from(no_keyring);
+        assert_eq!(settings.ai_settings.api_key, "file-ai");
+        assert_eq!(settings.git_settings.github_api_key, "file-gh");
+    }
+
+    #[test]
+    fn the_keyring_beats_the_file() {
+        let settings = file("file-ai", "file-gh")
+            .with_env_from(vars(&[]))
+            .with_tokens_from(keyring("ring-ai", "ring-gh"));
+        assert_eq!(settings.ai_settings.api_key, "ring-ai");
+        assert_eq!(settings.git_settings.github_api_key, "ring-gh");
+    }
+
+    #[test]
+    fn the_environment_beats_the_keyring_and_the_file() {
+        let settings = file("file-ai", "file-gh")
+            .with_env_from(vars(&[
+                ("GITAI_AI_API_KEY", "env-ai"),
+                ("GITAI_GITHUB_TOKEN", "env-gh"),
+            ]))
+            .with_tokens_from(keyring("ring-ai", "ring-gh"));
+        assert_eq!(settings.ai_settings.api_key, "env-ai");
+        assert_eq!(settings.git_settings.github_api_key, "env-gh");
+    }
+
+    #[test]
+    fn the_keyring_fills_in_what_the_environment_left() {
+        let settings = file("file-ai", "file-gh")
+            .with_env_from(vars(&[("GITAI_AI_API_KEY", "env-ai")]))
+            .with_tokens_from(keyring("ring-ai", "ring-gh"));
+        assert_eq!(settings.ai_settings.api_key, "env-ai");
+        assert_eq!(settings.git_settings.github_api_key, "ring-gh");
+    }
+
+    #[test]
+    fn an_empty_variable_is_not_set() {
+        let settings = file("file-ai", "file-gh")
+            .with_env_from(vars(&[
+                ("GITAI_AI_API_KEY", "  "),
+                ("GITAI_GITHUB_TOKEN", ""),
+            ]))
+            .with_tokens_from(no_keyring);
+        assert_eq!(settings.ai_settings.api_key, "file-ai");
+        assert_eq!(settings.git_settings.github_api_key, "file-gh");
+    }
+
+    #[test]
+    fn openai_api_key_is_used_when_there_is_no_key() {
+        let settings = file("", "")
+            .with_env_from(vars(&[(OPENAI_API_KEY, "openai")]))
+            .with_tokens_from(no_keyring);
+        assert_eq!(settings.ai_settings.api_key, "openai");
+    }
+
+    #[test]
+    fn openai_api_key_loses_to_gitai_ai_api_key() {
+        let settings = file("", "")
+            .with_env_from(vars(&[
+                (OPENAI_API_KEY, "openai"),
+                ("GITAI_AI_API_KEY", "env-ai"),
+            ]))
+            .with_tokens_from(no_keyring);
+        assert_eq!(settings.ai_settings.api_key, "env-ai");
+    }
+
+    #[test]
+    fn openai_api_key_loses_to_the_file_and_the_keyring() {
+        let openai = vars(&[(OPENAI_API_KEY, "openai")]);
+        let settings = file("file-ai", "").with_env_from(&openai);
+        assert_eq!(settings.ai_settings.api_key, "file-ai");
+        let settings = file("", "")
+            .with_env_from(&openai)
+            .with_tokens_from(keyring("ring-ai", "ring-gh"));
+        assert_eq!(settings.ai_settings.api_key, "ring-ai");
+    }
+
+    #[test]
+    fn saving_what_was_loaded_leaves_the_environment_out() {
+        let dir = env::temp_dir().join(format!("gitai-settings-{}", std::process::id()));
+        let path = dir.join("settings.json");
+        file("file-ai", "").save_to(&path).unwrap();
+        env::set_var("GITAI_AI_API_KEY", "env-ai");
+        env::set_var("GITAI__GIT_SETTINGS__REMOTE", "env-remote");
+        // what the settings form does, the settings as JSON and back
+        let loaded = Settings::load(&path, None, false);
+        env::remove_var("GITAI_AI_API_KEY");
+        env::remove_var("GITAI__GIT_SETTINGS__REMOTE");
+        let value = serde_json::to_value(loaded.unwrap()).unwrap();
+        let saved = serde_json::from_value::<Settings>(value)
+            .unwrap()
+            .save_to(&path);
+        let text = fs::read_to_string(&path);
+        fs::remove_dir_all(&dir).unwrap();
+        saved.unwrap();
+        let text = text.unwrap();
+        assert!(text.contains("file-ai"));
+        assert!(!text.contains("env-ai"));
+        assert!(!text.contains("env-remote"));
+    }
+
+    /// The default settings with `vars` as the environment
+    fn with_environment(vars: &[(&str, &str)]) -> Result<Settings, ConfigError> {
+        let text = serde_json::to_string(&Settings::default()).unwrap();
+        let vars = vars
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        layered(
+            File::from_str(&text, FileFormat::Json),
+            None,
+            Some(environment().source(Some(vars))),
+        )
+    }
+
+    #[test]
+    fn a_double_underscore_path_sets_the_setting() {
+        let settings = with_environment(&[
+            ("GITAI__AI_SETTINGS__AI_OPTIONS__MODEL", "gpt-4"),
+            ("GITAI__AI_SETTINGS__AI_OPTIONS__CONTEXT_WINDOW", "16000"),
+            ("GITAI__GIT_SETTINGS__REMOTE", "upstream"),
+        ])
+        .unwrap();
+        assert_eq!(settings.ai_settings.ai_options.model, "gpt-4");
+        assert_eq!(settings.ai_settings.ai_options.context_window, 16000);
+        assert_eq!(settings.git_settings.remote, "upstream");
+    }
+
+    #[test]
+    fn an_unknown_path_is_ignored() {
+        let settings = with_environment(&[
+            ("GITAI__NOT__A__SETTING", "1"),
+            ("GITAI__AI_SETTINGS__AI_OPTIONS__NOPE", "1"),
+            // single underscores are the named variables, not a path
+            ("GITAI_AI_SETTINGS__AI_OPTIONS__MODEL", "gpt-4"),
+        ])
+        .unwrap();
+        let defaults = Settings::default();
+        assert_eq!(
+            settings.ai_settings.ai_options.model,
+            defaults.ai_settings.ai_options.model
+        );
+    }
+
+    #[test]
+    fn an_invalid_path_is_an_error() {
+        assert!(
+            with_environment(&[("GITAI__AI_SETTINGS__AI_OPTIONS__CONTEXT_WINDOW", "lots")])
+                .is_err()
+        );
+        assert!(with_environment(&[("GITAI__AI_SETTINGS", "flat")]).is_err());
+    }
+
+    #[test]
+    fn a_repository_only_sets_team_settings() {
+        let mut overrides = serde_json::json!({
+            "ai_settings": {
+                "api_url": "https://example.com",
+                "ai_options": {
+                    "conventional_commits": true,
+                    "prompt_pack": "theirs",
+                    "prompt": { "language": "Rust" }
+                }
+            },
+            "git_settings": {
+                "git_options": {
+                    "auto_push": true,
+                    "pr_labels": ["ai"],
+                    "private_trailer": { "recipients": ["them"] }
+                }
+            },
+            "commit_policy": { "max_subject_length": 50 },
+            "network": { "danger_accept_invalid_certs": true }
+        });
+        let mut dropped = keep_team_settings(&mut overrides, "");
+        dropped.sort();
+        assert_eq!(
+            dropped,
+            [
+                "ai_settings.ai_options.prompt_pack",
+                "ai_settings.api_url",
+                "git_settings.git_options.auto_push",
+                "git_settings.git_options.private_trailer",
+                "network",
+            ]
+        );
+        assert_eq!(
+            overrides,
+            serde_json::json!({
+                "ai_settings": {
+                    "ai_options": {
+                        "conventional_commits": true,
+                        "prompt": { "language": "Rust" }
+                    }
+                },
+                "git_settings": { "git_options": { "pr_labels": ["ai"] } },
+                "commit_policy": { "max_subject_length": 50 }
+            })
+        );
+    }
+
+    #[test]
+    fn a_repository_sets_the_model() {
+        let mut overrides = serde_json::json!({
+            "ai_settings": { "ai_options": { "model": "gpt-4o" } }
+        });
+        assert!(keep_team_settings(&mut overrides, "").is_empty());
+        assert_eq!(
+            overrides.pointer("/ai_settings/ai_options/model"),
+            Some(&serde_json::json!("gpt-4o"))
+        );
+    }
+}
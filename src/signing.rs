@@ -0,0 +1,265 @@
+use std::{
+    env, fs,
+    io::Write,
+    process::{self, Command, Stdio},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use git2::Config;
+use log::debug;
+
+use crate::error::{GitAiError, Result};
+use crate::settings::expand_home;
+
+/// The kind of signature, git's `gpg.format`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// gpg, the default
+    OpenPgp,
+    /// S/MIME certificates through gpgsm
+    X509,
+    /// An SSH key through `ssh-keygen -Y sign`
+    Ssh,
+}
+
+/// Signs commits the way `git commit -S` does, by handing the commit to gpg (or gpgsm, or
+/// ssh-keygen) and putting the detached signature it gives back in the commit's `gpgsig`
+/// header
+#[derive(Debug, Clone)]
+pub struct Signer {
+    /// `gpg.format`
+    format: Format,
+    /// `gpg.<format>.program`, plain `gpg`, `gpgsm` or `ssh-keygen` by default
+    program: String,
+    /// The key to sign with, for SSH a key file or the public key itself
+    key: String,
+}
+
+impl Signer {
+    /// How to sign a commit, `None` when it shouldn't be.  The flags and settings come
+    /// first, then git config (`commit.gpgsign`, `user.signingkey`, `gpg.format` and the
+    /// program for it), and without a key gpg picks one for the committer, like git does.
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - The repo's git config
+    /// * `sign` - Whether to sign, `None` leaves it to `commit.gpgsign`
+    /// * `key_id` - The key, `None` leaves it to `user.signingkey`
+    /// * `committer` - `Name <email>` of whoever makes the commit
+    pub fn from_config(
+        config: &Config,
+        sign: Option<bool>,
+        key_id: Option<&str>,
+        committer: &str,
+    ) -> Option<Signer> {
+        let sign = sign.unwrap_or_else(|| config.get_bool("commit.gpgsign").unwrap_or(false));
+        if !sign {
+            return None;
+        }
+        let format = match config.get_string("gpg.format").as_deref() {
+            Ok("ssh") => Format::Ssh,
+            Ok("x509") => Format::X509,
+            _ => Format::OpenPgp,
+        };
+        let key = match key_id {
+            Some(key) => key.to_string(),
+            // an SSH signature needs a key, gpg can find one by name
+            None => config
+                .get_string("user.signingkey")
+                .unwrap_or_else(|_| match format {
+                    Format::Ssh => String::new(),
+                    _ => committer.to_string(),
+                }),
+        };
+        let (setting, default) = match format {
+            Format::OpenPgp => ("gpg.program", "gpg"),
+            Format::X509 => ("gpg.x509.program", "gpgsm"),
+            Format::Ssh => ("gpg.ssh.program", "ssh-keygen"),
+        };
+        let program = config.get_string(setting).unwrap_or_else(|_| {
+            // `gpg.program` is the old name for `gpg.openpgp.program`
+            match format {
+                Format::OpenPgp => config
+                    .get_string("gpg.openpgp.program")
+                    .unwrap_or_else(|_| default.to_string()),
+                _ => default.to_string(),
+            }
+        });
+        Some(Signer {
+            format,
+            program,
+            key,
+        })
+    }
+
+    /// The armored detached signature of `buffer`, the commit as `commit_create_buffer`
+    /// writes it
+    ///
+    /// # Arguments
+    ///
+    /// * `buffer` - The commit to sign
+    pub fn sign(&self, buffer: &str) -> Result<String> {
+        match self.format {
+            Format::Ssh => self.sign_ssh(buffer),
+            Format::OpenPgp | Format::X509 => self.sign_gpg(buffer),
+        }
+    }
+
+    /// Whether the key is there to sign with, without signing anything.  Says which key
+    /// it found, the error says why there isn't one.
+    pub fn check(&self) -> Result<String> {
+        match self.format {
+            Format::Ssh => self.check_ssh(),
+            Format::OpenPgp | Format::X509 => {
+                let output = Command::new(&self.program)
+                    .args(["--list-secret-keys", "--with-colons", &self.key])
+                    .stdin(Stdio::null())
+                    .output()
+                    .map_err(|e| {
+                        GitAiError::Signing(format!("couldn't run {}: {}", self.program, e))
+                    })?;
+                if output.status.success() && !output.stdout.is_empty() {
+                    Ok(format!(
+                        "{} has a secret key for {}",
+                        self.program, self.key
+                    ))
+                } else {
+                    Err(GitAiError::Signing(format!(
+                        "{} has no secret key for {}",
+                        self.program, self.key
+                    )))
+                }
+            }
+        }
+    }
+
+    /// An SSH key is either a file or a public key the agent has to hold
+    fn check_ssh(&self) -> Result<String> {
+        if self.key.is_empty() {
+            return Err(GitAiError::Signing(
+                "gpg.format is ssh but there is no key, set user.signingkey to your public key (or its file)"
+                    .to_string(),
+            ));
+        }
+        let literal = self.key.strip_prefix("key::").unwrap_or(&self.key);
+        if !(literal.starts_with("ssh-") || literal.starts_with("ecdsa-")) {
+            let path = expand_home(&self.key);
+            return if path.is_file() {
+                Ok(format!("signing with {}", path.display()))
+            } else {
+                Err(GitAiError::Signing(format!(
+                    "{} isn't there",
+                    path.display()
+                )))
+            };
+        }
+        // the key type and the base64, a comment after them doesn't matter
+        let key: Vec<&str> = literal.split_whitespace().take(2).collect();
+        let output = Command::new("ssh-add")
+            .arg("-L")
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|e| GitAiError::Signing(format!("couldn't run ssh-add: {}", e)))?;
+        let held = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .any(|line| line.split_whitespace().take(2).eq(key.iter().copied()));
+        if held {
+            Ok("the ssh agent has the signing key".to_string())
+        } else {
+            Err(GitAiError::Signing(
+                "the ssh agent doesn't have the key in user.signingkey".to_string(),
+            ))
+        }
+    }
+
+    /// gpg and gpgsm take the commit on stdin and give the signature on stdout
+    fn sign_gpg(&self, buffer: &str) -> Result<String> {
+        debug!("Signing the commit with {} as {}", self.program, self.key);
+        let mut child = Command::new(&self.program)
+            .args(["--status-fd=2", "-bsau", &self.key])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| GitAiError::Signing(format!("couldn't run {}: {}", self.program, e)))?;
+        // dropping stdin closes it, gpg signs once it has everything
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(buffer.as_bytes())?;
+        }
+        let output = child.wait_with_output()?;
+        let status = String::from_utf8_lossy(&output.stderr);
+        if !output.status.success() || !status.contains("[GNUPG:] SIG_CREATED") {
+            return Err(GitAiError::Signing(format!(
+                "{} failed to sign as {}: {}",
+                self.program,
+                self.key,
+                status.trim()
+            )));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    /// ssh-keygen signs a file and writes the signature next to it.  A key given as the
+    /// public key itself (`ssh-ed25519 AAAA...`, or with git's `key::` in front) is used
+    /// through the ssh agent.
+    fn sign_ssh(&self, buffer: &str) -> Result<String> {
+        if self.key.is_empty() {
+            return Err(GitAiError::Signing(
+                "gpg.format is ssh but there is no key, set user.signingkey to your public key (or its file)"
+                    .to_string(),
+            ));
+        }
+        let stamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or_default();
+        let base = env::temp_dir().join(format!("gitai-sign-{}-{}", process::id(), stamp));
+        let commit_file = base.with_extension("commit");
+        let signature_file = base.with_extension("commit.sig");
+        let literal = self.key.strip_prefix("key::").unwrap_or(&self.key);
+        let (key_file, from_agent) = if literal.starts_with("ssh-") || literal.starts_with("ecdsa-")
+        {
+            let key_file = base.with_extension("pub");
+            fs::write(&key_file, literal)?;
+            (key_file, true)
+        } else {
+            (expand_home(&self.key), false)
+        };
+        debug!(
+            "Signing the commit with {} and {}",
+            self.program,
+            key_file.display()
+        );
+        fs::write(&commit_file, buffer)?;
+        let mut command = Command::new(&self.program);
+        command
+            .args(["-Y", "sign", "-n", "git", "-f"])
+            .arg(&key_file);
+        if from_agent {
+            command.arg("-U");
+        }
+        // stdin stays the terminal in case the key has a passphrase
+        let output = command
+            .arg(&commit_file)
+            .stdin(Stdio::inherit())
+            .output()
+            .map_err(|e| GitAiError::Signing(format!("couldn't run {}: {}", self.program, e)));
+        let signature = fs::read_to_string(&signature_file);
+        for file in [&commit_file, &signature_file] {
+            let _ = fs::remove_file(file);
+        }
+        if from_agent {
+            let _ = fs::remove_file(&key_file);
+        }
+        let output = output?;
+        match signature {
+            Ok(signature) if output.status.success() => Ok(signature),
+            _ => Err(GitAiError::Signing(format!(
+                "{} failed to sign with {}: {}",
+                self.program,
+                self.key,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ))),
+        }
+    }
+}
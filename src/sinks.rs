@@ -0,0 +1,267 @@
+use std::{collections::BTreeMap, fs, path::Path};
+
+use git2::Repository;
+use log::{debug, info};
+use serde_json::{json, Value};
+
+use crate::changelog;
+use crate::error::{check_status, GitAiError, Result};
+use crate::hosting::GitHub;
+use crate::network::{self, Service};
+use crate::settings::{expand_home, GitSettings, Sink};
+
+/// Something gitai wrote that a sink can take
+#[derive(Debug, Clone, Copy)]
+pub struct Artifact<'a> {
+    /// What it is, `changelog`, so a webhook can tell
+    pub kind: &'a str,
+    /// Its name, the release for a changelog
+    pub title: &'a str,
+    /// The markdown
+    pub text: &'a str,
+}
+
+/// The sink called `name` in the settings.  `stdout` works without being set up.
+///
+/// # Arguments
+///
+/// * `sinks` - The sinks from the settings
+/// * `name` - The name given to `--sink`
+pub fn lookup(sinks: &BTreeMap<String, Sink>, name: &str) -> Result<Sink> {
+    match (sinks.get(name), name) {
+        (Some(sink), _) => Ok(sink.clone()),
+        (None, "stdout") => Ok(Sink::Stdout),
+        (None, _) => {
+            let known: Vec<&str> = sinks.keys().map(String::as_str).collect();
+            Err(GitAiError::Usage(format!(
+                "There is no sink called {}, the ones in the settings are: {}",
+                name,
+                if known.is_empty() {
+                    "none yet, add some to `sinks`".to_string()
+                } else {
+                    known.join(", ")
+                }
+            )))
+        }
+    }
+}
+
+/// Sends the artifact to the sink, and says where it went (`None` when it was printed)
+///
+/// # Arguments
+///
+/// * `sink` - Where it goes
+/// * `artifact` - What goes there
+/// * `repo` - The repository, for relative paths and the GitHub repo
+/// * `git_settings` - The GitHub token and url, and the remote whose repo it is
+pub async fn deliver(
+    sink: &Sink,
+    artifact: &Artifact<'_>,
+    repo: &Repository,
+    git_settings: &GitSettings,
+) -> Result<Option<String>> {
+    match sink {
+        Sink::Stdout => {
+            println!("{}", artifact.text);
+            Ok(None)
+        }
+        Sink::File { path, prepend } => {
+            let path = if path.starts_with('~') {
+                expand_home(path)
+            } else {
+                repo.workdir().unwrap_or(repo.path()).join(path)
+            };
+            write_file(&path, artifact.text, *prepend)?;
+            Ok(Some(path.display().to_string()))
+        }
+        Sink::GithubRelease { tag, draft } => {
+            let tag = if tag.is_empty() { artifact.title } else { tag };
+            if tag.is_empty() || tag == "Unreleased" {
+                return Err(GitAiError::Usage(
+                    "A GitHub release needs a tag, set `tag` on the sink or pass --release"
+                        .to_string(),
+                ));
+            }
+            let github = GitHub::new(
+                &git_settings.github_api_key,
+                &git_settings.github_api_url,
+                &git_settings.github_ca_cert,
+            )
+            .with_remote(&git_settings.remote);
+            let url = github
+                .publish_release(repo, tag, artifact.title, artifact.text, *draft)
+                .await?;
+            Ok(Some(url))
+        }
+        Sink::Confluence {
+            url,
+            page_id,
+            user,
+            api_token,
+            replace,
+        } => {
+            publish_confluence(url, page_id, user, api_token, *replace, artifact).await?;
+            Ok(Some(format!("Confluence page {}", page_id)))
+        }
+        Sink::Webhook { url, headers } => {
+            let mut request = network::client(Service::Git)?.post(url).json(&json!({
+                "kind": artifact.kind,
+                "title": artifact.title,
+                "text": artifact.text,
+            }));
+            for (name, value) in headers {
+                request = request.header(name, value);
+            }
+            check_status(request.send().await?, "The webhook").await?;
+            Ok(Some(url.to_string()))
+        }
+    }
+}
+
+fn write_file(path: &Path, text: &str, prepend: bool) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    if prepend {
+        changelog::prepend(path, text)
+    } else {
+        fs::write(path, format!("{}\n", text.trim_end()))?;
+        Ok(())
+    }
+}
+
+/// Confluence wants the whole page back with the next version number, so it's read first
+async fn publish_confluence(
+    url: &str,
+    page_id: &str,
+    user: &str,
+    api_token: &str,
+    replace: bool,
+    artifact: &Artifact<'_>,
+) -> Result<()> {
+    let page_url = format!("{}/rest/api/content/{}", url.trim_end_matches('/'), page_id);
+    let client = network::client(Service::Git)?;
+    let auth = |request: reqwest::RequestBuilder| {
+        if user.is_empty() {
+            request.bearer_auth(api_token)
+        } else {
+            request.basic_auth(user, Some(api_token))
+        }
+    };
+    let page = check_status(
+        auth(client.get(&page_url))
+            .query(&[("expand", "version,body.storage")])
+            .send()
+            .await?,
+        "Confluence",
+    )
+    .await?
+    .json::<Value>()
+    .await?;
+    let version = page
+        .pointer("/version/number")
+        .and_then(Value::as_u64)
+        .ok_or_else(|| {
+            GitAiError::BadResponse("Confluence didn't send back the page's version".to_string())
+        })?;
+    let title = page
+        .get("title")
+        .and_then(Value::as_str)
+        .unwrap_or(artifact.title);
+    let html = storage_format(artifact.text);
+    let body = if replace {
+        html
+    } else {
+        let existing = page
+            .pointer("/body/storage/value")
+            .and_then(Value::as_str)
+            .unwrap_or("");
+        format!("{}{}", html, existing)
+    };
+    debug!("Updating {} to version {}", page_url, version + 1);
+    info!("Sending {} to Confluence page {}", artifact.title, page_id);
+    check_status(
+        auth(client.put(&page_url))
+            .json(&json!({
+                "id": page_id,
+                "type": "page",
+                "title": title,
+                "version": { "number": version + 1 },
+                "body": { "storage": { "value": body, "representation": "storage" } },
+            }))
+            .send()
+            .await?,
+        "Confluence",
+    )
+    .await?;
+    Ok(())
+}
+
+/// Confluence pages are XHTML, this covers what gitai writes: headings, lists and paragraphs
+/// with `code` in them
+fn storage_format(markdown: &str) -> String {
+    let mut out = String::new();
+    let mut in_list = false;
+    let mut paragraph: Vec<String> = Vec::new();
+    let flush = |out: &mut String, paragraph: &mut Vec<String>| {
+        if !paragraph.is_empty() {
+            out.push_str(&format!("<p>{}</p>", paragraph.join(" ")));
+            paragraph.clear();
+        }
+    };
+    for line in markdown.lines() {
+        let line = line.trim_end();
+        let item = line
+            .trim_start()
+            .strip_prefix("- ")
+            .or_else(|| line.trim_start().strip_prefix("* "));
+        if !in_list && item.is_some() {
+            flush(&mut out, &mut paragraph);
+            out.push_str("<ul>");
+            in_list = true;
+        } else if in_list && item.is_none() {
+            out.push_str("</ul>");
+            in_list = false;
+        }
+        let heading = line.trim_start_matches('#');
+        let level = line.len() - heading.len();
+        if let Some(item) = item {
+            out.push_str(&format!("<li>{}</li>", inline(item)));
+        } else if (1..=6).contains(&level) && heading.starts_with(' ') {
+            flush(&mut out, &mut paragraph);
+            out.push_str(&format!("<h{0}>{1}</h{0}>", level, inline(heading.trim())));
+        } else if line.trim().is_empty() {
+            flush(&mut out, &mut paragraph);
+        } else {
+            paragraph.push(inline(line.trim()));
+        }
+    }
+    if in_list {
+        out.push_str("</ul>");
+    }
+    flush(&mut out, &mut paragraph);
+    out
+}
+
+/// Escapes a line and turns its `code` spans into `<code>`
+fn inline(text: &str) -> String {
+    let escaped = text
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;");
+    // an odd backtick out isn't code
+    if escaped.matches('`').count() % 2 == 1 {
+        return escaped;
+    }
+    escaped
+        .split('`')
+        .enumerate()
+        .map(|(i, part)| {
+            if i % 2 == 1 {
+                format!("<code>{}</code>", part)
+            } else {
+                part.to_string()
+            }
+        })
+        .collect()
+}
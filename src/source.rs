@@ -0,0 +1,111 @@
+use git2::{Diff, Repository};
+
+use crate::audit::ChangeSize;
+use crate::error::Result;
+use crate::generate;
+use crate::git::Git;
+
+/// Where the change a message gets written about comes from
+pub trait DiffSource {
+    /// The diff, as the AI should see it
+    fn diff_text(&self) -> Result<String>;
+
+    /// How big the change is, the message quality checks expect a longer message for a bigger one
+    fn change_size(&self) -> Result<ChangeSize>;
+}
+
+/// A diff out of a repository, shown to the AI the same way the gitai CLI does it
+pub struct RepoDiff<'a> {
+    git: Git<'a>,
+    diff: Diff<'a>,
+    compress: bool,
+    token_budget: usize,
+}
+
+impl<'a> RepoDiff<'a> {
+    /// Wraps a diff you already have
+    ///
+    /// # Arguments
+    ///
+    /// * `git` - The git options, used to turn the diff into text
+    /// * `diff` - The diff
+    pub fn new(git: Git<'a>, diff: Diff<'a>) -> Self {
+        RepoDiff {
+            git,
+            diff,
+            compress: false,
+            token_budget: usize::MAX,
+        }
+    }
+
+    /// What is staged, what `gitai commit` writes about.  With `auto_add` set in `git`
+    /// everything gets staged first.
+    ///
+    /// # Arguments
+    ///
+    /// * `git` - The git options
+    /// * `repo` - The repository
+    pub fn staged(git: Git<'a>, repo: &'a Repository) -> Result<Self> {
+        Ok(RepoDiff::new(git, git.get_commit_diff(repo)?))
+    }
+
+    /// What `from` adds on top of `to`, what `gitai pr` writes about
+    ///
+    /// # Arguments
+    ///
+    /// * `git` - The git options
+    /// * `repo` - The repository
+    /// * `from` - The branch with the changes
+    /// * `to` - The branch it would be merged into
+    pub fn branches(git: Git<'a>, repo: &'a Repository, from: &str, to: &str) -> Result<Self> {
+        Ok(RepoDiff::new(git, git.get_branch_diff(repo, from, to)?))
+    }
+
+    /// Compress the text the AI sees (drops comments, collapses import-only hunks, ...)
+    pub fn compress(mut self, compress: bool) -> Self {
+        self.compress = compress;
+        self
+    }
+
+    /// Trim the text the AI sees to about this many tokens
+    pub fn token_budget(mut self, token_budget: usize) -> Self {
+        self.token_budget = token_budget;
+        self
+    }
+
+    /// The diff itself
+    pub fn diff(&self) -> &Diff<'a> {
+        &self.diff
+    }
+}
+
+impl DiffSource for RepoDiff<'_> {
+    fn diff_text(&self) -> Result<String> {
+        generate::prompt_diff_text(&self.git, &self.diff, self.compress, self.token_budget)
+    }
+
+    fn change_size(&self) -> Result<ChangeSize> {
+        generate::change_size(&self.diff)
+    }
+}
+
+/// A unified diff you already have as text, `git diff` output for example
+impl DiffSource for str {
+    fn diff_text(&self) -> Result<String> {
+        Ok(self.to_string())
+    }
+
+    fn change_size(&self) -> Result<ChangeSize> {
+        let mut size = ChangeSize::default();
+        for line in self.lines() {
+            if line.starts_with("diff --git ") {
+                size.files += 1;
+            } else if (line.starts_with('+') && !line.starts_with("+++"))
+                || (line.starts_with('-') && !line.starts_with("---"))
+            {
+                size.lines += 1;
+            }
+        }
+        Ok(size)
+    }
+}
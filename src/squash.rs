@@ -0,0 +1,48 @@
+use crate::settings::AiPrompt;
+
+/// How many of the branch's messages go in the prompt, past that the diff says enough
+pub const MAX_MESSAGES: usize = 50;
+
+/// One of the commits being squashed
+#[derive(Debug, Clone)]
+pub struct SquashedCommit {
+    /// The short commit id
+    pub id: String,
+    /// The whole message
+    pub message: String,
+}
+
+/// The commit prompt, told the diff is a whole branch being squashed into one commit
+/// and what its commits said along the way, so the one message covers the lot and not
+/// the last fixup
+///
+/// # Arguments
+///
+/// * `prompt` - The commit prompt, for the branch's combined diff
+/// * `commits` - The branch's commits, newest first
+pub fn prompt(prompt: AiPrompt, commits: &[SquashedCommit]) -> AiPrompt {
+    let mut listing = String::new();
+    // oldest first, the order the work was done in
+    for commit in commits.iter().take(MAX_MESSAGES).rev() {
+        listing.push_str(&format!(
+            "commit {}: {}\n",
+            commit.id,
+            commit.message.trim_end()
+        ));
+    }
+    if commits.len() > MAX_MESSAGES {
+        listing.push_str(&format!(
+            "(and {} older commits)\n",
+            commits.len() - MAX_MESSAGES
+        ));
+    }
+    AiPrompt {
+        postmessage: format!(
+            "{} This diff is a whole branch of {} commit(s) being squashed into one for the merge.  Their messages, oldest first, were:\n{}Write a single message for the change as a whole, what it does and why.  Leave out work that was undone or fixed up again on the branch, and don't list the commits.",
+            prompt.postmessage.trim_end(),
+            commits.len(),
+            listing
+        ),
+        ..prompt
+    }
+}
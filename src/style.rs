@@ -0,0 +1,177 @@
+use std::sync::OnceLock;
+
+use regex::Regex;
+
+use crate::settings::{AiPrompt, MessageStyle};
+
+/// How long a body line gets before it wraps, what `git log` and most tools expect
+pub const BODY_WIDTH: usize = 72;
+
+/// A trailer line, `Signed-off-by: ...`, `BREAKING CHANGE: ...`, those are never wrapped
+fn trailer() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| {
+        Regex::new(r"^([A-Za-z][A-Za-z0-9-]*|BREAKING CHANGE): \S").expect("Bad trailer regex")
+    })
+}
+
+/// A bullet the way the AI writes them, `- `, `* `, `• ` or `1. `
+fn bullet() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r"^\s*([-*•]|\d+[.)])\s+").expect("Bad bullet regex"))
+}
+
+/// Asks for the shape of message `style` wants, whatever the rest of the prompt is
+///
+/// # Arguments
+///
+/// * `prompt` - The commit prompt
+/// * `style` - `message_style`
+pub fn prompt(prompt: AiPrompt, style: MessageStyle) -> AiPrompt {
+    let ask = match style {
+        MessageStyle::Paragraph => return prompt,
+        MessageStyle::SubjectOnly => {
+            "Write just the subject line, under 72 characters, and no body."
+        }
+        MessageStyle::SubjectBody => {
+            "Write a subject line under 72 characters, then a blank line, then a body of a few sentences saying what changed and why."
+        }
+        MessageStyle::BulletedBody => {
+            "Write a subject line under 72 characters, then a blank line, then a body of short bullet points starting with `- `, one for each thing that changed."
+        }
+    };
+    AiPrompt {
+        postmessage: format!("{} {}", prompt.postmessage.trim_end(), ask),
+        ..prompt
+    }
+}
+
+/// Wraps `text` at `BODY_WIDTH`, the lines after the first get `indent` in front.  A
+/// word longer than a line (a url, say) gets a line of its own rather than being broken.
+///
+/// # Arguments
+///
+/// * `text` - One paragraph or bullet
+/// * `first` - What the first line starts with, `- ` for a bullet
+/// * `indent` - What the other lines start with
+pub fn wrap(text: &str, first: &str, indent: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = first.to_string();
+    let mut empty = true;
+    for word in text.split_whitespace() {
+        if !empty && line.chars().count() + 1 + word.chars().count() > BODY_WIDTH {
+            lines.push(line);
+            line = indent.to_string();
+            empty = true;
+        }
+        if !empty {
+            line.push(' ');
+        }
+        line.push_str(word);
+        empty = false;
+    }
+    if !empty {
+        lines.push(line);
+    }
+    lines
+}
+
+/// Ends the paragraph in progress, wrapped, with a blank line before it if it isn't
+/// the first thing in the body
+fn flush(paragraph: &mut Vec<&str>, out: &mut Vec<String>) {
+    if paragraph.is_empty() {
+        return;
+    }
+    if !out.is_empty() {
+        out.push(String::new());
+    }
+    out.extend(wrap(&paragraph.join(" "), "", ""));
+    paragraph.clear();
+}
+
+/// The body as paragraphs, each wrapped.  Bullets the AI wrote anyway stay bullets and
+/// trailers stay as they were.
+fn paragraphs(body: &[&str]) -> Vec<String> {
+    let mut out = Vec::new();
+    let mut paragraph: Vec<&str> = Vec::new();
+    for line in body {
+        let line = line.trim();
+        if line.is_empty() {
+            flush(&mut paragraph, &mut out);
+        } else if trailer().is_match(line) {
+            flush(&mut paragraph, &mut out);
+            out.push(line.to_string());
+        } else if bullet().is_match(line) {
+            flush(&mut paragraph, &mut out);
+            out.extend(wrap(&bullet().replace(line, ""), "- ", "  "));
+        } else {
+            paragraph.push(line);
+        }
+    }
+    flush(&mut paragraph, &mut out);
+    out
+}
+
+/// The body as bullets, each wrapped with its lines lined up under the text.  A line
+/// that isn't a bullet carries on the one before it (or starts one, if it is first).
+fn bullets(body: &[&str]) -> Vec<String> {
+    let mut items: Vec<String> = Vec::new();
+    let mut trailers = Vec::new();
+    let mut continues = false;
+    for line in body {
+        let line = line.trim();
+        if line.is_empty() {
+            continues = false;
+        } else if trailer().is_match(line) {
+            trailers.push(line.to_string());
+            continues = false;
+        } else if bullet().is_match(line) || !continues {
+            items.push(bullet().replace(line, "").to_string());
+            continues = true;
+        } else if let Some(item) = items.last_mut() {
+            item.push(' ');
+            item.push_str(line);
+        }
+    }
+    let mut out: Vec<String> = items
+        .iter()
+        .flat_map(|item| wrap(item, "- ", "  "))
+        .collect();
+    if !trailers.is_empty() {
+        if !out.is_empty() {
+            out.push(String::new());
+        }
+        out.extend(trailers);
+    }
+    out
+}
+
+/// Shapes a tidied message into `style`: the subject on its own, a blank line and the
+/// body wrapped at 72 columns, as paragraphs or as bullets.  `Paragraph` leaves it alone.
+///
+/// # Arguments
+///
+/// * `message` - The message, after the Conventional Commits or gitmoji fix up
+/// * `style` - `message_style`
+pub fn shape(message: &str, style: MessageStyle) -> String {
+    let mut lines = message
+        .trim()
+        .lines()
+        .skip_while(|line| line.trim().is_empty());
+    let subject = match lines.next() {
+        Some(subject) => subject.trim().to_string(),
+        None => return String::new(),
+    };
+    let body: Vec<&str> = lines.collect();
+    let body = match style {
+        MessageStyle::Paragraph => return message.to_string(),
+        MessageStyle::SubjectOnly => return subject,
+        MessageStyle::SubjectBody => paragraphs(&body),
+        MessageStyle::BulletedBody => bullets(&body),
+    };
+    if body.is_empty() {
+        subject
+    } else {
+        format!("{}\n\n{}", subject, body.join("\n"))
+    }
+}
@@ -0,0 +1,173 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use log::debug;
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::error::{GitAiError, Result};
+use crate::settings::{gitai_home, AiPrompt};
+
+/// Where the diff goes in a template, it can only go in once
+const DIFF: &str = "{{diff}}";
+
+/// A prompt written out in full, `~/.gitai/prompts/<name>.toml`.  `{{language}}`,
+/// `{{branch}}` and `{{diff}}` are filled in when it is used.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct PromptTemplate {
+    /// What it is for, shown next to its name
+    pub description: String,
+    /// The prompt commit messages are written from
+    pub commit: Option<String>,
+    /// The prompt pull requests are written from
+    pub pr: Option<String>,
+}
+
+/// Where templates are looked for, the repo's `.gitai/prompts` first so a team can keep
+/// theirs with the code, then `~/.gitai/prompts`
+///
+/// # Arguments
+///
+/// * `repo_root` - The top of the repository, if there is one
+pub fn prompt_dirs(repo_root: Option<&Path>) -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    if let Some(root) = repo_root {
+        dirs.push(root.join(".gitai").join("prompts"));
+    }
+    dirs.push(gitai_home().join("prompts"));
+    dirs
+}
+
+/// Every template in `dirs` by name, with its description.  A name in an earlier
+/// directory hides the same name in a later one.
+///
+/// # Arguments
+///
+/// * `dirs` - Where to look, from `prompt_dirs`
+pub fn list(dirs: &[PathBuf]) -> BTreeMap<String, String> {
+    let mut templates = BTreeMap::new();
+    for dir in dirs.iter().rev() {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for path in entries.filter_map(|entry| entry.ok().map(|entry| entry.path())) {
+            if path.extension().is_some_and(|ext| ext == "toml") {
+                let name = path
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().to_string())
+                    .unwrap_or_default();
+                let description = read(&path).map(|t| t.description).unwrap_or_default();
+                templates.insert(name, description);
+            }
+        }
+    }
+    templates
+}
+
+fn read(path: &Path) -> Result<PromptTemplate> {
+    toml::from_str(&fs::read_to_string(path)?).map_err(|e| {
+        GitAiError::Settings(config::ConfigError::Message(format!(
+            "{} isn't a prompt template: {}",
+            path.display(),
+            e
+        )))
+    })
+}
+
+/// The template called `name`, from the first of `dirs` that has it
+///
+/// # Arguments
+///
+/// * `dirs` - Where to look, from `prompt_dirs`
+/// * `name` - Its file name without `.toml`
+pub fn load(dirs: &[PathBuf], name: &str) -> Result<PromptTemplate> {
+    for dir in dirs {
+        let path = dir.join(format!("{}.toml", name));
+        if path.exists() {
+            debug!("Using the prompt template in {}", path.display());
+            return read(&path);
+        }
+    }
+    let known: Vec<String> = list(dirs).into_keys().collect();
+    Err(GitAiError::Usage(format!(
+        "There is no prompt template called {}, the ones there are: {}",
+        name,
+        if known.is_empty() {
+            format!(
+                "none yet, add some to {}",
+                gitai_home().join("prompts").display()
+            )
+        } else {
+            known.join(", ")
+        }
+    )))
+}
+
+fn variable() -> &'static Regex {
+    static VARIABLE: OnceLock<Regex> = OnceLock::new();
+    VARIABLE
+        .get_or_init(|| Regex::new(r"\{\{\s*(\w+)\s*\}\}").expect("Bad template variable regex"))
+}
+
+/// Fills in a template's variables and makes the prompt of it.  Everything before
+/// `{{diff}}` comes before the diff and everything after it after, without `{{diff}}`
+/// the diff goes at the end.
+///
+/// # Arguments
+///
+/// * `template` - The template text
+/// * `vars` - What each variable is, `language` and `branch`
+pub fn render(template: &str, vars: &BTreeMap<&str, String>) -> Result<AiPrompt> {
+    // spaces inside the braces are fine, `{{ diff }}` included
+    let mut unknown = Vec::new();
+    let text = variable().replace_all(template, |caps: &regex::Captures| {
+        let name = &caps[1];
+        match (name, vars.get(name)) {
+            ("diff", _) => DIFF.to_string(),
+            (_, Some(value)) => value.to_string(),
+            (_, None) => {
+                unknown.push(name.to_string());
+                caps[0].to_string()
+            }
+        }
+    });
+    if !unknown.is_empty() {
+        let known: Vec<String> = vars
+            .keys()
+            .map(|name| format!("{{{{{}}}}}", name))
+            .chain([DIFF.to_string()])
+            .collect();
+        return Err(GitAiError::Usage(format!(
+            "The prompt template uses {} but only {} can be filled in",
+            unknown
+                .iter()
+                .map(|name| format!("{{{{{}}}}}", name))
+                .collect::<Vec<_>>()
+                .join(", "),
+            known.join(", ")
+        )));
+    }
+    let (before, after) = match text.split_once(DIFF) {
+        Some((_, after)) if after.contains(DIFF) => {
+            return Err(GitAiError::Usage(
+                "The prompt template has {{diff}} more than once".to_string(),
+            ))
+        }
+        Some((before, after)) => (before, after),
+        None => (text.as_ref(), ""),
+    };
+    Ok(AiPrompt {
+        preamble: before.trim().to_string(),
+        language: String::new(),
+        postamble: String::new(),
+        postmessage: after.trim().to_string(),
+        templated: true,
+        ..Default::default()
+    })
+}
@@ -0,0 +1,205 @@
+use std::{
+    io::{self, BufRead, IsTerminal, Write},
+    path::Path,
+    process::Command,
+};
+
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    execute, terminal,
+};
+
+/// Whether `stream` is a terminal rather than a file or a pipe
+///
+/// # Arguments
+///
+/// * `stream` - `io::stdin()`, `io::stdout()` or `io::stderr()`
+pub fn is_tty<S: IsTerminal>(stream: &S) -> bool {
+    stream.is_terminal()
+}
+
+/// Whether the terminal understands the escape codes colors are written with.  Always
+/// on Unix, on Windows the console is switched over to them the first time this is asked
+/// and it is only false for an old console that can't be.
+pub fn supports_ansi() -> bool {
+    #[cfg(windows)]
+    {
+        crossterm::ansi_support::supports_ansi()
+    }
+    #[cfg(not(windows))]
+    {
+        true
+    }
+}
+
+/// The terminal's width and height, `None` when there isn't one
+pub fn size() -> Option<(u16, u16)> {
+    terminal::size().ok()
+}
+
+/// Reads a line, without the line ending (`\r\n` on Windows too).  `None` at the end of
+/// the input.
+///
+/// # Arguments
+///
+/// * `input` - Where to read it, usually stdin
+pub fn read_line(input: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut line = String::new();
+    if input.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    let end = line.trim_end_matches(['\r', '\n']).len();
+    line.truncate(end);
+    Ok(Some(line))
+}
+
+/// Reads a line without showing what is typed, for a key or a token.  `None` if it was
+/// given up on with ctrl-c or ctrl-d.  When stdin isn't a terminal there is nothing to
+/// hide and it is read like any other line.
+pub fn read_password() -> io::Result<Option<String>> {
+    if !is_tty(&io::stdin()) {
+        return read_line(&mut io::stdin().lock());
+    }
+    terminal::enable_raw_mode()?;
+    let mut password = String::new();
+    let typed = loop {
+        let key = match event::read() {
+            Ok(Event::Key(key)) if key.kind != KeyEventKind::Release => key,
+            Ok(_) => continue,
+            Err(e) => break Err(e),
+        };
+        match key_of(key) {
+            Key::Char('\n') => break Ok(Some(password)),
+            Key::Ctrl('c') | Key::Ctrl('d') => break Ok(None),
+            Key::Backspace => {
+                password.pop();
+            }
+            Key::Char(c) if !c.is_control() => password.push(c),
+            _ => (),
+        }
+    };
+    terminal::disable_raw_mode()?;
+    typed
+}
+
+/// The editor to fall back on when git, `VISUAL` and `EDITOR` don't name one
+#[cfg(not(windows))]
+pub const DEFAULT_EDITOR: &str = "vi";
+/// The editor to fall back on when git, `VISUAL` and `EDITOR` don't name one
+#[cfg(windows)]
+pub const DEFAULT_EDITOR: &str = "notepad";
+
+/// The command that opens `path` in `editor`.  The editor can come with arguments,
+/// `code --wait` for example, so it goes through the shell: `sh` on Unix, `cmd` on
+/// Windows.
+///
+/// # Arguments
+///
+/// * `editor` - The editor, as git or the environment names it
+/// * `path` - The file to edit
+pub fn editor_command(editor: &str, path: &Path) -> Command {
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        let mut command = Command::new("cmd");
+        command
+            .arg("/C")
+            .raw_arg(format!("{} \"{}\"", editor, path.display()));
+        command
+    }
+    #[cfg(not(windows))]
+    {
+        let mut command = Command::new("sh");
+        command
+            .arg("-c")
+            .arg(format!("{} \"$1\"", editor))
+            .arg("sh")
+            .arg(path);
+        command
+    }
+}
+
+/// A key that was pressed, the way the settings form wants it.  Enter and tab come
+/// through as `Char('\n')` and `Char('\t')`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Char(char),
+    Ctrl(char),
+    Up,
+    Down,
+    Left,
+    Right,
+    BackTab,
+    Backspace,
+    Esc,
+    /// Anything else, a function key or a key with alt
+    Other,
+}
+
+fn key_of(key: KeyEvent) -> Key {
+    match key.code {
+        KeyCode::Char(c) if key.modifiers.contains(KeyModifiers::CONTROL) => Key::Ctrl(c),
+        KeyCode::Char(_) if key.modifiers.contains(KeyModifiers::ALT) => Key::Other,
+        KeyCode::Char(c) => Key::Char(c),
+        KeyCode::Enter => Key::Char('\n'),
+        KeyCode::Tab => Key::Char('\t'),
+        KeyCode::BackTab => Key::BackTab,
+        KeyCode::Backspace => Key::Backspace,
+        KeyCode::Esc => Key::Esc,
+        KeyCode::Up => Key::Up,
+        KeyCode::Down => Key::Down,
+        KeyCode::Left => Key::Left,
+        KeyCode::Right => Key::Right,
+        _ => Key::Other,
+    }
+}
+
+/// The whole terminal, in raw mode on the alternate screen with the cursor hidden.
+/// Everything is put back when it is dropped, on an error too.
+#[derive(Debug)]
+pub struct Screen {
+    out: io::Stdout,
+}
+
+impl Screen {
+    /// Takes the terminal over
+    pub fn enter() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        let mut out = io::stdout();
+        if let Err(e) = execute!(out, terminal::EnterAlternateScreen, cursor::Hide) {
+            let _ = terminal::disable_raw_mode();
+            return Err(e);
+        }
+        Ok(Screen { out })
+    }
+
+    /// Waits for the next key.  Windows reports a key being let go of as well as being
+    /// pressed, only presses are passed on.
+    pub fn read_key(&mut self) -> io::Result<Key> {
+        loop {
+            if let Event::Key(key) = event::read()? {
+                if key.kind != KeyEventKind::Release {
+                    return Ok(key_of(key));
+                }
+            }
+        }
+    }
+}
+
+impl Write for Screen {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.out.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = execute!(self.out, cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
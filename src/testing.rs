@@ -0,0 +1,149 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+use log::debug;
+use regex::Regex;
+
+use crate::workspace::{Member, Workspace};
+
+/// What `npm init` puts in `scripts.test`, which runs nothing
+const NPM_PLACEHOLDER: &str = "no test specified";
+
+/// A Makefile rule, `target:` but not `VAR := value`
+fn make_target() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| {
+        Regex::new(r"^([A-Za-z0-9][A-Za-z0-9_.-]*)\s*:([^=]|$)").expect("Bad make target regex")
+    })
+}
+
+/// The workspace members the change touches (and the ones depending on them), empty
+/// when the repo isn't a workspace or nothing in one changed
+fn affected_members(root: &Path, changed_files: &[PathBuf]) -> Vec<Member> {
+    match Workspace::discover(root) {
+        Some(workspace) => {
+            let impact = workspace.impact(changed_files);
+            workspace
+                .members
+                .into_iter()
+                .filter(|m| {
+                    impact.changed.contains(&m.name) || impact.dependents.contains_key(&m.name)
+                })
+                .collect()
+        }
+        None => Vec::new(),
+    }
+}
+
+/// `cargo test`, just for the affected crates of a workspace
+fn cargo_commands(root: &Path, affected: &[Member]) -> Vec<String> {
+    let manifest = match fs::read_to_string(root.join("Cargo.toml")) {
+        Ok(manifest) => manifest,
+        Err(_) => return Vec::new(),
+    };
+    let crates: Vec<String> = affected
+        .iter()
+        .filter(|m| root.join(&m.dir).join("Cargo.toml").exists())
+        .map(|m| format!("-p {}", m.name))
+        .collect();
+    let command = if !crates.is_empty() {
+        format!("cargo test {}", crates.join(" "))
+    } else if manifest.contains("[workspace]") {
+        "cargo test --workspace".to_string()
+    } else {
+        "cargo test".to_string()
+    };
+    vec![command]
+}
+
+/// The test scripts in `package.json`, run with whichever package manager the lockfile says
+fn npm_commands(root: &Path, affected: &[Member]) -> Vec<String> {
+    let package = match fs::read_to_string(root.join("package.json"))
+        .ok()
+        .and_then(|text| serde_json::from_str::<serde_json::Value>(&text).ok())
+    {
+        Some(package) => package,
+        None => return Vec::new(),
+    };
+    let runner = if root.join("pnpm-lock.yaml").exists() {
+        "pnpm"
+    } else if root.join("yarn.lock").exists() {
+        "yarn"
+    } else {
+        "npm"
+    };
+    let scripts = match package.get("scripts").and_then(|s| s.as_object()) {
+        Some(scripts) => scripts,
+        None => return Vec::new(),
+    };
+    let mut commands = Vec::new();
+    for (name, script) in scripts {
+        let script = script.as_str().unwrap_or_default();
+        if !name.contains("test") || script.contains(NPM_PLACEHOLDER) {
+            continue;
+        }
+        if name == "test" {
+            let packages: Vec<String> = affected
+                .iter()
+                .filter(|m| root.join(&m.dir).join("package.json").exists())
+                .map(|m| format!(" --workspace={}", m.name))
+                .collect();
+            if runner == "npm" && !packages.is_empty() {
+                commands.push(format!("npm test{}", packages.concat()));
+            } else {
+                commands.push(format!("{} test", runner));
+            }
+        } else {
+            commands.push(format!("{} run {}", runner, name));
+        }
+    }
+    commands
+}
+
+/// The Makefile targets that look like they test something
+fn make_commands(root: &Path) -> Vec<String> {
+    let makefile = match fs::read_to_string(root.join("Makefile")) {
+        Ok(makefile) => makefile,
+        Err(_) => return Vec::new(),
+    };
+    let mut commands: Vec<String> = Vec::new();
+    for line in makefile.lines() {
+        if let Some(caps) = make_target().captures(line) {
+            let target = &caps[1];
+            let command = format!("make {}", target);
+            if (target.contains("test") || target == "check") && !commands.contains(&command) {
+                commands.push(command);
+            }
+        }
+    }
+    commands
+}
+
+/// The commands that run the project's tests, read from its `Cargo.toml`, `package.json`
+/// and `Makefile` so a reviewer can paste them.  In a workspace they only cover the
+/// packages the change touches.
+///
+/// # Arguments
+///
+/// * `root` - The root of the repo
+/// * `changed_files` - The changed files, relative to `root`
+pub fn commands(root: &Path, changed_files: &[PathBuf]) -> Vec<String> {
+    let affected = affected_members(root, changed_files);
+    let mut commands = cargo_commands(root, &affected);
+    commands.extend(npm_commands(root, &affected));
+    commands.extend(make_commands(root));
+    debug!("Found the test commands {:?}", commands);
+    commands
+}
+
+/// The markdown that goes under a section with `commands` set
+///
+/// # Arguments
+///
+/// * `commands` - The test commands
+pub fn markdown(commands: &[String]) -> String {
+    format!("To run the tests:\n\n```sh\n{}\n```", commands.join("\n"))
+}
@@ -0,0 +1,263 @@
+use log::{debug, warn};
+use regex::Regex;
+use reqwest::header::{HeaderValue, AUTHORIZATION};
+use reqwest::{Client, RequestBuilder};
+use serde_json::json;
+
+use crate::conventional;
+use crate::error::{check_status, GitAiError, Result};
+use crate::network::{self, Service};
+use crate::settings::{AiPrompt, TicketSettings, TicketTracker};
+
+/// Linear's GraphQL endpoint, used when `api_url` is empty
+const LINEAR_API_URL: &str = "https://api.linear.app/graphql";
+
+/// The ticket a branch is for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ticket {
+    /// The id, like `PROJ-123`
+    pub key: String,
+    /// Its title, if the tracker told us
+    pub title: Option<String>,
+}
+
+/// The ticket id in the branch name, if there is one
+///
+/// # Arguments
+///
+/// * `settings` - The ticket settings
+/// * `branch` - The branch name
+pub fn ticket_id(settings: &TicketSettings, branch: &str) -> Result<Option<String>> {
+    if settings.branch_regex.is_empty() {
+        return Ok(None);
+    }
+    let regex = Regex::new(&settings.branch_regex).map_err(|e| {
+        GitAiError::Settings(config::ConfigError::Message(format!(
+            "tickets.branch_regex isn't a valid regex: {}",
+            e
+        )))
+    })?;
+    Ok(regex.find(branch).map(|found| found.as_str().to_string()))
+}
+
+/// The Jira site, which has to be set for Jira
+fn jira_url(settings: &TicketSettings) -> Result<&str> {
+    if settings.api_url.is_empty() {
+        return Err(GitAiError::Usage(
+            "Set tickets.api_url to your Jira site to use Jira".to_string(),
+        ));
+    }
+    Ok(settings.api_url.trim_end_matches('/'))
+}
+
+/// Signs a Jira request in
+fn jira_auth(request: RequestBuilder, settings: &TicketSettings) -> RequestBuilder {
+    // Jira Cloud wants the account email with the token, Server takes a bearer token
+    if settings.user.is_empty() {
+        request.bearer_auth(&settings.api_token)
+    } else {
+        request.basic_auth(&settings.user, Some(&settings.api_token))
+    }
+}
+
+/// Sends a GraphQL query to Linear
+async fn linear_query(
+    client: &Client,
+    settings: &TicketSettings,
+    query: &str,
+    variables: serde_json::Value,
+) -> Result<serde_json::Value> {
+    let url = if settings.api_url.is_empty() {
+        LINEAR_API_URL
+    } else {
+        settings.api_url.as_str()
+    };
+    debug!("Posting to {}", url);
+    // Linear API keys go in the header as they are, no Bearer
+    let token = HeaderValue::from_str(&settings.api_token).map_err(|_| {
+        GitAiError::Usage(
+            "The Linear api token has characters that can't be sent in a header".to_string(),
+        )
+    })?;
+    let response = client
+        .post(url)
+        .header(AUTHORIZATION, token)
+        .json(&json!({ "query": query, "variables": variables }))
+        .send()
+        .await?;
+    Ok(check_status(response, "Linear")
+        .await?
+        .json::<serde_json::Value>()
+        .await?)
+}
+
+/// The ticket's title from the tracker, `None` if there isn't a tracker set up
+///
+/// # Arguments
+///
+/// * `settings` - The ticket settings
+/// * `key` - The ticket id
+pub async fn fetch_title(settings: &TicketSettings, key: &str) -> Result<Option<String>> {
+    let tracker = match settings.tracker {
+        Some(tracker) => tracker,
+        None => return Ok(None),
+    };
+    let client = network::client(Service::Git)?;
+    let (service, response, pointer) = match tracker {
+        TicketTracker::Jira => {
+            let url = format!(
+                "{}/rest/api/2/issue/{}?fields=summary",
+                jira_url(settings)?,
+                key
+            );
+            debug!("Getting {}", url);
+            let response = jira_auth(client.get(url), settings).send().await?;
+            (
+                "Jira",
+                check_status(response, "Jira")
+                    .await?
+                    .json::<serde_json::Value>()
+                    .await?,
+                "/fields/summary",
+            )
+        }
+        TicketTracker::Linear => (
+            "Linear",
+            linear_query(
+                &client,
+                settings,
+                "query($id: String!) { issue(id: $id) { title } }",
+                json!({ "id": key }),
+            )
+            .await?,
+            "/data/issue/title",
+        ),
+    };
+    match response.pointer(pointer).and_then(|title| title.as_str()) {
+        Some(title) => Ok(Some(title.trim().to_string())),
+        None => Err(GitAiError::BadResponse(format!(
+            "{} didn't send back a title for {}",
+            service, key
+        ))),
+    }
+}
+
+/// Adds a comment to the ticket
+///
+/// # Arguments
+///
+/// * `settings` - The ticket settings
+/// * `key` - The ticket id
+/// * `text` - The comment
+pub async fn comment(settings: &TicketSettings, key: &str, text: &str) -> Result<()> {
+    let tracker = settings.tracker.ok_or_else(|| {
+        GitAiError::Usage("Set tickets.tracker to post to the ticket".to_string())
+    })?;
+    let client = network::client(Service::Git)?;
+    match tracker {
+        TicketTracker::Jira => {
+            let url = format!("{}/rest/api/2/issue/{}/comment", jira_url(settings)?, key);
+            debug!("Posting to {}", url);
+            let response = jira_auth(client.post(url), settings)
+                .json(&json!({ "body": text }))
+                .send()
+                .await?;
+            check_status(response, "Jira").await?;
+        }
+        TicketTracker::Linear => {
+            // commentCreate wants the issue's uuid, the identifier only works for lookups
+            let issue = linear_query(
+                &client,
+                settings,
+                "query($id: String!) { issue(id: $id) { id } }",
+                json!({ "id": key }),
+            )
+            .await?;
+            let id = issue
+                .pointer("/data/issue/id")
+                .and_then(|id| id.as_str())
+                .ok_or_else(|| GitAiError::BadResponse(format!("Linear doesn't know {}", key)))?;
+            let created = linear_query(
+                &client,
+                settings,
+                "mutation($issueId: String!, $body: String!) { commentCreate(input: { issueId: $issueId, body: $body }) { success } }",
+                json!({ "issueId": id, "body": text }),
+            )
+            .await?;
+            if created.pointer("/data/commentCreate/success") != Some(&json!(true)) {
+                return Err(GitAiError::BadResponse(format!(
+                    "Linear didn't add the comment to {}",
+                    key
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The ticket the branch is for.  A tracker that can't be reached only costs the title,
+/// the id is still used.
+///
+/// # Arguments
+///
+/// * `settings` - The ticket settings
+/// * `branch` - The branch name
+pub async fn for_branch(settings: &TicketSettings, branch: &str) -> Result<Option<Ticket>> {
+    let key = match ticket_id(settings, branch)? {
+        Some(key) => key,
+        None => return Ok(None),
+    };
+    let title = match fetch_title(settings, &key).await {
+        Ok(title) => title,
+        Err(e) => {
+            warn!(
+                "Couldn't look {} up, going on without its title: {}",
+                key, e
+            );
+            None
+        }
+    };
+    Ok(Some(Ticket { key, title }))
+}
+
+/// The commit prompt, telling the AI which ticket the change is for
+///
+/// # Arguments
+///
+/// * `prompt` - The commit prompt
+/// * `ticket` - The ticket
+pub fn prompt(prompt: AiPrompt, ticket: &Ticket) -> AiPrompt {
+    let about = match &ticket.title {
+        Some(title) => format!("The change is for ticket {}: \"{}\".", ticket.key, title),
+        None => format!("The change is for ticket {}.", ticket.key),
+    };
+    AiPrompt {
+        postmessage: format!("{} {}", prompt.postmessage.trim_end(), about),
+        ..prompt
+    }
+}
+
+/// Starts the message with the ticket id, after the type of a Conventional Commit.
+/// Subjects that already mention it are left alone.
+///
+/// # Arguments
+///
+/// * `message` - The commit message
+/// * `key` - The ticket id
+pub fn prefix(message: &str, key: &str) -> String {
+    let subject = message.lines().next().unwrap_or("");
+    if subject.contains(key) {
+        return message.to_string();
+    }
+    let subject = match conventional::parse(subject) {
+        Ok(parsed) => match subject.find(": ") {
+            Some(colon) => format!("{}: {} {}", &subject[..colon], key, parsed.description),
+            None => format!("{} {}", key, subject),
+        },
+        Err(_) => format!("{} {}", key, subject),
+    };
+    match message.split_once('\n') {
+        Some((_, rest)) => format!("{}\n{}", subject, rest),
+        None => subject,
+    }
+}
@@ -0,0 +1,162 @@
+use std::{
+    io::{ErrorKind, Write},
+    process::{Command, Stdio},
+    sync::OnceLock,
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use regex::Regex;
+
+use crate::error::{GitAiError, Result};
+use crate::settings::{EncryptionTool, PrivateTrailer};
+
+/// The trailer the encrypted text goes in
+pub const TRAILER_KEY: &str = "Gitai-Private";
+
+/// A `Key: value` trailer line
+fn trailer_line() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| Regex::new(r"^[A-Za-z0-9-]+: \S").expect("Bad trailer regex"))
+}
+
+/// The name of the tool, as it goes in the trailer and on the command line
+fn tool_name(tool: EncryptionTool) -> &'static str {
+    match tool {
+        EncryptionTool::Gpg => "gpg",
+        EncryptionTool::Age => "age",
+    }
+}
+
+/// Runs `program` with `input` on stdin and returns what it wrote to stdout
+fn run(program: &str, args: &[String], input: &[u8]) -> Result<Vec<u8>> {
+    let mut child = match Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            return Err(GitAiError::Usage(format!(
+                "{} isn't installed, it is needed for private trailers",
+                program
+            )))
+        }
+        Err(e) => return Err(e.into()),
+    };
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(input)?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(GitAiError::Usage(format!(
+            "{} failed: {}",
+            program,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(output.stdout)
+}
+
+/// Encrypts `text` for everyone in `settings.recipients`, ready to go in the trailer
+///
+/// # Arguments
+///
+/// * `settings` - Who to encrypt it for, and with what
+/// * `text` - The private text
+pub fn encrypt(settings: &PrivateTrailer, text: &str) -> Result<String> {
+    if settings.recipients.is_empty() {
+        return Err(GitAiError::Usage(
+            "Set git_options.private_trailer.recipients to say who can read private trailers"
+                .to_string(),
+        ));
+    }
+    let mut args: Vec<String> = match settings.tool {
+        EncryptionTool::Gpg => vec![
+            "--batch".to_string(),
+            "--yes".to_string(),
+            "--trust-model".to_string(),
+            "always".to_string(),
+            "--encrypt".to_string(),
+        ],
+        EncryptionTool::Age => Vec::new(),
+    };
+    for recipient in &settings.recipients {
+        args.push("--recipient".to_string());
+        args.push(recipient.to_string());
+    }
+    let encrypted = run(tool_name(settings.tool), &args, text.as_bytes())?;
+    Ok(format!(
+        "{}:{}",
+        tool_name(settings.tool),
+        STANDARD.encode(encrypted)
+    ))
+}
+
+/// Decrypts what `encrypt` made, with whatever tool it says it was made with
+///
+/// # Arguments
+///
+/// * `settings` - Where the age identity is
+/// * `value` - The trailer's value
+pub fn decrypt(settings: &PrivateTrailer, value: &str) -> Result<String> {
+    let (tool, payload) = value
+        .trim()
+        .split_once(':')
+        .ok_or_else(|| GitAiError::BadResponse("The private trailer is malformed".to_string()))?;
+    let encrypted = STANDARD
+        .decode(payload.trim())
+        .map_err(|e| GitAiError::BadResponse(format!("The private trailer is malformed: {}", e)))?;
+    let args: Vec<String> = match tool {
+        "gpg" => vec![
+            "--batch".to_string(),
+            "--quiet".to_string(),
+            "--decrypt".to_string(),
+        ],
+        "age" if settings.identity.is_empty() => {
+            return Err(GitAiError::Usage(
+                "Set git_options.private_trailer.identity to your age identity file".to_string(),
+            ))
+        }
+        "age" => vec![
+            "--decrypt".to_string(),
+            "--identity".to_string(),
+            settings.identity.to_string(),
+        ],
+        other => {
+            return Err(GitAiError::Usage(format!(
+                "The private trailer was encrypted with {}, which gitai doesn't know",
+                other
+            )))
+        }
+    };
+    let decrypted = run(tool, &args, &encrypted)?;
+    Ok(String::from_utf8_lossy(&decrypted).to_string())
+}
+
+/// Adds the trailer to the end of `message`, with the other trailers if it has some
+///
+/// # Arguments
+///
+/// * `message` - The commit message
+/// * `value` - What `encrypt` made
+pub fn attach(message: &str, value: &str) -> String {
+    let message = message.trim_end();
+    let last_paragraph = message.rsplit("\n\n").next().unwrap_or("");
+    let has_trailers = message.contains("\n\n")
+        && last_paragraph
+            .lines()
+            .all(|line| trailer_line().is_match(line));
+    let separator = if has_trailers { "\n" } else { "\n\n" };
+    format!("{}{}{}: {}\n", message, separator, TRAILER_KEY, value)
+}
+
+/// The private trailer's value in `message`, if it has one
+pub fn find(message: &str) -> Option<&str> {
+    message.lines().rev().find_map(|line| {
+        line.strip_prefix(TRAILER_KEY)
+            .and_then(|rest| rest.strip_prefix(':'))
+            .map(str::trim)
+    })
+}
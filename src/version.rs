@@ -0,0 +1,190 @@
+use std::{fmt, sync::OnceLock};
+
+use regex::Regex;
+
+use crate::conventional;
+use crate::settings::AiPrompt;
+
+/// A tag that is a version, `v1.2.3` or `1.2.3` with anything after it
+fn version_tag() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| {
+        Regex::new(r"^(?P<prefix>[^\d]*)(?P<major>\d+)\.(?P<minor>\d+)\.(?P<patch>\d+)")
+            .expect("Bad version tag regex")
+    })
+}
+
+/// How big the next release is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Bump {
+    Patch,
+    Minor,
+    Major,
+}
+
+impl Bump {
+    /// Reads the AI's answer, `major`, `minor` or `patch` at the start
+    pub fn parse(text: &str) -> Option<Self> {
+        let word: String = text
+            .trim()
+            .trim_start_matches(|c: char| !c.is_alphabetic())
+            .chars()
+            .take_while(|c| c.is_alphabetic())
+            .collect();
+        match word.to_lowercase().as_str() {
+            "major" => Some(Bump::Major),
+            "minor" => Some(Bump::Minor),
+            "patch" => Some(Bump::Patch),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Bump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Bump::Major => "major",
+            Bump::Minor => "minor",
+            Bump::Patch => "patch",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A semantic version, keeping whatever came before the numbers in the tag (`v` usually)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Version {
+    pub prefix: String,
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+}
+
+impl Version {
+    /// Reads a version out of a tag, `None` if the tag isn't one
+    pub fn parse(tag: &str) -> Option<Self> {
+        let caps = version_tag().captures(tag.trim())?;
+        Some(Version {
+            prefix: caps["prefix"].to_string(),
+            major: caps["major"].parse().ok()?,
+            minor: caps["minor"].parse().ok()?,
+            patch: caps["patch"].parse().ok()?,
+        })
+    }
+
+    /// The next version.  Before 1.0.0 anything can break, so a major bump only bumps
+    /// the minor version the way Cargo reads them.
+    pub fn bump(&self, bump: Bump) -> Self {
+        let (major, minor, patch) = match bump {
+            Bump::Major if self.major == 0 => (0, self.minor + 1, 0),
+            Bump::Major => (self.major + 1, 0, 0),
+            Bump::Minor => (self.major, self.minor + 1, 0),
+            Bump::Patch => (self.major, self.minor, self.patch + 1),
+        };
+        Version {
+            prefix: self.prefix.to_string(),
+            major,
+            minor,
+            patch,
+        }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}{}.{}.{}",
+            self.prefix, self.major, self.minor, self.patch
+        )
+    }
+}
+
+/// The bump the commits and the public API changes call for without asking anybody,
+/// with why
+///
+/// # Arguments
+///
+/// * `messages` - The commit messages since the last release
+/// * `api_changes` - The changed public declarations, from `docs::public_api_changes`
+pub fn heuristic_bump(messages: &[String], api_changes: &[String]) -> (Bump, Vec<String>) {
+    let mut bump = Bump::Patch;
+    let mut reasons = Vec::new();
+    let breaking = messages
+        .iter()
+        .filter(|message| {
+            conventional::parse(message.trim())
+                .map(|parsed| parsed.breaking)
+                .unwrap_or(false)
+        })
+        .count();
+    let features = messages
+        .iter()
+        .filter(|message| {
+            conventional::parse(message.trim())
+                .map(|parsed| parsed.kind == "feat")
+                .unwrap_or(false)
+        })
+        .count();
+    // a public declaration that was removed (or changed, which shows up as removed and added)
+    let removed = api_changes.iter().filter(|l| l.starts_with('-')).count();
+    let added = api_changes.iter().filter(|l| l.starts_with('+')).count();
+    if breaking > 0 {
+        bump = Bump::Major;
+        reasons.push(format!("{} commit(s) marked as breaking", breaking));
+    }
+    if removed > 0 {
+        bump = Bump::Major;
+        reasons.push(format!(
+            "{} public declaration(s) removed or changed",
+            removed
+        ));
+    }
+    if features > 0 {
+        bump = bump.max(Bump::Minor);
+        reasons.push(format!("{} feat commit(s)", features));
+    }
+    if added > removed {
+        bump = bump.max(Bump::Minor);
+        reasons.push(format!("{} new public declaration(s)", added - removed));
+    }
+    (bump, reasons)
+}
+
+/// The prompt asking the AI how big the next release is
+///
+/// # Arguments
+///
+/// * `language` - The programming language
+/// * `messages` - The commit messages since the last release
+/// * `api_changes` - The changed public declarations
+pub fn prompt(language: &str, messages: &[String], api_changes: &[String]) -> AiPrompt {
+    let api = if api_changes.is_empty() {
+        "None".to_string()
+    } else {
+        api_changes.join("\n")
+    };
+    AiPrompt {
+        language: language.to_string(),
+        postamble: "maintainer deciding the version number of the next release, following Semantic Versioning. These are the commit messages since the last release:"
+            .to_string(),
+        git_diff: format!(
+            "{}\n\nAnd these are the changed public declarations:\n{}",
+            messages
+                .iter()
+                .map(|message| message.trim())
+                .collect::<Vec<&str>>()
+                .join("\n---\n"),
+            api
+        ),
+        postmessage: "Should the next release be a major, minor or patch bump? Answer with just major, minor or patch on the first line and one short reason on the second line.".to_string(),
+        ..Default::default()
+    }
+}
+
+/// Reads the AI's answer to `prompt`, `None` if it didn't start with a bump
+pub fn parse_answer(answer: &str) -> Option<(Bump, String)> {
+    let answer = answer.trim();
+    let (first, rest) = answer.split_once('\n').unwrap_or((answer, ""));
+    Some((Bump::parse(first)?, rest.trim().to_string()))
+}
@@ -0,0 +1,53 @@
+use crate::settings::AiPrompt;
+
+/// One commit of a pull request
+#[derive(Debug, Clone)]
+pub struct WalkthroughCommit {
+    /// The short commit id
+    pub id: String,
+    /// The first line of the message
+    pub subject: String,
+    /// What it changed, as the AI should see it
+    pub diff: String,
+}
+
+/// The prompt asking the AI to walk a reviewer through the commits in order
+///
+/// # Arguments
+///
+/// * `language` - The programming language
+/// * `commits` - The pull request's commits, oldest first
+pub fn prompt(language: &str, commits: &[WalkthroughCommit]) -> AiPrompt {
+    let mut listing = String::new();
+    for (i, commit) in commits.iter().enumerate() {
+        listing.push_str(&format!(
+            "commit {} ({}): {}\n{}\n",
+            i + 1,
+            commit.id,
+            commit.subject,
+            commit.diff.trim_end()
+        ));
+    }
+    AiPrompt {
+        language: language.to_string(),
+        postamble: "developer reviewing a pull request one commit at a time. These are its commits, oldest first, with the diff of each:".to_string(),
+        git_diff: listing,
+        postmessage: format!(
+            "Write a numbered markdown list with exactly {} items, one per commit in the order given, without a title.  Each item starts with the commit id in backticks and says in a sentence or two what that commit introduces and how it builds on the ones before it, so a reviewer knows what to look for in each.",
+            commits.len()
+        ),
+        ..Default::default()
+    }
+}
+
+/// The walkthrough section for a pull request body
+///
+/// # Arguments
+///
+/// * `walkthrough` - What the AI wrote
+pub fn markdown_section(walkthrough: &str) -> String {
+    format!(
+        "## Walkthrough\n\nThis is easiest to review one commit at a time:\n\n{}",
+        walkthrough.trim()
+    )
+}
@@ -0,0 +1,153 @@
+use crossterm::style::{Attribute, Color, SetForegroundColor};
+
+/// Past this many words on either side the diff isn't worth working out
+const MAX_WORDS: usize = 3000;
+
+/// One piece of a word diff
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+/// The words of `text`, line breaks count as a word so the diff keeps the lines
+fn words(text: &str) -> Vec<&str> {
+    let mut words = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        if i > 0 {
+            words.push("\n");
+        }
+        words.extend(line.split_whitespace());
+    }
+    words
+}
+
+/// The word diff between two candidates, whitespace doesn't count as a change
+///
+/// # Arguments
+///
+/// * `old` - The previous candidate
+/// * `new` - The new one
+pub fn diff<'a>(old: &'a str, new: &'a str) -> Vec<Change<'a>> {
+    let old = words(old);
+    let new = words(new);
+    if old.len() > MAX_WORDS || new.len() > MAX_WORDS {
+        return old
+            .into_iter()
+            .map(Change::Removed)
+            .chain(new.into_iter().map(Change::Added))
+            .collect();
+    }
+    // longest common subsequence, lcs[i][j] is for old[i..] and new[j..]
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    let mut changes = Vec::new();
+    while i < old.len() && j < new.len() {
+        if old[i] == new[j] {
+            changes.push(Change::Same(old[i]));
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            changes.push(Change::Removed(old[i]));
+            i += 1;
+        } else {
+            changes.push(Change::Added(new[j]));
+            j += 1;
+        }
+    }
+    changes.extend(old[i..].iter().map(|w| Change::Removed(w)));
+    changes.extend(new[j..].iter().map(|w| Change::Added(w)));
+    changes
+}
+
+/// How many words went and how many came in
+pub fn counts(changes: &[Change]) -> (usize, usize) {
+    changes
+        .iter()
+        .fold((0, 0), |(removed, added), change| match change {
+            Change::Removed(w) if *w != "\n" => (removed + 1, added),
+            Change::Added(w) if *w != "\n" => (removed, added + 1),
+            _ => (removed, added),
+        })
+}
+
+/// The diff as text, `[-removed-]` and `{+added+}` like `git diff --word-diff`, in red
+/// and green too with `color`
+///
+/// # Arguments
+///
+/// * `changes` - The diff
+/// * `color` - Color it in for a terminal
+pub fn render(changes: &[Change], color: bool) -> String {
+    let mut out = String::new();
+    for change in changes {
+        let (word, open, close) = match change {
+            Change::Same(w) => (*w, String::new(), String::new()),
+            Change::Removed(w) if color => (
+                *w,
+                format!(
+                    "{}{}",
+                    SetForegroundColor(Color::Red),
+                    Attribute::CrossedOut
+                ),
+                Attribute::Reset.to_string(),
+            ),
+            Change::Added(w) if color => (
+                *w,
+                format!(
+                    "{}{}",
+                    SetForegroundColor(Color::Green),
+                    Attribute::Underlined
+                ),
+                Attribute::Reset.to_string(),
+            ),
+            Change::Removed(w) => (*w, "[-".to_string(), "-]".to_string()),
+            Change::Added(w) => (*w, "{+".to_string(), "+}".to_string()),
+        };
+        if word == "\n" {
+            // a line break that went or came in is still a line break
+            out.push('\n');
+            continue;
+        }
+        if !out.is_empty() && !out.ends_with('\n') {
+            out.push(' ');
+        }
+        out.push_str(&open);
+        out.push_str(word);
+        out.push_str(&close);
+    }
+    out
+}
+
+/// How the new candidate compares to the previous one, ready to print
+///
+/// # Arguments
+///
+/// * `old` - The previous candidate
+/// * `new` - The new one
+/// * `color` - Color it in for a terminal
+pub fn describe(old: &str, new: &str, color: bool) -> String {
+    let changes = diff(old, new);
+    match counts(&changes) {
+        (0, 0) => format!(
+            "Same words as the last one, only the whitespace changed\n\n{}",
+            new
+        ),
+        (removed, added) => format!(
+            "{} word(s) out, {} in since the last one\n\n{}",
+            removed,
+            added,
+            render(&changes, color)
+        ),
+    }
+}
@@ -0,0 +1,276 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use log::debug;
+
+/// One package in a Cargo or npm workspace
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Member {
+    /// The package name
+    pub name: String,
+    /// The directory it lives in, relative to the workspace root
+    pub dir: PathBuf,
+    /// The other workspace members it depends on
+    pub depends_on: BTreeSet<String>,
+}
+
+/// A Cargo or npm workspace, built from the manifests in the repo
+#[derive(Debug, Clone, Default)]
+pub struct Workspace {
+    pub members: Vec<Member>,
+}
+
+/// What a change does to a workspace
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Impact {
+    /// Packages with changed files
+    pub changed: BTreeSet<String>,
+    /// Packages that (directly or not) depend on a changed package, and which one
+    pub dependents: BTreeMap<String, String>,
+}
+
+impl Impact {
+    /// Nothing in the workspace changed
+    pub fn is_empty(&self) -> bool {
+        self.changed.is_empty()
+    }
+
+    /// A short section for a commit message
+    pub fn to_commit_section(&self) -> String {
+        let mut section = format!(
+            "Affected packages: {}",
+            self.changed
+                .iter()
+                .cloned()
+                .collect::<Vec<String>>()
+                .join(", ")
+        );
+        if !self.dependents.is_empty() {
+            section.push_str(&format!(
+                " (dependents: {})",
+                self.dependents
+                    .keys()
+                    .cloned()
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            ));
+        }
+        section
+    }
+
+    /// A markdown section for a pull request body
+    pub fn to_markdown_section(&self) -> String {
+        let mut section = "## Affected packages\n".to_string();
+        for name in &self.changed {
+            section.push_str(&format!("\n- `{}` (changed)", name));
+        }
+        for (name, via) in &self.dependents {
+            section.push_str(&format!("\n- `{}` (depends on `{}`)", name, via));
+        }
+        section
+    }
+}
+
+impl Workspace {
+    /// Looks for a Cargo or npm workspace at `root`, `None` if there isn't one with
+    /// at least two members (a single package has nobody else to affect)
+    ///
+    /// # Arguments
+    ///
+    /// * `root` - The root of the repo
+    pub fn discover(root: &Path) -> Option<Self> {
+        let mut members = cargo_members(root);
+        members.extend(npm_members(root));
+        debug!("Found {} workspace members", members.len());
+        if members.len() < 2 {
+            return None;
+        }
+        Some(Workspace { members })
+    }
+
+    /// Which members a list of changed files touches, and who depends on them
+    ///
+    /// # Arguments
+    ///
+    /// * `changed_files` - The changed files, relative to the workspace root
+    pub fn impact(&self, changed_files: &[PathBuf]) -> Impact {
+        let mut impact = Impact::default();
+        for file in changed_files {
+            // the most specific member wins, members can be nested
+            if let Some(member) = self
+                .members
+                .iter()
+                .filter(|m| file.starts_with(&m.dir))
+                .max_by_key(|m| m.dir.components().count())
+            {
+                impact.changed.insert(member.name.to_string());
+            }
+        }
+        // walk the reverse dependencies until nothing new shows up
+        let mut frontier: Vec<String> = impact.changed.iter().cloned().collect();
+        while let Some(name) = frontier.pop() {
+            for member in &self.members {
+                if member.depends_on.contains(&name)
+                    && !impact.changed.contains(&member.name)
+                    && !impact.dependents.contains_key(&member.name)
+                {
+                    impact
+                        .dependents
+                        .insert(member.name.to_string(), name.to_string());
+                    frontier.push(member.name.to_string());
+                }
+            }
+        }
+        impact
+    }
+}
+
+/// Expands a workspace member pattern, `crates/*`, `packages/**` or a plain path
+fn expand_member_glob(root: &Path, pattern: &str) -> Vec<PathBuf> {
+    let pattern = pattern.trim_end_matches('/');
+    if let Some(base) = pattern.strip_suffix("/**") {
+        let mut found = Vec::new();
+        collect_dirs(&root.join(base), &mut found);
+        found
+    } else if let Some(base) = pattern.strip_suffix("/*") {
+        fs::read_dir(root.join(base))
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .map(|entry| entry.path())
+                    .filter(|path| path.is_dir())
+                    .collect()
+            })
+            .unwrap_or_default()
+    } else {
+        vec![root.join(pattern)]
+    }
+}
+
+fn collect_dirs(dir: &Path, found: &mut Vec<PathBuf>) {
+    if let Ok(entries) = fs::read_dir(dir) {
+        for path in entries.flatten().map(|entry| entry.path()) {
+            let skip = path
+                .file_name()
+                .map(|name| name == "node_modules" || name == "target" || name == ".git")
+                .unwrap_or(true);
+            if path.is_dir() && !skip {
+                found.push(path.to_path_buf());
+                collect_dirs(&path, found);
+            }
+        }
+    }
+}
+
+/// The members of a Cargo workspace, from `[workspace] members` in the root `Cargo.toml`
+fn cargo_members(root: &Path) -> Vec<Member> {
+    let manifest = match read_toml(&root.join("Cargo.toml")) {
+        Some(manifest) => manifest,
+        None => return Vec::new(),
+    };
+    let patterns: Vec<String> = manifest
+        .get("workspace")
+        .and_then(|ws| ws.get("members"))
+        .and_then(|members| members.as_array())
+        .map(|members| {
+            members
+                .iter()
+                .filter_map(|m| m.as_str().map(String::from))
+                .collect()
+        })
+        .unwrap_or_default();
+    let mut members = Vec::new();
+    for dir in patterns.iter().flat_map(|p| expand_member_glob(root, p)) {
+        let member_manifest = match read_toml(&dir.join("Cargo.toml")) {
+            Some(manifest) => manifest,
+            None => continue,
+        };
+        let name = match member_manifest
+            .get("package")
+            .and_then(|package| package.get("name"))
+            .and_then(|name| name.as_str())
+        {
+            Some(name) => name.to_string(),
+            None => continue,
+        };
+        let mut depends_on = BTreeSet::new();
+        for table in ["dependencies", "dev-dependencies", "build-dependencies"] {
+            if let Some(deps) = member_manifest.get(table).and_then(|d| d.as_table()) {
+                for (key, value) in deps {
+                    // `foo = { package = "real-name", ... }` renames a dependency
+                    let dep = value.get("package").and_then(|p| p.as_str()).unwrap_or(key);
+                    depends_on.insert(dep.to_string());
+                }
+            }
+        }
+        members.push(Member {
+            name,
+            dir: dir.strip_prefix(root).unwrap_or(&dir).to_path_buf(),
+            depends_on,
+        });
+    }
+    keep_workspace_deps(members)
+}
+
+/// The members of an npm (or yarn/pnpm style) workspace, from `workspaces` in the root `package.json`
+fn npm_members(root: &Path) -> Vec<Member> {
+    let manifest = match read_json(&root.join("package.json")) {
+        Some(manifest) => manifest,
+        None => return Vec::new(),
+    };
+    // either `"workspaces": [...]` or `"workspaces": { "packages": [...] }`
+    let patterns = manifest.get("workspaces").and_then(|ws| {
+        ws.as_array()
+            .or_else(|| ws.get("packages").and_then(|p| p.as_array()))
+    });
+    let patterns: Vec<String> = patterns
+        .map(|p| {
+            p.iter()
+                .filter_map(|v| v.as_str().map(String::from))
+                .collect()
+        })
+        .unwrap_or_default();
+    let mut members = Vec::new();
+    for dir in patterns.iter().flat_map(|p| expand_member_glob(root, p)) {
+        let member_manifest = match read_json(&dir.join("package.json")) {
+            Some(manifest) => manifest,
+            None => continue,
+        };
+        let name = match member_manifest.get("name").and_then(|n| n.as_str()) {
+            Some(name) => name.to_string(),
+            None => continue,
+        };
+        let mut depends_on = BTreeSet::new();
+        for table in ["dependencies", "devDependencies", "peerDependencies"] {
+            if let Some(deps) = member_manifest.get(table).and_then(|d| d.as_object()) {
+                depends_on.extend(deps.keys().cloned());
+            }
+        }
+        members.push(Member {
+            name,
+            dir: dir.strip_prefix(root).unwrap_or(&dir).to_path_buf(),
+            depends_on,
+        });
+    }
+    keep_workspace_deps(members)
+}
+
+/// Drops every dependency that isn't another member, we only care about the graph inside the workspace
+fn keep_workspace_deps(mut members: Vec<Member>) -> Vec<Member> {
+    let names: BTreeSet<String> = members.iter().map(|m| m.name.to_string()).collect();
+    for member in members.iter_mut() {
+        member.depends_on.retain(|dep| names.contains(dep));
+    }
+    members
+}
+
+fn read_toml(path: &Path) -> Option<toml::Value> {
+    toml::from_str(&fs::read_to_string(path).ok()?).ok()
+}
+
+fn read_json(path: &Path) -> Option<serde_json::Value> {
+    serde_json::from_str(&fs::read_to_string(path).ok()?).ok()
+}